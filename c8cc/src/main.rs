@@ -1,6 +1,7 @@
-use std::{env, fs::File, io::Write, path::Path, process::Command};
+use std::{env, fs, path::Path};
 
 use c8cc::{self, compile::compile, lexer::lex, parser::parse};
+use c8util::asm::{AssemblyOptions, assemble};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -15,30 +16,27 @@ fn main() {
     println!("{program:?}");
     let instructions = compile(&program);
 
+    let source = instructions
+        .iter()
+        .map(|instruction| format!("{instruction}\n"))
+        .collect::<String>();
     let of_path = if_path.with_extension("asm");
-    let mut of = File::create(of_path.clone()).unwrap();
-    for instruction in instructions {
-        of.write_all(instruction.asm().as_bytes()).unwrap();
-        of.write_all(b"\n").unwrap();
-    }
+    fs::write(&of_path, &source).unwrap();
 
     // Convert asm to bytecode
-    env::set_current_dir("../c8asm").expect("missing crate: c8asm");
-    let command = Command::new("cargo")
-        .arg("run")
-        .arg(format!(
-            "../c8cc/{}",
-            of_path.file_name().unwrap().to_str().unwrap()
-        ))
-        .arg(format!(
-            "../c8cc/{}",
-            of_path
-                .with_extension("c8")
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap(),
-        ))
-        .output();
-    println!("{command:?}");
+    let rom = match assemble(&source, &AssemblyOptions::default()) {
+        Ok(rom) => rom,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                eprintln!("{diagnostic}");
+            }
+            eprintln!(
+                "the compiled program could not be assembled ({} error(s)), see {}",
+                diagnostics.len(),
+                of_path.display()
+            );
+            std::process::exit(1);
+        }
+    };
+    fs::write(of_path.with_extension("c8"), rom).unwrap();
 }
@@ -0,0 +1,178 @@
+use std::fmt::Write as _;
+
+use c8util::{
+    disasm::{DisassembledLine, DisassemblyOptions, disassemble},
+    instructions::InstructionSet,
+};
+
+use crate::{Syntax, line_text, read_rom};
+
+/// The number of unchanged lines that are shown around each change.
+const CONTEXT: usize = 2;
+
+/// A line of the diff, as indices into the lines of each disassembly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// The line is in both disassemblies.
+    Same(usize, usize),
+    /// The line is only in the first disassembly.
+    Removed(usize),
+    /// The line is only in the second disassembly.
+    Added(usize),
+}
+
+/// Disassemble the ROMs at `a` and `b`, which are both loaded at `org`, and return the lines that differ between
+/// them, with a few unchanged lines around each change.
+///
+/// # Errors
+/// Returns an error if either ROM can't be read, or doesn't fit in memory.
+pub fn diff_roms(
+    a: &str,
+    b: &str,
+    org: usize,
+    set: InstructionSet,
+    syntax: Syntax,
+) -> Result<String, String> {
+    let opts = DisassemblyOptions {
+        org,
+        instruction_set: set,
+        ..DisassemblyOptions::default()
+    };
+    let a_lines = disassemble(read_rom(a, org)?.data(), &opts).lines;
+    let b_lines = disassemble(read_rom(b, org)?.data(), &opts).lines;
+    // Labels are left out, since they're named after addresses that may have moved
+    let code = |lines: &[DisassembledLine]| {
+        lines
+            .iter()
+            .map(|line| (line.instruction, line.raw.clone()))
+            .collect::<Vec<_>>()
+    };
+    let changes = diff_lines(&code(&a_lines), &code(&b_lines));
+
+    let mut diff = format!("--- {a}\n+++ {b}\n");
+    let is_change = |change: &Change| !matches!(change, Change::Same(..));
+    let mut next = 0;
+    while let Some(first) = changes[next..].iter().position(is_change).map(|i| next + i) {
+        // Changes that are close enough to share their context are in the same hunk
+        let mut last = first;
+        for (i, change) in changes.iter().enumerate().skip(first) {
+            if i > last + 2 * CONTEXT {
+                break;
+            }
+            if is_change(change) {
+                last = i;
+            }
+        }
+        let hunk = first.saturating_sub(CONTEXT)..(last + CONTEXT + 1).min(changes.len());
+        let addr = |lines: &[DisassembledLine], i: Option<usize>| {
+            i.and_then(|i| lines.get(i))
+                .map_or(String::from("end"), |line| format!("{:#05X}", line.addr))
+        };
+        let (a_start, b_start) =
+            changes[hunk.clone()]
+                .iter()
+                .fold((None, None), |(a, b), change| match change {
+                    Change::Same(i, j) => (a.or(Some(*i)), b.or(Some(*j))),
+                    Change::Removed(i) => (a.or(Some(*i)), b),
+                    Change::Added(j) => (a, b.or(Some(*j))),
+                });
+        writeln!(
+            diff,
+            "@@ {a} {}, {b} {} @@",
+            addr(&a_lines, a_start),
+            addr(&b_lines, b_start)
+        )
+        .unwrap();
+        for change in &changes[hunk.clone()] {
+            match *change {
+                Change::Same(i, j) => writeln!(
+                    diff,
+                    "  {:#05X} {:#05X}  {}",
+                    a_lines[i].addr,
+                    b_lines[j].addr,
+                    line_text(&a_lines[i], syntax)
+                ),
+                Change::Removed(i) => writeln!(
+                    diff,
+                    "- {:#05X}        {}",
+                    a_lines[i].addr,
+                    line_text(&a_lines[i], syntax)
+                ),
+                Change::Added(j) => writeln!(
+                    diff,
+                    "+       {:#05X}  {}",
+                    b_lines[j].addr,
+                    line_text(&b_lines[j], syntax)
+                ),
+            }
+            .unwrap();
+        }
+        next = hunk.end;
+    }
+
+    let removed = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Removed(_)))
+        .count();
+    let added = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Added(_)))
+        .count();
+    if removed == 0 && added == 0 {
+        diff.push_str("no differences\n");
+    } else {
+        writeln!(
+            diff,
+            "{removed} lines only in {a}, {added} lines only in {b}"
+        )
+        .unwrap();
+    }
+    Ok(diff)
+}
+
+/// Align the lines of `a` and `b` by finding their longest common subsequence, and return each line as a change.
+fn diff_lines<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Change> {
+    // Only the part between the common prefix and suffix needs to be aligned
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a_middle = &a[prefix..a.len() - suffix];
+    let b_middle = &b[prefix..b.len() - suffix];
+
+    // lengths[i * width + j] is the length of the longest common subsequence of a_middle[i..] and b_middle[j..]
+    let width = b_middle.len() + 1;
+    let mut lengths = vec![0_u32; (a_middle.len() + 1) * width];
+    for i in (0..a_middle.len()).rev() {
+        for j in (0..b_middle.len()).rev() {
+            lengths[i * width + j] = if a_middle[i] == b_middle[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut changes = (0..prefix).map(|i| Change::Same(i, i)).collect::<Vec<_>>();
+    let (mut i, mut j) = (0, 0);
+    while i < a_middle.len() || j < b_middle.len() {
+        if i < a_middle.len() && j < b_middle.len() && a_middle[i] == b_middle[j] {
+            changes.push(Change::Same(prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if j == b_middle.len()
+            || (i < a_middle.len() && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1])
+        {
+            changes.push(Change::Removed(prefix + i));
+            i += 1;
+        } else {
+            changes.push(Change::Added(prefix + j));
+            j += 1;
+        }
+    }
+    changes.extend((0..suffix).map(|k| Change::Same(a.len() - suffix + k, b.len() - suffix + k)));
+    changes
+}
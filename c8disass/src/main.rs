@@ -1,72 +1,558 @@
-use std::{env, fs::File, io::Read};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    io::{self, Read as _},
+    path::Path,
+};
 
-use c8util::{decode::decode, instructions::Instruction};
+use c8util::{
+    asm::{AssemblyOptions, assemble},
+    coverage::CoverageMap,
+    disasm::{
+        self, CrossReferences, DisassembledLine, DisassemblyOptions, ROM_START, call_graph,
+        sprite_row,
+    },
+    instructions::{Flavor, Instruction, InstructionSet, Platform},
+    octo,
+    rom::{MEMORY_SIZE, Rom},
+    symbols::{format_symbols, parse_symbols},
+};
+use clap::{Parser, ValueEnum};
+
+mod diff;
+
+/// The assembly syntax to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Syntax {
+    /// The syntax of c8asm (see c8asm/spec.md).
+    C8asm,
+    /// The syntax of the Octo assembler.
+    Octo,
+}
+
+impl Syntax {
+    /// The character that starts a comment.
+    fn comment(self) -> char {
+        match self {
+            Self::C8asm => ';',
+            Self::Octo => '#',
+        }
+    }
+}
+
+impl From<Syntax> for Flavor {
+    fn from(syntax: Syntax) -> Self {
+        match syntax {
+            Syntax::C8asm => Self::C8asm,
+            Syntax::Octo => Self::Octo,
+        }
+    }
+}
+
+/// A disassembler for Chip-8 programs.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// The ROM to disassemble, or `-` to read it from stdin.
+    #[arg(required_unless_present = "diff")]
+    rom: Option<String>,
+    /// A coverage map saved by c8rs. Code that was executed is disassembled even if it can't be reached by
+    /// following the program from its start (e.g. after a `BNNN` jump).
+    coverage: Option<String>,
+    /// A coverage map saved by c8rs (the same as the second argument).
+    #[arg(long = "coverage", value_name = "PATH", conflicts_with = "coverage")]
+    coverage_path: Option<String>,
+    /// Write the disassembly to this file, instead of stdout.
+    #[arg(short, long)]
+    output: Option<String>,
+    /// The address that the ROM is loaded at (e.g. 0x600 for ETI-660 ROMs).
+    #[arg(long, alias = "base", default_value = "0x200", value_parser = parse_addr)]
+    org: usize,
+    /// The assembly syntax to write.
+    #[arg(long, value_enum, default_value_t = Syntax::C8asm)]
+    syntax: Syntax,
+    /// The instructions to decode. Instructions from newer sets are disassembled as data.
+    #[arg(long, value_enum, default_value_t = Platform::Xochip)]
+    instruction_set: Platform,
+    /// The address to start disassembling at [default: --org].
+    #[arg(long, value_parser = parse_addr)]
+    start: Option<usize>,
+    /// The address to stop disassembling at, not included [default: the end of the ROM].
+    #[arg(long, value_parser = parse_addr)]
+    end: Option<usize>,
+    /// A symbol file naming addresses in the ROM (e.g. the one used by c8rs). The names are used as labels,
+    /// instead of the generated ones.
+    #[arg(long)]
+    symbols: Option<String>,
+    /// Save the labels (generated, and from --symbols) to this file, as a symbol file that c8rs can load.
+    #[arg(long)]
+    write_symbols: Option<String>,
+    /// List the instructions that refer to each label after the disassembly, as comments.
+    #[arg(long)]
+    xrefs: bool,
+    /// Describe what each instruction does in a comment after it, e.g. `skip next if V3 == 0x10`.
+    #[arg(long)]
+    comments: bool,
+    /// Save the subroutine call graph to this file, in the Graphviz DOT format.
+    #[arg(long)]
+    callgraph: Option<String>,
+    /// Assemble the disassembly again (with c8asm's assembler, or its Octo front-end for the Octo syntax), and check
+    /// that it matches the ROM byte for byte. Exits with code 1 if it doesn't.
+    #[arg(long)]
+    verify_roundtrip: bool,
+    /// Disassemble two ROMs and print the lines that differ between them, instead of disassembling one.
+    #[arg(long, num_args = 2, value_names = ["A", "B"], conflicts_with_all = [
+        "rom", "coverage", "coverage_path", "start", "end", "symbols", "write_symbols", "xrefs", "comments", "callgraph",
+        "verify_roundtrip",
+    ])]
+    diff: Option<Vec<String>>,
+}
+
+/// Returns the line in the given syntax, without any labels.
+fn line_text(line: &DisassembledLine, syntax: Syntax) -> String {
+    match (line.instruction, syntax) {
+        (Some(ins), _) => ins.display(syntax.into()).to_string(),
+        (None, Syntax::C8asm) => {
+            format!("byte  {:#04X} ; {}", line.raw[0], sprite_row(line.raw[0]))
+        }
+        (None, Syntax::Octo) => format!("{:#04X} # {}", line.raw[0], sprite_row(line.raw[0])),
+    }
+}
+
+/// Parse an address in base 10 or hex (`0x2A4`).
+fn parse_addr(addr: &str) -> Result<usize, String> {
+    let parsed = match addr.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => addr.parse(),
+    }
+    .map_err(|e| format!("could not parse address {addr}: {e}"))?;
+    if parsed > MEMORY_SIZE {
+        return Err(format!(
+            "address {parsed:#06X} is outside of memory (should be at most {MEMORY_SIZE:#06X})"
+        ));
+    }
+    Ok(parsed)
+}
+
+/// Read the ROM at `path`, or from stdin if it's `-`, which is loaded at `org`.
+fn read_rom(path: &str, org: usize) -> Result<Rom, String> {
+    if path == "-" {
+        let mut rom = Vec::new();
+        io::stdin()
+            .read_to_end(&mut rom)
+            .map_err(|e| format!("failed to read the ROM from stdin: {e}"))?;
+        Rom::from_bytes(rom, org)
+    } else {
+        Rom::load(Path::new(path), org)
+    }
+}
+
+/// Read a coverage map saved by c8rs.
+fn read_coverage(path: &str) -> Result<CoverageMap, String> {
+    fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {path}: {e}"))
+        .and_then(|contents| CoverageMap::parse(&contents, MEMORY_SIZE))
+}
+
+/// Read a symbol file.
+fn read_symbols(path: &str) -> Result<BTreeMap<u16, String>, String> {
+    fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {path}: {e}"))
+        .and_then(|contents| parse_symbols(&contents).map_err(|e| format!("{path}:{e}")))
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <path>", args[0]);
+    let args = Args::parse();
+
+    if let Some(paths) = &args.diff {
+        let set = InstructionSet::from(args.instruction_set);
+        let diff = match diff::diff_roms(&paths[0], &paths[1], args.org, set, args.syntax) {
+            Ok(diff) => diff,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        };
+        write_output(args.output.as_deref(), &diff);
         return;
     }
 
-    let mut buf = Vec::new();
-    let mut f = File::open(&args[1]).expect("failed to open file");
-    f.read_to_end(&mut buf).expect("failed to read file");
-    disassemble(&buf);
+    let rom = match read_rom(args.rom.as_deref().unwrap(), args.org) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let coverage_path = args.coverage.as_deref().or(args.coverage_path.as_deref());
+    let coverage = match coverage_path.map(read_coverage).transpose() {
+        Ok(coverage) => coverage,
+        Err(e) => {
+            eprintln!("invalid coverage file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let rom_end = rom.end_addr();
+    let start = args.start.unwrap_or(args.org);
+    let end = args.end.unwrap_or(rom_end);
+    if start < args.org || end > rom_end || start >= end {
+        eprintln!(
+            "invalid range {start:#06X}..{end:#06X}: the ROM is at {:#06X}..{rom_end:#06X}",
+            args.org
+        );
+        std::process::exit(1);
+    }
+
+    let symbols = match args.symbols.as_deref().map(read_symbols).transpose() {
+        Ok(symbols) => symbols.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("invalid symbol file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let set = InstructionSet::from(args.instruction_set);
+    let opts = DisassemblyOptions {
+        org: args.org,
+        instruction_set: set,
+        coverage: coverage.as_ref(),
+        symbols,
+        range: Some(start..end),
+    };
+    let disassembly = disasm::disassemble(rom.data(), &opts);
+    if let Some(path) = args.write_symbols {
+        write_output(Some(&path), &format_symbols(&disassembly.labels));
+    }
+    if let Some(path) = args.callgraph {
+        let dot = call_graph_dot(&call_graph(rom.data(), args.org, set), &disassembly.labels);
+        write_output(Some(&path), &dot);
+    }
+
+    let mut asm = format_asm(&disassembly, start, args.syntax, args.comments);
+    if args.xrefs {
+        asm.push_str(&xref_report(
+            &disassembly.xrefs,
+            &disassembly.labels,
+            args.syntax,
+        ));
+    }
+    if args.verify_roundtrip {
+        let original = &rom.data()[start - args.org..end - args.org];
+        if let Err(e) = verify_roundtrip(&asm, args.syntax, original, start) {
+            eprintln!("round trip failed: {e}");
+            std::process::exit(1);
+        }
+        eprintln!(
+            "round trip OK: the disassembly assembles to the same {} bytes",
+            original.len()
+        );
+    }
+    write_output(args.output.as_deref(), &asm);
+}
+
+/// Write the output to the file at `path`, or stdout if there isn't one.
+fn write_output(path: Option<&str>, output: &str) {
+    match path {
+        Some(path) => {
+            if let Err(e) = fs::write(path, output) {
+                eprintln!("failed to write {path}: {e}");
+                std::process::exit(1);
+            }
+        }
+        None => print!("{output}"),
+    }
+}
+
+/// Check that assembling `asm`, which is in the given syntax, gives back `original`, which is loaded at `start`.
+///
+/// # Errors
+/// Returns an error if the disassembly could not be assembled, or where it differs from `original`.
+fn verify_roundtrip(
+    asm: &str,
+    syntax: Syntax,
+    original: &[u8],
+    start: usize,
+) -> Result<(), String> {
+    let assembled = match syntax {
+        Syntax::C8asm => assemble(
+            asm,
+            &AssemblyOptions {
+                org: start,
+                ..AssemblyOptions::default()
+            },
+        )
+        .map_err(|diagnostics| {
+            diagnostics
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        })?,
+        // Octo programs always start at 0x200, and the `:org` at the start pads them up to `start`
+        Syntax::Octo => octo::assemble(asm)
+            .map_err(|diagnostic| diagnostic.to_string())?
+            .rom
+            .get(start.saturating_sub(ROM_START)..)
+            .unwrap_or_default()
+            .to_vec(),
+    };
+    if let Some(offset) =
+        (0..original.len().min(assembled.len())).find(|i| assembled[*i] != original[*i])
+    {
+        return Err(format!(
+            "the byte at {:#06X} is {:#04X}, but was assembled as {:#04X}",
+            start + offset,
+            original[offset],
+            assembled[offset]
+        ));
+    }
+    if assembled.len() != original.len() {
+        return Err(format!(
+            "the ROM is {} bytes long, but was assembled to {} bytes",
+            original.len(),
+            assembled.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the call graph in the Graphviz DOT format, with a node for each routine (named by its label, if it has one)
+/// and an edge for each call, labeled with the address of the call.
+fn call_graph_dot(
+    routines: &BTreeMap<usize, Vec<(usize, usize)>>,
+    labels: &BTreeMap<u16, String>,
+) -> String {
+    let mut dot = String::from("digraph calls {\n    node [shape=box, fontname=\"monospace\"];\n");
+    for addr in routines.keys() {
+        match u16::try_from(*addr).ok().and_then(|addr| labels.get(&addr)) {
+            Some(name) => writeln!(dot, "    r{addr:03X} [label=\"{name}\\n{addr:#05X}\"];"),
+            None => writeln!(dot, "    r{addr:03X} [label=\"{addr:#05X}\"];"),
+        }
+        .unwrap();
+    }
+    for (caller, calls) in routines {
+        for (call, callee) in calls {
+            writeln!(
+                dot,
+                "    r{caller:03X} -> r{callee:03X} [label=\"{call:#05X}\"];"
+            )
+            .unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
 }
 
-fn disassemble(v: &[u8]) {
-    v.iter()
-        .enumerate()
-        .step_by(2)
-        .map(|(i, &val)| (u16::from(val) << 8) | u16::from(*v.get(i + 1).unwrap()))
-        .for_each(|code| {
-            let ins = decode(code);
-            if let Some(i) = ins {
-                println!("{}", get_instruction(i));
-            } else {
-                println!("db    {code:#06X}");
+/// Returns a line for each label that's referred to, listing the instructions that refer to it (e.g.
+/// `sub_21E (0x21E) referenced by: call@0x204, jmp@0x2F0`), as comments in the given syntax.
+fn xref_report(xrefs: &CrossReferences, labels: &BTreeMap<u16, String>, syntax: Syntax) -> String {
+    let comment = syntax.comment();
+    let mut report = format!("\n{comment} Cross-references\n");
+    for (addr, name) in labels {
+        let Some(refs) = xrefs.get(addr) else {
+            continue;
+        };
+        let refs = refs
+            .iter()
+            .map(|(ref_addr, kind)| format!("{kind}@{ref_addr:#05X}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            report,
+            "{comment} {name} ({addr:#05X}) referenced by: {refs}"
+        )
+        .unwrap();
+    }
+    report
+}
+
+/// Write the lines of the disassembly, which start at `start`, in the given syntax. Instructions that refer to a
+/// labeled address use its name. The warnings for each address are written as comments before its line. With
+/// `comments`, each instruction is followed by a description of what it does.
+fn format_asm(
+    disassembly: &disasm::Disassembly,
+    start: usize,
+    syntax: Syntax,
+    comments: bool,
+) -> String {
+    let disasm::Disassembly {
+        lines,
+        labels,
+        warnings,
+        ..
+    } = disassembly;
+    let mut asm = String::new();
+    if syntax == Syntax::Octo && start != ROM_START {
+        writeln!(asm, ":org {start:#05X}").unwrap();
+    }
+    let is_line_start = |addr: u16| {
+        lines
+            .binary_search_by_key(&usize::from(addr), |line| line.addr)
+            .is_ok()
+    };
+    for line in lines {
+        if let Some(name) = &line.label {
+            match syntax {
+                Syntax::C8asm => writeln!(asm, "; {name}:"),
+                Syntax::Octo => writeln!(asm, ": {name}"),
+            }
+            .unwrap();
+        }
+        for warning in warnings.get(&line.addr).into_iter().flatten() {
+            writeln!(asm, "{} warning: {warning}", syntax.comment()).unwrap();
+        }
+        let label = |ins: Instruction| {
+            ins.target_addr()
+                .and_then(|addr| Some((addr, labels.get(&addr)?)))
+        };
+        let code = match (line.instruction, syntax) {
+            (Some(ins), Syntax::C8asm) => match label(ins) {
+                Some((_, name)) => format!("{ins} ; {name}"),
+                None => line_text(line, syntax),
+            },
+            (Some(ins), Syntax::Octo) => match (ins, label(ins)) {
+                (Instruction::Jump(_), Some((addr, name))) if is_line_start(addr) => {
+                    format!("jump {name}")
+                }
+                (Instruction::SubroutineCall(_), Some((addr, name))) if is_line_start(addr) => {
+                    format!(":call {name}")
+                }
+                (Instruction::SetIndexRegister(_), Some((addr, name))) if is_line_start(addr) => {
+                    format!("i := {name}")
+                }
+                (Instruction::LoadIndexLong(_), Some((addr, name))) if is_line_start(addr) => {
+                    format!("i := long {name}")
+                }
+                (Instruction::JumpOffset(_), Some((addr, name))) if is_line_start(addr) => {
+                    format!("jump0 {name}")
+                }
+                // The label isn't in the output, so Octo wouldn't know it
+                (_, Some((_, name))) => format!("{} # {name}", ins.display(Flavor::Octo)),
+                (_, None) => line_text(line, syntax),
+            },
+            (None, _) => line_text(line, syntax),
+        };
+        match line.instruction {
+            Some(ins) if comments => {
+                writeln!(asm, "{code:<23} {} {}", syntax.comment(), ins.describe())
+            }
+            _ => writeln!(asm, "{code}"),
+        }
+        .unwrap();
+    }
+    asm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Disassemble `rom` the way `main` does without coverage or symbols, for every instruction set, in both syntaxes
+    /// and with and without comments, and check that each disassembly assembles back to `rom`.
+    fn assert_roundtrip(rom: &[u8]) {
+        for set in [
+            InstructionSet::Chip8,
+            InstructionSet::Chip48,
+            InstructionSet::SuperChip,
+            InstructionSet::XoChip,
+        ] {
+            let opts = DisassemblyOptions {
+                instruction_set: set,
+                ..DisassemblyOptions::default()
             };
-        });
-}
-
-fn get_instruction(ins: Instruction) -> String {
-    match ins {
-        Instruction::ExecuteMachineLanguageRoutine => String::from("(bad)"),
-        Instruction::Clear => String::from("clear"),
-        Instruction::SubroutineReturn => String::from("ret"),
-        Instruction::Jump(nnn) => format!("jmp   {nnn:#06X}"),
-        Instruction::SubroutineCall(nnn) => format!("call  {nnn:#06X}"),
-        Instruction::SkipConditional1(vx, nn) => format!("ske   ${vx}, {nn:#02X}"),
-        Instruction::SkipConditional2(vx, nn) => format!("skn   ${vx}, {nn:#02X}"),
-        Instruction::SkipConditional3(vx, vy) => format!("ske   ${vx}, ${vy}"),
-        Instruction::SetRegister(vx, nn) => format!("mov   ${vx}, {nn:#02X}"),
-        Instruction::Add(vx, nn) => format!("add   ${vx}, {nn:#02X}"),
-        Instruction::RegSet(vx, vy) => format!("mov   ${vx}, ${vy}"),
-        Instruction::BinaryOr(vx, vy) => format!("or    ${vx}, ${vy}"),
-        Instruction::BinaryAnd(vx, vy) => format!("and   ${vx}, ${vy}"),
-        Instruction::BinaryXor(vx, vy) => format!("xor   ${vx}, ${vy}"),
-        Instruction::RegAdd(vx, vy) => format!("add   ${vx}, ${vy}"),
-        Instruction::Subtract1(vx, vy) => format!("sub1  ${vx}, ${vy}"),
-        Instruction::Subtract2(vx, vy) => format!("sub2  ${vx}, ${vy}"),
-        Instruction::ShiftRight(vx, vy) => format!("shr   ${vx}, ${vy}"),
-        Instruction::ShiftLeft(vx, vy) => format!("shl   ${vx}, ${vy}"),
-        Instruction::SkipConditional4(vx, vy) => format!("skn   ${vx}, ${vy}"),
-        Instruction::SetIndexRegister(nnn) => format!("mov   $i, {nnn:#06X}"),
-        Instruction::JumpOffset(nnn) => format!("jo    {nnn:#06X}"),
-        Instruction::Random(vx, nn) => format!("rand  ${vx}, {nn:#04X}"),
-        Instruction::Draw(vx, vy, n) => format!("draw  ${vx}, ${vy}, {n:#02X}"),
-        Instruction::SkipIfKey(vx) => format!("skk   ${vx}"),
-        Instruction::SkipIfNotKey(vx) => format!("sknk  ${vx}"),
-        Instruction::GetDelayTimer(vx) => format!("mov   ${vx}, $d"),
-        Instruction::GetKey(vx) => format!("key   ${vx}"),
-        Instruction::SetDelayTimer(vx) => format!("mov   $d, ${vx}"),
-        Instruction::SetSoundTimer(vx) => format!("mov   $s, ${vx}"),
-        Instruction::AddToIndex(vx) => format!("add   $i, ${vx}"),
-        Instruction::FontCharacter(vx) => format!("font  ${vx}"),
-        Instruction::BCD(vx) => format!("bcd   ${vx}"),
-        Instruction::StoreMemory(nn) => format!("store {nn:#02X}"),
-        Instruction::LoadMemory(nn) => format!("load  {nn:#02X}"),
-        Instruction::Db(nnnn) => format!("db    {nnnn:#06X}"),
+            let disassembly = disasm::disassemble(rom, &opts);
+            for syntax in [Syntax::C8asm, Syntax::Octo] {
+                for comments in [false, true] {
+                    let asm = format_asm(&disassembly, ROM_START, syntax, comments);
+                    if let Err(e) = verify_roundtrip(&asm, syntax, rom, ROM_START) {
+                        panic!("{set} {syntax:?} (comments: {comments}): {e}\n{asm}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_with_a_data_tail() {
+        let rom = c8util::rom! {
+            start:
+            Clear;
+            SetRegister(V0, 0);
+            SetIndexRegister => sprite;
+            SubroutineCall => draw;
+            Add(V0, 8);
+            SkipConditional1(V0, 64);
+            Jump => start;
+            halt:
+            Jump => halt;
+            draw:
+            Draw(V0, V1, 4);
+            SubroutineReturn;
+            sprite:
+            [0x3C, 0x42, 0x42, 0x3C];
+        }
+        .unwrap();
+        assert_roundtrip(&rom);
+    }
+
+    #[test]
+    fn roundtrip_with_an_odd_length() {
+        // Unreachable data that decodes as code, which leaves the rest of the instructions at odd addresses
+        let rom = c8util::rom! {
+            Jump => main;
+            [0xA2, 0x00, 0x60];
+            main:
+            SetIndexRegister => sprite;
+            Draw(V0, V0, 2);
+            Jump => main;
+            sprite:
+            [0x80, 0x40];
+        }
+        .unwrap();
+        assert_eq!(rom.len() % 2, 1);
+        assert_roundtrip(&rom);
+    }
+
+    #[test]
+    fn roundtrip_superchip() {
+        let rom = c8util::rom! {
+            HighRes;
+            SetRegister(V0, 3);
+            BigFontCharacter(V0);
+            Draw(V1, V1, 0);
+            ScrollDown(4);
+            ScrollRight;
+            ScrollLeft;
+            LowRes;
+            JumpOffset(0x210);
+            Exit;
+        }
+        .unwrap();
+        assert_roundtrip(&rom);
+    }
+
+    #[test]
+    fn roundtrip_xochip() {
+        let rom = c8util::rom! {
+            start:
+            SelectPlanes(3);
+            LoadIndexLong => sprite;
+            Draw(V0, V1, 2);
+            ScrollUp(2);
+            SaveRange(V1, V3);
+            LoadIndexLong => pattern;
+            LoadAudio;
+            SetPitch(V2);
+            Jump => start;
+            sprite:
+            [0xFF, 0x81, 0x81, 0xFF];
+            pattern:
+            // Ends with the start of a long I load (F000 NNNN) that the end of the ROM cuts off
+            [0x55, 0xAA, 0xF0, 0x00, 0x02];
+        }
+        .unwrap();
+        assert_roundtrip(&rom);
     }
 }
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+/// Commands that can't be replaced by an alias or macro, so that they can always be managed.
+const RESERVED_NAMES: &[&str] = &["alias", "unalias", "define", "undefine"];
+
+/// Short names for debug terminal commands, e.g. `dw` for `x 8 i`.
+pub static mut ALIASES: BTreeMap<String, String> = BTreeMap::new();
+
+/// Named lists of debug terminal commands that are run one after another, e.g. `step3` for `n; n; n`.
+pub static mut MACROS: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+/// Returns all aliases, sorted by name.
+pub fn get_aliases() -> BTreeMap<String, String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        ALIASES.clone()
+    }
+}
+
+/// Add an alias, replacing any alias with the same name.
+///
+/// # Errors
+/// Returns an error if the name is empty or belongs to one of the commands that manage aliases and macros.
+pub fn set_alias(name: &str, command: &str) -> Result<(), String> {
+    check_name(name)?;
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        ALIASES.insert(name.to_string(), command.to_string());
+    }
+    Ok(())
+}
+
+/// Remove the alias with the given name. Returns whether there was one.
+pub fn remove_alias(name: &str) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        ALIASES.remove(name).is_some()
+    }
+}
+
+/// Returns all macros, sorted by name.
+pub fn get_macros() -> BTreeMap<String, Vec<String>> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        MACROS.clone()
+    }
+}
+
+/// Add a macro, replacing any macro with the same name.
+///
+/// # Errors
+/// Returns an error if the name is empty or belongs to one of the commands that manage aliases and macros, or
+/// the macro has no commands.
+pub fn set_macro(name: &str, commands: Vec<String>) -> Result<(), String> {
+    check_name(name)?;
+    if commands.is_empty() {
+        return Err(format!("macro {name} has no commands"));
+    }
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        MACROS.insert(name.to_string(), commands);
+    }
+    Ok(())
+}
+
+/// Remove the macro with the given name. Returns whether there was one.
+pub fn remove_macro(name: &str) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        MACROS.remove(name).is_some()
+    }
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(char::is_whitespace) || name.contains(';') {
+        Err(format!("invalid name: {name}"))
+    } else if RESERVED_NAMES.contains(&name) {
+        Err(format!(
+            "{name} is a built-in command and can't be redefined"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Split a line into the commands separated by `;`, skipping empty ones.
+/// Lines that define aliases and macros aren't split, since they contain other commands.
+pub fn split_commands(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let name = line.split_whitespace().next().unwrap_or_default();
+    if RESERVED_NAMES.contains(&name) {
+        return vec![line.to_string()];
+    }
+    line.split(';')
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Split the line into commands, and replace any aliases and macros in them with the commands they stand for.
+///
+/// An alias is replaced along with the rest of the command, e.g. `dw 2` for `x 8 i 2`. Aliases and macros can use
+/// other aliases and macros, but not themselves: inside its own definition, a name is the built-in command, so
+/// e.g. `alias x x/dec` works as expected.
+///
+/// # Errors
+/// Returns an error if a macro is given arguments.
+pub fn expand_line(line: &str) -> Result<Vec<String>, String> {
+    let mut commands = Vec::new();
+    for command in split_commands(line) {
+        expand_command(&command, &mut Vec::new(), &mut commands)?;
+    }
+    Ok(commands)
+}
+
+/// Expand a single command into `out`. `expanding` is the aliases and macros that are being expanded.
+fn expand_command(
+    command: &str,
+    expanding: &mut Vec<String>,
+    out: &mut Vec<String>,
+) -> Result<(), String> {
+    let (name, rest) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    if RESERVED_NAMES.contains(&name) || expanding.iter().any(|n| n == name) {
+        out.push(command.to_string());
+        return Ok(());
+    }
+
+    let replacement = if let Some(alias) = get_aliases().get(name) {
+        split_commands(&format!("{alias} {rest}"))
+    } else if let Some(commands) = get_macros().get(name) {
+        if !rest.trim().is_empty() {
+            return Err(format!("macro {name} doesn't take any arguments"));
+        }
+        commands.clone()
+    } else {
+        out.push(command.to_string());
+        return Ok(());
+    };
+
+    expanding.push(name.to_string());
+    for command in replacement {
+        expand_command(&command, expanding, out)?;
+    }
+    expanding.pop();
+    Ok(())
+}
+
+/// Set the aliases and macros from the config file.
+///
+/// # Errors
+/// Returns an error if any of the names are invalid, or a macro has no commands.
+pub fn load_aliases(
+    aliases: &BTreeMap<String, String>,
+    macros: &BTreeMap<String, Vec<String>>,
+) -> Result<(), String> {
+    for (name, command) in aliases {
+        set_alias(name, command)?;
+    }
+    for (name, commands) in macros {
+        set_macro(name, commands.clone())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use std::sync::Mutex;
+
+use cpal::{
+    FromSample, SampleFormat, SizedSample, Stream, StreamConfig,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+};
+
+use crate::system::{
+    AUDIO_PATTERN_SIZE, DEFAULT_PITCH, get_audio_pattern, get_pitch, get_sound_timer,
+};
+
+/// The volume of the output, from 0 to 1.
+const AMPLITUDE: f32 = 0.1;
+
+/// The number of bits in the audio pattern.
+const PATTERN_BITS: f64 = 128.0;
+
+/// The state that the audio thread needs to generate samples.
+#[derive(Debug, Clone, Copy)]
+struct AudioState {
+    pattern: [u8; AUDIO_PATTERN_SIZE],
+    pitch: u8,
+    /// Whether the sound timer is not 0.
+    playing: bool,
+}
+
+/// Shared with the audio thread, which can't read the emulator state directly.
+static AUDIO_STATE: Mutex<AudioState> = Mutex::new(AudioState {
+    pattern: [0; AUDIO_PATTERN_SIZE],
+    pitch: DEFAULT_PITCH,
+    playing: false,
+});
+
+/// Copy the audio pattern, pitch and sound timer to the audio thread.
+/// This should be called once per frame.
+pub fn update_audio() {
+    let mut state = AUDIO_STATE.lock().unwrap();
+    state.pattern = get_audio_pattern();
+    state.pitch = get_pitch();
+    state.playing = get_sound_timer() > 0;
+}
+
+/// Returns the rate the audio pattern is played at with the given pitch, in bits per second.
+pub fn playback_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((f64::from(pitch) - 64.0) / 48.0)
+}
+
+/// Start playing audio on the default output device.
+/// The stream plays for as long as it is kept alive.
+///
+/// # Errors
+/// Returns an error if there is no output device, or if the stream could not be started.
+pub fn start_audio() -> Result<Stream, String> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| String::from("no audio output device"))?;
+    let config = device
+        .default_output_config()
+        .map_err(|e| format!("failed to get the audio output config: {e}"))?;
+
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config.into()),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config.into()),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config.into()),
+        format => Err(format!("unsupported audio sample format: {format:?}")),
+    }?;
+    stream
+        .play()
+        .map_err(|e| format!("failed to start audio: {e}"))?;
+    Ok(stream)
+}
+
+fn build_stream<T>(device: &cpal::Device, config: &StreamConfig) -> Result<Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let sample_rate = f64::from(config.sample_rate.0);
+    let channels = usize::from(config.channels);
+    // The position in the pattern, in bits
+    let mut position = 0.0;
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let state = *AUDIO_STATE.lock().unwrap();
+                let step = playback_rate(state.pitch) / sample_rate;
+                for frame in data.chunks_mut(channels) {
+                    let value = if state.playing {
+                        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                        let bit = position as usize;
+                        if state.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                            AMPLITUDE
+                        } else {
+                            -AMPLITUDE
+                        }
+                    } else {
+                        0.0
+                    };
+                    frame.fill(T::from_sample(value));
+                    position = (position + step) % PATTERN_BITS;
+                }
+            },
+            // Printing from the audio thread would corrupt the display, and a glitch in the sound isn't worth stopping for
+            |_| {},
+            None,
+        )
+        .map_err(|e| format!("failed to open the audio output: {e}"))
+}
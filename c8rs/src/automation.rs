@@ -0,0 +1,164 @@
+use std::{fmt::Write as _, fs};
+
+use c8util::instructions::Instruction;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    system::{
+        DISPLAY_HEIGHT, get_delay_timer, get_full_display, get_full_memory, get_i, get_pc,
+        get_registers, get_sound_timer, get_stack,
+    },
+    timing::get_frame_count,
+};
+
+/// The number of instructions to execute before exiting, if any.
+pub static mut MAX_INSTRUCTIONS: Option<u128> = None;
+
+/// Whether to exit once the program halts by jumping to the instruction itself (e.g. `1206` at 0x206).
+pub static mut EXIT_ON_LOOP: bool = false;
+
+/// Whether to print the state of the machine to stdout when the emulator exits.
+pub static mut PRINT_STATE: bool = false;
+
+/// The number of instructions that have been executed, as of the last call to `check_stop`.
+pub static mut INSTRUCTIONS_EXECUTED: u128 = 0;
+
+/// Why the emulator was stopped by `check_stop`, if it was.
+pub static mut STOP_REASON: Option<String> = None;
+
+/// The path to save the display to as text when the emulator exits, if any.
+pub static mut DUMP_DISPLAY_PATH: Option<&str> = None;
+
+/// The path to save the contents of memory to when the emulator exits, if any.
+pub static mut DUMP_MEMORY_PATH: Option<&str> = None;
+
+pub fn set_max_instructions(max: Option<u128>) {
+    // SAFETY: single threaded
+    unsafe { MAX_INSTRUCTIONS = max };
+}
+
+pub fn set_exit_on_loop(exit_on_loop: bool) {
+    // SAFETY: single threaded
+    unsafe { EXIT_ON_LOOP = exit_on_loop };
+}
+
+pub fn set_print_state(print_state: bool) {
+    // SAFETY: single threaded
+    unsafe { PRINT_STATE = print_state };
+}
+
+pub fn set_dump_display_path(path: &'static str) {
+    // SAFETY: single threaded
+    unsafe { DUMP_DISPLAY_PATH = Some(path) };
+}
+
+pub fn set_dump_memory_path(path: &'static str) {
+    // SAFETY: single threaded
+    unsafe { DUMP_MEMORY_PATH = Some(path) };
+}
+
+/// Returns whether the emulator should exit after the given instruction, which was just executed at the given
+/// address, because `--max-instructions` was reached or the program halted with `--exit-on-loop`.
+/// `instructions_executed` includes the instruction.
+pub fn check_stop(addr: u16, instruction: Instruction, instructions_executed: u128) -> bool {
+    // SAFETY: single threaded
+    unsafe {
+        INSTRUCTIONS_EXECUTED = instructions_executed;
+        let reason = if MAX_INSTRUCTIONS.is_some_and(|max| instructions_executed >= max) {
+            format!("executed {instructions_executed} instructions")
+        } else if EXIT_ON_LOOP && instruction == Instruction::Jump(addr) {
+            format!("halted at {addr:#06X}")
+        } else {
+            return false;
+        };
+        STOP_REASON = Some(reason);
+    }
+    true
+}
+
+/// Returns the SHA-1 of the display, as hex. Each pixel is hashed as a byte (1 if it's on, 0 if it's off), row by
+/// row from the top left.
+pub fn display_hash() -> String {
+    let display = get_full_display();
+    let mut hasher = Sha1::new();
+    for y in 0..DISPLAY_HEIGHT {
+        for column in &display {
+            hasher.update([u8::from(column[y])]);
+        }
+    }
+    let mut hash = String::new();
+    for byte in hasher.finalize() {
+        write!(hash, "{byte:02x}").unwrap();
+    }
+    hash
+}
+
+/// Returns the display as text: a line for each row, with `#` for each pixel that's on and `.` for each pixel
+/// that's off.
+pub fn display_text() -> String {
+    let display = get_full_display();
+    let mut text = String::new();
+    for y in 0..DISPLAY_HEIGHT {
+        for column in &display {
+            text.push(if column[y] { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Save the display as text and the contents of memory to the paths given with `--dump-display` and
+/// `--dump-memory`, if any.
+///
+/// # Errors
+/// Returns an error if a file could not be written.
+pub fn save_dumps() -> Result<(), String> {
+    // SAFETY: single threaded
+    let (display_path, memory_path) = unsafe { (DUMP_DISPLAY_PATH, DUMP_MEMORY_PATH) };
+    if let Some(path) = display_path {
+        fs::write(path, display_text()).map_err(|e| format!("failed to write {path}: {e}"))?;
+    }
+    if let Some(path) = memory_path {
+        fs::write(path, get_full_memory()).map_err(|e| format!("failed to write {path}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Returns the state of the machine as `key: value` lines, for scripts to check, if `--print-state` was given.
+/// `code` is the exit code.
+pub fn final_state(code: u8) -> Option<String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let (print_state, instructions, reason) =
+        unsafe { (PRINT_STATE, INSTRUCTIONS_EXECUTED, STOP_REASON.clone()) };
+    if !print_state {
+        return None;
+    }
+    let registers = get_registers()
+        .iter()
+        .map(|val| format!("{val:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let stack = get_stack().iter().fold(String::new(), |mut stack, addr| {
+        write!(stack, " {addr:#06X}").unwrap();
+        stack
+    });
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "stopped: {}",
+        reason.unwrap_or_else(|| format!("exit code {code}"))
+    )
+    .unwrap();
+    writeln!(out, "instructions: {instructions}").unwrap();
+    writeln!(out, "frames: {}", get_frame_count()).unwrap();
+    writeln!(out, "pc: {:#06X}", get_pc()).unwrap();
+    writeln!(out, "i: {:#06X}", get_i()).unwrap();
+    writeln!(out, "v: {registers}").unwrap();
+    writeln!(out, "stack:{stack}").unwrap();
+    writeln!(out, "delay: {}", get_delay_timer()).unwrap();
+    writeln!(out, "sound: {}", get_sound_timer()).unwrap();
+    writeln!(out, "display: {}", display_hash()).unwrap();
+    Some(out)
+}
@@ -0,0 +1,204 @@
+use std::{
+    fmt::{self, Display},
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use c8util::{decode::decode, instructions::Instruction};
+
+use crate::{
+    coverage::CoverageObserver,
+    debug_terminal::is_break_requested,
+    display::Frame,
+    error::C8Error,
+    execute::{execute, get_instruction_set},
+    hooks::{
+        InstructionEvent, Outcome, notify_before_instruction, notify_instruction, with_observers,
+    },
+    input::is_interrupted,
+    movie::movie_input,
+    profile::ProfileObserver,
+    run::{fetch, get_exit_code, render_frame},
+    system::{get_full_display, get_memory_accesses, get_pc},
+    timing::{end_instruction, frame_tick},
+};
+
+/// When to stop a benchmark.
+#[derive(Debug, Clone, Copy)]
+pub enum BenchLimit {
+    /// Run for the given amount of wall clock time.
+    Time(Duration),
+    /// Run the given number of instructions.
+    Instructions(u64),
+}
+
+/// The results of a benchmark.
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub instructions: u64,
+    pub frames: u64,
+    /// The total time taken.
+    pub elapsed: Duration,
+    /// The time spent fetching, decoding and executing instructions.
+    pub execute_time: Duration,
+    /// The time spent building the frames that would have been drawn to the terminal.
+    pub draw_time: Duration,
+    pub memory_accesses: u64,
+    /// Why the benchmark stopped before reaching its limit, if it did.
+    pub stopped_early: Option<String>,
+    /// The error that stopped the benchmark, if an instruction failed.
+    pub error: Option<C8Error>,
+}
+
+impl Display for BenchReport {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        let percent = |time: Duration| 100.0 * time.as_secs_f64() / seconds;
+
+        if let Some(e) = &self.error {
+            writeln!(f, "Error: {e}")?;
+        }
+        if let Some(reason) = &self.stopped_early {
+            writeln!(f, "Stopped early: {reason}")?;
+        }
+        writeln!(
+            f,
+            "Executed {} instructions ({} frames) in {seconds:.3}s",
+            self.instructions, self.frames
+        )?;
+        writeln!(
+            f,
+            "Instructions per second:    {:.0}",
+            self.instructions as f64 / seconds
+        )?;
+        writeln!(
+            f,
+            "Execute:                    {:.3}s ({:.1}%)",
+            self.execute_time.as_secs_f64(),
+            percent(self.execute_time)
+        )?;
+        writeln!(
+            f,
+            "Draw:                       {:.3}s ({:.1}%)",
+            self.draw_time.as_secs_f64(),
+            percent(self.draw_time)
+        )?;
+        write!(
+            f,
+            "Memory accesses per second: {:.0}",
+            self.memory_accesses as f64 / seconds
+        )
+    }
+}
+
+/// Run the loaded ROM as fast as possible without a terminal or keyboard, and measure how long it takes.
+/// Frames are still rendered (but not printed), so that the cost of drawing is included.
+/// If a movie is being played back, its input is used. Otherwise no keys are pressed.
+/// Only coverage and profiling observe the benchmark, so that tracepoints, watches and breaking on sound don't
+/// log or stop it.
+pub fn run_bench(limit: BenchLimit) -> BenchReport {
+    with_observers(
+        vec![Box::new(CoverageObserver), Box::new(ProfileObserver)],
+        || bench(limit),
+    )
+}
+
+/// Runs the benchmark, with the observers already chosen.
+fn bench(limit: BenchLimit) -> BenchReport {
+    let mut n_instructions_executed = 0u128;
+    let mut keypad = movie_input(0);
+    let mut last_keypad = keypad;
+    let mut frames = 0;
+    let mut execute_time = Duration::ZERO;
+    let mut draw_time = Duration::ZERO;
+    let mut stopped_early = None;
+    let mut error = None;
+    let start_memory_accesses = get_memory_accesses();
+
+    let start = Instant::now();
+    let mut frame_start = start;
+    loop {
+        if let BenchLimit::Instructions(max) = limit {
+            if n_instructions_executed >= u128::from(max) {
+                break;
+            }
+        }
+        if is_break_requested() {
+            stopped_early = Some(String::from("an instruction failed"));
+            break;
+        }
+        if is_interrupted() {
+            stopped_early = Some(String::from("interrupted"));
+            break;
+        }
+
+        let instruction_addr = get_pc();
+        let fetched = fetch();
+        let instruction_raw = *fetched.as_ref().unwrap_or(&0);
+        let instruction = decode(instruction_raw, get_instruction_set())
+            .unwrap_or(Instruction::Db(instruction_raw));
+        notify_before_instruction(instruction_addr, instruction, n_instructions_executed);
+        let result =
+            fetched.and_then(|_| execute(instruction, get_instruction_set(), keypad, last_keypad));
+        notify_instruction(&InstructionEvent {
+            addr: instruction_addr,
+            instruction,
+            instruction_count: n_instructions_executed,
+            outcome: Outcome::of(&result, instruction_addr, instruction),
+        });
+        if let Err(e) = result {
+            stopped_early = Some(format!("an instruction at {instruction_addr:#06X} failed"));
+            error = Some(e);
+            break;
+        }
+        last_keypad = keypad;
+
+        if get_exit_code().is_some() {
+            n_instructions_executed += 1;
+            stopped_early = Some(String::from("the program exited"));
+            break;
+        }
+
+        let is_new_frame = end_instruction(n_instructions_executed, instruction);
+        n_instructions_executed += 1;
+
+        // Time whole frames at once, since timing every instruction would slow them down
+        if is_new_frame {
+            frame_tick();
+            frames += 1;
+            keypad = movie_input(0);
+
+            let draw_start = Instant::now();
+            execute_time += draw_start - frame_start;
+            let display = get_full_display();
+            black_box(render_frame(&Frame {
+                pixels: &display,
+                old_pixels: &display,
+                is_debug: false,
+                info_lines: &[],
+            }));
+            frame_start = Instant::now();
+            draw_time += frame_start - draw_start;
+
+            if let BenchLimit::Time(max) = limit {
+                if frame_start - start >= max {
+                    break;
+                }
+            }
+        }
+    }
+    let end = Instant::now();
+    execute_time += end - frame_start;
+
+    BenchReport {
+        instructions: u64::try_from(n_instructions_executed).unwrap(),
+        frames,
+        elapsed: end - start,
+        execute_time,
+        draw_time,
+        memory_accesses: get_memory_accesses() - start_memory_accesses,
+        stopped_early,
+        error,
+    }
+}
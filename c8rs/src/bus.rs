@@ -0,0 +1,103 @@
+use crate::{error::C8Error, system::MEMORY_SIZE};
+
+/// The memory that instructions read and write through.
+///
+/// The default is `Ram`, the 4KB of RAM. An embedder can replace it with `system::set_bus` to change how memory
+/// behaves without changing the emulator, e.g. to add banked memory, make the ROM read-only, or log accesses to
+/// certain addresses. Errors are shown the same way as any other error from an instruction.
+///
+/// Only the 4KB address space is supported: addresses are 12 bits (the PC and I can't go past 0xFFF), and
+/// `contents` and `set_contents` only cover those 4KB. Banked memory has to switch banks within it, and XO-CHIP's
+/// 64KB memory can't be built on this trait.
+pub trait Bus {
+    /// Read the byte at `addr`.
+    ///
+    /// # Errors
+    /// Returns an error if the byte can't be read, e.g. if the address is outside of memory.
+    fn read8(&mut self, addr: u16) -> Result<u8, C8Error>;
+
+    /// Write the byte at `addr`.
+    ///
+    /// # Errors
+    /// Returns an error if the byte can't be written, e.g. if the address is outside of memory.
+    fn write8(&mut self, addr: u16, val: u8) -> Result<(), C8Error>;
+
+    /// Read the big-endian word at `addr`.
+    ///
+    /// # Errors
+    /// Returns an error if either byte can't be read.
+    fn read16(&mut self, addr: u16) -> Result<u16, C8Error> {
+        let next_addr = addr.checked_add(1).ok_or(C8Error::MemoryOutOfRange(addr))?;
+        Ok(u16::from_be_bytes([
+            self.read8(addr)?,
+            self.read8(next_addr)?,
+        ]))
+    }
+
+    /// Write the big-endian word at `addr`.
+    ///
+    /// # Errors
+    /// Returns an error if either byte can't be written. The first byte may have been written if the second
+    /// can't be.
+    fn write16(&mut self, addr: u16, val: u16) -> Result<(), C8Error> {
+        let next_addr = addr.checked_add(1).ok_or(C8Error::MemoryOutOfRange(addr))?;
+        let [high, low] = val.to_be_bytes();
+        self.write8(addr, high)?;
+        self.write8(next_addr, low)
+    }
+
+    /// Returns the contents of the 4KB address space, for snapshots, dumps and the debugger. This isn't an access
+    /// by the program, so it shouldn't trigger any hooks.
+    fn contents(&self) -> [u8; MEMORY_SIZE];
+
+    /// Replace the contents of the 4KB address space, e.g. when loading the ROM or restoring a snapshot. This isn't
+    /// an access by the program, so it should bypass any protection.
+    fn set_contents(&mut self, memory: [u8; MEMORY_SIZE]);
+}
+
+/// 4KB of RAM, which can all be read and written.
+#[derive(Debug, Clone)]
+pub struct Ram(pub [u8; MEMORY_SIZE]);
+
+impl Default for Ram {
+    fn default() -> Self {
+        Self([0; MEMORY_SIZE])
+    }
+}
+
+impl Bus for Ram {
+    fn read8(&mut self, addr: u16) -> Result<u8, C8Error> {
+        self.0
+            .get(usize::from(addr))
+            .copied()
+            .ok_or(C8Error::MemoryOutOfRange(addr))
+    }
+
+    fn write8(&mut self, addr: u16, val: u8) -> Result<(), C8Error> {
+        let byte = self
+            .0
+            .get_mut(usize::from(addr))
+            .ok_or(C8Error::MemoryOutOfRange(addr))?;
+        *byte = val;
+        Ok(())
+    }
+
+    fn write16(&mut self, addr: u16, val: u16) -> Result<(), C8Error> {
+        // Check both bytes first, so that nothing is written if the second is out of range
+        let next_addr = addr.checked_add(1).ok_or(C8Error::MemoryOutOfRange(addr))?;
+        if usize::from(next_addr) >= MEMORY_SIZE {
+            return Err(C8Error::MemoryOutOfRange(next_addr));
+        }
+        let [high, low] = val.to_be_bytes();
+        self.write8(addr, high)?;
+        self.write8(next_addr, low)
+    }
+
+    fn contents(&self) -> [u8; MEMORY_SIZE] {
+        self.0
+    }
+
+    fn set_contents(&mut self, memory: [u8; MEMORY_SIZE]) {
+        self.0 = memory;
+    }
+}
@@ -0,0 +1,177 @@
+use std::{collections::BTreeMap, env, fs, io::ErrorKind};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    font::FontSet,
+    quirks::{QuirkPreset, Quirks},
+    rom_db::RomSettings,
+    run::{RenderMode, Rgb},
+    timing::TimingMode,
+};
+
+/// The path of the config file, relative to the user's config directory.
+const CONFIG_FILE: &str = "c8rs/config.toml";
+
+/// The user interfaces the emulator can run in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frontend {
+    /// Draw the display in the terminal, with the debugger below it.
+    #[default]
+    Terminal,
+}
+
+/// The colors of the display in the terminal, as `#RRGGBB`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorConfig {
+    /// The color of pixels that are on. Defaults to white.
+    pub on: Option<String>,
+    /// The color of pixels that are off. Defaults to the terminal's background color.
+    pub off: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Don't play any sound.
+    pub mute: bool,
+}
+
+/// The user's preferences, loaded from `~/.config/c8rs/config.toml`.
+/// Anything that isn't set uses the defaults. Command line options take precedence over the config file,
+/// and the settings for a specific ROM in the ROM database take precedence over the ones here.
+///
+/// e.g.
+/// ```toml
+/// ips = 1000
+/// timing = "ips"
+/// font = "vip"
+/// render = "halfblock"
+/// keymap = ["X", "Key1", "Key2", "Key3", "Q", "W", "E", "A", "S", "D", "Z", "C", "Key4", "R", "F", "V"]
+///
+/// [quirks]
+/// wrap_sprites = true
+///
+/// [colors]
+/// on = "#33FF66"
+/// off = "#001100"
+///
+/// [audio]
+/// mute = true
+///
+/// [aliases]
+/// dw = "x 8 i"
+///
+/// [macros]
+/// step3 = ["n", "n", "n"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The number of instructions to execute per second.
+    pub ips: Option<usize>,
+    pub timing: Option<TimingMode>,
+    pub font: Option<FontSet>,
+    /// The maximum number of entries in the stack.
+    pub stack_depth: Option<usize>,
+    /// The compatibility quirks.
+    pub quirks: Option<Quirks>,
+    /// The interpreter whose quirks are used, if `quirks` isn't set.
+    pub quirk_preset: Option<QuirkPreset>,
+    /// The key on the keyboard for each key on the keypad, from 0 to F.
+    /// Keys are named like `Key1` and `Q`.
+    pub keymap: Option<[String; 16]>,
+    /// The path of the ROM database.
+    pub rom_db: Option<String>,
+    /// The number of image pixels per display pixel in screenshots and recordings.
+    pub screenshot_scale: Option<usize>,
+    pub frontend: Option<Frontend>,
+    /// How the display is drawn in the terminal.
+    pub render: Option<RenderMode>,
+    /// Show the speed statistics below the display.
+    pub stats: bool,
+    /// The number of frames pixels take to fade out after they're turned off.
+    pub phosphor: Option<u8>,
+    pub colors: ColorConfig,
+    pub audio: AudioConfig,
+    /// Short names for debug terminal commands.
+    pub aliases: BTreeMap<String, String>,
+    /// Named lists of debug terminal commands.
+    pub macros: BTreeMap<String, Vec<String>>,
+}
+
+impl Config {
+    /// Returns the settings that can also be set for a specific ROM in the ROM database.
+    pub fn rom_settings(&self) -> RomSettings {
+        RomSettings {
+            name: None,
+            ips: self.ips,
+            quirks: self
+                .quirks
+                .or_else(|| self.quirk_preset.map(QuirkPreset::quirks)),
+            keymap: self.keymap.clone(),
+        }
+    }
+
+    /// Returns the colors of pixels that are on and off, as RGB.
+    ///
+    /// # Errors
+    /// Returns an error if a color isn't written as `#RRGGBB`.
+    pub fn display_colors(&self) -> Result<(Option<Rgb>, Option<Rgb>), String> {
+        let on = self.colors.on.as_deref().map(parse_color).transpose()?;
+        let off = self.colors.off.as_deref().map(parse_color).transpose()?;
+        Ok((on, off))
+    }
+}
+
+/// Parse a color written as `#RRGGBB`.
+fn parse_color(s: &str) -> Result<Rgb, String> {
+    let invalid = || format!("invalid color: {s} (should be #RRGGBB)");
+    let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut rgb = [0; 3];
+    for (i, component) in rgb.iter_mut().enumerate() {
+        *component = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(rgb)
+}
+
+/// Returns the user's config directory: `$XDG_CONFIG_HOME`, or `~/.config`. None if there's no home directory.
+pub fn config_dir() -> Option<String> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(dir.to_string_lossy().into_owned());
+    }
+    let home = env::var_os("HOME").filter(|dir| !dir.is_empty())?;
+    Some(format!("{}/.config", home.to_string_lossy()))
+}
+
+/// Returns the default path of the config file: `$XDG_CONFIG_HOME/c8rs/config.toml`, or
+/// `~/.config/c8rs/config.toml`. None if there's no home directory.
+pub fn default_config_path() -> Option<String> {
+    config_dir().map(|dir| format!("{dir}/{CONFIG_FILE}"))
+}
+
+/// Load the config file at the given path, or at the default path if there is none.
+/// If no path is given and the default config file doesn't exist, the default config is returned.
+///
+/// # Errors
+/// Returns an error if the config file could not be read or is invalid.
+pub fn load_config(path: Option<&str>) -> Result<Config, String> {
+    let (path, is_default) = match path {
+        Some(path) => (path.to_string(), false),
+        None => match default_config_path() {
+            Some(path) => (path, true),
+            None => return Ok(Config::default()),
+        },
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => toml::from_str(&contents).map_err(|e| format!("invalid {path}: {e}")),
+        Err(e) if is_default && e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(format!("failed to read {path}: {e}")),
+    }
+}
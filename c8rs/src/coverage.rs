@@ -0,0 +1,252 @@
+use std::{fmt::Write as _, fs};
+
+use c8util::{
+    coverage::{CoverageMap, EXECUTED, READ, WRITTEN},
+    instructions::Instruction,
+};
+
+use crate::{
+    hooks::Observer,
+    run::Rgb,
+    system::{AUDIO_PATTERN_SIZE, MEMORY_SIZE, get_i},
+};
+
+/// The number of addresses on each line of the memory map printed by the debugger.
+const MAP_WIDTH: usize = 64;
+
+/// The color of addresses that were never accessed in the heatmap.
+const HEATMAP_UNUSED_COLOR: Rgb = [0x30, 0x30, 0x30];
+
+/// Which addresses have been executed, read and written during this run. None until the first instruction is
+/// executed.
+pub static mut COVERAGE: Option<CoverageMap> = None;
+
+/// How many times an address has been executed, read and written.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AccessCounts {
+    pub executed: u64,
+    pub read: u64,
+    pub written: u64,
+}
+
+/// How many times each address has been accessed during this run, indexed by address. Empty until the first
+/// instruction is executed.
+pub static mut ACCESS_COUNTS: Vec<AccessCounts> = Vec::new();
+
+/// Which accesses are shown in the heatmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeatmapKind {
+    /// Every access, colored like the coverage map.
+    All,
+    Executed,
+    Read,
+    Written,
+}
+
+/// The path to save the coverage map to when the emulator exits, if any.
+pub static mut COVERAGE_OUT_PATH: Option<&str> = None;
+
+pub fn set_coverage_out_path(path: &'static str) {
+    // SAFETY: single threaded
+    unsafe { COVERAGE_OUT_PATH = Some(path) };
+}
+
+pub fn get_coverage_out_path() -> Option<&'static str> {
+    // SAFETY: single threaded
+    unsafe { COVERAGE_OUT_PATH }
+}
+
+/// Returns the coverage map for this run.
+fn coverage() -> &'static mut CoverageMap {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        COVERAGE.get_or_insert_with(|| CoverageMap::new(MEMORY_SIZE))
+    }
+}
+
+/// Returns the access counts for this run.
+fn access_counts() -> &'static mut Vec<AccessCounts> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        if ACCESS_COUNTS.is_empty() {
+            ACCESS_COUNTS.resize(MEMORY_SIZE, AccessCounts::default());
+        }
+        &mut ACCESS_COUNTS
+    }
+}
+
+/// Record the memory used by the given instruction, at the given address.
+/// This must be called before the instruction is executed, since executing it can change I.
+pub fn cover_instruction(addr: u16, instruction: Instruction) {
+    let coverage = coverage();
+    let counts = access_counts();
+    for addr in [usize::from(addr), usize::from(addr) + 1] {
+        coverage.mark(addr, EXECUTED);
+        if let Some(counts) = counts.get_mut(addr) {
+            counts.executed += 1;
+        }
+    }
+
+    let (flags, len) = match instruction {
+        Instruction::Draw(_, _, n) => (READ, usize::from(n)),
+        Instruction::LoadMemory(x) => (READ, usize::from(x) + 1),
+        Instruction::LoadAudio => (READ, AUDIO_PATTERN_SIZE),
+        Instruction::BCD(_) => (WRITTEN, 3),
+        Instruction::StoreMemory(x) => (WRITTEN, usize::from(x) + 1),
+        _ => return,
+    };
+    let start = usize::from(get_i());
+    for addr in start..start + len {
+        coverage.mark(addr % MEMORY_SIZE, flags);
+        let counts = &mut counts[addr % MEMORY_SIZE];
+        if flags == READ {
+            counts.read += 1;
+        } else {
+            counts.written += 1;
+        }
+    }
+}
+
+/// Records the memory used by each instruction.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CoverageObserver;
+
+impl Observer for CoverageObserver {
+    fn before_instruction(
+        &mut self,
+        addr: u16,
+        instruction: Instruction,
+        _instruction_count: u128,
+    ) {
+        cover_instruction(addr, instruction);
+    }
+}
+
+/// Clear the coverage map and the access counts.
+pub fn reset_coverage() {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        COVERAGE = None;
+        ACCESS_COUNTS.clear();
+    }
+}
+
+/// Save the coverage map to the given path, so that it can be used by the disassembler.
+///
+/// # Errors
+/// Returns an error if the file could not be written.
+pub fn save_coverage(path: &str) -> Result<(), String> {
+    fs::write(path, coverage().to_string()).map_err(|e| format!("failed to write {path}: {e}"))
+}
+
+/// Save the coverage map to the path given with `--coverage-out`, if there is one.
+///
+/// # Errors
+/// Returns an error if the file could not be written.
+pub fn save_coverage_out() -> Result<(), String> {
+    match get_coverage_out_path() {
+        Some(path) => save_coverage(path),
+        None => Ok(()),
+    }
+}
+
+/// Returns a colored map of the memory from `start` to `end` (exclusive), with one character per address.
+pub fn coverage_map(start: usize, end: usize) -> String {
+    let coverage = coverage();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "\x1b[42m \x1b[0m executed  \x1b[44m \x1b[0m read  \x1b[41m \x1b[0m written  \x1b[43m \x1b[0m read and written  \x1b[45m \x1b[0m executed and written"
+    )
+    .unwrap();
+    for line_start in (start..end).step_by(MAP_WIDTH) {
+        write!(out, "{line_start:#06X}  ").unwrap();
+        for addr in line_start..end.min(line_start + MAP_WIDTH) {
+            let flags = coverage.get(addr);
+            let color = if flags & EXECUTED != 0 && flags & WRITTEN != 0 {
+                "45"
+            } else if flags & EXECUTED != 0 {
+                "42"
+            } else if flags & (READ | WRITTEN) == READ | WRITTEN {
+                "43"
+            } else if flags & READ != 0 {
+                "44"
+            } else if flags & WRITTEN != 0 {
+                "41"
+            } else {
+                out.push('.');
+                continue;
+            };
+            write!(out, "\x1b[{color}m \x1b[0m").unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Returns a heatmap of the whole address space, as a grid with one cell per address and 64 addresses per row.
+/// Each cell is colored by the kind of access (like the coverage map), and is brighter the more often the
+/// address was accessed, on a logarithmic scale.
+pub fn heatmap(kind: HeatmapKind) -> String {
+    let counts = access_counts();
+    let count = |counts: &AccessCounts| match kind {
+        HeatmapKind::All => counts.executed + counts.read + counts.written,
+        HeatmapKind::Executed => counts.executed,
+        HeatmapKind::Read => counts.read,
+        HeatmapKind::Written => counts.written,
+    };
+    let max = counts.iter().map(count).max().unwrap_or(0);
+    #[allow(clippy::cast_precision_loss)]
+    let max_log = ((max + 1) as f64).ln();
+
+    let color = |counts: &AccessCounts| -> Rgb {
+        let n = count(counts);
+        if n == 0 {
+            return HEATMAP_UNUSED_COLOR;
+        }
+        let base: Rgb = match kind {
+            HeatmapKind::All if counts.executed > 0 && counts.written > 0 => [0xFF, 0x40, 0xFF],
+            HeatmapKind::All if counts.executed > 0 => [0x40, 0xFF, 0x40],
+            HeatmapKind::All if counts.read > 0 && counts.written > 0 => [0xFF, 0xFF, 0x40],
+            HeatmapKind::All | HeatmapKind::Read if counts.read > 0 => [0x40, 0x80, 0xFF],
+            HeatmapKind::Executed => [0x40, 0xFF, 0x40],
+            _ => [0xFF, 0x40, 0x40],
+        };
+        // Even addresses that were accessed once are clearly visible
+        #[allow(clippy::cast_precision_loss)]
+        let brightness = 0.3 + 0.7 * ((n + 1) as f64).ln() / max_log;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        base.map(|c| (f64::from(c) * brightness) as u8)
+    };
+
+    let mut out = String::new();
+    let legend = match kind {
+        HeatmapKind::All => {
+            "\x1b[42m \x1b[0m executed  \x1b[44m \x1b[0m read  \x1b[41m \x1b[0m written  \x1b[43m \x1b[0m read and written  \x1b[45m \x1b[0m executed and written"
+        }
+        HeatmapKind::Executed => "\x1b[42m \x1b[0m executed",
+        HeatmapKind::Read => "\x1b[44m \x1b[0m read",
+        HeatmapKind::Written => "\x1b[41m \x1b[0m written",
+    };
+    writeln!(out, "{legend}  (brighter is more often, up to {max} times)").unwrap();
+    // Each line of text shows two rows of addresses, using the top and bottom halves of the characters
+    for line_start in (0..MEMORY_SIZE).step_by(MAP_WIDTH * 2) {
+        write!(out, "{line_start:#06X}  ").unwrap();
+        for addr in line_start..line_start + MAP_WIDTH {
+            let [tr, tg, tb] = color(&counts[addr]);
+            let [br, bg, bb] = counts
+                .get(addr + MAP_WIDTH)
+                .map_or(HEATMAP_UNUSED_COLOR, color);
+            write!(
+                out,
+                "\x1b[38;2;{tr};{tg};{tb}m\x1b[48;2;{br};{bg};{bb}m\u{2580}"
+            )
+            .unwrap();
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
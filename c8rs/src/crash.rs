@@ -0,0 +1,97 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    panic, process,
+};
+
+use c8util::{decode::iter, instructions::Instruction};
+
+use crate::{
+    debug_terminal::raw_hex,
+    execute::get_instruction_set,
+    input::disable_input,
+    screenshot::timestamped_path,
+    system::{
+        DISPLAY_HEIGHT, get_delay_timer, get_full_display, get_full_memory, get_i, get_pc,
+        get_registers, get_sound_timer, get_stack,
+    },
+};
+
+/// The number of instructions to disassemble on each side of the PC in a crash dump.
+const DISASSEMBLY_WINDOW: u16 = 8;
+
+/// Install a panic hook that restores the terminal and saves a crash dump (see `crash_dump`) before exiting.
+pub fn install_crash_handler() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // Reset any colors, and start below whatever was being drawn
+        println!("\x1b[0m\n");
+        let _ = io::stdout().flush();
+        disable_input();
+        default_hook(info);
+
+        let path = timestamped_path("crash", "txt");
+        match fs::write(&path, crash_dump(&info.to_string())) {
+            Ok(()) => eprintln!("Saved crash dump to {path}"),
+            Err(e) => eprintln!("Could not save crash dump to {path}: {e}"),
+        }
+        process::exit(101);
+    }));
+}
+
+/// Returns a description of the state of the machine: the registers, the stack, the instructions around the PC,
+/// and the display.
+pub fn crash_dump(reason: &str) -> String {
+    let mut out = String::new();
+    writeln!(out, "c8rs crashed: {reason}").unwrap();
+
+    writeln!(out, "\nRegisters:").unwrap();
+    for (i, val) in get_registers().iter().enumerate() {
+        write!(out, "V{i:X}: {val:#04X}  ").unwrap();
+        if i % 8 == 7 {
+            out.push('\n');
+        }
+    }
+    writeln!(out, "I:  {:#06X}", get_i()).unwrap();
+    writeln!(out, "PC: {:#06X}", get_pc()).unwrap();
+    writeln!(out, "Delay timer: {:#04X}", get_delay_timer()).unwrap();
+    writeln!(out, "Sound timer: {:#04X}", get_sound_timer()).unwrap();
+
+    writeln!(out, "\nStack (top first):").unwrap();
+    let stack = get_stack();
+    if stack.is_empty() {
+        writeln!(out, "(empty)").unwrap();
+    }
+    for addr in stack.iter().rev() {
+        writeln!(out, "{addr:#06X}").unwrap();
+    }
+
+    writeln!(out, "\nDisassembly:").unwrap();
+    let pc = get_pc();
+    let start = pc.saturating_sub(2 * DISASSEMBLY_WINDOW);
+    let memory = get_full_memory();
+    let rest = memory.get(usize::from(start)..).unwrap_or_default();
+    for (addr, raw, instruction) in iter(rest, start, get_instruction_set()) {
+        // Stop after the window, or at an odd byte at the end of memory
+        let [high, low, ..] = *raw else {
+            break;
+        };
+        if addr > pc + 2 * DISASSEMBLY_WINDOW {
+            break;
+        }
+        let marker = if addr == pc { "PC ->" } else { "     " };
+        let asm = instruction.unwrap_or(Instruction::Db(u16::from_be_bytes([high, low])));
+        writeln!(out, "{marker} {addr:#06X}  {}  {asm}", raw_hex(raw)).unwrap();
+    }
+
+    writeln!(out, "\nDisplay:").unwrap();
+    let display = get_full_display();
+    for y in 0..DISPLAY_HEIGHT {
+        for column in &display {
+            out.push(if column[y] { '#' } else { '.' });
+        }
+        out.push('\n');
+    }
+    out
+}
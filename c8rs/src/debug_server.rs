@@ -0,0 +1,174 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        mpsc::{Receiver, RecvTimeoutError, channel},
+    },
+    thread,
+    time::Duration,
+};
+
+use crossterm::event::{self, Event};
+
+use crate::input::{LineInput, is_interrupt, is_interrupted};
+
+/// How long to wait for a line from the client before checking the terminal for Ctrl+C again.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Serves the debug terminal over TCP, so that it can be used from another terminal (e.g. with `telnet` or
+/// `nc`) while the display stays in its own.
+///
+/// Only one client is served at a time: a new connection replaces the current one. While a client is
+/// connected, the debug terminal reads commands from it and writes its output to it, and a line sent while
+/// the program is running breaks into the debugger and runs it as a command.
+pub struct DebugServer {
+    /// Receives the lines sent by the client.
+    rx: Receiver<String>,
+    /// The connected client, if any.
+    client: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl DebugServer {
+    /// Start listening for a client on the given address, e.g. `127.0.0.1:4444`.
+    ///
+    /// # Errors
+    /// Returns an error if the address could not be bound.
+    pub fn listen(addr: &str) -> Result<Self, String> {
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("failed to listen on {addr}: {e}"))?;
+        let (tx, rx) = channel();
+        let client = Arc::new(Mutex::new(None::<TcpStream>));
+        let accepted = Arc::clone(&client);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let Ok(mut writer) = stream.try_clone() else {
+                    continue;
+                };
+                let _ = write!(
+                    writer,
+                    "Connected to the c8rs debug terminal. Send a line to break, h: help, c: continue\r\n"
+                );
+                if let Some(old) = accepted.lock().unwrap().replace(writer) {
+                    let _ = old.shutdown(Shutdown::Both);
+                }
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    // Read bytes rather than strings, so that stray telnet control codes don't end the connection
+                    for line in BufReader::new(stream).split(b'\n') {
+                        let Ok(line) = line else {
+                            return;
+                        };
+                        let line = String::from_utf8_lossy(&line)
+                            .trim_end_matches('\r')
+                            .to_string();
+                        if tx.send(line).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Ok(Self { rx, client })
+    }
+
+    /// Write text to the client, if one is connected. Newlines are sent as `\r\n`, as telnet expects.
+    fn write(&self, text: &str) {
+        let mut client = self.client.lock().unwrap();
+        if client.as_mut().is_some_and(|stream| {
+            stream
+                .write_all(text.replace('\n', "\r\n").as_bytes())
+                .is_err()
+        }) {
+            *client = None;
+        }
+    }
+}
+
+/// The debug terminal server, if it's enabled.
+pub static mut DEBUG_SERVER: Option<DebugServer> = None;
+
+/// Start serving the debug terminal on the given address.
+///
+/// # Errors
+/// Returns an error if the address could not be bound.
+pub fn start_debug_server(addr: &str) -> Result<(), String> {
+    let server = DebugServer::listen(addr)?;
+    // SAFETY: single threaded
+    unsafe { DEBUG_SERVER = Some(server) };
+    Ok(())
+}
+
+/// Returns whether the debug terminal is served over TCP instead of being shown below the display.
+pub fn is_debug_server() -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        DEBUG_SERVER.is_some()
+    }
+}
+
+/// Print the output of the debug terminal: to the client if the debug terminal is served over TCP (or
+/// nowhere if no client is connected), and to the terminal otherwise.
+/// Use the `dprint!` and `dprintln!` macros instead of calling this directly.
+pub fn debug_print(text: &str) {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    if let Some(server) = unsafe { DEBUG_SERVER.as_ref() } {
+        server.write(text);
+    } else {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(text.as_bytes()).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+/// Returns the next line sent by the client, if there is one, without waiting.
+pub fn poll_client_line() -> Option<String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe { DEBUG_SERVER.as_ref() }.and_then(|server| server.rx.try_recv().ok())
+}
+
+/// Wait for the client to send a line, and return it. Ctrl+C in the terminal still quits while waiting.
+pub fn read_client_line() -> LineInput {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let Some(server) = (unsafe { DEBUG_SERVER.as_ref() }) else {
+        return LineInput::Interrupt;
+    };
+    loop {
+        match server.rx.recv_timeout(POLL_INTERVAL) {
+            Ok(line) => return LineInput::Line(line),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return LineInput::Interrupt,
+        }
+        if is_interrupted() {
+            return LineInput::Interrupt;
+        }
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            if matches!(event::read(), Ok(Event::Key(event)) if is_interrupt(&event)) {
+                return LineInput::Interrupt;
+            }
+        }
+    }
+}
+
+/// Like `print!`, but for the output of the debug terminal. See `debug_print`.
+macro_rules! dprint {
+    ($($arg:tt)*) => {
+        $crate::debug_server::debug_print(&format!($($arg)*))
+    };
+}
+
+/// Like `println!`, but for the output of the debug terminal. See `debug_print`.
+macro_rules! dprintln {
+    () => {
+        $crate::debug_server::debug_print("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::debug_server::debug_print(&format!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+pub(crate) use {dprint, dprintln};
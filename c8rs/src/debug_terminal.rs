@@ -1,21 +1,61 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{BTreeSet, VecDeque},
+    fmt::Write as _,
     io::{self, Write},
-    thread,
-    time::Duration,
 };
 
-use device_query::{DeviceQuery, DeviceState, Keycode};
+use clap::ValueEnum;
 
-use c8util::instructions::Instruction;
+use c8util::{
+    asm::{AssemblyOptions, assemble},
+    decode::{decode, iter},
+    instructions::{Category, Instruction},
+    register::Register,
+};
 
 use crate::{
-    run::{draw, print_debug},
-    stdin::NonblockingReader,
+    alias::{
+        expand_line, get_aliases, get_macros, remove_alias, remove_macro, set_alias, set_macro,
+        split_commands,
+    },
+    coverage::{
+        HeatmapKind, coverage_map, get_coverage_out_path, heatmap, reset_coverage, save_coverage,
+    },
+    debug_server::{dprint, dprintln, is_debug_server, read_client_line},
+    execute::get_instruction_set,
+    expr::{eval, format_value},
+    hooks::{InstructionEvent, Observer},
+    inject::{get_injected_keypad, press_key, release_key, tap_key},
+    input::{LineEditor, LineInput},
+    movie::{is_recording_movie, seek_movie},
+    profile::{
+        PROFILE_OUT_ENTRIES, get_profile_out_path, instruction_histogram, profile_report,
+        reset_profile, save_profile,
+    },
+    quirks::{QuirkPreset, Quirks, get_quirks, set_quirks},
+    record::{is_recording, start_recording, stop_recording},
+    replay::replay_to,
+    rom_db::{get_rom_db_path, save_rom_settings},
+    run::{decode_at, draw, exit, frame_height, print_debug},
+    screenshot::{DEFAULT_SCREENSHOT_SCALE, save_screenshot, timestamped_path},
+    snapshot::{pop_history, recent_history_addrs},
+    symbols::{
+        addr_label, get_symbols, load_symbols, source_line, symbol_addr, symbol_name, symbolize,
+    },
     system::{
-        DISPLAY_HEIGHT, DISPLAY_WIDTH, get_delay_timer, get_i, get_memory_u8, get_pc, get_register,
-        get_sound_timer, set_delay_timer, set_i, set_memory_u8, set_pc, set_register,
-        set_sound_timer, stack_pop, stack_push,
+        DISPLAY_HEIGHT, DISPLAY_WIDTH, KeyWait, MEMORY_SIZE, get_full_memory, get_key_wait,
+        get_memory_u8, get_memory_u16, get_pc, get_register, get_sound_timer, get_stack,
+        peek_stack, set_delay_timer, set_i, set_memory_u8, set_pc, set_register, set_sound_timer,
+        stack_pop, stack_push,
+    },
+    timing::get_frame_count,
+    trace::{
+        Tracepoint, add_tracepoint, clear_trace_log, get_trace_file_path, get_trace_log,
+        get_tracepoints, remove_tracepoint, set_trace_file,
+    },
+    watch::{
+        MemoryWatch, add_register_watch, add_watch, clear_write_log, get_register_watches,
+        get_watches, get_write_log, remove_register_watch, remove_watch,
     },
 };
 
@@ -34,82 +74,268 @@ pub struct DebugState {
     pub old_i_state: (u16, u8, u8),
     /// The state of the display on the previous frame.
     pub old_display_state: [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
-    /// A list of the currently set breakpoints.
-    pub breakpoints: HashSet<u16>,
-    /// The previous commands run in the session.
-    /// Used when pressing up/down in the debug terminal.
-    pub history: Vec<String>,
-    /// The reader used to read lines from stdin.
-    pub reader: NonblockingReader,
-    /// The keys that were previously pressed.
-    pub last_pressed_keys: Vec<Keycode>,
-}
-
-#[allow(dead_code)]
-fn print_message(_debug_state: &mut DebugState, message: String) {
-    print!("\x1b[2K\r> ");
-    // This is necessary to clear the [[^A that's printed when arrow keys are pressed
-    thread::spawn(move || {
-        thread::sleep(Duration::from_millis(1));
-        print!("\x1b[2K\r> {message}");
-        io::stdout().flush().unwrap();
+    /// A breakpoint that is removed once execution breaks, e.g. at the return address of a subroutine that's
+    /// being stepped over.
+    pub temporary_breakpoint: Option<TemporaryBreakpoint>,
+    /// The frame being stepped through with `frame`, if any. Removed once execution breaks, like a temporary
+    /// breakpoint.
+    pub frame_step: Option<FrameStep>,
+    /// The keypad on the current frame and on the last instruction after `goto-ins`, which replace the ones in
+    /// the core loop so that execution carries on with the input that was replayed.
+    pub replayed_keypad: Option<(u16, u16)>,
+    /// The address that the next `list` without an address continues from, if it should continue.
+    pub list_addr: Option<u16>,
+    /// Which sprite is shown in the debug panel.
+    pub sprite_view: SpriteView,
+    /// The height of the last sprite that was drawn, in rows.
+    pub last_draw_height: u8,
+    /// Commands from a macro (or a line with several commands) that are waiting to be run, in order.
+    /// They're run before reading another line, even if the debug terminal is left and entered again in between.
+    pub pending_commands: VecDeque<String>,
+    /// The line the pending commands came from, which is repeated when pressing "enter" once they've run.
+    pub pending_line: Option<String>,
+    /// Reads the commands typed into the debug terminal, and keeps their history.
+    pub line_editor: LineEditor,
+}
+
+/// Which sprite at I is shown in the debug panel, set by the `spr` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpriteView {
+    Hidden,
+    /// As many rows as the sprite being drawn, or the last one that was drawn.
+    LastDraw,
+    /// A fixed number of rows.
+    Rows(u8),
+}
+
+impl SpriteView {
+    /// Returns the number of rows to show, or None if the sprite view is hidden.
+    /// `draw_height` is the height of the sprite being drawn, or the last one that was drawn.
+    pub fn rows(self, draw_height: u8) -> Option<u8> {
+        match self {
+            Self::Hidden => None,
+            Self::LastDraw => Some(draw_height),
+            Self::Rows(rows) => Some(rows),
+        }
+    }
+}
+
+/// A breakpoint that only lasts until execution next breaks.
+#[derive(Debug, Clone, Copy)]
+pub struct TemporaryBreakpoint {
+    pub addr: u16,
+    /// The breakpoint is only hit when the stack is no deeper than this, so that it isn't hit by recursive calls.
+    pub stack_depth: usize,
+}
+
+impl TemporaryBreakpoint {
+    /// Returns whether the next instruction is at the breakpoint.
+    pub fn is_hit(&self) -> bool {
+        get_pc() == self.addr && get_stack().len() <= self.stack_depth
+    }
+}
+
+/// Runs until the end of the current frame, or until a sprite is drawn, whichever comes first.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStep {
+    /// The frame that the step started on.
+    pub frame: u64,
+    /// Whether a sprite has been drawn since the step started.
+    pub is_drawn: bool,
+}
+
+impl FrameStep {
+    /// Returns whether the step has finished.
+    pub fn is_done(&self) -> bool {
+        self.is_drawn || get_frame_count() != self.frame
+    }
+}
+
+/// The addresses that break into the debug terminal when they're about to be executed.
+pub static mut BREAKPOINTS: BTreeSet<u16> = BTreeSet::new();
+
+/// Returns all breakpoints, sorted by address.
+pub fn get_breakpoints() -> BTreeSet<u16> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        BREAKPOINTS.clone()
+    }
+}
+
+pub fn is_breakpoint(addr: u16) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        BREAKPOINTS.contains(&addr)
+    }
+}
+
+/// Breaks into the debug terminal at breakpoints.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BreakpointObserver;
+
+impl Observer for BreakpointObserver {
+    fn should_break(&mut self, pc: u16, _keypad: u16, _last_keypad: u16) -> bool {
+        is_breakpoint(pc)
+    }
+}
+
+/// Add a breakpoint. Returns whether it wasn't already set.
+pub fn add_breakpoint(addr: u16) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        BREAKPOINTS.insert(addr)
+    }
+}
+
+/// Remove a breakpoint. Returns whether it was set.
+pub fn remove_breakpoint(addr: u16) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        BREAKPOINTS.remove(&addr)
+    }
+}
+
+/// Whether execution breaks into the debug terminal when `FX18` sets the sound timer to a non-zero value.
+pub static mut BREAK_ON_SOUND: bool = false;
+
+pub fn get_break_on_sound() -> bool {
+    // SAFETY: single threaded
+    unsafe { BREAK_ON_SOUND }
+}
+
+pub fn set_break_on_sound(break_on_sound: bool) {
+    // SAFETY: single threaded
+    unsafe { BREAK_ON_SOUND = break_on_sound };
+}
+
+/// Break into the debug terminal if the instruction that was just executed started a sound, and `b sound` is
+/// set. The reason shows where the sound was started from, and the subroutine call that led there.
+pub fn check_sound_break(addr: u16, instruction: Instruction) {
+    if !get_break_on_sound() || !matches!(instruction, Instruction::SetSoundTimer(_)) {
+        return;
+    }
+    let sound_timer = get_sound_timer();
+    if sound_timer == 0 {
+        return;
+    }
+    let location =
+        |addr: u16| symbolize(addr).map_or_else(String::new, |name| format!(" in {name}"));
+    let caller = get_stack().last().map_or_else(String::new, |return_addr| {
+        let call_site = return_addr.wrapping_sub(2);
+        format!(", called from {call_site:#06X}{}", location(call_site))
     });
+    request_break(format!(
+        "{instruction} at {addr:#06X}{} set the sound timer to {sound_timer}{caller}",
+        location(addr)
+    ));
 }
 
-/// Gets the next line to interpret as a command, including history.
-fn get_line(debug_state: &mut DebugState) -> String {
-    let device_state = DeviceState::new();
-    // let mut current_history_idx = 0usize;
-    loop {
-        let keys = device_state.get_keys();
-
-        // // History management
-        // if keys.contains(&Keycode::Up) && !debug_state.last_pressed_keys.contains(&Keycode::Up) {
-        //     // Go back in history
-        //     if debug_state.history.is_empty() {
-        //         print!("\x1b[2K\r> ");
-        //         io::stdout().flush().unwrap();
-        //         continue;
-        //     }
-        //     current_history_idx = current_history_idx
-        //         .saturating_add(1)
-        //         .min(debug_state.history.len());
-        //     let message =
-        //         debug_state.history[debug_state.history.len() - current_history_idx].clone();
-        //     debug_state.reader.set_contents(message.clone());
-        //     print_message(debug_state, message);
-        // }
-
-        // if keys.contains(&Keycode::Down) && !debug_state.last_pressed_keys.contains(&Keycode::Down)
-        // {
-        //     // Go forward in history
-        //     if debug_state.history.is_empty() {
-        //         print!("\x1b[2K\r> ");
-        //         io::stdout().flush().unwrap();
-        //         continue;
-        //     }
-        //     current_history_idx = current_history_idx.saturating_sub(1);
-        //     if current_history_idx == debug_state.history.len() {
-        //         current_history_idx = current_history_idx.saturating_sub(1);
-        //     }
-        //     let a = debug_state.history.len() - current_history_idx;
-
-        //     let message = format!(
-        //         "\x1b[2K\r> {}",
-        //         if a < debug_state.history.len() {
-        //             debug_state.history[debug_state.history.len() - current_history_idx].clone()
-        //         } else {
-        //             String::new()
-        //         }
-        //     );
-        //     debug_state.reader.set_contents(message.clone());
-        //     print_message(debug_state, message);
-        // }
-        if let Some(line) = debug_state.reader.readline() {
-            return line;
+/// Breaks into the debug terminal when a sound is started, if `b sound` is set.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SoundBreakObserver;
+
+impl Observer for SoundBreakObserver {
+    fn on_instruction(&mut self, event: &InstructionEvent) {
+        check_sound_break(event.addr, event.instruction);
+    }
+}
+
+/// The key that `b key` is waiting for the ROM to see pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyBreak {
+    Any,
+    Key(u8),
+}
+
+/// The key that breaks into the debug terminal the next time an instruction sees it pressed, if any.
+pub static mut KEY_BREAK: Option<KeyBreak> = None;
+
+pub fn get_key_break() -> Option<KeyBreak> {
+    // SAFETY: single threaded
+    unsafe { KEY_BREAK }
+}
+
+pub fn set_key_break(key_break: Option<KeyBreak>) {
+    // SAFETY: single threaded
+    unsafe { KEY_BREAK = key_break };
+}
+
+/// Break into the debug terminal if the instruction at the PC is about to see the key that `b key` is waiting
+/// for, so that it's the instruction that's shown. `keypad` and `last_keypad` are the ones it will be executed
+/// with. The key breakpoint is removed once it's hit.
+pub fn check_key_break(keypad: u16, last_keypad: u16) {
+    let Some(key_break) = get_key_break() else {
+        return;
+    };
+    let pc = get_pc();
+    let Some(instruction) = decode_at(pc) else {
+        return;
+    };
+    // The keys that the instruction will see pressed. Bit N is set if key N is seen.
+    let seen = match instruction {
+        Instruction::SkipIfKey(vx) | Instruction::SkipIfNotKey(vx) => {
+            let key = get_register(vx);
+            if key < 16 { keypad & (1 << key) } else { 0 }
+        }
+        // FX0A only sees keys that weren't already held, and takes the lowest one
+        Instruction::GetKey(_) if matches!(get_key_wait(), KeyWait::Idle | KeyWait::Press) => {
+            let newly_pressed = keypad & !last_keypad;
+            newly_pressed & newly_pressed.wrapping_neg()
         }
+        _ => 0,
+    };
+    let key = match key_break {
+        KeyBreak::Any if seen != 0 => u8::try_from(seen.trailing_zeros()).unwrap(),
+        KeyBreak::Key(key) if seen & (1 << key) != 0 => key,
+        _ => return,
+    };
+    set_key_break(None);
+    request_break(format!(
+        "{instruction} at {pc:#06X} sees key {key:X} pressed"
+    ));
+}
+
+/// Breaks into the debug terminal when an instruction is about to see the key that `b key` is waiting for.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KeyBreakObserver;
 
-        debug_state.last_pressed_keys = keys;
+impl Observer for KeyBreakObserver {
+    fn should_break(&mut self, _pc: u16, keypad: u16, last_keypad: u16) -> bool {
+        // The reason is shown by the debug terminal, which is entered because the break was requested
+        check_key_break(keypad, last_keypad);
+        false
+    }
+}
+
+/// The reason that execution should break into the debug terminal, if any.
+/// This is used when something goes wrong while executing an instruction.
+pub static mut BREAK_REASON: Option<String> = None;
+
+/// Request that execution break into the debug terminal, showing the given reason.
+pub fn request_break(reason: String) {
+    // SAFETY: single threaded
+    unsafe { BREAK_REASON = Some(reason) };
+}
+
+/// Returns whether a break into the debug terminal has been requested.
+pub fn is_break_requested() -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        BREAK_REASON.is_some()
+    }
+}
+
+/// Returns the reason for the requested break, if any, and clears it.
+pub fn take_break_reason() -> Option<String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        BREAK_REASON.take()
     }
 }
 
@@ -118,34 +344,63 @@ fn get_line(debug_state: &mut DebugState) -> String {
 #[allow(clippy::too_many_lines)]
 pub fn debug_terminal(
     n_instructions_executed: &mut u128,
-    instruction: Instruction,
-    instruction_raw: u16,
+    mut instruction: Instruction,
+    mut instruction_raw: u16,
     debug_state: &mut DebugState,
 ) -> bool {
+    if let Some(reason) = take_break_reason() {
+        dprintln!("\x1b[31m{reason}\x1b[0m");
+    }
+    // The PC has moved, so start listing around it again
+    debug_state.list_addr = None;
+
     loop {
         // Clear the line, print the prompt, and move the cursor to the end of the prompt
-        println!("\x1b[2K\r> ");
-        print!("\x1b[1A\x1b[2C");
-        io::stdout().flush().unwrap();
+        if is_debug_server() {
+            dprint!("> ");
+        } else {
+            println!("\x1b[2K\r> ");
+            print!("\x1b[1A\x1b[2C");
+            io::stdout().flush().unwrap();
+        }
 
-        let mut line = get_line(debug_state);
-        // let mut line = String::new();
-        // io::stdin().read_line(&mut line).unwrap();
+        let line = if let Some(command) = debug_state.pending_commands.pop_front() {
+            // Show the command as if it had been typed
+            dprintln!("{command}");
+            command
+        } else {
+            if let Some(line) = debug_state.pending_line.take() {
+                debug_state.last_debug_command = line;
+            }
 
-        // Remove escapes
-        line = line.replace(['\x1b'], "");
+            let input = if is_debug_server() {
+                read_client_line()
+            } else {
+                debug_state.line_editor.read_line()
+            };
+            let mut line = match input {
+                LineInput::Line(line) => line,
+                LineInput::Interrupt => exit(130),
+            };
 
-        if line.trim() == "" {
-            line = debug_state.last_debug_command.clone();
-        }
+            if line.trim() == "" {
+                line.clone_from(&debug_state.last_debug_command);
+            }
 
-        if debug_state
-            .history
-            .last()
-            .is_none_or(|val| *val != line.trim())
-        {
-            debug_state.history.push(line.trim().to_string());
-        }
+            // Replace aliases and macros, and run each command separately if there's more than one
+            match expand_line(&line) {
+                Ok(commands) if commands.len() == 1 && commands[0] == line.trim() => line,
+                Ok(commands) => {
+                    debug_state.pending_commands.extend(commands);
+                    debug_state.pending_line = Some(line.trim().to_string());
+                    continue;
+                }
+                Err(e) => {
+                    dprintln!("{e}");
+                    continue;
+                }
+            }
+        };
 
         let args = line.trim().split(' ').collect::<Vec<_>>();
         match args[0] {
@@ -154,116 +409,413 @@ pub fn debug_terminal(
                 debug_state.last_debug_command.clear();
                 debug_state.last_debug_command.push_str(line.trim());
                 if args.len() > 1 {
-                    print!("Unexpected args for command {}: ", args[0]);
+                    dprint!("Unexpected args for command {}: ", args[0]);
                     for arg in &args[1..] {
-                        print!("{arg} ");
+                        dprint!("{arg} ");
                     }
-                    println!();
+                    dprintln!();
                     continue;
                 }
-                println!("b | breakpoint  Manage breakpoints");
-                println!("                    Usage:");
-                println!(
+                dprintln!(
+                    "a, assemble     Assemble an instruction or directive and write it to memory at the given address"
+                );
+                dprintln!("                    Usage: <a | assemble> <address> <instruction>");
+                dprintln!(
+                    "                    Instructions are written like in c8asm, e.g. a 0x230 mov $v0, 0x01"
+                );
+                dprintln!(
+                    "                    Symbols can be used as values, and data too, e.g. a 0x300 db 0xF0, 0x90"
+                );
+                dprintln!();
+                dprintln!("alias           List or add short names for commands");
+                dprintln!("                    Usage:");
+                dprintln!("                        alias                       List all aliases");
+                dprintln!(
+                    "                        alias <name> <command>      Make name run the command, with any args added to the end"
+                );
+                dprintln!("                        unalias <name>              Remove an alias");
+                dprintln!("                    e.g. alias dw \"x 8 i\"");
+                dprintln!();
+                dprintln!("b | breakpoint  Manage breakpoints");
+                dprintln!("                    Usage:");
+                dprintln!(
                     "                        <b | breakpoint> <address>               Set a breakpoint at the given address"
                 );
-                println!(
+                dprintln!(
+                    "                        <b | breakpoint> sound                   Break when FX18 sets the sound timer to a non-zero value"
+                );
+                dprintln!(
+                    "                        <b | breakpoint> key [key]               Break once, the next time EX9E, EXA1 or FX0A sees the key (default: any key) pressed"
+                );
+                dprintln!(
                     "                        <b | breakpoint> <l | list>              List all breakpoints"
                 );
-                println!(
+                dprintln!(
                     "                        <b | breakpoint> <r | remove> <address>  Remove the breakpoint at the given address"
                 );
-                println!("                    Valid formats for address are:");
-                println!("                        123     Number");
-                println!("                        0x123   Hex");
-                println!("                        0b101   Binary");
-                println!();
-                println!("c, continue     Exit debug mode and continue program execution");
-                println!();
-                println!("h, help         Print this message");
-                println!();
-                println!(
+                dprintln!(
+                    "                        <b | breakpoint> <r | remove> sound      Stop breaking when the sound timer is set"
+                );
+                dprintln!(
+                    "                        <b | breakpoint> <r | remove> key        Stop waiting for a key"
+                );
+                dprintln!("                    Valid formats for address are:");
+                dprintln!("                        123     Number");
+                dprintln!("                        0x123   Hex");
+                dprintln!("                        0b101   Binary");
+                dprintln!("                        main    The name of a symbol (see sym)");
+                dprintln!();
+                dprintln!(
+                    "bt, backtrace   Print the call stack, with the call site and return address of each subroutine"
+                );
+                dprintln!();
+                dprintln!("c, continue     Exit debug mode and continue program execution");
+                dprintln!();
+                dprintln!(
+                    "coverage        Show which addresses have been executed, read and written as a colored map"
+                );
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        coverage                 Show the map from 0x200 to the end of memory"
+                );
+                dprintln!(
+                    "                        coverage <start> <end>   Show the map from start to end (exclusive)"
+                );
+                dprintln!(
+                    "                        coverage save [path]     Save the map for c8disass (default: the --coverage-out path)"
+                );
+                dprintln!(
+                    "                        coverage reset           Clear the map, and the counts in the heatmap"
+                );
+                dprintln!();
+                dprintln!(
+                    "d, disassemble  Disassemble instructions at the given address, without moving the listing (see list)"
+                );
+                dprintln!("                    Usage: <d | disassemble> <address> [count]");
+                dprintln!("                    count defaults to 16");
+                dprintln!();
+                dprintln!(
+                    "define          List or add macros, which run several commands one after another"
+                );
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        define                                List all macros"
+                );
+                dprintln!(
+                    "                        define <name> <command>; <command> end   Make name run the commands"
+                );
+                dprintln!(
+                    "                        undefine <name>                       Remove a macro"
+                );
+                dprintln!("                    e.g. define step3 n; n; n; end");
+                dprintln!(
+                    "                    Several commands can also be run at once by separating them with ;, e.g. n; p v0"
+                );
+                dprintln!();
+                dprintln!(
+                    "f, frame        Run until the next frame starts (1/60 s), or until a sprite is drawn"
+                );
+                dprintln!();
+                dprintln!("fill            Set a range of memory to a byte");
+                dprintln!("                    Usage: fill <address> <len> <byte>");
+                dprintln!("                    e.g. fill 0x300 8 0");
+                dprintln!();
+                dprintln!("fin, finish     Run until the current subroutine returns");
+                dprintln!();
+                dprintln!(
+                    "goto-ins        Restart the ROM and run it until the given number of instructions have been executed"
+                );
+                dprintln!("                    Usage: goto-ins <count>");
+                dprintln!(
+                    "                    The input from each frame is replayed, so everything happens exactly as before"
+                );
+                dprintln!(
+                    "                    Breakpoints are ignored, and changes made in the debug terminal aren't replayed"
+                );
+                dprintln!();
+                dprintln!("h, help         Print this message");
+                dprintln!();
+                dprintln!(
+                    "heatmap         Show how often each address has been accessed, as a grid of the whole memory"
+                );
+                dprintln!("                    Usage: heatmap [exec | read | write]");
+                dprintln!(
+                    "                    Shows every kind of access by default. Each line is 0x80 addresses, 0x40 per half"
+                );
+                dprintln!();
+                dprintln!(
                     "j, jump         Set PC to the given address. Addresses must be <= 12-bit."
                 );
-                println!("                    Usage: <j | jump> <address>");
-                println!("                        Valid formats for addresses are:");
-                println!("                            123     Number");
-                println!("                            0x123   Hex");
-                println!("                            0b101   Binary");
-                println!();
-                println!("n, next         Execute the next instruction");
-                println!();
-                println!(
-                    "p, print        Print the value in the given register or at the given address"
-                );
-                println!("                    Usage: <p | print> <target>");
-                println!("                    Valid targets are:");
-                println!("                        VX         Register VX");
-                println!("                        i, index   Register I");
-                println!("                        pc         Register PC");
-                println!("                        s, delay   Delay timer");
-                println!("                        s, sound   Sound timer");
-                println!("                        address    The byte in memory at the address");
-                println!("                            Valid formats for addresses are:");
-                println!("                                123     Number");
-                println!("                                0x123   Hex");
-                println!("                                0b101   Binary");
-                println!();
-                println!("pop             Pop the stack.");
-                println!();
-                println!("push            Push the given value to the stack.");
-                println!("                    Usage: push <value>");
-                println!("                    value must be <= 8 bits");
-                println!("                    Valid formats for value are:");
-                println!("                        123     Number");
-                println!("                        0x123   Hex");
-                println!("                        0b101   Binary");
-                println!();
-                println!(
+                dprintln!("                    Usage: <j | jump> <address>");
+                dprintln!("                        Valid formats for addresses are:");
+                dprintln!("                            123     Number");
+                dprintln!("                            0x123   Hex");
+                dprintln!("                            0b101   Binary");
+                dprintln!("                            main    The name of a symbol (see sym)");
+                dprintln!();
+                dprintln!(
+                    "key             Press or release a key on the keypad while execution is paused"
+                );
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        key                     List the keys pressed from the debug terminal"
+                );
+                dprintln!(
+                    "                        key press <key>         Hold down a key until it's released"
+                );
+                dprintln!("                        key release <key>       Release a key");
+                dprintln!(
+                    "                        key tap <key>           Press a key for the rest of this frame and the next one"
+                );
+                dprintln!(
+                    "                    Keys are 0-F, and take effect on the next instruction"
+                );
+                dprintln!();
+                dprintln!(
+                    "l, list         Disassemble the instructions around the PC or the given address"
+                );
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        <l | list>              List the instructions around the PC, or continue the last listing"
+                );
+                dprintln!(
+                    "                        <l | list> <address>    List the instructions starting at the given address"
+                );
+                dprintln!("                    Breakpoints are marked with *, and the PC with ->");
+                dprintln!();
+                dprintln!("memcpy          Copy a range of memory. The ranges may overlap.");
+                dprintln!("                    Usage: memcpy <dst> <src> <len>");
+                dprintln!();
+                dprintln!("n, next         Execute the next instruction");
+                dprintln!();
+                dprintln!(
+                    "no, next-over   Execute the next instruction, running any subroutine it calls until it returns"
+                );
+                dprintln!();
+                dprintln!(
+                    "rn, reverse-next  Step back to before the last instruction (up to 1024 instructions)"
+                );
+                dprintln!();
+                dprintln!(
+                    "p, print        Print the value of an expression, or the byte in memory at the given address"
+                );
+                dprintln!("                    Usage: <p | print> <expression | address>");
+                dprintln!("                    Expressions can use:");
+                dprintln!("                        VX         Register VX");
+                dprintln!("                        i, index   Register I");
+                dprintln!("                        pc         Register PC");
+                dprintln!("                        d, delay   Delay timer");
+                dprintln!("                        s, sound   Sound timer");
+                dprintln!("                        [expr]     The byte in memory at the address");
+                dprintln!("                        123, 0x123, 0b101, symbols");
+                dprintln!(
+                    "                        + - * / % & | ^ ~ << >> == != < <= > >= && || !, and brackets"
+                );
+                dprintln!("                    e.g. p v3 + v4*2, p [i+1], p (v1 & 0x0F) == 3");
+                dprintln!();
+                dprintln!("pop             Pop the stack.");
+                dprintln!();
+                dprintln!("push            Push the given value to the stack.");
+                dprintln!("                    Usage: push <value>");
+                dprintln!("                    value must be <= 8 bits");
+                dprintln!("                    Valid formats for value are:");
+                dprintln!("                        123     Number");
+                dprintln!("                        0x123   Hex");
+                dprintln!("                        0b101   Binary");
+                dprintln!();
+                dprintln!(
+                    "profile         Show the most executed addresses and the subroutines that ran the longest"
+                );
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        profile [n]              Print the n hottest addresses and subroutines (default 10)"
+                );
+                dprintln!(
+                    "                        profile save [path]      Save the profile (default: the --profile-out path)"
+                );
+                dprintln!("                        profile reset            Clear the profile");
+                dprintln!();
+                dprintln!("q, quirks       List or change compatibility quirks");
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        <q | quirks>                      List all quirks and their values"
+                );
+                dprintln!(
+                    "                        <q | quirks> <name> <on | off>    Enable or disable the given quirk"
+                );
+                dprintln!(
+                    "                        <q | quirks> preset <name>        Use the quirks of the given interpreter (vip or chip48)"
+                );
+                dprintln!(
+                    "                        <q | quirks> save                 Save the quirks, IPS and keymap for this ROM to the ROM database"
+                );
+                dprintln!();
+                dprintln!(
                     "s, set          Set the value in the given register or at the given address"
                 );
-                println!("                    Usage: <s | set> <target> <value>");
-                println!("                    Valid targets are:");
-                println!("                        VX         Register VX");
-                println!("                            value must be <= 8 bits");
-                println!("                        i, index   Register I");
-                println!("                            value must be <= 12 bits");
-                println!("                        pc         Register PC");
-                println!("                            value must be <= 12 bits");
-                println!("                        s, delay   Delay timer");
-                println!("                            value must be <= 8 bits");
-                println!("                        s, sound   Sound timer");
-                println!("                            value must be <= 8 bits");
-                println!("                        address    The byte in memory at the address");
-                println!("                            value must be <= 8 bits");
-                println!("                            Valid formats for addresses are:");
-                println!("                                123     Number");
-                println!("                                0x123   Hex");
-                println!("                                0b101   Binary");
-                println!();
-                println!(
+                dprintln!("                    Usage: <s | set> <target> <value>");
+                dprintln!(
+                    "                    value can be an expression (see print), e.g. s v0 (v1 & 0x0F)"
+                );
+                dprintln!("                    Valid targets are:");
+                dprintln!("                        VX         Register VX");
+                dprintln!("                            value must be <= 8 bits");
+                dprintln!("                        i, index   Register I");
+                dprintln!("                            value must be <= 12 bits");
+                dprintln!("                        pc         Register PC");
+                dprintln!("                            value must be <= 12 bits");
+                dprintln!("                        s, delay   Delay timer");
+                dprintln!("                            value must be <= 8 bits");
+                dprintln!("                        s, sound   Sound timer");
+                dprintln!("                            value must be <= 8 bits");
+                dprintln!("                        address    The byte in memory at the address");
+                dprintln!("                            value must be <= 8 bits");
+                dprintln!("                            Valid formats for addresses are:");
+                dprintln!("                                123     Number");
+                dprintln!("                                0x123   Hex");
+                dprintln!("                                0b101   Binary");
+                dprintln!();
+                dprintln!(
+                    "record          Start or stop recording the display to an animated GIF."
+                );
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        record           Stop recording, or start recording to recording-<time>.gif"
+                );
+                dprintln!(
+                    "                        record <path>    Start recording to the given path"
+                );
+                dprintln!();
+                dprintln!("screenshot      Save the display to an image file.");
+                dprintln!("                    Usage: screenshot <path> [scale]");
+                dprintln!(
+                    "                    The format is PBM if path ends in .pbm, otherwise PNG."
+                );
+                dprintln!(
+                    "                    scale is the number of image pixels per display pixel (default {DEFAULT_SCREENSHOT_SCALE})"
+                );
+                dprintln!();
+                dprintln!(
+                    "spr, sprite     Show the sprite at I next to the registers, updating as I changes"
+                );
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        <spr | sprite>          Show as many rows as the last sprite that was drawn"
+                );
+                dprintln!(
+                    "                        <spr | sprite> <rows>   Show the given number of rows"
+                );
+                dprintln!("                        <spr | sprite> off      Hide the sprite");
+                dprintln!();
+                dprintln!(
+                    "stats           Show how many instructions of each kind (draws, skips, ALU ops, ...) have run"
+                );
+                dprintln!(
+                    "                    Counts and averages per frame since the ROM started or the profile was reset"
+                );
+                dprintln!();
+                dprintln!("sym, symbols    List or load the names of addresses in the ROM");
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        <sym | symbols>                List all symbols"
+                );
+                dprintln!(
+                    "                        <sym | symbols> load <path>    Load symbols from a file, replacing the current ones"
+                );
+                dprintln!(
+                    "                    Each line of the file is an address and a name, e.g. 0x23A draw_player"
+                );
+                dprintln!(
+                    "                    Symbols can be used in place of addresses, e.g. b draw_player"
+                );
+                dprintln!();
+                dprintln!(
+                    "trace           Log the values of expressions whenever an address is executed, without breaking"
+                );
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        trace                          List the tracepoints"
+                );
+                dprintln!(
+                    "                        trace <address> <expr>, ...    Log the expressions (see print) whenever address is executed"
+                );
+                dprintln!(
+                    "                        trace remove <address>         Remove the tracepoint at address"
+                );
+                dprintln!(
+                    "                        trace log [n]                  Print the last n lines of the log (default 20)"
+                );
+                dprintln!("                        trace clear                    Clear the log");
+                dprintln!(
+                    "                        trace file <path | off>        Also append the log to a file, or stop"
+                );
+                dprintln!("                    The last line logged is shown below the display");
+                dprintln!("                    e.g. trace update_score [score], v3");
+                dprintln!();
+                dprintln!("watch           Break when a register changes");
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        watch                   List the watched registers"
+                );
+                dprintln!(
+                    "                        watch <vx>              Watch a register, e.g. watch v5"
+                );
+                dprintln!(
+                    "                        watch remove <vx>       Stop watching a register"
+                );
+                dprintln!();
+                dprintln!("watchmem        Log every write to a range of memory");
+                dprintln!("                    Usage:");
+                dprintln!(
+                    "                        watchmem <address> <len> [break]   Watch len bytes from address. With break, break when they change"
+                );
+                dprintln!(
+                    "                        watchmem list                      List the watched ranges"
+                );
+                dprintln!(
+                    "                        watchmem remove <address>          Stop watching the range that starts at address"
+                );
+                dprintln!(
+                    "                        watchmem log [n]                   Print the last n writes (default 20): instruction count, address, old -> new value, and the instruction that wrote it"
+                );
+                dprintln!(
+                    "                        watchmem clear                     Clear the log"
+                );
+                dprintln!();
+                dprintln!(
                     "x, examine       Examine (print) the given number of bytes at the given address."
                 );
-                println!("                    Usage: <x | examine> <num> <address>");
-                println!(
-                    "                        Valid formats for both addresses and the number of bytes to print are:"
+                dprintln!("                    Usage: <x | examine>[/format] <num> <address>");
+                dprintln!("                    Valid formats are:");
+                dprintln!("                        hex      Hex bytes (the default)");
+                dprintln!("                        dec      Decimal bytes");
+                dprintln!(
+                    "                        ascii    Text, with unprintable bytes shown as ."
+                );
+                dprintln!(
+                    "                        sprite   An 8 pixel wide sprite, one byte per row"
+                );
+                dprintln!(
+                    "                    address can be an expression (see print), e.g. x 8 i"
+                );
+                dprintln!(
+                    "                    Valid formats for the number of bytes to print are:"
                 );
-                println!("                            123     Number");
-                println!("                            0x123   Hex");
-                println!("                            0b101   Binary");
+                dprintln!("                        123     Number");
+                dprintln!("                        0x123   Hex");
+                dprintln!("                        0b101   Binary");
             }
             // Continue program execution
             "c" | "continue" => {
                 if args.len() > 1 {
-                    print!("Unexpected args for command {}: ", args[0]);
+                    dprint!("Unexpected args for command {}: ", args[0]);
                     for arg in &args[1..] {
-                        print!("{arg} ");
+                        dprint!("{arg} ");
                     }
-                    println!();
+                    dprintln!();
                     continue;
                 }
-                for _ in 0..DISPLAY_HEIGHT + 5 {
-                    println!();
-                }
+                make_room_for_frame(false);
                 debug_state.last_debug_command.clear();
                 return false;
             }
@@ -272,264 +824,580 @@ pub fn debug_terminal(
                 debug_state.last_debug_command.clear();
                 debug_state.last_debug_command.push_str(line.trim());
                 if args.len() > 1 {
-                    print!("Unexpected args for command {}: ", args[0]);
+                    dprint!("Unexpected args for command {}: ", args[0]);
+                    for arg in &args[1..] {
+                        dprint!("{arg} ");
+                    }
+                    dprintln!();
+                    continue;
+                }
+                make_room_for_frame(true);
+                return true;
+            }
+            // Next instruction, stepping over subroutine calls
+            "no" | "next-over" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() > 1 {
+                    dprint!("Unexpected args for command {}: ", args[0]);
                     for arg in &args[1..] {
-                        print!("{arg} ");
+                        dprint!("{arg} ");
                     }
-                    println!();
+                    dprintln!();
                     continue;
                 }
-                for _ in 0..DISPLAY_HEIGHT + 5 {
-                    println!();
+                let pc = get_pc();
+                if let Some(Instruction::SubroutineCall(_)) = decode_at(pc) {
+                    // Run until the subroutine returns to the instruction after the call
+                    debug_state.temporary_breakpoint = Some(TemporaryBreakpoint {
+                        addr: pc + 2,
+                        stack_depth: get_stack().len(),
+                    });
+                    make_room_for_frame(false);
+                    return false;
                 }
+                make_room_for_frame(true);
                 return true;
             }
-            // Jump to the given address.
-            "j" | "jump" => {
+            // Step back to before the last instruction
+            "rn" | "reverse-next" => {
                 debug_state.last_debug_command.clear();
                 debug_state.last_debug_command.push_str(line.trim());
-                if args.len() != 2 {
-                    println!("usage: {} address", args[0]);
+                if args.len() > 1 {
+                    dprint!("Unexpected args for command {}: ", args[0]);
+                    for arg in &args[1..] {
+                        dprint!("{arg} ");
+                    }
+                    dprintln!();
                     continue;
                 }
-                let Some(addr) = str_to_num(args[1]) else {
+                let Some(snapshot) = pop_history() else {
+                    dprintln!("can't step back any further");
                     continue;
                 };
-
-                if addr & 0x0FFF != addr {
-                    println!("address {addr:#06X} is too large to jump to (should be 12 bits)",);
-                }
-                #[allow(clippy::cast_possible_truncation)]
-                set_pc(addr as u16);
-                debug_redraw(
-                    debug_state,
-                    instruction,
-                    instruction_raw,
-                    n_instructions_executed,
-                );
-                continue;
+                snapshot.restore();
+                // The count is incremented again when execution continues, like after any instruction
+                *n_instructions_executed = snapshot.instructions_executed.wrapping_sub(1);
+                // Show the debug info as it was after the instruction before the one that was undone
+                (instruction_raw, instruction) = redraw_from_history(debug_state);
             }
-            // Print the value of something
-            // - v{x}: VX
-            // - i: I
-            // - pc: PC
-            // - d | delay: Delay timer
-            // - s | sound: Sound timer
-            // - addr: Memory
-            "p" | "print" => {
+            // Restart the ROM and run it until the given number of instructions have been executed, e.g.
+            // goto-ins 12345
+            "goto-ins" => {
                 debug_state.last_debug_command.clear();
                 debug_state.last_debug_command.push_str(line.trim());
                 if args.len() != 2 {
-                    println!("invalid usage of command {}", args[0]);
+                    dprintln!("invalid usage of command {}", args[0]);
                     continue;
                 }
-                // Registers
-                if args[1].starts_with(['v', 'V']) {
-                    if args[1].len() != 2 {
-                        println!("invalid usage of command {}", args[0]);
-                        continue;
-                    }
-                    let reg_idx = match u8::from_str_radix(&args[1][1..2], 16) {
-                        Ok(val) => val,
-                        Err(e) => {
-                            println!("could not parse hex value {}: {}", args[1], e);
-                            continue;
-                        }
-                    };
-                    println!("{:#04X}", get_register((reg_idx).into()));
+                let Some(target) = str_to_num(args[1]) else {
+                    continue;
+                };
+                if is_recording_movie() {
+                    dprintln!("can't go to another instruction while recording a movie");
                     continue;
                 }
-                match args[1] {
-                    "i" | "index" => {
-                        println!("{:#06X}", get_i());
-                    }
-                    "pc" => {
-                        println!("{:#06X}", get_pc());
-                    }
-                    "d" | "delay" => {
-                        println!("{:#04X}", get_delay_timer());
-                    }
-                    "s" | "sound" => {
-                        println!("{:#04X}", get_sound_timer());
+                let replay = match replay_to(target as u128) {
+                    Ok(replay) => replay,
+                    Err(e) => {
+                        dprintln!("{e}");
+                        continue;
                     }
-                    // Unknown => try to interpret as an address
-                    _ => {
-                        let Some(addr) = str_to_num(args[1]) else {
-                            continue;
-                        };
-                        if addr & 0x0FFF != addr {
-                            println!(
-                                "address {addr:#06X} is too large to jump to (should be 12 bits)",
-                            );
-                        }
-                        println!("{:#04X}", get_memory_u8(u16::try_from(addr).unwrap()));
+                };
+                debug_state.replayed_keypad = Some((replay.keypad, replay.last_keypad));
+                // The input for the current frame has already been used
+                seek_movie(usize::try_from(get_frame_count()).unwrap() + 1);
+                // The count is incremented again when execution continues, like after any instruction
+                *n_instructions_executed = replay.instructions.wrapping_sub(1);
+                (instruction_raw, instruction) = redraw_from_history(debug_state);
+                if let Some(reason) = replay.stopped_early {
+                    dprintln!("Stopped early: {reason}");
+                }
+                dprintln!("At instruction #{}", replay.instructions);
+            }
+            // Run until the current subroutine returns
+            // Run until the next frame starts or a sprite is drawn
+            "f" | "frame" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() > 1 {
+                    dprint!("Unexpected args for command {}: ", args[0]);
+                    for arg in &args[1..] {
+                        dprint!("{arg} ");
                     }
+                    dprintln!();
+                    continue;
                 }
-                continue;
+                debug_state.frame_step = Some(FrameStep {
+                    frame: get_frame_count(),
+                    is_drawn: false,
+                });
+                make_room_for_frame(false);
+                return false;
             }
-            // Set something to a value
-            // - v{x}: VX
-            // - i: I
-            // - pc: PC
-            // - d | delay: Delay timer
-            // - s | sound: Sound timer
-            // - addr: Memory
-            "s" | "set" => {
+            "fin" | "finish" => {
                 debug_state.last_debug_command.clear();
                 debug_state.last_debug_command.push_str(line.trim());
-                if args.len() != 3 {
-                    println!("invalid usage of command {}", args[0]);
+                if args.len() > 1 {
+                    dprint!("Unexpected args for command {}: ", args[0]);
+                    for arg in &args[1..] {
+                        dprint!("{arg} ");
+                    }
+                    dprintln!();
                     continue;
                 }
-                let Some(val) = str_to_num(args[2]) else {
+                let Some(return_addr) = peek_stack() else {
+                    dprintln!("not in a subroutine");
                     continue;
                 };
-                // Registers
-                if args[1].starts_with(['v', 'V']) {
-                    if args[1].len() != 2 {
-                        println!("invalid usage of command {}", args[0]);
-                        continue;
+                debug_state.temporary_breakpoint = Some(TemporaryBreakpoint {
+                    addr: return_addr,
+                    stack_depth: get_stack().len() - 1,
+                });
+                make_room_for_frame(false);
+                return false;
+            }
+            // Manage symbols
+            // sym: List symbols
+            // sym load game.sym: Load the symbols in game.sym
+            "sym" | "symbols" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.len() {
+                    1 => {
+                        let symbols = get_symbols();
+                        if symbols.is_empty() {
+                            dprintln!("no symbols loaded");
+                        }
+                        for (addr, name) in symbols {
+                            dprintln!("{addr:#06X}  {name}");
+                        }
                     }
-                    let reg_idx = match u8::from_str_radix(&args[1][1..2], 16) {
-                        Ok(val) => val,
+                    3 if args[1] == "load" => match load_symbols(args[2], false) {
+                        Ok(()) => {
+                            dprintln!("loaded {} symbols from {}", get_symbols().len(), args[2]);
+                        }
+                        Err(e) => dprintln!("could not load symbols: {e}"),
+                    },
+                    _ => dprintln!("invalid usage of command {}", args[0]),
+                }
+            }
+            // Print the call stack
+            "bt" | "backtrace" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() > 1 {
+                    dprint!("Unexpected args for command {}: ", args[0]);
+                    for arg in &args[1..] {
+                        dprint!("{arg} ");
+                    }
+                    dprintln!();
+                    continue;
+                }
+                dprint!("{}", backtrace());
+            }
+            // Disassemble memory
+            // l: List around the PC, or continue the last listing
+            // l 0x300: List from 0x300
+            "l" | "list" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push('l');
+                let start = match args.len() {
+                    1 => debug_state
+                        .list_addr
+                        .unwrap_or_else(|| get_pc().saturating_sub(2 * LIST_CONTEXT)),
+                    2 => {
+                        let Some(addr) = str_to_addr(args[1]) else {
+                            continue;
+                        };
+                        if addr >= MEMORY_SIZE {
+                            dprintln!("address {addr:#06X} is out of range");
+                            continue;
+                        }
+                        u16::try_from(addr).unwrap()
+                    }
+                    _ => {
+                        dprintln!("invalid usage of command {}", args[0]);
+                        continue;
+                    }
+                };
+                dprint!("{}", disassembly_listing(start, LIST_LENGTH));
+                debug_state.list_addr = Some(start + 2 * LIST_LENGTH);
+            }
+            // Disassemble the instructions at an address
+            // d 0x300: Disassemble 16 instructions from 0x300
+            // d draw_player 4: Disassemble 4 instructions from draw_player
+            "d" | "disassemble" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() != 2 && args.len() != 3 {
+                    dprintln!("invalid usage of command {}", args[0]);
+                    continue;
+                }
+                let Some(addr) = str_to_addr(args[1]) else {
+                    continue;
+                };
+                if addr >= MEMORY_SIZE {
+                    dprintln!("address {addr:#06X} is out of range");
+                    continue;
+                }
+                let count = if args.len() == 3 {
+                    let Some(count) = str_to_num(args[2]) else {
+                        continue;
+                    };
+                    count
+                } else {
+                    usize::from(LIST_LENGTH)
+                };
+                // The listing stops at the end of memory, so larger counts don't need to be shown
+                let count = u16::try_from(count.min(MEMORY_SIZE)).unwrap();
+                dprint!(
+                    "{}",
+                    disassembly_listing(u16::try_from(addr).unwrap(), count)
+                );
+            }
+            // Jump to the given address.
+            "j" | "jump" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() != 2 {
+                    dprintln!("usage: {} address", args[0]);
+                    continue;
+                }
+                let Some(addr) = str_to_addr(args[1]) else {
+                    continue;
+                };
+
+                if addr & 0x0FFF != addr {
+                    dprintln!("address {addr:#06X} is too large to jump to (should be 12 bits)",);
+                    continue;
+                }
+                if let Err(e) = set_pc(u16::try_from(addr).unwrap()) {
+                    dprintln!("could not jump: {e}");
+                    continue;
+                }
+                debug_redraw(debug_state, instruction, instruction_raw);
+                continue;
+            }
+            // Print the value of something
+            // - v{x}: VX
+            // - i: I
+            // - pc: PC
+            // - d | delay: Delay timer
+            // - s | sound: Sound timer
+            // - addr: Memory
+            "p" | "print" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() < 2 {
+                    dprintln!("invalid usage of command {}", args[0]);
+                    continue;
+                }
+                // A lone address prints the byte in memory there, as if it were [address]
+                let expr = if args.len() == 2
+                    && (parse_num(args[1]).is_ok() || symbol_addr(args[1]).is_some())
+                {
+                    format!("[{}]", args[1])
+                } else {
+                    args[1..].join(" ")
+                };
+                match eval(&expr) {
+                    Ok(val) => dprintln!("{}", format_value(val)),
+                    Err(e) => dprintln!("{e}"),
+                }
+            }
+            // Set something to a value
+            // - v{x}: VX
+            // - i: I
+            // - pc: PC
+            // - d | delay: Delay timer
+            // - s | sound: Sound timer
+            // - addr: Memory
+            "s" | "set" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() < 3 {
+                    dprintln!("invalid usage of command {}", args[0]);
+                    continue;
+                }
+                let value = args[2..].join(" ");
+                let val = match eval(&value).map(usize::try_from) {
+                    Ok(Ok(val)) => val,
+                    Ok(Err(_)) => {
+                        dprintln!("could not set {}: value ({value}) is negative", args[1]);
+                        continue;
+                    }
+                    Err(e) => {
+                        dprintln!("{e}");
+                        continue;
+                    }
+                };
+                // Registers
+                if args[1].starts_with(['v', 'V']) {
+                    if args[1].len() != 2 {
+                        dprintln!("invalid usage of command {}", args[0]);
+                        continue;
+                    }
+                    let reg_idx = match u8::from_str_radix(&args[1][1..2], 16) {
+                        Ok(val) => val,
                         Err(e) => {
-                            println!("could not parse hex value {}: {}", args[1], e);
+                            dprintln!("could not parse hex value {}: {}", args[1], e);
                             continue;
                         }
                     };
                     if val & 0xFF != val {
-                        println!(
+                        dprintln!(
                             "could not set {}: value ({}) was more than 8 bits",
-                            args[1], args[2]
+                            args[1],
+                            value
                         );
                         continue;
                     }
-                    set_register((reg_idx as u8).into(), u8::try_from(val).unwrap());
-                    debug_redraw(
-                        debug_state,
-                        instruction,
-                        instruction_raw,
-                        n_instructions_executed,
-                    );
+                    set_register(Register::from_nibble(reg_idx), u8::try_from(val).unwrap());
+                    debug_redraw(debug_state, instruction, instruction_raw);
                     continue;
                 }
                 match args[1] {
                     "i" | "index" => {
                         if val & 0x0FFF != val {
-                            println!(
+                            dprintln!(
                                 "could not set {}: value ({}) was more than 12 bits",
-                                args[1], args[2]
+                                args[1],
+                                value
                             );
                             continue;
                         }
-                        set_i(u16::try_from(val).unwrap());
-                        debug_redraw(
-                            debug_state,
-                            instruction,
-                            instruction_raw,
-                            n_instructions_executed,
-                        );
+                        if let Err(e) = set_i(u16::try_from(val).unwrap()) {
+                            dprintln!("could not set {}: {e}", args[1]);
+                            continue;
+                        }
+                        debug_redraw(debug_state, instruction, instruction_raw);
                         continue;
                     }
                     "pc" => {
                         let val = val + 2;
                         if val & 0x0FFF != val {
-                            println!(
+                            dprintln!(
                                 "could not set {}: value ({}) was more than 12 bits",
-                                args[1], args[2]
+                                args[1],
+                                value
                             );
                             continue;
                         }
-                        set_pc(u16::try_from(val).unwrap());
-                        debug_redraw(
-                            debug_state,
-                            instruction,
-                            instruction_raw,
-                            n_instructions_executed,
-                        );
+                        if let Err(e) = set_pc(u16::try_from(val).unwrap()) {
+                            dprintln!("could not set {}: {e}", args[1]);
+                            continue;
+                        }
+                        debug_redraw(debug_state, instruction, instruction_raw);
                         continue;
                     }
                     "d" | "delay" => {
                         if val & 0xFF != val {
-                            println!(
+                            dprintln!(
                                 "could not set {}: value ({}) was more than 8 bits",
-                                args[1], args[2]
+                                args[1],
+                                value
                             );
                             continue;
                         }
                         set_delay_timer(u8::try_from(val).unwrap());
-                        debug_redraw(
-                            debug_state,
-                            instruction,
-                            instruction_raw,
-                            n_instructions_executed,
-                        );
+                        debug_redraw(debug_state, instruction, instruction_raw);
                         continue;
                     }
                     "s" | "sound" => {
                         if val & 0xFF != val {
-                            println!(
+                            dprintln!(
                                 "could not set {}: value ({}) was more than 8 bits",
-                                args[1], args[2]
+                                args[1],
+                                value
                             );
                             continue;
                         }
                         set_sound_timer(u8::try_from(val).unwrap());
-                        debug_redraw(
-                            debug_state,
-                            instruction,
-                            instruction_raw,
-                            n_instructions_executed,
-                        );
+                        debug_redraw(debug_state, instruction, instruction_raw);
                         continue;
                     }
                     // Unknown => try to interpret as an address
                     _ => {
-                        let Some(addr) = str_to_num(args[1]) else {
+                        let Some(addr) = str_to_addr(args[1]) else {
                             continue;
                         };
                         if addr & 0x0FFF != addr {
-                            println!(
+                            dprintln!(
                                 "address {addr:#06X} is too large to jump to (should be 12 bits)",
                             );
                             continue;
                         }
                         if val & 0xFF != val {
-                            println!(
+                            dprintln!(
                                 "could not set memory at {}: value ({}) was more than 8 bits",
-                                args[1], args[2]
+                                args[1],
+                                value
                             );
                             continue;
                         }
-                        set_memory_u8(u16::try_from(addr).unwrap(), u8::try_from(val).unwrap());
+                        if let Err(e) =
+                            set_memory_u8(u16::try_from(addr).unwrap(), u8::try_from(val).unwrap())
+                        {
+                            dprintln!("could not set memory at {}: {e}", args[1]);
+                        }
                         continue;
                     }
                 }
             }
+            // Assemble an instruction into memory
+            // a 0x230 jmp 0x240: Write 0x1240 to 0x230
+            "a" | "assemble" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() < 3 {
+                    dprintln!("invalid usage of command {}", args[0]);
+                    continue;
+                }
+                let Some(addr) = str_to_addr(args[1]) else {
+                    continue;
+                };
+                // The symbols of the ROM can be used like labels, e.g. a 0x230 jmp draw_player
+                let source = args[2..].join(" ");
+                let opts = AssemblyOptions {
+                    org: addr,
+                    defines: get_symbols()
+                        .into_iter()
+                        .map(|(addr, name)| (name, addr))
+                        .collect(),
+                    ..AssemblyOptions::default()
+                };
+                let bytes = match assemble(&source, &opts) {
+                    Ok(bytes) if bytes.is_empty() => {
+                        dprintln!("invalid usage of command {}", args[0]);
+                        continue;
+                    }
+                    Ok(bytes) => bytes,
+                    Err(diagnostics) => {
+                        for diagnostic in diagnostics {
+                            dprintln!("could not assemble instruction: {}", diagnostic.message);
+                        }
+                        continue;
+                    }
+                };
+                if addr + bytes.len() > MEMORY_SIZE {
+                    dprintln!("an instruction at {addr:#06X} would go past the end of memory");
+                    continue;
+                }
+                let addr = u16::try_from(addr).unwrap();
+                if let Err(e) = (0..)
+                    .zip(&bytes)
+                    .try_for_each(|(offset, byte)| set_memory_u8(addr + offset, *byte))
+                {
+                    dprintln!("could not assemble at {addr:#06X}: {e}");
+                    continue;
+                }
+                let mut raw = String::new();
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i > 0 && i % 2 == 0 {
+                        raw.push(' ');
+                    }
+                    let _ = write!(raw, "{byte:02X}");
+                }
+                dprintln!("{addr:#06X}: 0x{raw}  {}", source.trim());
+            }
+            // Set a range of memory to a byte
+            // fill 0x300 8 0: Set 0x300-0x307 to 0
+            "fill" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() != 4 {
+                    dprintln!("invalid usage of command {}", args[0]);
+                    continue;
+                }
+                let Some(addr) = str_to_addr(args[1]) else {
+                    continue;
+                };
+                let Some(len) = str_to_num(args[2]) else {
+                    continue;
+                };
+                let Some(val) = str_to_num(args[3]) else {
+                    continue;
+                };
+                if addr.checked_add(len).is_none_or(|end| end > MEMORY_SIZE) {
+                    dprintln!("range {addr:#06X} + {len} goes past the end of memory");
+                    continue;
+                }
+                let Ok(val) = u8::try_from(val) else {
+                    dprintln!("value ({}) was more than 8 bits", args[3]);
+                    continue;
+                };
+                if let Err(e) = (addr..addr + len)
+                    .try_for_each(|addr| set_memory_u8(u16::try_from(addr).unwrap(), val))
+                {
+                    dprintln!("could not fill memory: {e}");
+                }
+            }
+            // Copy a range of memory
+            // memcpy 0x400 0x300 8: Copy 0x300-0x307 to 0x400-0x407
+            "memcpy" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() != 4 {
+                    dprintln!("invalid usage of command {}", args[0]);
+                    continue;
+                }
+                let Some(dst) = str_to_addr(args[1]) else {
+                    continue;
+                };
+                let Some(src) = str_to_addr(args[2]) else {
+                    continue;
+                };
+                let Some(len) = str_to_num(args[3]) else {
+                    continue;
+                };
+                if let Some(start) = [dst, src]
+                    .into_iter()
+                    .find(|start| start.checked_add(len).is_none_or(|end| end > MEMORY_SIZE))
+                {
+                    dprintln!("range {start:#06X} + {len} goes past the end of memory");
+                    continue;
+                }
+                // Read the whole source range first, in case it overlaps the destination
+                let bytes = match (src..src + len)
+                    .map(|addr| get_memory_u8(u16::try_from(addr).unwrap()))
+                    .collect::<Result<Vec<u8>, _>>()
+                {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        dprintln!("could not copy memory: {e}");
+                        continue;
+                    }
+                };
+                if let Err(e) = (dst..)
+                    .zip(bytes)
+                    .try_for_each(|(addr, val)| set_memory_u8(u16::try_from(addr).unwrap(), val))
+                {
+                    dprintln!("could not copy memory: {e}");
+                }
+            }
             // Push to stack
             "push" => {
                 debug_state.last_debug_command.clear();
                 debug_state.last_debug_command.push_str(line.trim());
                 if args.len() != 2 {
-                    println!("invalid usage of command {}", args[0]);
+                    dprintln!("invalid usage of command {}", args[0]);
                     continue;
                 }
-                let Some(addr) = str_to_num(args[1]) else {
+                let Some(addr) = str_to_addr(args[1]) else {
                     continue;
                 };
                 if addr & 0x0FFF != addr {
-                    println!(
+                    dprintln!(
                         "address {addr:#06X} is too large to push to stack (should be 12 bits)",
                     );
                     continue;
                 }
-                stack_push(u16::try_from(addr).unwrap());
-                debug_redraw(
-                    debug_state,
-                    instruction,
-                    instruction_raw,
-                    n_instructions_executed,
-                );
+                if let Err(e) = stack_push(u16::try_from(addr).unwrap()) {
+                    dprintln!("could not push: {e}");
+                    continue;
+                }
+                debug_redraw(debug_state, instruction, instruction_raw);
                 continue;
             }
             // Pop from stack
@@ -538,17 +1406,12 @@ pub fn debug_terminal(
                 debug_state.last_debug_command.push_str(line.trim());
                 match stack_pop() {
                     None => {
-                        println!("could not pop: stack was empty");
+                        dprintln!("could not pop: stack was empty");
                         continue;
                     }
                     Some(val) => {
-                        println!("{val:#06X}");
-                        debug_redraw(
-                            debug_state,
-                            instruction,
-                            instruction_raw,
-                            n_instructions_executed,
-                        );
+                        dprintln!("{val:#06X}");
+                        debug_redraw(debug_state, instruction, instruction_raw);
                         continue;
                     }
                 }
@@ -556,99 +1419,664 @@ pub fn debug_terminal(
             // Manage breakpoints
             "b" | "breakpoint" => {
                 // b 0x200: Set a breakpoint at 0x200
+                // b sound: Break when the sound timer is set
                 // b l | list: List breakpoints
                 // b r | remove 0x200: Delete the breakpoint at 0x200
+                // b r | remove sound: Stop breaking when the sound timer is set
+                // b key: Break the next time an instruction sees any key pressed
+                // b key A: Break the next time an instruction sees key A pressed
+                // b r | remove key: Stop waiting for a key
                 debug_state.last_debug_command.clear();
                 debug_state.last_debug_command.push_str(line.trim());
                 if args.len() < 2 {
-                    println!("invalid usage of command {}", args[0]);
+                    dprintln!("invalid usage of command {}", args[0]);
                     continue;
                 }
                 match args[1] {
                     // List breakpoints
                     "l" | "list" => {
                         if args.len() != 2 {
-                            println!("invalid usage of command {}", args[0]);
+                            dprintln!("invalid usage of command {}", args[0]);
                             continue;
                         }
-                        let mut breakpoints = debug_state.breakpoints.iter().collect::<Vec<_>>();
-                        breakpoints.sort();
-                        for b in breakpoints {
-                            println!("{b:#06X}");
+                        for b in get_breakpoints() {
+                            dprintln!("{b:#06X}");
+                        }
+                        if get_break_on_sound() {
+                            dprintln!("sound");
+                        }
+                        match get_key_break() {
+                            Some(KeyBreak::Any) => dprintln!("key"),
+                            Some(KeyBreak::Key(key)) => dprintln!("key {key:X}"),
+                            None => {}
                         }
                     }
                     // Delete a breakpoint
                     "r" | "remove" => {
                         if args.len() != 3 {
-                            println!("invalid usage of command {}", args[0]);
+                            dprintln!("invalid usage of command {}", args[0]);
                             continue;
                         }
-                        let Some(addr) = str_to_num(args[2]) else {
+                        if args[2] == "sound" {
+                            if !get_break_on_sound() {
+                                dprintln!("was not breaking when the sound timer is set");
+                            }
+                            set_break_on_sound(false);
+                            continue;
+                        }
+                        if args[2] == "key" {
+                            if get_key_break().is_none() {
+                                dprintln!("was not waiting for a key");
+                            }
+                            set_key_break(None);
+                            continue;
+                        }
+                        let Some(addr) = str_to_addr(args[2]) else {
                             continue;
                         };
                         if addr & 0x0FFF != addr {
                             // This address will never be in breakpoints
                             continue;
                         }
-                        if !debug_state
-                            .breakpoints
-                            .remove(&(u16::try_from(addr).unwrap()))
-                        {
-                            println!("address {addr:#06X} was not a breakpoint");
+                        if !remove_breakpoint(u16::try_from(addr).unwrap()) {
+                            dprintln!("address {addr:#06X} was not a breakpoint");
                         }
                         continue;
                     }
+                    // Break when FX18 starts a sound
+                    "sound" => {
+                        if args.len() != 2 {
+                            dprintln!("invalid usage of command {}", args[0]);
+                            continue;
+                        }
+                        if get_break_on_sound() {
+                            dprintln!("already breaking when the sound timer is set");
+                        }
+                        set_break_on_sound(true);
+                    }
+                    // Break when an instruction sees a key pressed
+                    "key" => {
+                        let key_break = match args.len() {
+                            2 => KeyBreak::Any,
+                            3 => {
+                                let Some(key) = str_to_key(args[2]) else {
+                                    continue;
+                                };
+                                KeyBreak::Key(key)
+                            }
+                            _ => {
+                                dprintln!("invalid usage of command {}", args[0]);
+                                continue;
+                            }
+                        };
+                        set_key_break(Some(key_break));
+                    }
                     // Add a new breakpoint
                     _ => {
                         if args.len() != 2 {
-                            println!("invalid usage of command {}", args[0]);
+                            dprintln!("invalid usage of command {}", args[0]);
                             continue;
                         }
-                        let Some(addr) = str_to_num(args[1]) else {
+                        let Some(addr) = str_to_addr(args[1]) else {
                             continue;
                         };
                         if addr & 0x0FFF != addr {
-                            println!("address {addr:#06X} is too large (should be 12 bits)");
+                            dprintln!("address {addr:#06X} is too large (should be 12 bits)");
                             continue;
                         }
-                        if !debug_state.breakpoints.insert(u16::try_from(addr).unwrap()) {
-                            println!("address {addr:#06X} was already a breakpoint");
+                        if !add_breakpoint(u16::try_from(addr).unwrap()) {
+                            dprintln!("address {addr:#06X} was already a breakpoint");
                         }
                         continue;
                     }
                 }
             }
-            "x" | "examine" => {
-                if args.len() != 3 {
-                    println!("invalid usage of command {}", args[0]);
+            // Print memory
+            // x 16 0x300: Print 16 bytes from 0x300 in hex
+            // x/dec 16 0x300: Print them in decimal
+            // x/ascii 16 0x300: Print them as text
+            // x/sprite 5 0x300: Draw them as a sprite
+            // x 8 i + 2: Print 8 bytes from I + 2
+            cmd if cmd
+                .split('/')
+                .next()
+                .is_some_and(|cmd| cmd == "x" || cmd == "examine") =>
+            {
+                let format = cmd.split_once('/').map_or("hex", |(_, format)| format);
+                let Some(format) = ExamineFormat::from_name(format) else {
+                    dprintln!("unknown format for command {cmd}: {format}");
+                    continue;
+                };
+                if args.len() < 3 {
+                    dprintln!("invalid usage of command {}", args[0]);
                     continue;
                 }
                 let Some(n) = str_to_num(args[1]) else {
                     continue;
                 };
-                let Some(addr) = str_to_num(args[2]) else {
+                let addr = match eval(&args[2..].join(" ")).map(usize::try_from) {
+                    Ok(Ok(addr)) => addr,
+                    Ok(Err(_)) => {
+                        dprintln!("address can't be negative");
+                        continue;
+                    }
+                    Err(e) => {
+                        dprintln!("{e}");
+                        continue;
+                    }
+                };
+                dprint!("{}", examine(format, n, addr));
+            }
+            // Show the sprite at I in the debug panel
+            // spr: As many rows as the last Draw
+            // spr 5: 5 rows
+            // spr off: Hide it
+            "spr" | "sprite" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                debug_state.sprite_view = match args.len() {
+                    1 => SpriteView::LastDraw,
+                    2 if args[1] == "off" => SpriteView::Hidden,
+                    2 => {
+                        let Some(rows) = str_to_num(args[1]) else {
+                            continue;
+                        };
+                        if !(1..=15).contains(&rows) {
+                            dprintln!("sprites are between 1 and 15 rows tall");
+                            continue;
+                        }
+                        SpriteView::Rows(u8::try_from(rows).unwrap())
+                    }
+                    _ => {
+                        dprintln!("invalid usage of command {}", args[0]);
+                        continue;
+                    }
+                };
+                debug_redraw(debug_state, instruction, instruction_raw);
+            }
+            // Manage aliases
+            // alias: List aliases
+            // alias dw "x 8 i": Make dw run x 8 i
+            "alias" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.len() {
+                    1 => {
+                        for (name, command) in get_aliases() {
+                            dprintln!("{name} = {command}");
+                        }
+                    }
+                    2 => dprintln!("usage: alias <name> <command>"),
+                    _ => {
+                        let command = args[2..].join(" ");
+                        let command = command
+                            .strip_prefix('"')
+                            .and_then(|command| command.strip_suffix('"'))
+                            .unwrap_or(&command);
+                        if let Err(e) = set_alias(args[1], command) {
+                            dprintln!("{e}");
+                        }
+                    }
+                }
+            }
+            "unalias" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() != 2 {
+                    dprintln!("usage: unalias <name>");
+                } else if !remove_alias(args[1]) {
+                    dprintln!("no alias named {}", args[1]);
+                }
+            }
+            // Manage macros
+            // define: List macros
+            // define step3 n; n; n; end: Make step3 run n three times
+            "define" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.len() {
+                    1 => {
+                        for (name, commands) in get_macros() {
+                            dprintln!("{name}: {}", commands.join("; "));
+                        }
+                    }
+                    2 => dprintln!("usage: define <name> <command>; <command>; ... end"),
+                    _ => {
+                        let mut commands = split_commands(&args[2..].join(" "));
+                        if commands.last().is_some_and(|command| command == "end") {
+                            commands.pop();
+                        }
+                        if let Err(e) = set_macro(args[1], commands) {
+                            dprintln!("{e}");
+                        }
+                    }
+                }
+            }
+            "undefine" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() != 2 {
+                    dprintln!("usage: undefine <name>");
+                } else if !remove_macro(args[1]) {
+                    dprintln!("no macro named {}", args[1]);
+                }
+            }
+            // List or change quirks
+            // q: List quirks
+            // q get_key_on_release off: Disable the get_key_on_release quirk
+            // q preset chip48: Use the CHIP-48 quirks
+            // q save: Save the quirks for this ROM
+            "q" | "quirks" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                let mut quirks = get_quirks();
+                match args.len() {
+                    1 => {
+                        for name in Quirks::NAMES {
+                            let val = if quirks.get(name).unwrap() {
+                                "on"
+                            } else {
+                                "off"
+                            };
+                            dprintln!("{name:<24}{val}");
+                        }
+                    }
+                    2 if args[1] == "save" => match save_rom_settings() {
+                        Ok(()) => dprintln!("saved settings to {}", get_rom_db_path()),
+                        Err(e) => dprintln!("could not save settings: {e}"),
+                    },
+                    3 if args[1] == "preset" => match QuirkPreset::from_str(args[2], true) {
+                        Ok(preset) => set_quirks(preset.quirks()),
+                        Err(_) => dprintln!("unknown quirk preset: {}", args[2]),
+                    },
+                    3 => {
+                        let val = match args[2] {
+                            "on" | "1" | "true" => true,
+                            "off" | "0" | "false" => false,
+                            _ => {
+                                dprintln!("invalid value for quirk {}: {}", args[1], args[2]);
+                                continue;
+                            }
+                        };
+                        if let Err(e) = quirks.set(args[1], val) {
+                            dprintln!("{e}");
+                            continue;
+                        }
+                        set_quirks(quirks);
+                    }
+                    _ => dprintln!("invalid usage of command {}", args[0]),
+                }
+            }
+            // Show which addresses have been executed, read and written
+            // coverage: Show the map for the whole program
+            // coverage 0x300 0x400: Show the map from 0x300 to 0x3FF
+            // coverage save out.cov: Save the map to out.cov
+            // coverage reset: Clear the map
+            "coverage" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.len() {
+                    1 => dprint!("{}", coverage_map(0x200, MEMORY_SIZE)),
+                    2 if args[1] == "reset" => reset_coverage(),
+                    2 | 3 if args[1] == "save" => {
+                        let Some(path) = args.get(2).copied().or(get_coverage_out_path()) else {
+                            dprintln!("invalid usage of command {}: no path given", args[0]);
+                            continue;
+                        };
+                        match save_coverage(path) {
+                            Ok(()) => dprintln!("saved coverage to {path}"),
+                            Err(e) => dprintln!("could not save coverage: {e}"),
+                        }
+                    }
+                    3 => {
+                        let Some(start) = str_to_addr(args[1]) else {
+                            continue;
+                        };
+                        let Some(end) = str_to_addr(args[2]) else {
+                            continue;
+                        };
+                        dprint!("{}", coverage_map(start, end.min(MEMORY_SIZE)));
+                    }
+                    _ => dprintln!("invalid usage of command {}", args[0]),
+                }
+            }
+            // Show how often each address has been accessed
+            // heatmap: Show every kind of access
+            // heatmap write: Show how often each address has been written
+            "heatmap" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                let kind = match args.get(1..) {
+                    Some([]) => HeatmapKind::All,
+                    Some(["exec"]) => HeatmapKind::Executed,
+                    Some(["read"]) => HeatmapKind::Read,
+                    Some(["write"]) => HeatmapKind::Written,
+                    _ => {
+                        dprintln!("invalid usage of command {}", args[0]);
+                        continue;
+                    }
+                };
+                dprint!("{}", heatmap(kind));
+            }
+            // Show the profile
+            // profile: Print the 10 hottest addresses and subroutines
+            // profile 20: Print the 20 hottest addresses and subroutines
+            // profile save out.txt: Save the profile to out.txt
+            // profile reset: Clear the profile
+            "profile" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.len() {
+                    1 => dprint!("{}", profile_report(10)),
+                    2 if args[1] == "reset" => reset_profile(),
+                    2 | 3 if args[1] == "save" => {
+                        let Some(path) = args.get(2).copied().or(get_profile_out_path()) else {
+                            dprintln!("invalid usage of command {}: no path given", args[0]);
+                            continue;
+                        };
+                        match save_profile(path, PROFILE_OUT_ENTRIES) {
+                            Ok(()) => dprintln!("saved profile to {path}"),
+                            Err(e) => dprintln!("could not save profile: {e}"),
+                        }
+                    }
+                    2 => {
+                        let Some(n) = str_to_num(args[1]) else {
+                            continue;
+                        };
+                        dprint!("{}", profile_report(n));
+                    }
+                    _ => dprintln!("invalid usage of command {}", args[0]),
+                }
+            }
+            // Show how many instructions of each category have been executed
+            // stats
+            "stats" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() != 1 {
+                    dprintln!("invalid usage of command {}", args[0]);
                     continue;
+                }
+                dprint!("{}", instruction_histogram());
+            }
+            // Start or stop recording the display
+            // record: Stop recording if we are recording, otherwise start recording
+            // record out.gif: Start recording to out.gif
+            "record" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.len() {
+                    1 if is_recording() => match stop_recording() {
+                        Ok(path) => dprintln!("saved recording to {path}"),
+                        Err(e) => dprintln!("could not save recording: {e}"),
+                    },
+                    1 | 2 => {
+                        let path = args.get(1).map_or_else(
+                            || timestamped_path("recording", "gif"),
+                            |s| String::from(*s),
+                        );
+                        match start_recording(&path) {
+                            Ok(()) => dprintln!("recording to {path}"),
+                            Err(e) => dprintln!("could not start recording: {e}"),
+                        }
+                    }
+                    _ => dprintln!("invalid usage of command {}", args[0]),
+                }
+            }
+            // Save the display to an image file
+            // screenshot out.png 4: Save the display to out.png, scaled up 4 times
+            "screenshot" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                if args.len() != 2 && args.len() != 3 {
+                    dprintln!("invalid usage of command {}", args[0]);
+                    continue;
+                }
+                let scale = if args.len() == 3 {
+                    let Some(scale) = str_to_num(args[2]) else {
+                        continue;
+                    };
+                    scale
+                } else {
+                    DEFAULT_SCREENSHOT_SCALE
                 };
-
-                // Print memory at 8 bytes per line
-                for i in 0..n {
-                    if i % 8 == 0 {
-                        if i != 0 {
-                            println!();
+                match save_screenshot(args[1], scale) {
+                    Ok(()) => dprintln!("saved screenshot to {}", args[1]),
+                    Err(e) => dprintln!("could not save screenshot: {e}"),
+                }
+            }
+            // Log expressions whenever an address is executed
+            // trace: List the tracepoints
+            // trace 0x230 v3, [i]: Log V3 and the byte at I whenever 0x230 is executed
+            // trace remove 0x230: Remove the tracepoint at 0x230
+            // trace log 20: Print the last 20 lines of the log
+            // trace clear: Clear the log
+            // trace file trace.txt: Also append the log to trace.txt
+            "trace" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.get(1).copied() {
+                    None => {
+                        let tracepoints = get_tracepoints();
+                        if tracepoints.is_empty() {
+                            dprintln!("no tracepoints set");
+                        }
+                        for tracepoint in tracepoints {
+                            dprintln!(
+                                "{}  {}",
+                                addr_label(tracepoint.addr),
+                                tracepoint.exprs.join(", ")
+                            );
+                        }
+                        if let Some(path) = get_trace_file_path() {
+                            dprintln!("logging to {path}");
                         }
-                        print!("{:#06X}:  ", addr + i);
                     }
-                    print!("{:#04X} ", get_memory_u8(u16::try_from(addr + i).unwrap()));
+                    Some("remove") if args.len() == 3 => {
+                        let Some(addr) = str_to_addr(args[2]) else {
+                            continue;
+                        };
+                        if u16::try_from(addr).map_or(true, |addr| !remove_tracepoint(addr)) {
+                            dprintln!("no tracepoint at {addr:#06X}");
+                        }
+                    }
+                    Some("log") if args.len() <= 3 => {
+                        let n = if args.len() == 3 {
+                            let Some(n) = str_to_num(args[2]) else {
+                                continue;
+                            };
+                            n
+                        } else {
+                            20
+                        };
+                        let log = get_trace_log();
+                        for line in &log[log.len().saturating_sub(n)..] {
+                            dprintln!("{line}");
+                        }
+                    }
+                    Some("clear") if args.len() == 2 => clear_trace_log(),
+                    Some("file") if args.len() == 3 => {
+                        let path = Some(args[2]).filter(|path| *path != "off");
+                        if let Err(e) = set_trace_file(path) {
+                            dprintln!("{e}");
+                        }
+                    }
+                    Some(_) if args.len() >= 3 => {
+                        let Some(addr) = str_to_addr(args[1]) else {
+                            continue;
+                        };
+                        if addr & 0x0FFF != addr {
+                            dprintln!("address {addr:#06X} is too large (should be 12 bits)");
+                            continue;
+                        }
+                        let exprs = args[2..]
+                            .join(" ")
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|expr| !expr.is_empty())
+                            .map(String::from)
+                            .collect();
+                        add_tracepoint(Tracepoint {
+                            addr: u16::try_from(addr).unwrap(),
+                            exprs,
+                        });
+                    }
+                    _ => dprintln!("invalid usage of command {}", args[0]),
+                }
+            }
+            // Press or release a key on the keypad
+            // key: List the keys that are pressed from the debug terminal
+            // key press 5: Hold down key 5
+            // key release 5: Release key 5
+            // key tap 5: Press key 5 for the rest of this frame and the next one
+            "key" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.as_slice() {
+                    [_] => {
+                        let keypad = get_injected_keypad();
+                        if keypad == 0 {
+                            dprintln!("no keys are pressed");
+                        }
+                        for key in (0..16).filter(|key| keypad & (1 << key) != 0) {
+                            dprintln!("{key:X}");
+                        }
+                    }
+                    [_, action @ ("press" | "release" | "tap"), key] => {
+                        let Some(key) = str_to_key(key) else {
+                            continue;
+                        };
+                        match *action {
+                            "press" => press_key(key),
+                            "release" => release_key(key),
+                            _ => tap_key(key),
+                        }
+                    }
+                    _ => dprintln!("invalid usage of command {}", args[0]),
+                }
+            }
+            // Watch a register
+            // watch: List the watched registers
+            // watch v5: Break when V5 changes
+            // watch remove v5: Stop watching V5
+            "watch" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.len() {
+                    1 => {
+                        let watches = get_register_watches();
+                        if watches.is_empty() {
+                            dprintln!("no registers are watched");
+                        }
+                        for reg in watches {
+                            dprintln!("{reg}");
+                        }
+                    }
+                    2 => {
+                        let Some(reg) = str_to_register(args[1]) else {
+                            continue;
+                        };
+                        if !add_register_watch(reg) {
+                            dprintln!("{reg} is already watched");
+                        }
+                    }
+                    3 if args[1] == "remove" => {
+                        let Some(reg) = str_to_register(args[2]) else {
+                            continue;
+                        };
+                        if !remove_register_watch(reg) {
+                            dprintln!("{reg} isn't watched");
+                        }
+                    }
+                    _ => dprintln!("invalid usage of command {}", args[0]),
+                }
+            }
+            // Watch a range of memory
+            // watchmem 0x300 8: Log writes to 0x300-0x307
+            // watchmem 0x300 8 break: Log writes to 0x300-0x307, and break when they change
+            // watchmem list: List the watched ranges
+            // watchmem remove 0x300: Stop watching the range that starts at 0x300
+            // watchmem log 20: Print the last 20 writes
+            // watchmem clear: Clear the log
+            "watchmem" => {
+                debug_state.last_debug_command.clear();
+                debug_state.last_debug_command.push_str(line.trim());
+                match args.get(1).copied() {
+                    Some("list") if args.len() == 2 => {
+                        for watch in get_watches() {
+                            dprintln!(
+                                "{:#06X}-{:#06X}{}",
+                                watch.start,
+                                watch.start + watch.len - 1,
+                                if watch.break_on_change {
+                                    " (break)"
+                                } else {
+                                    ""
+                                }
+                            );
+                        }
+                    }
+                    Some("remove") if args.len() == 3 => {
+                        let Some(addr) = str_to_addr(args[2]) else {
+                            continue;
+                        };
+                        if u16::try_from(addr).map_or(true, |addr| !remove_watch(addr)) {
+                            dprintln!("no watch starts at {addr:#06X}");
+                        }
+                    }
+                    Some("log") if args.len() <= 3 => {
+                        let n = if args.len() == 3 {
+                            let Some(n) = str_to_num(args[2]) else {
+                                continue;
+                            };
+                            n
+                        } else {
+                            20
+                        };
+                        let log = get_write_log();
+                        for write in &log[log.len().saturating_sub(n)..] {
+                            dprintln!(
+                                "#{:<10} {:#06X}: {:#04X} -> {:#04X}  by {:#06X}  {}",
+                                write.instruction_count,
+                                write.addr,
+                                write.old,
+                                write.new,
+                                write.pc,
+                                write.instruction
+                            );
+                        }
+                    }
+                    Some("clear") if args.len() == 2 => clear_write_log(),
+                    Some(_) if args.len() == 3 || (args.len() == 4 && args[3] == "break") => {
+                        let Some(addr) = str_to_addr(args[1]) else {
+                            continue;
+                        };
+                        let Some(len) = str_to_num(args[2]) else {
+                            continue;
+                        };
+                        if addr & 0x0FFF != addr {
+                            dprintln!("address {addr:#06X} is too large (should be 12 bits)");
+                            continue;
+                        }
+                        if len == 0 || len > MEMORY_SIZE {
+                            dprintln!("length must be between 1 and {MEMORY_SIZE}");
+                            continue;
+                        }
+                        add_watch(MemoryWatch {
+                            start: u16::try_from(addr).unwrap(),
+                            len: u16::try_from(len).unwrap(),
+                            break_on_change: args.len() == 4,
+                        });
+                    }
+                    _ => dprintln!("invalid usage of command {}", args[0]),
                 }
-                println!();
-                continue;
             }
             // Key press
             // Key release
             // Unknown instruction or blank line
             _ => {
                 if line.trim() != "" {
-                    println!("Unknown command: {}", line.trim());
+                    dprintln!("Unknown command: {}", line.trim());
                 }
             }
         };
@@ -657,24 +2085,24 @@ pub fn debug_terminal(
 
 /// Redraw the screen in debug mode.
 /// This is not a full redraw, and it should only be used when things like registers are changed in debug mode but we don't want to advance another instruction.
-fn debug_redraw(
-    debug_state: &mut DebugState,
-    instruction: Instruction,
-    instruction_raw: u16,
-    n_instructions_executed: &mut u128,
-) {
-    for _ in 0..DISPLAY_HEIGHT + 5 {
+/// Make room below the display for the next frame, so that it doesn't overwrite the output of the debug terminal.
+/// When the debug terminal is served over TCP, nothing is printed below the display, so the next debug frame
+/// is drawn over the last one instead.
+fn make_room_for_frame(is_debug: bool) {
+    if is_debug && is_debug_server() {
+        return;
+    }
+    for _ in 0..frame_height(is_debug) {
         println!();
     }
+}
+
+fn debug_redraw(debug_state: &mut DebugState, instruction: Instruction, instruction_raw: u16) {
+    make_room_for_frame(true);
     debug_state.info_lines.clear();
-    print_debug(
-        n_instructions_executed,
-        instruction,
-        instruction_raw,
-        debug_state,
-    );
+    print_debug(instruction, instruction_raw, debug_state);
     draw(
-        *n_instructions_executed,
+        false,
         true,
         &debug_state.old_display_state,
         &mut debug_state.info_lines,
@@ -682,32 +2110,302 @@ fn debug_redraw(
     io::stdout().flush().unwrap();
 }
 
-/// Try to convert the given string to a number.
-/// Supports hex (0x123), binary (0b111), and base 10 (123).
-fn str_to_num(addr: &str) -> Option<usize> {
-    if addr.contains("0x") {
-        match usize::from_str_radix(&addr[2..], 16) {
-            Ok(val) => Some(val),
-            Err(e) => {
-                println!("could not parse hex value {addr}: {e}");
-                None
-            }
+/// Redraw the debug info after the machine was put back into an earlier state, showing the instructions in the
+/// history as the last ones that were executed. Returns the instruction shown as the current one, raw and
+/// decoded.
+fn redraw_from_history(debug_state: &mut DebugState) -> (u16, Instruction) {
+    let pc = get_pc();
+    let decode_entry = |addr: u16| {
+        let raw = get_memory_u16(addr).unwrap_or(0);
+        (
+            addr,
+            raw,
+            decode(raw, get_instruction_set()).unwrap_or(Instruction::Db(raw)),
+        )
+    };
+    debug_state.last_instructions = recent_history_addrs(3)
+        .into_iter()
+        .map(decode_entry)
+        .collect();
+    let current = debug_state
+        .last_instructions
+        .pop_front()
+        .unwrap_or_else(|| decode_entry(pc));
+    // The debug info expects the PC to be past the current instruction, as it is after fetching it
+    let _ = set_pc(current.0 + 2);
+    debug_redraw(debug_state, current.2, current.1);
+    debug_state.last_instructions.push_front(current);
+    set_pc(pc).unwrap();
+    (current.1, current.2)
+}
+
+/// The number of instructions shown by `list`.
+const LIST_LENGTH: u16 = 16;
+
+/// The number of instructions shown before the PC by `list`.
+const LIST_CONTEXT: u16 = 5;
+
+/// Returns the disassembly of `count` instructions starting at `start`, one per line.
+/// Breakpoints are marked, and the instruction at the PC is highlighted.
+fn disassembly_listing(start: u16, count: u16) -> String {
+    let pc = get_pc();
+    let memory = get_full_memory();
+    let mut out = String::new();
+    let mut listed = 0;
+    let rest = memory.get(usize::from(start)..).unwrap_or_default();
+    for (addr, raw, instruction) in
+        iter(rest, start, get_instruction_set()).take(usize::from(count))
+    {
+        // An odd byte at the end of memory isn't an instruction
+        let [high, low, ..] = *raw else {
+            break;
+        };
+        listed += 1;
+        if let Some(name) = symbol_name(addr) {
+            writeln!(out, "{name}:").unwrap();
         }
-    } else if addr.contains("0b") {
-        match usize::from_str_radix(&addr[2..], 2) {
-            Ok(val) => Some(val),
-            Err(e) => {
-                println!("could not parse binary value {addr}: {e}");
-                None
+        let instruction_raw = raw_hex(raw);
+        let instruction_or_data =
+            instruction.unwrap_or(Instruction::Db(u16::from_be_bytes([high, low])));
+        // The line at the PC is highlighted as a whole instead
+        let mut asm = if addr == pc {
+            instruction_or_data.to_string()
+        } else {
+            let color = category_color(instruction_or_data.category());
+            format!("{color}{instruction_or_data}\x1b[0m")
+        };
+        // Name the address the instruction refers to, if it has a symbol, and the source line it came from
+        let mut comments = Vec::new();
+        if let Some(name) = instruction
+            .and_then(|ins| ins.target_addr())
+            .and_then(symbol_name)
+        {
+            comments.push(name);
+        }
+        if let Some(location) = source_line(addr) {
+            comments.push(location.to_string());
+        }
+        if !comments.is_empty() {
+            write!(asm, "  ; {}", comments.join(", ")).unwrap();
+        }
+        let breakpoint = if is_breakpoint(addr) {
+            "\x1b[31m*\x1b[0m"
+        } else {
+            " "
+        };
+        if addr == pc {
+            writeln!(
+                out,
+                "{breakpoint} \x1b[1;32m-> {addr:#06X}  {instruction_raw}  {asm}\x1b[0m"
+            )
+            .unwrap();
+        } else {
+            writeln!(out, "{breakpoint}    {addr:#06X}  {instruction_raw}  {asm}").unwrap();
+        }
+    }
+    if listed < count {
+        out.push_str("(end of memory)\n");
+    }
+    out
+}
+
+/// Returns the escape code that instructions of the given category are coloured with in `list`.
+fn category_color(category: Category) -> &'static str {
+    match category {
+        Category::ControlFlow => "\x1b[33m",
+        Category::Alu => "\x1b[39m",
+        Category::Memory => "\x1b[36m",
+        Category::Display => "\x1b[35m",
+        Category::Input => "\x1b[34m",
+        Category::Timer => "\x1b[32m",
+        Category::Data => "\x1b[2m",
+    }
+}
+
+/// Returns the bytes of an instruction in hex, e.g. `00E0`.
+pub fn raw_hex(raw: &[u8]) -> String {
+    raw.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02X}").unwrap();
+        hex
+    })
+}
+
+/// How `examine` prints memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExamineFormat {
+    Hex,
+    Dec,
+    Ascii,
+    Sprite,
+}
+
+impl ExamineFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hex" => Some(Self::Hex),
+            "dec" => Some(Self::Dec),
+            "ascii" => Some(Self::Ascii),
+            "sprite" => Some(Self::Sprite),
+            _ => None,
+        }
+    }
+
+    /// The number of bytes printed on each line.
+    fn bytes_per_line(self) -> usize {
+        match self {
+            Self::Hex | Self::Dec => 8,
+            Self::Ascii => 16,
+            Self::Sprite => 1,
+        }
+    }
+}
+
+/// Returns `n` bytes of memory from `addr` in the given format, with the address at the start of each line.
+fn examine(format: ExamineFormat, n: usize, addr: usize) -> String {
+    let mut out = String::new();
+    for i in 0..n {
+        if i % format.bytes_per_line() == 0 {
+            if i != 0 {
+                out.push('\n');
             }
+            write!(out, "{:#06X}:  ", addr + i).unwrap();
         }
-    } else {
-        match addr.parse::<usize>() {
-            Ok(val) => Some(val),
-            Err(e) => {
-                println!("could not parse base 10 value {addr}: {e}");
-                None
+        let Some(Ok(val)) = u16::try_from(addr + i).ok().map(get_memory_u8) else {
+            out.push_str(match format {
+                ExamineFormat::Hex => "??   ",
+                ExamineFormat::Dec => "  ? ",
+                ExamineFormat::Ascii => "?",
+                ExamineFormat::Sprite => "??",
+            });
+            continue;
+        };
+        match format {
+            ExamineFormat::Hex => write!(out, "{val:#04X} ").unwrap(),
+            ExamineFormat::Dec => write!(out, "{val:>3} ").unwrap(),
+            ExamineFormat::Ascii => out.push(if val.is_ascii_graphic() || val == b' ' {
+                char::from(val)
+            } else {
+                '.'
+            }),
+            ExamineFormat::Sprite => {
+                write!(out, "{val:#04X}  ").unwrap();
+                // Each pixel is 2 characters wide, so that the sprite isn't squashed
+                for bit in (0..8).rev() {
+                    out.push_str(if val & (1 << bit) == 0 {
+                        "··"
+                    } else {
+                        "██"
+                    });
+                }
             }
         }
     }
+    out.push('\n');
+    out
+}
+
+/// Returns the call stack, innermost first: the PC, then the call site and return address of each subroutine that
+/// hasn't returned yet. Addresses are shown relative to the nearest symbol, if there is one.
+fn backtrace() -> String {
+    let location =
+        |addr: u16| symbolize(addr).map_or_else(String::new, |name| format!("  in {name}"));
+    let pc = get_pc();
+    let mut out = String::new();
+    writeln!(out, "#0  {pc:#06X}  (PC){}", location(pc)).unwrap();
+    for (i, return_addr) in get_stack().into_iter().rev().enumerate() {
+        let call_site = return_addr.wrapping_sub(2);
+        let asm = match get_memory_u16(call_site)
+            .ok()
+            .and_then(|ins| decode(ins, get_instruction_set()))
+        {
+            Some(instruction @ Instruction::SubroutineCall(nnn)) => match symbol_name(nnn) {
+                Some(name) => format!("{instruction} <{name}>"),
+                None => instruction.to_string(),
+            },
+            Some(instruction) => instruction.to_string(),
+            None => String::from("??"),
+        };
+        writeln!(
+            out,
+            "#{}  {call_site:#06X}  {asm:<28}returns to {return_addr:#06X}{}",
+            i + 1,
+            location(call_site)
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Try to convert the given string to an address, printing an error if it fails.
+/// Supports the name of a symbol, as well as the number formats that `str_to_num` supports.
+fn str_to_addr(addr: &str) -> Option<usize> {
+    symbol_addr(addr).map_or_else(|| str_to_num(addr), |addr| Some(usize::from(addr)))
+}
+
+/// Try to convert the given string to a general-purpose register (V0-VF), printing an error if it fails.
+fn str_to_register(reg: &str) -> Option<Register> {
+    let index = reg
+        .strip_prefix(['v', 'V'])
+        .filter(|index| index.len() == 1)
+        .and_then(|index| u8::from_str_radix(index, 16).ok())
+        .and_then(|index| Register::try_from(index).ok());
+    if index.is_none() {
+        dprintln!("invalid register: {reg} (should be V0-VF)");
+    }
+    index
+}
+
+/// Try to convert the given string to a key on the keypad (0-F), printing an error if it fails.
+fn str_to_key(key: &str) -> Option<u8> {
+    let index = u8::from_str_radix(key.strip_prefix("0x").unwrap_or(key), 16)
+        .ok()
+        .filter(|index| *index < 16);
+    if index.is_none() {
+        dprintln!("invalid key: {key} (should be 0-F)");
+    }
+    index
+}
+
+/// Try to convert the given string to a number, printing an error if it fails.
+/// Supports hex (0x123), binary (0b111), and base 10 (123).
+fn str_to_num(addr: &str) -> Option<usize> {
+    match parse_num(addr) {
+        Ok(val) => Some(val),
+        Err(e) => {
+            dprintln!("{e}");
+            None
+        }
+    }
+}
+
+/// Parse an address: the name of a symbol, or a number in any of the formats that `parse_num` supports.
+///
+/// # Errors
+/// Returns an error if the address could not be parsed, or is more than 12 bits.
+pub fn parse_addr(addr: &str) -> Result<u16, String> {
+    if let Some(addr) = symbol_addr(addr) {
+        return Ok(addr);
+    }
+    let addr = parse_num(addr)?;
+    u16::try_from(addr)
+        .ok()
+        .filter(|addr| addr & 0x0FFF == *addr)
+        .ok_or_else(|| format!("address {addr:#06X} is too large (should be 12 bits)"))
+}
+
+/// Parse a number in base 10, hex (`0x123`) or binary (`0b101`).
+///
+/// # Errors
+/// Returns an error if the number could not be parsed.
+pub fn parse_num(addr: &str) -> Result<usize, String> {
+    if addr.contains("0x") {
+        usize::from_str_radix(&addr[2..], 16)
+            .map_err(|e| format!("could not parse hex value {addr}: {e}"))
+    } else if addr.contains("0b") {
+        usize::from_str_radix(&addr[2..], 2)
+            .map_err(|e| format!("could not parse binary value {addr}: {e}"))
+    } else {
+        addr.parse::<usize>()
+            .map_err(|e| format!("could not parse base 10 value {addr}: {e}"))
+    }
 }
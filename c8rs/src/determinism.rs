@@ -0,0 +1,498 @@
+use std::{
+    fmt::{self, Display},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use c8util::{decode::decode, instructions::Instruction};
+
+use crate::{
+    execute::{execute, get_instruction_set},
+    hooks::{
+        InstructionEvent, Outcome, notify_before_instruction, notify_instruction, with_observers,
+    },
+    movie::movie_frame,
+    quirks::{Quirks, get_quirks, set_quirks},
+    run::{cancel_exit, fetch, get_exit_code},
+    snapshot::Snapshot,
+    system::{MEMORY_SIZE, get_pc},
+    timing::{end_instruction, frame_tick, get_frame_count},
+};
+
+/// The number of frames that `--verify-determinism` and `--compare-quirks` run for, unless `--verify-frames` is given.
+pub const DEFAULT_VERIFY_FRAMES: u64 = 600;
+
+/// The parts of the machine's state that are hashed separately, so that a divergence shows what differed.
+const PARTS: [&str; 8] = [
+    "memory",
+    "display",
+    "registers, I, PC and stack",
+    "timers",
+    "audio",
+    "key wait",
+    "RNG",
+    "user flags",
+];
+
+/// The state of the machine at the end of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameHash {
+    /// A hash of the state on this frame and every frame before it.
+    rolling: u64,
+    /// A hash of each of the `PARTS` on this frame.
+    parts: [u64; PARTS.len()],
+    /// The number of instructions executed by the end of the frame.
+    instructions: u128,
+}
+
+/// How a run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Run {
+    frames: Vec<FrameHash>,
+    /// Why the run stopped before the last frame, if it did.
+    stopped_early: Option<String>,
+}
+
+/// Where two runs of the same ROM first differed.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// The frame that the state differed at the end of, counting from 0.
+    pub frame: usize,
+    /// The number of instructions that each run had executed by the end of the frame, if it got that far.
+    pub instructions: (Option<u128>, Option<u128>),
+    /// The parts of the state that differed.
+    pub parts: Vec<&'static str>,
+    /// Why each run stopped early, if it did.
+    pub stopped_early: (Option<String>, Option<String>),
+}
+
+/// The result of running the ROM twice and comparing the state on every frame.
+#[derive(Debug, Clone)]
+pub struct DeterminismReport {
+    /// The number of frames that were compared.
+    pub frames: usize,
+    /// Where the runs first differed, if they did.
+    pub divergence: Option<Divergence>,
+    /// Why both runs stopped before the last frame, if they did and didn't differ.
+    pub stopped_early: Option<String>,
+}
+
+impl Display for DeterminismReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(divergence) = &self.divergence else {
+            write!(
+                f,
+                "Deterministic: both runs matched on all {} frames",
+                self.frames
+            )?;
+            if let Some(reason) = &self.stopped_early {
+                write!(f, "\nBoth runs stopped early: {reason}")?;
+            }
+            return Ok(());
+        };
+        writeln!(
+            f,
+            "Not deterministic: the runs first differ on frame {}",
+            divergence.frame
+        )?;
+        for (run, instructions, stopped_early) in [
+            (1, divergence.instructions.0, &divergence.stopped_early.0),
+            (2, divergence.instructions.1, &divergence.stopped_early.1),
+        ] {
+            match (instructions, stopped_early) {
+                (Some(instructions), _) => {
+                    writeln!(f, "Run {run}: {instructions} instructions executed")?;
+                }
+                (None, Some(reason)) => writeln!(f, "Run {run}: stopped early: {reason}")?,
+                (None, None) => writeln!(f, "Run {run}: did not reach the frame")?,
+            }
+        }
+        write!(f, "Differences: {}", divergence.parts.join(", "))
+    }
+}
+
+/// Returns a hash of each part of the state in the snapshot, in the order of `PARTS`.
+fn hash_parts(snapshot: &Snapshot) -> [u64; PARTS.len()] {
+    let hash = |write: &dyn Fn(&mut DefaultHasher)| {
+        let mut hasher = DefaultHasher::new();
+        write(&mut hasher);
+        hasher.finish()
+    };
+    [
+        hash(&|h| snapshot.memory.hash(h)),
+        hash(&|h| (snapshot.display, snapshot.phosphor).hash(h)),
+        hash(&|h| (snapshot.registers, snapshot.i, snapshot.pc, &snapshot.stack).hash(h)),
+        hash(&|h| {
+            (
+                snapshot.delay_timer,
+                snapshot.sound_timer,
+                snapshot.vblank,
+                snapshot.frame_count,
+                snapshot.frame_elapsed_cycles,
+            )
+                .hash(h);
+        }),
+        hash(&|h| (snapshot.audio_pattern, snapshot.pitch).hash(h)),
+        hash(&|h| snapshot.key_wait.hash(h)),
+        hash(&|h| snapshot.rng_state.hash(h)),
+        hash(&|h| snapshot.user_flags.hash(h)),
+    ]
+}
+
+/// Returns the input for the current frame: the keys from the movie that's being played back, if any, and otherwise
+/// no keys.
+fn frame_input() -> u16 {
+    movie_frame(usize::try_from(get_frame_count()).unwrap()).unwrap_or(0)
+}
+
+/// Execute the instruction at the PC, and tick the timers if it ended the frame. `n_instructions_executed` doesn't
+/// include the instruction.
+/// Returns the address and the instruction, and whether a new frame started, or why execution can't continue (the
+/// instruction failed or the program exited).
+fn step(
+    n_instructions_executed: u128,
+    keypad: u16,
+    last_keypad: u16,
+) -> (u16, Instruction, Result<bool, String>) {
+    let instruction_addr = get_pc();
+    let fetched = fetch();
+    let instruction_raw = *fetched.as_ref().unwrap_or(&0);
+    let instruction =
+        decode(instruction_raw, get_instruction_set()).unwrap_or(Instruction::Db(instruction_raw));
+    notify_before_instruction(instruction_addr, instruction, n_instructions_executed);
+    let result =
+        fetched.and_then(|_| execute(instruction, get_instruction_set(), keypad, last_keypad));
+    notify_instruction(&InstructionEvent {
+        addr: instruction_addr,
+        instruction,
+        instruction_count: n_instructions_executed,
+        outcome: Outcome::of(&result, instruction_addr, instruction),
+    });
+    if let Err(e) = result {
+        return (
+            instruction_addr,
+            instruction,
+            Err(format!(
+                "instruction #{n_instructions_executed} at {instruction_addr:#06X} failed: {e}"
+            )),
+        );
+    }
+    if get_exit_code().is_some() {
+        cancel_exit();
+        return (
+            instruction_addr,
+            instruction,
+            Err(format!(
+                "the program exited at instruction #{n_instructions_executed} ({instruction_addr:#06X})"
+            )),
+        );
+    }
+    let is_new_frame = end_instruction(n_instructions_executed, instruction);
+    if is_new_frame {
+        frame_tick();
+    }
+    (instruction_addr, instruction, Ok(is_new_frame))
+}
+
+/// Run the machine from its current state for the given number of frames without drawing, hashing the state at
+/// the end of each frame. The input on each frame is taken from the movie that's being played back, if any, and
+/// otherwise no keys are pressed. Stops early if an instruction fails or the program exits.
+fn run_frames(frames: u64) -> Run {
+    let mut frame_hashes = Vec::new();
+    let mut rolling = 0;
+    let mut n_instructions_executed = 0u128;
+    let mut keypad = frame_input();
+    let mut last_keypad = 0;
+    let mut stopped_early = None;
+    while (frame_hashes.len() as u64) < frames {
+        let is_new_frame = match step(n_instructions_executed, keypad, last_keypad).2 {
+            Ok(is_new_frame) => is_new_frame,
+            Err(e) => {
+                stopped_early = Some(e);
+                break;
+            }
+        };
+        last_keypad = keypad;
+        n_instructions_executed += 1;
+        if is_new_frame {
+            keypad = frame_input();
+
+            let parts = hash_parts(&Snapshot::take(n_instructions_executed));
+            let mut hasher = DefaultHasher::new();
+            (rolling, parts, n_instructions_executed).hash(&mut hasher);
+            rolling = hasher.finish();
+            frame_hashes.push(FrameHash {
+                rolling,
+                parts,
+                instructions: n_instructions_executed,
+            });
+        }
+    }
+    Run {
+        frames: frame_hashes,
+        stopped_early,
+    }
+}
+
+/// Run the loaded ROM twice from the same state, with the same seed and input, and compare the state of the machine
+/// at the end of every frame. This shows whether anything that isn't saved in snapshots (e.g. the wall clock)
+/// affects execution, which would break replays, movies and stepping back.
+/// The machine is left in the state it was in at the end of the second run.
+pub fn verify_determinism(frames: u64) -> DeterminismReport {
+    let start = Snapshot::take(0);
+    // The runs are rewound and repeated, so nothing observes them (they'd be profiled and traced twice)
+    let (first, second) = with_observers(Vec::new(), || {
+        let first = run_frames(frames);
+        start.restore();
+        (first, run_frames(frames))
+    });
+
+    let n_frames = first.frames.len().max(second.frames.len());
+    let divergence = (0..n_frames).find_map(|frame| {
+        let (a, b) = (first.frames.get(frame), second.frames.get(frame));
+        if a.map(|hash| hash.rolling) == b.map(|hash| hash.rolling) {
+            return None;
+        }
+        let parts = match (a, b) {
+            (Some(a), Some(b)) if a.parts == b.parts => vec!["the number of instructions executed"],
+            (Some(a), Some(b)) => PARTS
+                .iter()
+                .zip(a.parts.iter().zip(b.parts))
+                .filter(|(_, (a, b))| **a != *b)
+                .map(|(name, _)| *name)
+                .collect(),
+            _ => vec!["one run stopped early"],
+        };
+        Some(Divergence {
+            frame,
+            instructions: (
+                a.map(|hash| hash.instructions),
+                b.map(|hash| hash.instructions),
+            ),
+            parts,
+            stopped_early: (first.stopped_early.clone(), second.stopped_early.clone()),
+        })
+    });
+    DeterminismReport {
+        frames: n_frames,
+        stopped_early: divergence
+            .is_none()
+            .then_some(first.stopped_early)
+            .flatten(),
+        divergence,
+    }
+}
+
+/// One of the machines run by `compare_quirks`, which take turns executing an instruction.
+struct Machine {
+    quirks: Quirks,
+    /// The state of the machine while the other one is executing.
+    state: Snapshot,
+    keypad: u16,
+    last_keypad: u16,
+    /// The number of frames that have ended.
+    frames: u64,
+}
+
+/// Where two machines with different quirks first differed.
+#[derive(Debug, Clone)]
+pub struct QuirkDivergence {
+    /// The number of instructions that had been executed before the one that made the machines differ.
+    pub instructions: u128,
+    /// The frame that the instruction was executed on.
+    pub frame: u64,
+    /// The address of the instruction.
+    pub addr: u16,
+    pub instruction: Instruction,
+    /// How the state of the machines differed after the instruction, e.g. `V0: 0x01 vs 0x02`.
+    pub differences: Vec<String>,
+}
+
+/// The result of running the ROM with two sets of quirks in lockstep.
+#[derive(Debug, Clone)]
+pub struct QuirkComparison {
+    /// The quirks of the first and second machine.
+    pub quirks: (Quirks, Quirks),
+    /// The number of frames that both machines ran for.
+    pub frames: u64,
+    /// Where the machines first differed, if they did.
+    pub divergence: Option<QuirkDivergence>,
+    /// Why both machines stopped before the last frame, if they did and didn't differ.
+    pub stopped_early: Option<String>,
+}
+
+impl Display for QuirkComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (machine, quirks) in [(1, self.quirks.0), (2, self.quirks.1)] {
+            write!(f, "Machine {machine}:")?;
+            for name in Quirks::NAMES {
+                let val = if quirks.get(name).unwrap() {
+                    "on"
+                } else {
+                    "off"
+                };
+                write!(f, " {name}={val}")?;
+            }
+            writeln!(f)?;
+        }
+        let Some(divergence) = &self.divergence else {
+            write!(
+                f,
+                "Same: both machines matched on all {} frames",
+                self.frames
+            )?;
+            if let Some(reason) = &self.stopped_early {
+                write!(f, "\nBoth machines stopped early: {reason}")?;
+            }
+            return Ok(());
+        };
+        writeln!(
+            f,
+            "Diverged at instruction #{} on frame {}: {} at {:#06X}",
+            divergence.instructions, divergence.frame, divergence.instruction, divergence.addr
+        )?;
+        write!(f, "Differences (machine 1 vs machine 2):")?;
+        for difference in &divergence.differences {
+            write!(f, "\n  {difference}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns how the state in two snapshots differs, as `<part>: <value in a> vs <value in b>` for each part.
+fn differences(a: &Snapshot, b: &Snapshot) -> Vec<String> {
+    let mut differences = Vec::new();
+    for (x, (val_a, val_b)) in a.registers.iter().zip(b.registers).enumerate() {
+        if *val_a != val_b {
+            differences.push(format!("V{x:X}: {val_a:#04X} vs {val_b:#04X}"));
+        }
+    }
+    if a.i != b.i {
+        differences.push(format!("I: {:#06X} vs {:#06X}", a.i, b.i));
+    }
+    if a.pc != b.pc {
+        differences.push(format!("PC: {:#06X} vs {:#06X}", a.pc, b.pc));
+    }
+    if a.stack != b.stack {
+        differences.push(format!("stack: {:#06X?} vs {:#06X?}", a.stack, b.stack));
+    }
+    if a.delay_timer != b.delay_timer {
+        differences.push(format!(
+            "delay timer: {} vs {}",
+            a.delay_timer, b.delay_timer
+        ));
+    }
+    if a.sound_timer != b.sound_timer {
+        differences.push(format!(
+            "sound timer: {} vs {}",
+            a.sound_timer, b.sound_timer
+        ));
+    }
+    let mut memory = (0..MEMORY_SIZE).filter(|&addr| a.memory[addr] != b.memory[addr]);
+    if let Some(first) = memory.next() {
+        differences.push(format!(
+            "memory: {} bytes, the first at {first:#06X} ({:#04X} vs {:#04X})",
+            memory.count() + 1,
+            a.memory[first],
+            b.memory[first]
+        ));
+    }
+    let pixels = a
+        .display
+        .iter()
+        .flatten()
+        .zip(b.display.iter().flatten())
+        .filter(|(pixel_a, pixel_b)| pixel_a != pixel_b)
+        .count();
+    if pixels > 0 {
+        differences.push(format!("display: {pixels} pixels"));
+    }
+    if a.key_wait != b.key_wait {
+        differences.push(format!("key wait: {:?} vs {:?}", a.key_wait, b.key_wait));
+    }
+    if a.rng_state != b.rng_state {
+        differences.push(String::from("RNG state"));
+    }
+    if (a.audio_pattern, a.pitch) != (b.audio_pattern, b.pitch) {
+        differences.push(String::from("audio pattern or pitch"));
+    }
+    if a.user_flags != b.user_flags {
+        differences.push(String::from("user flags"));
+    }
+    if (a.frame_count, a.frame_elapsed_cycles) != (b.frame_count, b.frame_elapsed_cycles) {
+        differences.push(format!("frame: {} vs {}", a.frame_count, b.frame_count));
+    }
+    differences
+}
+
+/// Run the loaded ROM on two machines in lockstep, one with the current quirks and one with `other`, from the same
+/// state with the same input, and compare their state after every instruction. This shows which instruction makes
+/// a ROM behave differently with different quirks.
+/// The machine is left in the state of the first machine, with the current quirks.
+pub fn compare_quirks(other: Quirks, frames: u64) -> QuirkComparison {
+    let quirks = get_quirks();
+    let start = Snapshot::take(0);
+    let keypad = frame_input();
+    let mut machines = [quirks, other].map(|quirks| Machine {
+        quirks,
+        state: start.clone(),
+        keypad,
+        last_keypad: 0,
+        frames: 0,
+    });
+    let mut n_instructions_executed = 0u128;
+    let mut divergence = None;
+    let mut stopped_early = None;
+    // Both machines are rewound over and over, so nothing observes them
+    with_observers(Vec::new(), || {
+        while machines[0].frames < frames {
+            let frame = machines[0].state.frame_count;
+            let [(addr, instruction, result_a), (_, _, result_b)] =
+                machines.each_mut().map(|machine| {
+                    machine.state.restore();
+                    set_quirks(machine.quirks);
+                    let (addr, instruction, result) =
+                        step(n_instructions_executed, machine.keypad, machine.last_keypad);
+                    machine.last_keypad = machine.keypad;
+                    if result == Ok(true) {
+                        machine.keypad = frame_input();
+                        machine.frames += 1;
+                    }
+                    machine.state = Snapshot::take(n_instructions_executed + 1);
+                    (addr, instruction, result)
+                });
+
+            let mut differences = differences(&machines[0].state, &machines[1].state);
+            if result_a != result_b {
+                for (machine, result) in [(1, &result_a), (2, &result_b)] {
+                    if let Err(e) = result {
+                        differences.push(format!("machine {machine} stopped: {e}"));
+                    }
+                }
+            }
+            if !differences.is_empty() {
+                divergence = Some(QuirkDivergence {
+                    instructions: n_instructions_executed,
+                    frame,
+                    addr,
+                    instruction,
+                    differences,
+                });
+                break;
+            }
+            if let Err(e) = result_a {
+                stopped_early = Some(e);
+                break;
+            }
+            n_instructions_executed += 1;
+        }
+    });
+
+    machines[0].state.restore();
+    set_quirks(quirks);
+    QuirkComparison {
+        quirks: (quirks, other),
+        frames: machines[0].frames,
+        divergence,
+        stopped_early,
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{
+    run::TerminalDisplay,
+    system::{DISPLAY_HEIGHT, DISPLAY_WIDTH},
+};
+
+/// Everything a `DisplayBackend` is given to show a frame.
+pub struct Frame<'a> {
+    /// The pixels of the display, by column and then row.
+    pub pixels: &'a [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    /// The pixels before the last instruction, so that the ones it changed can be highlighted while debugging.
+    pub old_pixels: &'a [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    /// Whether the debugger is open. Every instruction is drawn while debugging, instead of once per frame.
+    pub is_debug: bool,
+    /// The lines of the debugger's info panel (registers, stack, timers, ...). Empty unless debugging.
+    pub info_lines: &'a [String],
+}
+
+/// Shows the display.
+///
+/// The default is `TerminalDisplay`, which draws it in the terminal. An embedder can replace it with
+/// `set_display_backend` to draw it somewhere else (e.g. a window or a canvas) with the same emulation loop.
+pub trait DisplayBackend {
+    /// Called before the first frame, and whenever the resolution of the display changes.
+    fn set_resolution(&mut self, width: usize, height: usize);
+
+    /// Forget what was shown, so that the next frame is drawn in full (e.g. after something else was drawn over
+    /// it).
+    fn clear(&mut self);
+
+    /// Show a frame. Returns whether it was shown, e.g. the terminal isn't drawn in when it's too small.
+    fn blit(&mut self, frame: &Frame<'_>) -> bool;
+}
+
+/// The backend that frames are shown with. None until the first frame, when it becomes a `TerminalDisplay` (unless
+/// `set_display_backend` was called first).
+pub static mut DISPLAY_BACKEND: Option<Box<dyn DisplayBackend>> = None;
+
+/// Returns the display backend.
+pub fn display_backend() -> &'static mut dyn DisplayBackend {
+    // SAFETY: single threaded, and the reference isn't held across calls that could replace the backend
+    #[allow(static_mut_refs)]
+    unsafe {
+        DISPLAY_BACKEND
+            .get_or_insert_with(|| {
+                let mut backend = Box::new(TerminalDisplay);
+                backend.set_resolution(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+                backend
+            })
+            .as_mut()
+    }
+}
+
+/// Replace the display backend.
+pub fn set_display_backend(mut backend: Box<dyn DisplayBackend>) {
+    backend.set_resolution(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+    // SAFETY: single threaded
+    unsafe { DISPLAY_BACKEND = Some(backend) };
+}
@@ -0,0 +1,72 @@
+use std::fmt::{self, Display};
+
+use c8util::{decode::decode_verbose, instructions::Instruction};
+
+use crate::execute::get_instruction_set;
+
+/// Something the ROM did that the emulator can't carry out.
+/// These are reported in the debugger (or end the run in headless mode), rather than crashing the emulator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum C8Error {
+    /// An address outside of memory was read or written.
+    MemoryOutOfRange(u16),
+    /// The PC was set to an address outside of memory.
+    PcOutOfRange(u16),
+    /// I was set to an address outside of memory.
+    IndexOutOfRange(u16),
+    /// A pixel outside of the display was read or written.
+    PixelOutOfRange { x: u8, y: u8 },
+    /// The word at the given address isn't a valid instruction.
+    InvalidInstruction { addr: u16, instruction: u16 },
+    /// The instruction at the given address is a SUPER-CHIP or XO-CHIP instruction that isn't supported yet.
+    UnsupportedInstruction { addr: u16, instruction: Instruction },
+    /// A subroutine was called when the stack was already at its maximum depth.
+    StackOverflow(usize),
+    /// A subroutine returned when the stack was empty.
+    StackUnderflow,
+    /// A machine language routine (0NNN) failed, or there is no handler for it.
+    Syscall(String),
+}
+
+impl Display for C8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MemoryOutOfRange(addr) => {
+                write!(
+                    f,
+                    "memory access out of range: {addr:#06X} is outside of memory"
+                )
+            }
+            Self::PcOutOfRange(addr) => {
+                write!(f, "PC out of range: {addr:#06X} is outside of memory")
+            }
+            Self::IndexOutOfRange(addr) => {
+                write!(f, "I out of range: {addr:#06X} is outside of memory")
+            }
+            Self::PixelOutOfRange { x, y } => {
+                write!(f, "pixel ({x}, {y}) is outside of the display")
+            }
+            Self::InvalidInstruction { addr, instruction } => {
+                match decode_verbose(*instruction, get_instruction_set()) {
+                    Err(e) => write!(f, "invalid instruction at {addr:#06X}: {e}"),
+                    Ok(_) => write!(f, "invalid instruction at {addr:#06X}: {instruction:#06X}"),
+                }
+            }
+            Self::UnsupportedInstruction { addr, instruction } => write!(
+                f,
+                "unsupported instruction at {addr:#06X}: {instruction} ({:#06X}) isn't supported yet",
+                instruction.serialize()
+            ),
+            Self::StackOverflow(len) => {
+                write!(f, "stack overflow: the stack is full ({len} entries)")
+            }
+            Self::StackUnderflow => write!(
+                f,
+                "stack underflow: attempted to return from a subroutine when the stack was empty"
+            ),
+            Self::Syscall(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for C8Error {}
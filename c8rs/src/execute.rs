@@ -1,75 +1,105 @@
-use std::{collections::HashSet, hash::RandomState, time::SystemTime};
-
-use device_query::Keycode;
-
-use c8util::{instructions::Instruction, register::Register};
+use c8util::{
+    instructions::{Instruction, InstructionSet},
+    register::Register,
+};
 
 use crate::{
-    // instructions::Instruction,
-    run::{KEYPRESS_MAP, REVERSE_KEYPRESS_MAP},
+    error::C8Error,
+    font::font_character_address,
+    hooks::notify_key_wait,
+    quirks::get_quirks,
+    rng::random_u8,
+    run::{request_exit, set_status_message},
+    syscall::call_syscall,
     system::{
-        DISPLAY_HEIGHT, DISPLAY_WIDTH, get_delay_timer, get_display, get_i, get_memory_u8, get_pc,
-        get_register, set_delay_timer, set_display, set_i, set_memory_u8, set_memory_u16, set_pc,
-        set_register, set_sound_timer, stack_pop, stack_push,
+        DISPLAY_HEIGHT, DISPLAY_WIDTH, KeyWait, USER_FLAGS_SIZE, get_audio_pattern,
+        get_delay_timer, get_display, get_i, get_key_wait, get_memory_u8, get_pc, get_register,
+        get_user_flags, get_vblank, set_audio_pattern, set_delay_timer, set_display, set_i,
+        set_key_wait, set_memory_u8, set_pc, set_pitch, set_register, set_sound_timer,
+        set_user_flags, set_vblank, stack_pop, stack_push,
     },
+    user_flags::save_user_flags,
 };
 
+/// The instructions that are decoded, set with `--instruction-set`. Only some of the SUPER-CHIP and XO-CHIP
+/// instructions are supported (e.g. `00FD` and `F002`), and executing the others fails with
+/// `C8Error::UnsupportedInstruction`.
+pub static mut INSTRUCTION_SET: InstructionSet = InstructionSet::XoChip;
+
+pub fn get_instruction_set() -> InstructionSet {
+    // SAFETY: single threaded
+    unsafe { INSTRUCTION_SET }
+}
+
+pub fn set_instruction_set(set: InstructionSet) {
+    // SAFETY: single threaded
+    unsafe { INSTRUCTION_SET = set };
+}
+
+/// Execute the given instruction, as it behaves in the given instruction set.
+/// `keypad` is the state of the keypad on this frame, and `last_keypad` is its state on the previous
+/// instruction. Bit N is set if key N is pressed.
+///
+/// # Errors
+/// Returns an error if the instruction can't be carried out, e.g. if it accesses memory out of range.
+/// The instruction may have been partially executed.
 #[allow(clippy::too_many_lines)]
 pub fn execute(
     instruction: Instruction,
-    pressed_keys: &HashSet<Keycode, RandomState>,
-    last_pressed_keys: &HashSet<Keycode, RandomState>,
-    n_instructions_executed: u128,
-) {
+    set: InstructionSet,
+    keypad: u16,
+    last_keypad: u16,
+) -> Result<(), C8Error> {
     match instruction {
         // 0NNN
-        Instruction::ExecuteMachineLanguageRoutine => {
-            panic!("Instruction ExecuteMachineLanguageRoutine (0NNN) is illegal!");
+        Instruction::ExecuteMachineLanguageRoutine(nnn) => {
+            call_syscall(nnn).map_err(C8Error::Syscall)?;
         }
         // 00E0
         Instruction::Clear => {
             // println!("Executing instruction: clear");
             for i in 0..DISPLAY_WIDTH {
                 for j in 0..DISPLAY_HEIGHT {
-                    set_display(u8::try_from(i).unwrap(), u8::try_from(j).unwrap(), false);
+                    set_display(u8::try_from(i).unwrap(), u8::try_from(j).unwrap(), false)?;
                 }
             }
         }
         // 00EE
         Instruction::SubroutineReturn => {
             // println!("Executing instruction: subroutine return");
-            set_pc(
-                stack_pop()
-                    .expect("attempted to execute a subroutine return when the stack was empty"),
-            );
+            set_pc(stack_pop().ok_or(C8Error::StackUnderflow)?)?;
+        }
+        // 00FD
+        Instruction::Exit => {
+            request_exit(get_register(Register::V0));
         }
         // 1NNN
         Instruction::Jump(nnn) => {
             // println!("Executing instruction: jump ({addr})");
-            set_pc(nnn);
+            set_pc(nnn)?;
         }
         // 2NNN
         Instruction::SubroutineCall(nnn) => {
             // println!("Executing instruction: subroutine call ({addr})");
-            stack_push(get_pc());
-            set_pc(nnn);
+            stack_push(get_pc())?;
+            set_pc(nnn)?;
         }
         // 3XNN
         Instruction::SkipConditional1(vx, nn) => {
             if get_register(vx) == nn {
-                set_pc(get_pc() + 2);
+                set_pc(get_pc() + 2)?;
             }
         }
         // 4XNN
         Instruction::SkipConditional2(vx, nn) => {
             if get_register(vx) != nn {
-                set_pc(get_pc() + 2);
+                set_pc(get_pc() + 2)?;
             }
         }
         // 5XNN
         Instruction::SkipConditional3(vx, vy) => {
             if get_register(vx) == get_register(vy) {
-                set_pc(get_pc() + 2);
+                set_pc(get_pc() + 2)?;
             }
         }
         // 6XNN
@@ -119,7 +149,9 @@ pub fn execute(
         }
         // 8XY6
         Instruction::ShiftRight(vx, vy) => {
-            set_register(vx, get_register(vy)); // TODO: Add option to disable
+            if !get_quirks().shift_vx_only {
+                set_register(vx, get_register(vy));
+            }
             let old_vx = get_register(vx);
             set_register(vx, (get_register(vx) >> 1) & 0b0111_1111);
             set_register(Register::VF, old_vx & 1);
@@ -132,7 +164,9 @@ pub fn execute(
         }
         // 8XYE
         Instruction::ShiftLeft(vx, vy) => {
-            set_register(vx, get_register(vy)); // TODO: Add option to disable
+            if !get_quirks().shift_vx_only {
+                set_register(vx, get_register(vy));
+            }
             let old_vx = get_register(vx);
             set_register(vx, (get_register(vx) << 1) & 0b1111_1110);
             set_register(Register::VF, u8::from(old_vx & 0b1000_0000 == 0b1000_0000));
@@ -140,60 +174,63 @@ pub fn execute(
         // 9XY0
         Instruction::SkipConditional4(vx, vy) => {
             if get_register(vx) != get_register(vy) {
-                set_pc(get_pc() + 2);
+                set_pc(get_pc() + 2)?;
             }
         }
         // ANNN
         Instruction::SetIndexRegister(nnn) => {
             // println!("Executing instruction: set i ({val})");
-            set_i(nnn);
+            set_i(nnn)?;
         }
         // BNNN
         Instruction::JumpOffset(nnn) => {
-            set_pc(nnn + u16::from(get_register(Register::V0)));
+            set_pc(nnn + u16::from(get_register(set.jump_offset_register(nnn))))?;
         }
         // CXNN
         Instruction::Random(vx, nnn) => {
-            let duration_since_epoch = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap();
-            let timestamp_nanos = duration_since_epoch.as_nanos();
-
-            #[allow(clippy::cast_possible_truncation)]
-            set_register(vx, (timestamp_nanos & u128::from(nnn)) as u8);
+            set_register(vx, random_u8() & nnn);
         }
         // DXYN
         Instruction::Draw(vx, vy, n) => {
             // Wait until just after vblank to draw
-            if (n_instructions_executed % 12) != 1 {
-                set_pc(get_pc().saturating_sub(2));
-                return;
+            if !get_vblank() {
+                set_pc(get_pc().saturating_sub(2))?;
+                return Ok(());
             }
+            set_vblank(false);
             set_register(Register::VF, 0);
 
             let sprite_location = get_i();
             let x = get_register(vx) % u8::try_from(DISPLAY_WIDTH).unwrap();
             let y = get_register(vy) % u8::try_from(DISPLAY_HEIGHT).unwrap();
 
+            let wrap = get_quirks().wrap_sprites;
+
             // Draw each pixel to the screen
             for i in 0..n {
-                let display_y = y + i;
+                let mut display_y = y + i;
                 if display_y as usize >= DISPLAY_HEIGHT {
-                    continue;
+                    if !wrap {
+                        continue;
+                    }
+                    display_y %= u8::try_from(DISPLAY_HEIGHT).unwrap();
                 }
-                let sprite_val = get_memory_u8(sprite_location + u16::from(i));
+                let sprite_val = get_memory_u8(sprite_location + u16::from(i))?;
 
                 for j in (0..8).rev() {
-                    let display_x = x + 8 - j - 1;
+                    let mut display_x = x + 8 - j - 1;
                     if display_x as usize >= DISPLAY_WIDTH {
-                        continue;
+                        if !wrap {
+                            continue;
+                        }
+                        display_x %= u8::try_from(DISPLAY_WIDTH).unwrap();
                     }
                     let is_set = ((sprite_val >> j) & 0x1) != 0;
-                    let display_val = get_display(display_x, display_y);
+                    let display_val = get_display(display_x, display_y)?;
                     let new_display_val = display_val ^ is_set;
 
                     if is_set {
-                        set_display(display_x, display_y, new_display_val);
+                        set_display(display_x, display_y, new_display_val)?;
 
                         if display_val {
                             set_register(Register::VF, 1);
@@ -207,21 +244,26 @@ pub fn execute(
             // println!("Executing instruction: skip if key ({reg}: get_register(reg))");
             let key = get_register(vx);
             // println!("{}", key);
-            if let Some(keycode) = REVERSE_KEYPRESS_MAP.get().unwrap().get(&key) {
-                if pressed_keys.contains(keycode) {
-                    set_pc(get_pc() + 2);
-                }
+            if is_key_pressed(keypad, key) {
+                set_pc(get_pc() + 2)?;
             }
         }
         // EXA1
         Instruction::SkipIfNotKey(vx) => {
             // println!("Executing instruction: skip if not key ({reg}: get_register(reg))");
             let key = get_register(vx);
-            let keycode = REVERSE_KEYPRESS_MAP.get().unwrap().get(&key).unwrap();
-            if !pressed_keys.contains(keycode) {
-                set_pc(get_pc() + 2);
+            if !is_key_pressed(keypad, key) {
+                set_pc(get_pc() + 2)?;
             }
         }
+        // F002
+        Instruction::LoadAudio => {
+            let mut pattern = get_audio_pattern();
+            for (i, byte) in (0u16..).zip(pattern.iter_mut()) {
+                *byte = get_memory_u8((get_i() + i) & 0x0FFF)?;
+            }
+            set_audio_pattern(pattern);
+        }
         // FX07
         Instruction::GetDelayTimer(vx) => {
             // println!("Executing instruction: get delay timer ({reg:?})");
@@ -239,21 +281,47 @@ pub fn execute(
         }
         // FX1E
         Instruction::AddToIndex(vx) => {
-            set_i(get_i() + u16::from(get_register(vx)));
+            set_i(get_i() + u16::from(get_register(vx)))?;
         }
         // FX0A
         Instruction::GetKey(vx) => {
-            if let Some(key) = last_pressed_keys.iter().find(|k| !pressed_keys.contains(k)) {
-                set_register(vx, *KEYPRESS_MAP.get().unwrap().get(key).unwrap());
-            } else {
-                set_pc(get_pc().saturating_sub(2));
+            match get_key_wait() {
+                KeyWait::Idle | KeyWait::Press => {
+                    // Only count keys that weren't already held, so that a key held from a previous
+                    // FX0A isn't immediately returned again
+                    let newly_pressed = keypad & !last_keypad;
+                    let key = (0..16).find(|key| is_key_pressed(newly_pressed, *key));
+                    match key {
+                        Some(key) if get_quirks().get_key_on_release => {
+                            set_key_wait(KeyWait::Release(key));
+                            set_pc(get_pc().saturating_sub(2))?;
+                        }
+                        Some(key) => {
+                            set_register(vx, key);
+                            set_key_wait(KeyWait::Idle);
+                        }
+                        None => {
+                            if get_key_wait() == KeyWait::Idle {
+                                notify_key_wait(vx);
+                            }
+                            set_key_wait(KeyWait::Press);
+                            set_pc(get_pc().saturating_sub(2))?;
+                        }
+                    }
+                }
+                KeyWait::Release(key) => {
+                    if is_key_pressed(keypad, key) {
+                        set_pc(get_pc().saturating_sub(2))?;
+                    } else {
+                        set_register(vx, key);
+                        set_key_wait(KeyWait::Idle);
+                    }
+                }
             }
         }
         // FX29
         Instruction::FontCharacter(vx) => {
-            let char = get_register(vx);
-            set_memory_u16(get_i(), 0x50 + u16::from(char) * 5);
-            // set_pc(0x50 + (char as u16) * 5);
+            set_i(font_character_address(get_register(vx)))?;
         }
         // FX33
         Instruction::BCD(vx) => {
@@ -262,24 +330,80 @@ pub fn execute(
             let tens = (val % 100) / 10;
             let ones = val % 10;
 
-            set_memory_u8(get_i(), hundreds);
-            set_memory_u8(get_i() + 1, tens);
-            set_memory_u8(get_i() + 2, ones);
+            set_memory_u8(get_i(), hundreds)?;
+            set_memory_u8(get_i() + 1, tens)?;
+            set_memory_u8(get_i() + 2, ones)?;
         }
+        // FX3A
+        Instruction::SetPitch(vx) => set_pitch(get_register(vx)),
         // FX55
         Instruction::StoreMemory(vx) => {
-            for i in 0..=vx {
-                set_memory_u8(get_i(), get_register(i.into()));
-                set_i(get_i() + 1);
+            for reg in Register::ALL.into_iter().take(usize::from(vx) + 1) {
+                set_memory_u8(get_i(), get_register(reg))?;
+                set_i(get_i() + 1)?;
             }
         }
         // FX65
         Instruction::LoadMemory(vx) => {
-            for i in 0..=vx {
-                set_register(i.into(), get_memory_u8(get_i()));
-                set_i(get_i() + 1);
+            for reg in Register::ALL.into_iter().take(usize::from(vx) + 1) {
+                set_register(reg, get_memory_u8(get_i())?);
+                set_i(get_i() + 1)?;
             }
         }
-        Instruction::Db(_) => {}
+        // FX75
+        Instruction::StoreFlags(vx) => {
+            let mut flags = get_user_flags();
+            for (reg, flag) in Register::ALL
+                .into_iter()
+                .zip(flags.iter_mut())
+                .take(usize::from(vx).min(USER_FLAGS_SIZE - 1) + 1)
+            {
+                *flag = get_register(reg);
+            }
+            set_user_flags(flags);
+            // Save immediately, since there's no guarantee the emulator will exit cleanly
+            if let Err(e) = save_user_flags() {
+                set_status_message(format!("Could not save user flags: {e}"));
+            }
+        }
+        // FX85
+        Instruction::LoadFlags(vx) => {
+            let flags = get_user_flags();
+            for (reg, flag) in Register::ALL
+                .into_iter()
+                .zip(flags.iter())
+                .take(usize::from(vx).min(USER_FLAGS_SIZE - 1) + 1)
+            {
+                set_register(reg, *flag);
+            }
+        }
+        Instruction::ScrollDown(_)
+        | Instruction::ScrollUp(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::LowRes
+        | Instruction::HighRes
+        | Instruction::SaveRange(..)
+        | Instruction::LoadRange(..)
+        | Instruction::LoadIndexLong(_)
+        | Instruction::SelectPlanes(_)
+        | Instruction::BigFontCharacter(_) => {
+            return Err(C8Error::UnsupportedInstruction {
+                addr: get_pc().saturating_sub(2),
+                instruction,
+            });
+        }
+        Instruction::Db(raw) => {
+            return Err(C8Error::InvalidInstruction {
+                addr: get_pc().saturating_sub(2),
+                instruction: raw,
+            });
+        }
     }
+    Ok(())
+}
+
+/// Returns whether the given key is pressed in the keypad state.
+fn is_key_pressed(keypad: u16, key: u8) -> bool {
+    key < 16 && keypad & (1 << key) != 0
 }
@@ -0,0 +1,238 @@
+use std::fmt::{self, Display};
+
+use c8util::register::Register;
+
+use crate::{
+    symbols::symbol_addr,
+    system::{get_delay_timer, get_i, get_memory_u8, get_pc, get_register, get_sound_timer},
+};
+
+/// A token in an expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    /// A register, timer or symbol.
+    Name(String),
+    /// An operator or bracket.
+    Op(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Num(n) => write!(f, "{n}"),
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Op(op) => write!(f, "{op}"),
+        }
+    }
+}
+
+/// The operators, longest first so that e.g. `<=` isn't read as `<`.
+const OPERATORS: &[&str] = &[
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "&", "|", "^", "<",
+    ">", "!", "~", "(", ")", "[", "]",
+];
+
+/// The binary operators, from the loosest to the tightest binding.
+const PRECEDENCE: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                let c = rest.chars().next().unwrap();
+                return Err(format!("unexpected character in expression: {c}"));
+            }
+            let word = &rest[..len];
+            tokens.push(if word.starts_with(|c: char| c.is_ascii_digit()) {
+                Token::Num(parse_literal(word)?)
+            } else {
+                Token::Name(word.to_string())
+            });
+            rest = &rest[len..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Parse a number in base 10, hex (`0x123`) or binary (`0b101`).
+fn parse_literal(s: &str) -> Result<i64, String> {
+    let parsed = if let Some(hex) = s.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        s.parse()
+    };
+    parsed.map_err(|e| format!("could not parse number {s}: {e}"))
+}
+
+/// Returns the value of a register, timer or symbol.
+fn lookup(name: &str) -> Result<i64, String> {
+    let lower = name.to_ascii_lowercase();
+    match lower.as_str() {
+        "i" | "index" => return Ok(i64::from(get_i())),
+        "pc" => return Ok(i64::from(get_pc())),
+        "d" | "dt" | "delay" => return Ok(i64::from(get_delay_timer())),
+        "s" | "st" | "sound" => return Ok(i64::from(get_sound_timer())),
+        _ => {}
+    }
+    let register = lower
+        .strip_prefix('v')
+        .filter(|reg| reg.len() == 1)
+        .and_then(|reg| u8::from_str_radix(reg, 16).ok())
+        .and_then(|reg| Register::try_from(reg).ok());
+    if let Some(reg) = register {
+        return Ok(i64::from(get_register(reg)));
+    }
+    symbol_addr(name)
+        .map(i64::from)
+        .ok_or_else(|| format!("unknown name in expression: {name}"))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            _ => Err(format!("expected {op} in expression")),
+        }
+    }
+
+    /// Parse the binary operators at the given precedence level and tighter.
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        let Some(ops) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self
+            .peek()
+            .filter(|token| matches!(token, Token::Op(op) if ops.contains(op)))
+        {
+            let op = *op;
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(n),
+            Some(Token::Name(name)) => lookup(&name),
+            Some(Token::Op("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Op("!")) => Ok(i64::from(self.unary()? == 0)),
+            Some(Token::Op("~")) => Ok(!self.unary()?),
+            Some(Token::Op("(")) => {
+                let val = self.binary(0)?;
+                self.expect(")")?;
+                Ok(val)
+            }
+            // Memory dereference: the byte at the address
+            Some(Token::Op("[")) => {
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                let addr =
+                    u16::try_from(addr).map_err(|_| format!("address {addr} is out of range"))?;
+                get_memory_u8(addr)
+                    .map(i64::from)
+                    .map_err(|e| e.to_string())
+            }
+            Some(Token::Op(op)) => Err(format!("unexpected {op} in expression")),
+            None => Err(String::from("unexpected end of expression")),
+        }
+    }
+}
+
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    Ok(match op {
+        "||" => i64::from(lhs != 0 || rhs != 0),
+        "&&" => i64::from(lhs != 0 && rhs != 0),
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "==" => i64::from(lhs == rhs),
+        "!=" => i64::from(lhs != rhs),
+        "<" => i64::from(lhs < rhs),
+        "<=" => i64::from(lhs <= rhs),
+        ">" => i64::from(lhs > rhs),
+        ">=" => i64::from(lhs >= rhs),
+        "<<" => lhs.wrapping_shl(u32::try_from(rhs).unwrap_or(u32::MAX)),
+        ">>" => lhs.wrapping_shr(u32::try_from(rhs).unwrap_or(u32::MAX)),
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err(String::from("division by zero")),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        _ => unreachable!("unknown operator {op}"),
+    })
+}
+
+/// Evaluate an expression on the current state of the machine.
+///
+/// Expressions can use numbers (`12`, `0x0C`, `0b1100`), registers (`v0`-`vf`, `i`, `pc`), timers (`d`, `s`),
+/// symbols, the byte in memory at an address (`[i + 1]`), and C-like operators. Comparisons and logical operators
+/// give 1 for true and 0 for false.
+///
+/// e.g. `v3 + v4 * 2`, `[i + 1]`, `(v1 & 0x0F) == 3`
+///
+/// # Errors
+/// Returns an error if the expression is invalid, or reads memory that's out of range.
+pub fn eval(expr: &str) -> Result<i64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?,
+        pos: 0,
+    };
+    let val = parser.binary(0)?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected {token} in expression"));
+    }
+    Ok(val)
+}
+
+/// Format a value the way the debug terminal prints it: hex and decimal, e.g. `0x2A (42)`.
+pub fn format_value(val: i64) -> String {
+    if (0..=0xFF).contains(&val) {
+        format!("{val:#04X} ({val})")
+    } else if val >= 0 {
+        format!("{val:#06X} ({val})")
+    } else {
+        val.to_string()
+    }
+}
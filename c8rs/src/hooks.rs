@@ -0,0 +1,182 @@
+use c8util::{instructions::Instruction, register::Register};
+
+use crate::{
+    coverage::CoverageObserver,
+    debug_terminal::{BreakpointObserver, KeyBreakObserver, SoundBreakObserver},
+    error::C8Error,
+    profile::ProfileObserver,
+    record::RecordObserver,
+    remote::RemoteObserver,
+    system::get_pc,
+    trace::TraceObserver,
+    watch::WatchObserver,
+};
+
+/// What happened when an instruction was executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The instruction finished.
+    Executed,
+    /// The instruction is waiting (a draw for the vertical blank, or `FX0A` for a key), and will be executed again.
+    Waiting,
+    /// The instruction failed, and will be executed again once the debugger is left.
+    Failed,
+}
+
+impl Outcome {
+    /// The outcome of executing the instruction at `addr`, given the result of executing it.
+    pub fn of(result: &Result<(), C8Error>, addr: u16, instruction: Instruction) -> Self {
+        match result {
+            Err(_) => Self::Failed,
+            // Instructions that are waiting go back to themselves, so that they're executed again
+            Ok(())
+                if matches!(instruction, Instruction::Draw(..) | Instruction::GetKey(_))
+                    && get_pc() == addr =>
+            {
+                Self::Waiting
+            }
+            Ok(()) => Self::Executed,
+        }
+    }
+}
+
+/// An instruction that was executed.
+#[derive(Debug, Clone, Copy)]
+pub struct InstructionEvent {
+    /// The address of the instruction.
+    pub addr: u16,
+    pub instruction: Instruction,
+    /// The number of instructions that were executed before this one.
+    pub instruction_count: u128,
+    pub outcome: Outcome,
+}
+
+/// Something that follows what the emulator does, like the profiler or the tracer.
+///
+/// Observers are called in the order they were added, after the built in ones. Every method does nothing by
+/// default, so an observer only needs to implement the events it cares about. An observer can add another one,
+/// which is notified from the next event on, but events caused by an observer aren't passed on to the others.
+pub trait Observer {
+    /// Called before each instruction is fetched, with the PC and the keypad the instruction will be executed
+    /// with. Returns whether to break into the debugger before it's executed (e.g. at a breakpoint). Use
+    /// `debug_terminal::request_break` instead to show why.
+    fn should_break(&mut self, _pc: u16, _keypad: u16, _last_keypad: u16) -> bool {
+        false
+    }
+
+    /// Called just before an instruction is executed, while the machine is still in the state it will read.
+    fn before_instruction(
+        &mut self,
+        _addr: u16,
+        _instruction: Instruction,
+        _instruction_count: u128,
+    ) {
+    }
+
+    /// Called after an instruction was executed, whether or not it succeeded.
+    fn on_instruction(&mut self, _event: &InstructionEvent) {}
+
+    /// Called after a frame was shown by the display backend.
+    fn on_draw(&mut self) {}
+
+    /// Called when `FX0A` starts waiting for a key, which will be stored in the given register.
+    fn on_key_wait(&mut self, _register: Register) {}
+
+    /// Called at the start of every frame, after the delay and sound timers were counted down.
+    fn on_timer_tick(&mut self) {}
+}
+
+/// The observers that are notified of events. None until the first event, when it becomes the built in observers
+/// (see `default_observers`), followed by the ones added with `add_observer`.
+pub static mut OBSERVERS: Option<Vec<Box<dyn Observer>>> = None;
+
+/// The built in observers: breakpoints, coverage, tracing, remote control, profiling, watches, breaking on sound
+/// and GIF recording.
+fn default_observers() -> Vec<Box<dyn Observer>> {
+    vec![
+        Box::new(BreakpointObserver),
+        Box::new(KeyBreakObserver),
+        Box::new(CoverageObserver),
+        Box::new(TraceObserver::default()),
+        Box::new(RemoteObserver),
+        Box::new(ProfileObserver),
+        Box::new(WatchObserver::default()),
+        Box::new(SoundBreakObserver),
+        Box::new(RecordObserver),
+    ]
+}
+
+/// Add an observer, which is notified of events after the built in observers.
+pub fn add_observer(observer: Box<dyn Observer>) {
+    // SAFETY: single threaded, and `notify` doesn't hold a reference to the observers while they're notified
+    #[allow(static_mut_refs)]
+    unsafe {
+        OBSERVERS
+            .get_or_insert_with(default_observers)
+            .push(observer);
+    }
+}
+
+/// Run `f` with only the given observers, e.g. so that a benchmark is only covered and profiled. The observers
+/// from before are put back afterwards.
+pub fn with_observers<T>(observers: Vec<Box<dyn Observer>>, f: impl FnOnce() -> T) -> T {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let old = unsafe { OBSERVERS.replace(observers) };
+    let result = f();
+    // SAFETY: single threaded
+    unsafe { OBSERVERS = old };
+    result
+}
+
+/// Notify every observer.
+fn notify(mut f: impl FnMut(&mut dyn Observer)) {
+    // Take the observers out while they're notified, so that one can add another without changing the list that's
+    // being iterated over. Observers added in the meantime go in the empty list that's left behind, and events
+    // caused by an observer are only passed on to them.
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let mut observers = unsafe { OBSERVERS.replace(Vec::new()) }.unwrap_or_else(default_observers);
+    for observer in &mut observers {
+        f(observer.as_mut());
+    }
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        observers.extend(OBSERVERS.take().into_iter().flatten());
+        OBSERVERS = Some(observers);
+    }
+}
+
+/// Returns whether any observer wants to break into the debugger before the instruction at `pc` is executed.
+pub fn notify_should_break(pc: u16, keypad: u16, last_keypad: u16) -> bool {
+    let mut should_break = false;
+    // Every observer is asked, since asking can have side effects (e.g. a key breakpoint is removed once it's hit)
+    notify(|observer| should_break |= observer.should_break(pc, keypad, last_keypad));
+    should_break
+}
+
+/// Let the observers know that an instruction is about to be executed.
+pub fn notify_before_instruction(addr: u16, instruction: Instruction, instruction_count: u128) {
+    notify(|observer| observer.before_instruction(addr, instruction, instruction_count));
+}
+
+/// Let the observers know that an instruction was executed.
+pub fn notify_instruction(event: &InstructionEvent) {
+    notify(|observer| observer.on_instruction(event));
+}
+
+/// Let the observers know that a frame was shown.
+pub fn notify_draw() {
+    notify(|observer| observer.on_draw());
+}
+
+/// Let the observers know that `FX0A` started waiting for a key.
+pub fn notify_key_wait(register: Register) {
+    notify(|observer| observer.on_key_wait(register));
+}
+
+/// Let the observers know that a new frame started.
+pub fn notify_timer_tick() {
+    notify(|observer| observer.on_timer_tick());
+}
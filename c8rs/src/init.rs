@@ -1,62 +1,66 @@
-use std::fs;
+use c8util::rom::Rom;
 
-use crate::system::{set_memory_u8, set_pc};
+use crate::{
+    font::load_font,
+    system::{get_full_memory, set_full_memory, set_pc},
+};
 
-pub static mut ROM_PATH: &str = "";
+/// The ROM that is running. It's set before anything else, since the ROM database and sessions are found by its
+/// SHA-1.
+pub static mut ROM: Option<Rom> = None;
 
-pub fn set_rom_path(path: &'static str) {
+pub fn set_rom(rom: Rom) {
     // SAFETY: single threaded
     unsafe {
-        ROM_PATH = path;
+        ROM = Some(rom);
     }
 }
 
-pub fn get_rom_path() -> &'static str {
+/// Returns the ROM that is running.
+///
+/// # Panics
+/// Panics if the ROM hasn't been set yet.
+pub fn get_rom() -> Rom {
     // SAFETY: single threaded
-    unsafe { ROM_PATH }
+    #[allow(static_mut_refs)]
+    unsafe {
+        ROM.clone().expect("the ROM hasn't been set")
+    }
 }
 
 /// Initialize memory:
-/// - Set the font data at 0x50
-fn init_memory() {
-    // Set font
-    let font: [u8; 80] = [
-        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-        0x20, 0x60, 0x20, 0x20, 0x70, // 1
-        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-        0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-    ];
-
-    for (i, item) in font.iter().enumerate() {
-        set_memory_u8(u16::try_from(0x50 + i).unwrap(), *item);
-    }
+/// - Set the font data at 0x50, and the large font data at 0xA0
+/// - Load the ROM at its load address (0x200)
+///
+/// Memory is replaced directly rather than written through the bus, so that a bus that protects the ROM can still
+/// be loaded.
+fn init_memory() -> Result<(), String> {
+    load_font();
 
     // Initialize the ROM
-    let rom_bytes = fs::read(get_rom_path()).expect("failed to read rom file");
-    for (i, item) in rom_bytes.iter().enumerate() {
-        set_memory_u8(u16::try_from(0x200 + i).unwrap(), *item);
-    }
+    let rom = get_rom();
+    let mut memory = get_full_memory();
+    let start = rom.load_addr();
+    memory
+        .get_mut(start..start + rom.data().len())
+        .ok_or_else(|| String::from("the ROM doesn't fit in memory"))?
+        .copy_from_slice(rom.data());
+    set_full_memory(memory);
+    Ok(())
 }
 
 /// Initialize the PC:
-/// - Set the PC to 0x200
+/// - Set the PC to the start of the ROM (0x200)
 fn init_pc() {
-    set_pc(0x200);
+    set_pc(u16::try_from(get_rom().load_addr()).unwrap()).unwrap();
 }
 
-pub fn init() {
-    init_memory();
+/// Initialize memory and the PC.
+///
+/// # Errors
+/// Returns an error if the ROM could not be written to memory.
+pub fn init() -> Result<(), String> {
+    init_memory()?;
     init_pc();
+    Ok(())
 }
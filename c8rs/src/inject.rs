@@ -0,0 +1,85 @@
+use crate::{input::InputSource, timing::get_frame_count};
+
+/// The keys held down from the debug terminal with `key press`. Bit N is set if key N is pressed.
+pub static mut HELD_KEYS: u16 = 0;
+
+/// The keys tapped from the debug terminal with `key tap`, and the frame they're released on.
+pub static mut TAPPED_KEYS: (u16, u64) = (0, 0);
+
+/// The keys pressed and released from the debug terminal since the last call to `take_key_changes`.
+pub static mut KEY_CHANGES: Option<(u16, u16)> = None;
+
+/// Returns the keys pressed from the debug terminal on the current frame.
+/// Bit N is set if key N is pressed.
+pub fn get_injected_keypad() -> u16 {
+    // SAFETY: single threaded
+    unsafe {
+        let (tapped, release_frame) = TAPPED_KEYS;
+        if get_frame_count() < release_frame {
+            HELD_KEYS | tapped
+        } else {
+            HELD_KEYS
+        }
+    }
+}
+
+/// The keys pressed from the debug terminal, as an input source.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InjectedKeypad;
+
+impl InputSource for InjectedKeypad {
+    fn keypad(&mut self) -> u16 {
+        get_injected_keypad()
+    }
+}
+
+/// Keep track of a key that was pressed or released, so that it can take effect right away.
+fn change_key(key: u8, is_pressed: bool) {
+    // SAFETY: single threaded
+    unsafe {
+        let (mut pressed, mut released) = KEY_CHANGES.unwrap_or_default();
+        if is_pressed {
+            pressed |= 1 << key;
+            released &= !(1 << key);
+        } else {
+            pressed &= !(1 << key);
+            released |= 1 << key;
+        }
+        KEY_CHANGES = Some((pressed, released));
+    }
+}
+
+/// Hold down a key until it's released with `release_key`.
+pub fn press_key(key: u8) {
+    // SAFETY: single threaded
+    unsafe { HELD_KEYS |= 1 << key };
+    change_key(key, true);
+}
+
+/// Release a key that was pressed or tapped.
+pub fn release_key(key: u8) {
+    // SAFETY: single threaded
+    unsafe {
+        HELD_KEYS &= !(1 << key);
+        TAPPED_KEYS.0 &= !(1 << key);
+    }
+    change_key(key, false);
+}
+
+/// Press a key for the rest of the current frame and all of the next one, then release it.
+/// This is long enough for any instruction that checks the keypad to see the key being pressed and released.
+pub fn tap_key(key: u8) {
+    // SAFETY: single threaded
+    unsafe { TAPPED_KEYS = (TAPPED_KEYS.0 | 1 << key, get_frame_count() + 2) };
+    change_key(key, true);
+}
+
+/// Returns the keys that were pressed and released from the debug terminal since this was last called, and
+/// forgets them. The keypad is only sampled at the start of each frame, so these are applied to it directly.
+pub fn take_key_changes() -> Option<(u16, u16)> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        KEY_CHANGES.take()
+    }
+}
@@ -0,0 +1,433 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    io::{self, Write},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, terminal,
+};
+
+use crate::{inject::InjectedKeypad, remote::RemoteKeypad, run::get_keymap};
+
+/// A key on the keyboard.
+/// Keys are named like `Key1`, `Q`, `Escape` and `F12`, which is how they're written in keymaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key(pub KeyCode);
+
+/// The names of the keys that aren't letters, digits or function keys.
+const KEY_NAMES: &[(KeyCode, &str)] = &[
+    (KeyCode::Esc, "Escape"),
+    (KeyCode::Char(' '), "Space"),
+    (KeyCode::Enter, "Enter"),
+    (KeyCode::Tab, "Tab"),
+    (KeyCode::Backspace, "Backspace"),
+    (KeyCode::Up, "Up"),
+    (KeyCode::Down, "Down"),
+    (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"),
+    (KeyCode::Char('-'), "Minus"),
+    (KeyCode::Char('='), "Equal"),
+    (KeyCode::Char('['), "LeftBracket"),
+    (KeyCode::Char(']'), "RightBracket"),
+    (KeyCode::Char('\\'), "BackSlash"),
+    (KeyCode::Char(';'), "Semicolon"),
+    (KeyCode::Char('\''), "Apostrophe"),
+    (KeyCode::Char(','), "Comma"),
+    (KeyCode::Char('.'), "Dot"),
+    (KeyCode::Char('/'), "Slash"),
+    (KeyCode::Char('`'), "Grave"),
+];
+
+impl Key {
+    /// Returns the key that was pressed in the given event, ignoring shift (e.g. `Q` for both `q` and `Q`).
+    fn from_event(event: &KeyEvent) -> Self {
+        match event.code {
+            KeyCode::Char(c) => Self(KeyCode::Char(c.to_ascii_lowercase())),
+            code => Self(code),
+        }
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((code, _)) = KEY_NAMES.iter().find(|(_, name)| *name == s) {
+            return Ok(Self(*code));
+        }
+        let mut chars = s.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_uppercase() => KeyCode::Char(c.to_ascii_lowercase()),
+            _ => match (
+                s.strip_prefix("Key").map(str::as_bytes),
+                s.strip_prefix('F').map(str::parse),
+            ) {
+                (Some([digit @ b'0'..=b'9']), _) => KeyCode::Char(char::from(*digit)),
+                (_, Some(Ok(n @ 1..=12))) => KeyCode::F(n),
+                _ => return Err(format!("unknown key: {s}")),
+            },
+        };
+        Ok(Self(code))
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((_, name)) = KEY_NAMES.iter().find(|(code, _)| *code == self.0) {
+            return write!(f, "{name}");
+        }
+        match self.0 {
+            KeyCode::Char(c @ '0'..='9') => write!(f, "Key{c}"),
+            KeyCode::Char(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+/// Whether the terminal has been set up for reading keys.
+static mut IS_INPUT_ENABLED: bool = false;
+
+/// Set up the terminal so that keys are read as they're pressed, without being echoed.
+/// Output is still translated as usual (i.e. `\n` starts a new line), since everything that's printed relies on it.
+/// Terminals that support it are also asked to report when keys are released.
+///
+/// # Errors
+/// Returns an error if the terminal could not be set up, e.g. if stdin isn't a terminal.
+pub fn enable_input() -> io::Result<()> {
+    terminal::enable_raw_mode()?;
+    #[cfg(unix)]
+    {
+        // SAFETY: termios is only read and written by tcgetattr and tcsetattr
+        unsafe {
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDOUT_FILENO, &raw mut termios) == 0 {
+                termios.c_oflag |= libc::OPOST;
+                libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, &raw const termios);
+            }
+        }
+    }
+    // Terminals that don't support this ignore it
+    let _ = execute!(
+        io::stdout(),
+        PushKeyboardEnhancementFlags(
+            KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+        )
+    );
+    // SAFETY: single threaded
+    unsafe { IS_INPUT_ENABLED = true };
+    Ok(())
+}
+
+/// Put the terminal back the way it was before `enable_input`. Does nothing if it wasn't set up.
+pub fn disable_input() {
+    // SAFETY: single threaded
+    if !unsafe { IS_INPUT_ENABLED } {
+        return;
+    }
+    let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+    let _ = terminal::disable_raw_mode();
+    // SAFETY: single threaded
+    unsafe { IS_INPUT_ENABLED = false };
+}
+
+/// Returns the next key event if one is waiting, without blocking. Other events (e.g. resizes) are skipped.
+fn next_key_event() -> Option<KeyEvent> {
+    while event::poll(Duration::ZERO).unwrap_or(false) {
+        if let Ok(Event::Key(event)) = event::read() {
+            return Some(event);
+        }
+    }
+    None
+}
+
+/// Returns whether the event is Ctrl+C, which exits the emulator.
+pub fn is_interrupt(event: &KeyEvent) -> bool {
+    event.kind == KeyEventKind::Press
+        && event.modifiers.contains(KeyModifiers::CONTROL)
+        && event.code == KeyCode::Char('c')
+}
+
+/// Whether the emulator was sent SIGINT or SIGTERM. Atomic, since it's set from a signal handler.
+static IS_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Handle SIGINT and SIGTERM like Ctrl+C, so that the emulator still finishes recordings and saves profiles and
+/// coverage when it's stopped while the terminal isn't reading keys (e.g. during `--bench`) or with `kill`. The
+/// signal only sets a flag (see `is_interrupted`), so a second one exits straight away in case nothing checks it.
+pub fn handle_interrupts() {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_signal(_signal: libc::c_int) {
+            if IS_INTERRUPTED.swap(true, Ordering::SeqCst) {
+                // SAFETY: _exit is async-signal-safe
+                unsafe { libc::_exit(130) };
+            }
+        }
+        // SAFETY: the handler only uses an atomic and _exit, which are async-signal-safe
+        unsafe {
+            let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+        }
+    }
+}
+
+/// Returns whether the emulator was interrupted by a signal since `handle_interrupts` was called.
+pub fn is_interrupted() -> bool {
+    IS_INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// How often the debugger prompt checks for signals while it waits for a key.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a key counts as held after it was last pressed, on terminals that don't report releases.
+/// Holding a key down repeats it, which keeps it held.
+pub const KEY_HOLD_TIME: Duration = Duration::from_millis(200);
+
+/// The keys that are held down, worked out from the keys the terminal reports.
+#[derive(Debug, Default)]
+pub struct Keyboard {
+    /// The keys that are held, and when they were last pressed or repeated.
+    held: HashMap<Key, Instant>,
+    /// Whether the terminal reports when keys are released. Until it does, keys are released once they haven't
+    /// been pressed for `KEY_HOLD_TIME`.
+    reports_releases: bool,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the key events that are waiting, without blocking.
+    /// Returns the events for keys that were pressed (not repeated), in order.
+    pub fn update(&mut self) -> Vec<KeyEvent> {
+        let mut pressed = Vec::new();
+        while let Some(event) = next_key_event() {
+            let key = Key::from_event(&event);
+            match event.kind {
+                KeyEventKind::Press => {
+                    self.held.insert(key, Instant::now());
+                    pressed.push(event);
+                }
+                KeyEventKind::Repeat => {
+                    self.held.insert(key, Instant::now());
+                }
+                KeyEventKind::Release => {
+                    self.reports_releases = true;
+                    self.held.remove(&key);
+                }
+            }
+        }
+        if !self.reports_releases {
+            self.held
+                .retain(|_, pressed_at| pressed_at.elapsed() < KEY_HOLD_TIME);
+        }
+        pressed
+    }
+
+    /// Returns the keys that are held down.
+    pub fn held_keys(&self) -> impl Iterator<Item = Key> + '_ {
+        self.held.keys().copied()
+    }
+
+    /// Release all keys, e.g. after the debug terminal read the events that would have released them.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+}
+
+impl InputSource for Keyboard {
+    /// The keys on the keypad for the keys that are held on the keyboard, according to the keymap.
+    fn keypad(&mut self) -> u16 {
+        let keymap = get_keymap();
+        self.held_keys()
+            .filter_map(|held| keymap.iter().position(|&key| key == held))
+            .fold(0, |keypad, key| keypad | (1 << key))
+    }
+}
+
+/// Something that presses keys on the Chip-8 keypad.
+///
+/// The keyboard is always read. Other sources (the remote control API and keys pressed from the debug terminal
+/// by default) are added with `add_input_source`, and the keys they press are combined with the keyboard's. While
+/// a movie is played back, its keys are used instead of all of them.
+pub trait InputSource {
+    /// Returns the keys that are pressed, sampled once at the start of each frame. Bit N is set if key N is
+    /// pressed.
+    fn keypad(&mut self) -> u16;
+}
+
+/// The input sources other than the keyboard. None until input is first read, when it becomes the remote control
+/// API and keys pressed from the debug terminal.
+pub static mut INPUT_SOURCES: Option<Vec<Box<dyn InputSource>>> = None;
+
+/// Returns the input sources other than the keyboard.
+fn input_sources() -> &'static mut Vec<Box<dyn InputSource>> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        INPUT_SOURCES.get_or_insert_with(|| vec![Box::new(RemoteKeypad), Box::new(InjectedKeypad)])
+    }
+}
+
+/// Add a source of input, e.g. a gamepad. The keys it presses are combined with the keyboard's.
+pub fn add_input_source(source: Box<dyn InputSource>) {
+    input_sources().push(source);
+}
+
+/// Returns the keys pressed by every input source other than the keyboard. Bit N is set if key N is pressed.
+/// This should be called exactly once per frame.
+pub fn poll_input_sources() -> u16 {
+    input_sources()
+        .iter_mut()
+        .fold(0, |keypad, source| keypad | source.keypad())
+}
+
+/// What was typed into a `LineEditor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineInput {
+    /// A line, once enter was pressed.
+    Line(String),
+    /// Ctrl+C was pressed, or the terminal could not be read.
+    Interrupt,
+}
+
+/// Reads lines typed after a prompt, with cursor movement and history.
+///
+/// - Left/Right, Home/End (or Ctrl+A/Ctrl+E) move the cursor
+/// - Backspace/Delete delete the character before/at the cursor, and Ctrl+U deletes everything before it
+/// - Up/Down go through the previous lines
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    /// The prompt, which is already printed at the start of the line.
+    prompt: &'static str,
+    line: Vec<char>,
+    /// The position of the cursor in the line.
+    cursor: usize,
+    /// The previous lines, oldest first.
+    history: Vec<String>,
+    /// The position in the history when going through it with Up/Down, or None if the line is new.
+    history_pos: Option<usize>,
+    /// The new line that was being typed before going through the history.
+    unsaved_line: Vec<char>,
+}
+
+impl LineEditor {
+    pub fn new(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            ..Self::default()
+        }
+    }
+
+    /// Wait for a line to be typed, and return it.
+    pub fn read_line(&mut self) -> LineInput {
+        loop {
+            // Wake up now and then to check for signals, which don't interrupt waiting for a key
+            if is_interrupted() {
+                println!();
+                return LineInput::Interrupt;
+            }
+            if !event::poll(INTERRUPT_POLL_INTERVAL).unwrap_or(true) {
+                continue;
+            }
+            let event = match event::read() {
+                Ok(Event::Key(event)) if event.kind != KeyEventKind::Release => event,
+                Ok(_) => continue,
+                Err(_) => return LineInput::Interrupt,
+            };
+            if is_interrupt(&event) {
+                println!();
+                return LineInput::Interrupt;
+            }
+            if event.code == KeyCode::Enter {
+                let line = self.line.drain(..).collect::<String>();
+                self.cursor = 0;
+                self.history_pos = None;
+                if !line.trim().is_empty() && self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                }
+                println!();
+                return LineInput::Line(line);
+            }
+            self.edit(&event);
+            self.redraw();
+        }
+    }
+
+    /// Apply a key that edits the line or moves the cursor.
+    fn edit(&mut self, event: &KeyEvent) {
+        let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+        match event.code {
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.line.len(),
+            KeyCode::Char('u') if ctrl => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char(c) if !ctrl && !event.modifiers.contains(KeyModifiers::ALT) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.line.len(),
+            KeyCode::Up => self.go_to_history(self.history_pos.map_or_else(
+                || self.history.len().checked_sub(1),
+                |pos| pos.checked_sub(1),
+            )),
+            KeyCode::Down => match self.history_pos {
+                Some(pos) if pos + 1 < self.history.len() => self.go_to_history(Some(pos + 1)),
+                Some(_) => {
+                    // Back to the line that was being typed
+                    self.history_pos = None;
+                    self.line = std::mem::take(&mut self.unsaved_line);
+                    self.cursor = self.line.len();
+                }
+                None => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Replace the line with the given line from the history. Does nothing if there is no such line, e.g. when
+    /// going up from the oldest line.
+    fn go_to_history(&mut self, pos: Option<usize>) {
+        let Some(pos) = pos else {
+            return;
+        };
+        if self.history_pos.is_none() {
+            self.unsaved_line = std::mem::take(&mut self.line);
+        }
+        self.history_pos = Some(pos);
+        self.line = self.history[pos].chars().collect();
+        self.cursor = self.line.len();
+    }
+
+    /// Redraw the line after the prompt, and put the cursor in the right place.
+    fn redraw(&self) {
+        let line = self.line.iter().collect::<String>();
+        let column = self.prompt.chars().count() + self.cursor + 1;
+        print!("\r\x1b[2K{}{line}\x1b[{column}G", self.prompt);
+        io::stdout().flush().unwrap();
+    }
+}
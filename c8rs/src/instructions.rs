@@ -1,11 +1,19 @@
-use c8util::{decode::decode, instructions::Instruction};
+use c8util::{
+    decode::decode,
+    instructions::{Flavor, Instruction},
+    register::Register,
+};
 
 use crate::{
-    run::REVERSE_KEYPRESS_MAP,
-    system::{get_memory_u16, get_register},
+    execute::get_instruction_set,
+    run::get_keymap,
+    symbols::addr_label,
+    syscall::has_syscall_handler,
+    system::{KeyWait, get_i, get_key_wait, get_memory_u16, get_register},
 };
 
-/// Fancy formatting of instructions (register values, jump predictions, etc)
+/// Fancy formatting of instructions (register values, jump predictions, etc). Instructions without anything to add
+/// are written like `Instruction::display(Flavor::Named)`.
 pub trait FancyInstruction {
     fn fancy_fmt(&self) -> String;
 }
@@ -14,48 +22,38 @@ impl FancyInstruction for Instruction {
     #[allow(clippy::too_many_lines)]
     fn fancy_fmt(&self) -> String {
         match *self {
-            Self::ExecuteMachineLanguageRoutine => {
-                String::from("ExecuteMachineLanguageRoutine (Invalid)")
+            Self::ExecuteMachineLanguageRoutine(nnn) if !has_syscall_handler(nnn) => {
+                format!("{} (no handler)", self.display(Flavor::Named))
             }
-            Self::Clear => String::from("Clear"),
-            Self::SubroutineReturn => String::from("SubroutineReturn"),
-            Self::Jump(nnn) => {
-                let instruction_raw = get_memory_u16(nnn);
-                let instruction = decode(instruction_raw);
-                if let Some(ins) = instruction {
-                    format!("Jump({nnn:#06X}) -> {}", ins.fancy_fmt())
-                } else {
-                    format!("Jump({nnn:#06X}) -> (invalid)")
+            Self::Exit => format!("Exit(V0 -> {:#04X})", get_register(Register::V0)),
+            Self::Jump(nnn) => match get_memory_u16(nnn)
+                .ok()
+                .and_then(|ins| decode(ins, get_instruction_set()))
+            {
+                // Jumps aren't followed any further, since they may loop (e.g. a jump to itself)
+                Some(Self::Jump(target)) => {
+                    format!("Jump({}) -> Jump({})", addr_label(nnn), addr_label(target))
                 }
-            }
+                Some(ins) => format!("Jump({}) -> {}", addr_label(nnn), ins.fancy_fmt()),
+                None => format!("Jump({}) -> (invalid)", addr_label(nnn)),
+            },
             Self::SubroutineCall(nnn) => {
-                format!("SubroutineCall({nnn:#06X})")
+                format!("SubroutineCall({})", addr_label(nnn))
             }
             Self::SkipConditional1(vx, nn) => {
                 format!("SkipEqual({vx} -> {:#04X}, {:#04X})", get_register(vx), nn)
             }
-            Self::SkipConditional2(vx, nn) => String::from(
-                format!(
-                    "SkipNotEqual({vx} -> {:#04X}, {:#04X})",
-                    get_register(vx),
-                    nn
-                )
-                .as_str(),
+            Self::SkipConditional2(vx, nn) => format!(
+                "SkipNotEqual({vx} -> {:#04X}, {:#04X})",
+                get_register(vx),
+                nn
             ),
             Self::SkipConditional3(vx, vy) => format!(
                 "SkipEqual({vx} -> {:#04X}, {vy} -> {:#04X})",
                 get_register(vx),
                 get_register(vy)
             ),
-
-            Self::SetRegister(vx, nn) => {
-                format!("SetRegister({vx}, {nn:#04X})")
-            }
-            Self::Add(vx, nn) => format!(
-                "SetRegister({vx} -> {:#04X}, {:#04X})",
-                get_register(vx),
-                nn
-            ),
+            Self::Add(vx, nn) => format!("Add({vx} -> {:#04X}, {:#04X})", get_register(vx), nn),
             Self::RegSet(vx, vy) => format!("SetRegister({vx}, {vy} -> {:#04X})", get_register(vy)),
             Self::BinaryOr(vx, vy) => format!(
                 "BinaryOr({vx} -> {:#04X}, {vy} -> {:#04X})",
@@ -102,9 +100,8 @@ impl FancyInstruction for Instruction {
                 get_register(vx),
                 get_register(vy)
             ),
-            Self::SetIndexRegister(nnn) => format!("SetI({nnn:#06X})"),
-            Self::JumpOffset(nnn) => format!("JumpOffset({nnn:#06X})"),
-            Self::Random(vx, nn) => format!("Random({vx}, {nn:#04X})"),
+            Self::SetIndexRegister(nnn) => format!("SetI({})", addr_label(nnn)),
+            Self::JumpOffset(nnn) => format!("JumpOffset({})", addr_label(nnn)),
             Self::Draw(vx, vy, n) => format!(
                 "Draw({vx} -> {:#04X}, {vy} -> {:#04X}, {:#04X})",
                 get_register(vx),
@@ -112,25 +109,20 @@ impl FancyInstruction for Instruction {
                 n
             ),
             Self::SkipIfKey(vx) => format!(
-                "SkipIfKey({vx} -> {:#04X} ({:?}))",
+                "SkipIfKey({vx} -> {:#04X} ({}))",
                 get_register(vx),
-                REVERSE_KEYPRESS_MAP
-                    .get()
-                    .unwrap()
-                    .get(&get_register(vx))
-                    .unwrap()
+                key_name(get_register(vx))
             ),
             Self::SkipIfNotKey(vx) => format!(
-                "SkipIfNotKey({vx} -> {:#04X} ({:?}))",
+                "SkipIfNotKey({vx} -> {:#04X} ({}))",
                 get_register(vx),
-                REVERSE_KEYPRESS_MAP
-                    .get()
-                    .unwrap()
-                    .get(&get_register(vx))
-                    .unwrap()
+                key_name(get_register(vx))
             ),
-            Self::GetDelayTimer(vx) => format!("GetDelayTimer({vx})"),
-            Self::GetKey(vx) => format!("GetKey({vx})"),
+            Self::LoadAudio => format!("LoadAudio(I -> {:#05X})", get_i()),
+            Self::GetKey(vx) => match get_key_wait() {
+                KeyWait::Release(key) => format!("GetKey({vx}) (waiting for {key:#03X} release)"),
+                _ => self.display(Flavor::Named).to_string(),
+            },
             Self::SetDelayTimer(vx) => {
                 format!("SetDelayTimer({vx} -> {:#04X})", get_register(vx))
             }
@@ -144,9 +136,19 @@ impl FancyInstruction for Instruction {
                 format!("FontAddress({vx} -> {:#04X})", get_register(vx))
             }
             Self::BCD(vx) => format!("BinaryCodedDecimal({vx} -> {:#04X})", get_register(vx)),
-            Self::StoreMemory(n) => format!("StoreMemory({n})"),
-            Self::LoadMemory(n) => format!("LoadMemory({n})"),
-            Self::Db(nnnn) => format!("db {nnnn}"),
+            Self::SetPitch(vx) => format!("SetPitch({vx} -> {:#04X})", get_register(vx)),
+            Self::BigFontCharacter(vx) => {
+                format!("BigFontAddress({vx} -> {:#04X})", get_register(vx))
+            }
+            // The rest don't read anything worth showing
+            _ => self.display(Flavor::Named).to_string(),
         }
     }
 }
+
+/// The key on the keyboard for a key on the keypad, or `none` if there isn't one (if the key is above F).
+fn key_name(key: u8) -> String {
+    get_keymap()
+        .get(usize::from(key))
+        .map_or_else(|| String::from("none"), ToString::to_string)
+}
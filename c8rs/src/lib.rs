@@ -1,7 +1,44 @@
+pub mod alias;
+pub mod audio;
+pub mod automation;
+pub mod bench;
+pub mod bus;
+pub mod config;
+pub mod coverage;
+pub mod crash;
+pub mod debug_server;
 pub mod debug_terminal;
+pub mod determinism;
+pub mod display;
+pub mod error;
 pub mod execute;
+pub mod expr;
+pub mod font;
+pub mod hooks;
 pub mod init;
+pub mod inject;
+pub mod input;
 pub mod instructions;
+pub mod movie;
+pub mod phosphor;
+pub mod profile;
+pub mod quicksave;
+pub mod quirks;
+pub mod record;
+pub mod remote;
+pub mod replay;
+pub mod rng;
+pub mod rom_db;
 pub mod run;
-pub mod stdin;
+pub mod screenshot;
+pub mod session;
+pub mod snapshot;
+pub mod stats;
+pub mod symbols;
+pub mod syscall;
 pub mod system;
+pub mod terminal;
+pub mod timing;
+pub mod trace;
+pub mod user_flags;
+pub mod watch;
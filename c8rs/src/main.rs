@@ -1,19 +1,407 @@
-use std::{env, process::exit};
+use std::{path::Path, time::Duration};
+
+use c8util::{disasm::ROM_START, instructions::Platform, rom::Rom};
+use clap::{Parser, builder::RangedU64ValueParser};
 
 use c8rs::{
-    init::{init, set_rom_path},
-    run::run,
+    alias::load_aliases,
+    audio::start_audio,
+    automation::{
+        save_dumps, set_dump_display_path, set_dump_memory_path, set_exit_on_loop,
+        set_max_instructions, set_print_state,
+    },
+    bench::{BenchLimit, run_bench},
+    config::{Frontend, load_config},
+    coverage::{save_coverage_out, set_coverage_out_path},
+    crash::install_crash_handler,
+    debug_server::start_debug_server,
+    debug_terminal::{add_breakpoint, parse_addr},
+    determinism::{DEFAULT_VERIFY_FRAMES, compare_quirks, verify_determinism},
+    execute::set_instruction_set,
+    font::{FontSet, set_font_set},
+    init::{init, set_rom},
+    input::{enable_input, handle_interrupts, is_interrupted},
+    movie::{start_movie_playback, start_movie_recording},
+    phosphor::set_phosphor_frames,
+    profile::{save_profile_out, set_profile_out_path},
+    quirks::{QuirkPreset, get_quirks, set_quirks},
+    record::start_recording,
+    remote::start_remote,
+    rng::{seed_rng, time_seed},
+    rom_db::{DEFAULT_ROM_DB_PATH, apply_rom_settings, load_rom_settings, set_rom_db_path},
+    run::{RenderMode, init_display_colors, run, set_render_mode, set_start_paused},
+    screenshot::{DEFAULT_SCREENSHOT_SCALE, MAX_SCREENSHOT_SCALE, set_screenshot_scale},
+    session::load_session,
+    stats::set_stats_shown,
+    symbols::{default_symbols_path, load_symbols},
+    system::{STACK_SIZE, set_stack_depth},
+    timing::{TimingMode, set_ips, set_timing_mode},
+    user_flags::{default_user_flags_path, load_user_flags, set_user_flags_path},
 };
 
+/// A Chip-8 emulator, with a built-in debugger.
+#[derive(Parser)]
+#[allow(clippy::struct_excessive_bools)]
+#[command(version, about)]
+struct Args {
+    /// The ROM to run.
+    rom: String,
+    /// The config file [default: ~/.config/c8rs/config.toml].
+    /// Command line options take precedence over the config file.
+    #[arg(long)]
+    config: Option<String>,
+    /// The user interface to run in [default: terminal].
+    #[arg(long, value_enum)]
+    frontend: Option<Frontend>,
+    /// How the display is drawn in the terminal [default: double]. `halfblock` and `braille` fit the display in
+    /// a much smaller terminal.
+    #[arg(long, value_enum)]
+    render: Option<RenderMode>,
+    /// The font set to load into memory [default: classic].
+    #[arg(long, value_enum)]
+    font: Option<FontSet>,
+    /// The maximum number of entries in the stack [default: 16].
+    #[arg(long)]
+    stack_depth: Option<usize>,
+    /// The number of instructions to execute per second [default: 720, or the value in the ROM database].
+    /// The timers always count down at 60 HZ, regardless of this value.
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    ips: Option<usize>,
+    /// How long instructions take to execute [default: ips]. With `vip`, each instruction takes roughly as long
+    /// as it did on the COSMAC VIP, and --ips is ignored.
+    #[arg(long, value_enum)]
+    timing: Option<TimingMode>,
+    /// The instructions to decode and execute. `chip48` and `schip` treat BNNN as BXNN: jump to XNN + VX.
+    #[arg(long, value_enum, default_value_t = Platform::Xochip)]
+    instruction_set: Platform,
+    /// Use the compatibility quirks of the given interpreter, instead of the ones in the ROM database.
+    #[arg(long, value_enum)]
+    quirks: Option<QuirkPreset>,
+    /// Record the display to an animated GIF at the given path. Press F11 to stop recording.
+    #[arg(long)]
+    record: Option<String>,
+    /// Record the keypad input on every frame to a movie at the given path, so that it can be replayed
+    /// with --playback.
+    #[arg(long, conflicts_with = "playback")]
+    record_movie: Option<String>,
+    /// Replay the input from the movie at the given path.
+    /// The seed, instructions per second and timing mode are taken from the movie.
+    #[arg(long)]
+    playback: Option<String>,
+    /// The seed for the random number generator. Defaults to a seed based on the current time.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Listen for remote control commands on the given address (e.g. 127.0.0.1:8008).
+    #[arg(long)]
+    remote: Option<String>,
+    /// Serve the debug terminal on the given address (e.g. 127.0.0.1:4444) instead of showing it below the
+    /// display. Connect with `telnet` or `nc`.
+    #[arg(long)]
+    debug_listen: Option<String>,
+    /// The ROM database, which contains the settings for specific ROMs [default: roms.toml].
+    /// Settings for the current ROM are applied automatically, and can be saved from the debugger with
+    /// `quirks save`.
+    #[arg(long)]
+    rom_db: Option<String>,
+    /// A file naming addresses in the ROM, shown in the debugger [default: <ROM>.sym, if it exists].
+    /// Each line is an address and a name, e.g. `0x23A draw_player`.
+    #[arg(long)]
+    symbols: Option<String>,
+    /// Start in the debug terminal at the first instruction, instead of running the ROM right away.
+    #[arg(long)]
+    start_paused: bool,
+    /// Set a breakpoint at the given address (e.g. 0x2A4, or the name of a symbol). Can be given more than
+    /// once.
+    #[arg(long = "break", value_name = "ADDRESS")]
+    breakpoints: Vec<String>,
+    /// Don't restore the breakpoints, watches, tracepoints, aliases and macros from the last time this ROM was
+    /// debugged, or save them when the emulator exits.
+    #[arg(long)]
+    no_session: bool,
+    /// The file the SUPER-CHIP user flags (FX75/FX85) are saved to [default: <ROM>.flags].
+    #[arg(long)]
+    flags_file: Option<String>,
+    /// The number of image pixels per display pixel in screenshots taken with F12, and in recordings
+    /// [default: 8].
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    screenshot_scale: Option<usize>,
+    /// Show the actual instructions per second, frames per second, timer rate and drift from the target speed
+    /// below the display. Press F10 to show or hide them.
+    #[arg(long)]
+    stats: bool,
+    /// Fade pixels out over this many frames after they're turned off, like the phosphor on a CRT, to reduce
+    /// flicker [default: 0].
+    #[arg(long, value_parser = RangedU64ValueParser::<u8>::new().range(0..=60))]
+    phosphor: Option<u8>,
+    /// Don't play any sound.
+    #[arg(long)]
+    mute: bool,
+    /// Run the ROM as fast as possible without drawing to the terminal, then print how fast it ran.
+    #[arg(long)]
+    bench: bool,
+    /// The number of seconds to benchmark for.
+    #[arg(long, default_value_t = 10, requires = "bench", value_parser = RangedU64ValueParser::<u64>::new().range(1..))]
+    bench_seconds: u64,
+    /// Benchmark this many instructions, instead of running for a fixed time.
+    #[arg(long, requires = "bench", conflicts_with = "bench_seconds")]
+    bench_instructions: Option<u64>,
+    /// Run the ROM twice without drawing, with the same seed and input (from --playback, or no keys), and
+    /// compare the state of the machine on every frame. Exits with code 1 if the runs differ.
+    #[arg(long, group = "verify", conflicts_with_all = ["bench", "record_movie"])]
+    verify_determinism: bool,
+    /// Run the ROM on two machines in lockstep without drawing, one with the current quirks and one with these
+    /// changes (a preset, or e.g. `shift_vx_only=on,wrap_sprites=off`), and compare their state after every
+    /// instruction. Exits with code 1 if they differ, showing the instruction that made them differ.
+    #[arg(long, value_name = "QUIRKS", group = "verify", conflicts_with_all = ["bench", "record_movie"])]
+    compare_quirks: Option<String>,
+    /// The number of frames to compare with --verify-determinism or --compare-quirks.
+    #[arg(long, default_value_t = DEFAULT_VERIFY_FRAMES, requires = "verify", value_parser = RangedU64ValueParser::<u64>::new().range(1..))]
+    verify_frames: u64,
+    /// Exit after executing this many instructions.
+    #[arg(long, value_parser = RangedU64ValueParser::<u64>::new().range(1..))]
+    max_instructions: Option<u64>,
+    /// Exit once the program halts by jumping to the same instruction (e.g. `1206` at 0x206), as many ROMs do
+    /// when they're finished.
+    #[arg(long)]
+    exit_on_loop: bool,
+    /// Print the registers, timers, stack and a hash of the display to stdout when the emulator exits, so that
+    /// scripts can check them.
+    #[arg(long)]
+    print_state: bool,
+    /// Save the display to the given path when the emulator exits, as text with a line for each row: `#` for
+    /// pixels that are on, and `.` for pixels that are off.
+    #[arg(long)]
+    dump_display: Option<String>,
+    /// Save the contents of memory (all 4096 bytes) to the given path when the emulator exits.
+    #[arg(long)]
+    dump_memory: Option<String>,
+    /// Save a report of the most executed addresses and subroutines to the given path when the emulator exits.
+    #[arg(long)]
+    profile_out: Option<String>,
+    /// Save a map of the memory addresses that were executed, read and written to the given path when the emulator
+    /// exits. Pass it to c8disass to tell code apart from data.
+    #[arg(long)]
+    coverage_out: Option<String>,
+}
+
+#[allow(clippy::too_many_lines)]
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <file>", args[0]);
-        exit(0);
+    let args = Args::parse();
+    install_crash_handler();
+    let config = load_config(args.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+
+    set_user_flags_path(
+        args.flags_file
+            .unwrap_or_else(|| default_user_flags_path(&args.rom))
+            .leak(),
+    );
+    // The default symbol file is optional
+    let symbols = match args.symbols {
+        Some(path) => load_symbols(&path, false),
+        None => load_symbols(&default_symbols_path(&args.rom), true),
+    };
+    if let Err(e) = symbols {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = load_aliases(&config.aliases, &config.macros) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    // The ROM is read first, since the session and the ROM database are found by its SHA-1
+    match Rom::load(Path::new(&args.rom), ROM_START) {
+        Ok(rom) => set_rom(rom),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+    // These are set before the session is loaded, so that they aren't saved to it unless something else changes
+    for addr in &args.breakpoints {
+        match parse_addr(addr) {
+            Ok(addr) => {
+                add_breakpoint(addr);
+            }
+            Err(e) => {
+                eprintln!("invalid --break: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    set_start_paused(args.start_paused);
+    if !args.no_session {
+        if let Err(e) = load_session() {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+    set_font_set(args.font.or(config.font).unwrap_or(FontSet::Classic));
+    set_stack_depth(
+        args.stack_depth
+            .or(config.stack_depth)
+            .unwrap_or(STACK_SIZE),
+    );
+    set_rom_db_path(
+        args.rom_db
+            .or_else(|| config.rom_db.clone())
+            .unwrap_or_else(|| String::from(DEFAULT_ROM_DB_PATH))
+            .leak(),
+    );
+    // The ROM database takes precedence over the config file
+    if let Err(e) = load_rom_settings().and_then(|settings| {
+        apply_rom_settings(&settings.unwrap_or_default().or(config.rom_settings()))
+    }) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    // Command line options take precedence over the ROM database
+    set_instruction_set(args.instruction_set.into());
+    if let Some(preset) = args.quirks {
+        set_quirks(preset.quirks());
+    }
+    if let Some(ips) = args.ips {
+        set_ips(ips);
+    }
+    set_timing_mode(args.timing.or(config.timing).unwrap_or(TimingMode::Ips));
+    seed_rng(args.seed.unwrap_or_else(time_seed));
+    let screenshot_scale = args
+        .screenshot_scale
+        .or(config.screenshot_scale)
+        .unwrap_or(DEFAULT_SCREENSHOT_SCALE);
+    if !(1..=MAX_SCREENSHOT_SCALE).contains(&screenshot_scale) {
+        eprintln!("the screenshot scale must be between 1 and {MAX_SCREENSHOT_SCALE}");
+        std::process::exit(1);
+    }
+    set_screenshot_scale(screenshot_scale);
+    set_render_mode(args.render.or(config.render).unwrap_or_default());
+    set_stats_shown(args.stats || config.stats);
+    set_phosphor_frames(args.phosphor.or(config.phosphor).unwrap_or(0));
+    match config.display_colors() {
+        Ok((on, off)) => init_display_colors(on, off),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+    set_max_instructions(args.max_instructions.map(u128::from));
+    set_exit_on_loop(args.exit_on_loop);
+    set_print_state(args.print_state);
+    if let Some(path) = args.dump_display {
+        set_dump_display_path(path.leak());
+    }
+    if let Some(path) = args.dump_memory {
+        set_dump_memory_path(path.leak());
+    }
+    if let Some(path) = args.profile_out {
+        set_profile_out_path(path.leak());
+    }
+    if let Some(path) = args.coverage_out {
+        set_coverage_out_path(path.leak());
     }
 
-    set_rom_path(args[1].clone().leak()); // TODO: Better way to do this?
+    if let Err(e) = init() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    if let Err(e) = load_user_flags() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    if let Err(e) = args.record.as_deref().map_or(Ok(()), start_recording) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = args.remote.as_deref().map_or(Ok(()), start_remote) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = args
+        .debug_listen
+        .as_deref()
+        .map_or(Ok(()), start_debug_server)
+    {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = args
+        .playback
+        .as_deref()
+        .map_or(Ok(()), start_movie_playback)
+    {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = args
+        .record_movie
+        .as_deref()
+        .map_or(Ok(()), start_movie_recording)
+    {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    if args.verify_determinism {
+        let report = verify_determinism(args.verify_frames);
+        println!("{report}");
+        std::process::exit(i32::from(report.divergence.is_some()));
+    }
+    if let Some(spec) = args.compare_quirks {
+        let other = match get_quirks().with_changes(&spec) {
+            Ok(quirks) => quirks,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        };
+        let report = compare_quirks(other, args.verify_frames);
+        println!("{report}");
+        std::process::exit(i32::from(report.divergence.is_some()));
+    }
 
-    init();
-    run();
+    if args.bench {
+        let limit = args.bench_instructions.map_or(
+            BenchLimit::Time(Duration::from_secs(args.bench_seconds)),
+            BenchLimit::Instructions,
+        );
+        // Ctrl+C stops the benchmark early, so that the profile and coverage are still saved
+        handle_interrupts();
+        let report = run_bench(limit);
+        println!("{report}");
+        if let Err(e) = save_profile_out()
+            .and_then(|()| save_coverage_out())
+            .and_then(|()| save_dumps())
+        {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        if is_interrupted() {
+            std::process::exit(130);
+        }
+        std::process::exit(i32::from(report.error.is_some()));
+    }
+
+    // The stream stops playing when it is dropped, so keep it alive until the emulator exits
+    let _audio_stream = if args.mute || config.audio.mute {
+        None
+    } else {
+        start_audio()
+            .inspect_err(|e| eprintln!("warning: no sound: {e}"))
+            .ok()
+    };
+
+    match args.frontend.or(config.frontend).unwrap_or_default() {
+        Frontend::Terminal => {
+            handle_interrupts();
+            if let Err(e) = enable_input() {
+                eprintln!("could not read keys from the terminal: {e}");
+                std::process::exit(1);
+            }
+            run();
+        }
+    }
 }
@@ -0,0 +1,205 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    input::InputSource,
+    rng::{get_rng_seed, seed_rng},
+    run::set_status_message,
+    timing::{TimingMode, get_ips, get_timing_mode, set_ips, set_timing_mode},
+};
+
+/// The first line of every movie file.
+const MOVIE_HEADER: &str = "c8m 1";
+
+/// A movie that is being recorded or played back.
+///
+/// Movies are text files. The first line is `c8m 1`, followed by the RNG seed, instructions per second and
+/// timing mode (`seed <n>`, `ips <n>` and `timing <ips | vip>`) and then the state of the keypad on each
+/// frame, one frame per line.
+/// The keypad state is a 16 bit hexadecimal number, where bit N is set if key N is pressed.
+pub enum Movie {
+    /// Every frame of input is written to the file as it happens.
+    Recording { path: String, file: File },
+    /// Input is read from the frames instead of the keyboard.
+    Playback(MoviePlayback),
+}
+
+/// The frames of a movie that is being played back, as an input source.
+pub struct MoviePlayback {
+    /// The keypad on each frame.
+    pub frames: Vec<u16>,
+    /// The frame that will be played next.
+    pub next_frame: usize,
+}
+
+impl MoviePlayback {
+    /// Returns whether every frame has been played.
+    pub fn is_finished(&self) -> bool {
+        self.next_frame >= self.frames.len()
+    }
+}
+
+impl InputSource for MoviePlayback {
+    /// The keypad on the next frame, or no keys once the movie has finished.
+    fn keypad(&mut self) -> u16 {
+        let keypad = self.frames.get(self.next_frame).copied().unwrap_or(0);
+        self.next_frame += 1;
+        keypad
+    }
+}
+
+/// The movie that is currently being recorded or played back, if any.
+pub static mut MOVIE: Option<Movie> = None;
+
+/// Start recording input to a movie at the given path.
+/// This should be called after the RNG has been seeded and the instructions per second and timing mode
+/// have been set, since they are saved in the movie.
+///
+/// # Errors
+/// Returns an error if the file could not be written.
+pub fn start_movie_recording(path: &str) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| format!("failed to create {path}: {e}"))?;
+    writeln!(
+        file,
+        "{MOVIE_HEADER}\nseed {}\nips {}\ntiming {}",
+        get_rng_seed(),
+        get_ips(),
+        get_timing_mode().to_possible_value().unwrap().get_name()
+    )
+    .map_err(|e| format!("failed to write {path}: {e}"))?;
+
+    // SAFETY: single threaded
+    unsafe {
+        MOVIE = Some(Movie::Recording {
+            path: String::from(path),
+            file,
+        });
+    };
+    Ok(())
+}
+
+/// Load the movie at the given path and start playing it back.
+/// This seeds the RNG and sets the instructions per second and timing mode to the values saved in the movie.
+///
+/// # Errors
+/// Returns an error if the file could not be read or is not a valid movie.
+pub fn start_movie_playback(path: &str) -> Result<(), String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    if lines.next().map(|(_, line)| line) != Some(MOVIE_HEADER) {
+        return Err(format!(
+            "{path} is not a movie (expected \"{MOVIE_HEADER}\")"
+        ));
+    }
+
+    let mut seed = None;
+    let mut ips = None;
+    // Older movies don't have a timing mode
+    let mut timing = TimingMode::Ips;
+    let mut frames = Vec::new();
+    for (line_number, line) in lines {
+        let invalid = || format!("{path}:{line_number}: invalid line: {line}");
+        if let Some(val) = line.strip_prefix("seed ") {
+            seed = Some(val.trim().parse::<u64>().map_err(|_| invalid())?);
+        } else if let Some(val) = line.strip_prefix("timing ") {
+            timing = TimingMode::from_str(val.trim(), true).map_err(|_| invalid())?;
+        } else if let Some(val) = line.strip_prefix("ips ") {
+            ips = Some(
+                val.trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|ips| *ips > 0)
+                    .ok_or_else(invalid)?,
+            );
+        } else {
+            frames.push(u16::from_str_radix(line, 16).map_err(|_| invalid())?);
+        }
+    }
+
+    seed_rng(seed.ok_or_else(|| format!("{path} does not contain a seed"))?);
+    set_ips(ips.ok_or_else(|| format!("{path} does not contain the instructions per second"))?);
+    set_timing_mode(timing);
+
+    // SAFETY: single threaded
+    unsafe {
+        MOVIE = Some(Movie::Playback(MoviePlayback {
+            frames,
+            next_frame: 0,
+        }));
+    };
+    Ok(())
+}
+
+/// Handles the input for a new frame, given the keys that are actually pressed.
+/// When recording, the keypad is saved to the movie. When playing back, the keypad from the movie is
+/// returned instead. This should be called exactly once per frame.
+pub fn movie_input(keypad: u16) -> u16 {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let movie = unsafe { MOVIE.as_mut() };
+    match movie {
+        None => keypad,
+        Some(Movie::Recording { path, file }) => {
+            if let Err(e) = writeln!(file, "{keypad:04X}") {
+                set_status_message(format!(
+                    "Movie recording stopped: failed to write {path}: {e}"
+                ));
+                // SAFETY: single threaded
+                unsafe { MOVIE = None };
+            }
+            keypad
+        }
+        Some(Movie::Playback(playback)) => {
+            if playback.is_finished() {
+                set_status_message(String::from("Movie playback finished"));
+                // SAFETY: single threaded
+                unsafe { MOVIE = None };
+                keypad
+            } else {
+                playback.keypad()
+            }
+        }
+    }
+}
+
+/// Returns whether a movie is being recorded.
+pub fn is_recording_movie() -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        matches!(MOVIE, Some(Movie::Recording { .. }))
+    }
+}
+
+/// Returns the keypad on the given frame of the movie that's being played back, if there is one and it's long
+/// enough.
+pub fn movie_frame(frame: usize) -> Option<u16> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        match &MOVIE {
+            Some(Movie::Playback(playback)) => playback.frames.get(frame).copied(),
+            _ => None,
+        }
+    }
+}
+
+/// Continue playing back the movie from the given frame, e.g. after travelling back in time.
+pub fn seek_movie(frame: usize) {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        if let Some(Movie::Playback(playback)) = &mut MOVIE {
+            playback.next_frame = frame;
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{
+    run::Rgb,
+    system::{DISPLAY_HEIGHT, DISPLAY_WIDTH, get_full_display},
+};
+
+/// The number of frames pixels take to fade out after they're turned off, like the phosphor on a CRT.
+/// 0 turns them off immediately.
+pub static mut PHOSPHOR_FRAMES: u8 = 0;
+
+pub fn get_phosphor_frames() -> u8 {
+    // SAFETY: single threaded
+    unsafe { PHOSPHOR_FRAMES }
+}
+
+pub fn set_phosphor_frames(val: u8) {
+    // SAFETY: single threaded
+    unsafe { PHOSPHOR_FRAMES = val };
+}
+
+/// How brightly each pixel is still glowing, from `PHOSPHOR_FRAMES` (just turned off) down to 0 (dark).
+/// Pixels that are on are always at `PHOSPHOR_FRAMES`.
+pub static mut PHOSPHOR: [[u8; DISPLAY_HEIGHT]; DISPLAY_WIDTH] =
+    [[0; DISPLAY_HEIGHT]; DISPLAY_WIDTH];
+
+/// Returns how brightly each pixel is still glowing. See `PHOSPHOR`.
+pub fn get_phosphor() -> [[u8; DISPLAY_HEIGHT]; DISPLAY_WIDTH] {
+    // SAFETY: single threaded
+    unsafe { PHOSPHOR }
+}
+
+pub fn set_phosphor(phosphor: [[u8; DISPLAY_HEIGHT]; DISPLAY_WIDTH]) {
+    // SAFETY: single threaded
+    unsafe { PHOSPHOR = phosphor };
+}
+
+/// Fade the pixels that are off by a frame. Called at the start of each frame.
+pub fn update_phosphor() {
+    let frames = get_phosphor_frames();
+    if frames == 0 {
+        return;
+    }
+    let display = get_full_display();
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        for (column, display_column) in PHOSPHOR.iter_mut().zip(display) {
+            for (level, is_set) in column.iter_mut().zip(display_column) {
+                *level = if is_set {
+                    frames
+                } else {
+                    level.saturating_sub(1)
+                };
+            }
+        }
+    }
+}
+
+/// Returns the color of a pixel that's fading out at the given level (see `PHOSPHOR`), between the colors of
+/// pixels that are on and off.
+pub fn fade_color(on: Rgb, off: Rgb, level: u8) -> Rgb {
+    let frames = u16::from(get_phosphor_frames().max(1));
+    let level = u16::from(level).min(frames);
+    let mut color = off;
+    for (c, (on, off)) in color.iter_mut().zip(on.into_iter().zip(off)) {
+        let (on, off) = (u16::from(on), u16::from(off));
+        // Fading pixels are never quite as bright as pixels that are on
+        let mixed = if on >= off {
+            off + (on - off) * level / (frames + 1)
+        } else {
+            off - (off - on) * level / (frames + 1)
+        };
+        *c = u8::try_from(mixed).unwrap();
+    }
+    color
+}
@@ -0,0 +1,288 @@
+use std::{collections::BTreeMap, fmt::Write as _, fs};
+
+use c8util::{
+    decode::decode,
+    instructions::{Category, Instruction, VIP_CYCLES_PER_FRAME},
+};
+
+use crate::{
+    execute::get_instruction_set,
+    hooks::{InstructionEvent, Observer, Outcome},
+    system::{MEMORY_SIZE, get_memory_u16, get_pc, get_stack_depth},
+    timing::{FRAME_RATE, get_frame_count},
+};
+
+/// The number of entries in each table of the report saved with `--profile-out`.
+pub const PROFILE_OUT_ENTRIES: usize = 20;
+
+/// How often a subroutine was called, and how long it ran for.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    /// The number of instructions executed while the subroutine was running, including the subroutines it called.
+    /// Time is measured in instructions, since that (not the speed of the host) is what limits how fast a ROM runs.
+    pub instructions: u64,
+}
+
+/// Returns the opcodes in the category, for the instruction histogram.
+fn category_opcodes(category: Category) -> &'static str {
+    match category {
+        Category::ControlFlow => "0NNN 00EE 00FD 1NNN 2NNN BNNN 3XNN 4XNN 5XY0 9XY0",
+        Category::Alu => "6XNN 7XNN 8XY0-8XYE CXNN",
+        Category::Memory => "ANNN F000 FX1E FX29 FX30 FX33 FX55 FX65 FX75 FX85 5XY2 5XY3",
+        Category::Display => "00E0 DXYN 00CN 00DN 00FB 00FC 00FE 00FF FN01",
+        Category::Input => "EX9E EXA1 FX0A",
+        Category::Timer => "FX07 FX15 FX18 FX3A F002",
+        Category::Data => "data executed as code",
+    }
+}
+
+/// How often each address and subroutine has been executed.
+pub struct Profile {
+    /// The number of times the instruction at each address has been executed.
+    counts: Vec<u64>,
+    /// The total number of instructions executed.
+    total: u64,
+    /// The stats for each subroutine, by address.
+    subroutines: BTreeMap<u16, SubroutineStats>,
+    /// The subroutines that are currently running, and the total number of instructions executed when they were called.
+    /// At most as deep as the emulator's stack (see `system::get_stack_depth`).
+    call_stack: Vec<(u16, u64)>,
+    /// The number of instructions executed in each category, in the order of `Category::ALL`.
+    categories: [u64; Category::ALL.len()],
+    /// Roughly how many machine cycles the instructions would have taken on the COSMAC VIP.
+    vip_cycles: u64,
+    /// The frame that profiling started on.
+    start_frame: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; MEMORY_SIZE],
+            total: 0,
+            subroutines: BTreeMap::new(),
+            call_stack: Vec::new(),
+            categories: [0; Category::ALL.len()],
+            vip_cycles: 0,
+            start_frame: get_frame_count(),
+        }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The profile of the current run. None until the first instruction is executed.
+pub static mut PROFILE: Option<Profile> = None;
+
+/// The path to save the profile to when the emulator exits, if any.
+pub static mut PROFILE_OUT_PATH: Option<&str> = None;
+
+pub fn set_profile_out_path(path: &'static str) {
+    // SAFETY: single threaded
+    unsafe { PROFILE_OUT_PATH = Some(path) };
+}
+
+pub fn get_profile_out_path() -> Option<&'static str> {
+    // SAFETY: single threaded
+    unsafe { PROFILE_OUT_PATH }
+}
+
+/// Record that the given instruction, at the given address, was executed.
+/// This should only be called once the instruction has executed successfully.
+pub fn profile_instruction(addr: u16, instruction: Instruction) {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let profile = unsafe { PROFILE.get_or_insert_with(Profile::new) };
+
+    profile.counts[usize::from(addr) % MEMORY_SIZE] += 1;
+    profile.total += 1;
+    // Draws and key waits are executed again until they finish, which shouldn't count as more of them
+    let is_waiting =
+        matches!(instruction, Instruction::Draw(..) | Instruction::GetKey(_)) && get_pc() == addr;
+    if !is_waiting {
+        profile.categories[instruction.category() as usize] += 1;
+        profile.vip_cycles += u64::from(instruction.cycles(get_instruction_set()));
+    }
+    match instruction {
+        Instruction::SubroutineCall(nnn) => {
+            profile.subroutines.entry(nnn).or_default().calls += 1;
+            // The stack can be changed from the debugger, so calls might never return. Keep it as deep as the
+            // emulator's, dropping the oldest call, so that it doesn't grow forever.
+            profile.call_stack.push((nnn, profile.total));
+            if profile.call_stack.len() > get_stack_depth() {
+                profile.call_stack.remove(0);
+            }
+        }
+        Instruction::SubroutineReturn => {
+            // The stack can be changed from the debugger, so there might not be a matching call
+            if let Some((nnn, start)) = profile.call_stack.pop() {
+                profile.subroutines.entry(nnn).or_default().instructions += profile.total - start;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Profiles each instruction once it has executed successfully.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProfileObserver;
+
+impl Observer for ProfileObserver {
+    fn on_instruction(&mut self, event: &InstructionEvent) {
+        // Failed instructions are executed again, so only count them once they succeed
+        if event.outcome != Outcome::Failed {
+            profile_instruction(event.addr, event.instruction);
+        }
+    }
+}
+
+/// Clear the profile.
+pub fn reset_profile() {
+    // SAFETY: single threaded
+    unsafe { PROFILE = None };
+}
+
+/// Returns the instruction at the given address in assembly.
+fn disassemble(addr: u16) -> String {
+    let Ok(instruction_raw) = get_memory_u16(addr) else {
+        return String::from("??");
+    };
+    decode(instruction_raw, get_instruction_set())
+        .unwrap_or(Instruction::Db(instruction_raw))
+        .to_string()
+}
+
+/// Returns a report of the `n` most executed addresses and the `n` subroutines that ran the longest, and roughly
+/// how long the instructions would have taken on the COSMAC VIP (see `Instruction::cycles`).
+#[allow(clippy::cast_precision_loss)]
+pub fn profile_report(n: usize) -> String {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let profile = unsafe { PROFILE.get_or_insert_with(Profile::new) };
+    let percent = |count: u64| 100.0 * count as f64 / profile.total.max(1) as f64;
+
+    let mut out = String::new();
+    writeln!(out, "{} instructions executed", profile.total).unwrap();
+    let vip_seconds = profile.vip_cycles as f64 / f64::from(VIP_CYCLES_PER_FRAME * FRAME_RATE);
+    writeln!(
+        out,
+        "About {vip_seconds:.2} seconds on a COSMAC VIP ({} machine cycles)",
+        profile.vip_cycles
+    )
+    .unwrap();
+
+    let mut addresses = (0u16..)
+        .zip(profile.counts.iter().copied())
+        .filter(|&(_, count)| count > 0)
+        .collect::<Vec<_>>();
+    addresses.sort_by(|(a_addr, a), (b_addr, b)| b.cmp(a).then(a_addr.cmp(b_addr)));
+    writeln!(out).unwrap();
+    writeln!(out, "Hottest addresses:").unwrap();
+    writeln!(out, "Address  Count         %       Instruction").unwrap();
+    for (addr, count) in addresses.into_iter().take(n) {
+        writeln!(
+            out,
+            "{addr:#06X}   {count:<12}  {:>5.1}%  {}",
+            percent(count),
+            disassemble(addr)
+        )
+        .unwrap();
+    }
+
+    // Include the subroutines that haven't returned yet
+    let mut subroutines = profile.subroutines.clone();
+    for &(nnn, start) in &profile.call_stack {
+        subroutines.entry(nnn).or_default().instructions += profile.total - start;
+    }
+    let mut subroutines = subroutines.into_iter().collect::<Vec<_>>();
+    subroutines.sort_by(|(a_addr, a), (b_addr, b)| {
+        b.instructions.cmp(&a.instructions).then(a_addr.cmp(b_addr))
+    });
+    writeln!(out).unwrap();
+    writeln!(out, "Hottest subroutines:").unwrap();
+    writeln!(
+        out,
+        "Address  Calls     Instructions  %       First instruction"
+    )
+    .unwrap();
+    for (addr, stats) in subroutines.into_iter().take(n) {
+        writeln!(
+            out,
+            "{addr:#06X}   {:<8}  {:<12}  {:>5.1}%  {}",
+            stats.calls,
+            stats.instructions,
+            percent(stats.instructions),
+            disassemble(addr)
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Returns a table of how many instructions of each category have been executed, and how many per frame on
+/// average, most executed first. Draws and key waits only count once they finish.
+#[allow(clippy::cast_precision_loss)]
+pub fn instruction_histogram() -> String {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let profile = unsafe { PROFILE.get_or_insert_with(Profile::new) };
+    // The frame count goes back when stepping back in the debugger
+    let frames = get_frame_count().saturating_sub(profile.start_frame);
+    let per_frame = |count: u64| count as f64 / frames.max(1) as f64;
+    let total = profile.categories.iter().sum::<u64>();
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{total} instructions executed over {frames} frames ({:.1} per frame)",
+        per_frame(total)
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "Category      Count         %       Per frame  Opcodes"
+    )
+    .unwrap();
+    let mut categories = Category::ALL
+        .into_iter()
+        .zip(profile.categories)
+        .collect::<Vec<_>>();
+    categories.sort_by(|(_, a), (_, b)| b.cmp(a));
+    for (category, count) in categories {
+        let name = category.to_string();
+        writeln!(
+            out,
+            "{name:<12}  {count:<12}  {:>5.1}%  {:<9.2}  {}",
+            100.0 * count as f64 / total.max(1) as f64,
+            per_frame(count),
+            category_opcodes(category)
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Save a report of the profile to the given path.
+///
+/// # Errors
+/// Returns an error if the file could not be written.
+pub fn save_profile(path: &str, n: usize) -> Result<(), String> {
+    fs::write(path, profile_report(n)).map_err(|e| format!("failed to write {path}: {e}"))
+}
+
+/// Save the profile to the path given with `--profile-out`, if there is one.
+///
+/// # Errors
+/// Returns an error if the file could not be written.
+pub fn save_profile_out() -> Result<(), String> {
+    match get_profile_out_path() {
+        Some(path) => save_profile(path, PROFILE_OUT_ENTRIES),
+        None => Ok(()),
+    }
+}
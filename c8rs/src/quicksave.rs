@@ -0,0 +1,43 @@
+use crate::{
+    movie::{is_recording_movie, seek_movie},
+    snapshot::{HISTORY, Snapshot},
+    timing::get_frame_count,
+};
+
+/// The number of quicksave slots, bound to F5 to F8.
+pub const QUICKSAVE_SLOTS: usize = 4;
+
+/// The state saved in each quicksave slot, if any. Slots are kept until the emulator exits.
+pub static mut SLOTS: [Option<Snapshot>; QUICKSAVE_SLOTS] = [None, None, None, None];
+
+/// Save the current state of the machine to the given slot (0 to 3), replacing what was saved there.
+pub fn quicksave(slot: usize, instructions_executed: u128) {
+    // SAFETY: single threaded
+    unsafe { SLOTS[slot] = Some(Snapshot::take(instructions_executed)) };
+}
+
+/// Put the machine back into the state saved in the given slot (0 to 3), and return the number of instructions
+/// that had been executed when it was saved.
+/// The history is cleared, since it belongs to the state that was left. A movie that's being played back
+/// continues from the frame that was loaded.
+///
+/// # Errors
+/// Returns an error if nothing was saved in the slot, or a movie is being recorded (loading would make the
+/// movie impossible to replay).
+pub fn quickload(slot: usize) -> Result<u128, String> {
+    if is_recording_movie() {
+        return Err(String::from("can't load while recording a movie"));
+    }
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let snapshot =
+        unsafe { SLOTS[slot].as_ref() }.ok_or_else(|| format!("slot {} is empty", slot + 1))?;
+    snapshot.restore();
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        HISTORY.clear();
+    }
+    seek_movie(usize::try_from(get_frame_count()).unwrap());
+    Ok(snapshot.instructions_executed)
+}
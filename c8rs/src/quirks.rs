@@ -0,0 +1,122 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Compatibility options for behavior that differs between Chip-8 interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    /// FX0A: Wait for the key to be pressed and then released before storing it (COSMAC VIP behavior).
+    /// If false, the key is stored as soon as it is pressed.
+    pub get_key_on_release: bool,
+    /// DXYN: Wrap sprites that are drawn past the edge of the screen around to the other side.
+    /// If false, the sprite is clipped at the edge of the screen.
+    pub wrap_sprites: bool,
+    /// 8XY6/8XYE: Shift VX in place and ignore VY (CHIP-48 and SUPER-CHIP behavior).
+    /// If false, VY is copied into VX before shifting (COSMAC VIP behavior).
+    pub shift_vx_only: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            get_key_on_release: true,
+            wrap_sprites: false,
+            shift_vx_only: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// The names of all quirks, as used in the debug terminal.
+    pub const NAMES: &[&str] = &["get_key_on_release", "wrap_sprites", "shift_vx_only"];
+
+    /// Get the value of the quirk with the given name.
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "get_key_on_release" => Some(self.get_key_on_release),
+            "wrap_sprites" => Some(self.wrap_sprites),
+            "shift_vx_only" => Some(self.shift_vx_only),
+            _ => None,
+        }
+    }
+
+    /// Set the value of the quirk with the given name.
+    ///
+    /// # Errors
+    /// Returns an error if there is no quirk with the given name.
+    pub fn set(&mut self, name: &str, val: bool) -> Result<(), String> {
+        match name {
+            "get_key_on_release" => self.get_key_on_release = val,
+            "wrap_sprites" => self.wrap_sprites = val,
+            "shift_vx_only" => self.shift_vx_only = val,
+            _ => return Err(format!("unknown quirk: {name}")),
+        }
+        Ok(())
+    }
+
+    /// Returns these quirks with the changes in `spec` applied. `spec` is either the name of a preset (e.g.
+    /// `chip48`), or a comma-separated list of quirks to set (e.g. `shift_vx_only=on,wrap_sprites=off`).
+    ///
+    /// # Errors
+    /// Returns an error if a quirk or value in `spec` is invalid.
+    pub fn with_changes(mut self, spec: &str) -> Result<Self, String> {
+        if let Ok(preset) = QuirkPreset::from_str(spec, true) {
+            return Ok(preset.quirks());
+        }
+        for change in spec.split(',') {
+            let Some((name, val)) = change.split_once('=') else {
+                return Err(format!(
+                    "invalid quirk change: {change} (expected a preset or <name>=<on | off>)"
+                ));
+            };
+            let val = match val.trim() {
+                "on" | "1" | "true" => true,
+                "off" | "0" | "false" => false,
+                _ => return Err(format!("invalid value for quirk {}: {val}", name.trim())),
+            };
+            self.set(name.trim(), val)?;
+        }
+        Ok(self)
+    }
+}
+
+/// Sets of quirks that match a particular interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuirkPreset {
+    /// The original COSMAC VIP interpreter. These are the default quirks.
+    Vip,
+    /// CHIP-48 on the HP-48 calculators, which the SUPER-CHIP is based on.
+    Chip48,
+}
+
+impl QuirkPreset {
+    /// Returns the quirks of the interpreter.
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Self::Vip => Quirks::default(),
+            Self::Chip48 => Quirks {
+                get_key_on_release: true,
+                wrap_sprites: false,
+                shift_vx_only: true,
+            },
+        }
+    }
+}
+
+/// The quirks that are currently in use.
+pub static mut QUIRKS: Quirks = Quirks {
+    get_key_on_release: true,
+    wrap_sprites: false,
+    shift_vx_only: false,
+};
+
+pub fn get_quirks() -> Quirks {
+    // SAFETY: single threaded
+    unsafe { QUIRKS }
+}
+
+pub fn set_quirks(quirks: Quirks) {
+    // SAFETY: single threaded
+    unsafe { QUIRKS = quirks };
+}
@@ -0,0 +1,170 @@
+use std::{borrow::Cow, fs::File, io::BufWriter};
+
+use crate::{
+    hooks::Observer,
+    run::set_status_message,
+    screenshot::{get_screenshot_scale, is_pixel_set},
+    system::{DISPLAY_HEIGHT, DISPLAY_WIDTH, get_full_display},
+    timing::FRAME_RATE,
+};
+
+/// Black and white.
+const PALETTE: [u8; 6] = [0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF];
+
+/// A recording of the display that is in progress.
+pub struct Recording {
+    /// The path the recording is being saved to.
+    path: String,
+    encoder: gif::Encoder<BufWriter<File>>,
+    /// The number of image pixels per display pixel.
+    scale: usize,
+    /// The number of frames that have been captured so far.
+    n_frames: u64,
+    /// The last captured display, and the frame it was first seen on.
+    /// It is only written once the display changes, so that identical frames are merged into one.
+    pending: Option<([[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH], u64)>,
+}
+
+impl Recording {
+    /// Write the pending frame, which lasted until the given frame.
+    fn flush(&mut self, end_frame: u64) -> Result<(), String> {
+        let Some((display, start_frame)) = self.pending.take() else {
+            return Ok(());
+        };
+
+        let width = DISPLAY_WIDTH * self.scale;
+        let height = DISPLAY_HEIGHT * self.scale;
+        let mut buffer = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                buffer.push(u8::from(is_pixel_set(&display, x, y, self.scale)));
+            }
+        }
+
+        let frame = gif::Frame {
+            width: u16::try_from(width).map_err(|e| e.to_string())?,
+            height: u16::try_from(height).map_err(|e| e.to_string())?,
+            // GIF delays are in hundredths of a second, which don't divide evenly into frames.
+            // Round the start and end times separately so the error doesn't accumulate.
+            delay: u16::try_from(
+                frame_to_centiseconds(end_frame) - frame_to_centiseconds(start_frame),
+            )
+            .unwrap_or(u16::MAX),
+            buffer: Cow::Owned(buffer),
+            ..gif::Frame::default()
+        };
+        self.encoder
+            .write_frame(&frame)
+            .map_err(|e| format!("failed to write {}: {e}", self.path))
+    }
+}
+
+/// Returns the time that the given frame starts at, in hundredths of a second.
+fn frame_to_centiseconds(frame: u64) -> u64 {
+    frame * 100 / u64::from(FRAME_RATE)
+}
+
+/// The recording that is in progress, if any.
+pub static mut RECORDING: Option<Recording> = None;
+
+/// Returns whether the display is currently being recorded.
+pub fn is_recording() -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        RECORDING.is_some()
+    }
+}
+
+/// Start recording the display to an animated GIF at the given path.
+/// Any recording that is already in progress is stopped first.
+///
+/// # Errors
+/// Returns an error if the file could not be created, or if the previous recording could not be saved.
+pub fn start_recording(path: &str) -> Result<(), String> {
+    if is_recording() {
+        stop_recording()?;
+    }
+
+    let scale = get_screenshot_scale();
+    let file = File::create(path).map_err(|e| format!("failed to create {path}: {e}"))?;
+    let mut encoder = gif::Encoder::new(
+        BufWriter::new(file),
+        u16::try_from(DISPLAY_WIDTH * scale).map_err(|e| e.to_string())?,
+        u16::try_from(DISPLAY_HEIGHT * scale).map_err(|e| e.to_string())?,
+        &PALETTE,
+    )
+    .map_err(|e| format!("failed to write {path}: {e}"))?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|e| format!("failed to write {path}: {e}"))?;
+
+    // SAFETY: single threaded
+    unsafe {
+        RECORDING = Some(Recording {
+            path: String::from(path),
+            encoder,
+            scale,
+            n_frames: 0,
+            pending: None,
+        });
+    };
+    Ok(())
+}
+
+/// Stop recording and finish writing the GIF. Returns the path it was saved to.
+///
+/// # Errors
+/// Returns an error if nothing is being recorded, or if the file could not be written.
+pub fn stop_recording() -> Result<String, String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let Some(mut recording) = (unsafe { RECORDING.take() }) else {
+        return Err(String::from("not recording"));
+    };
+
+    recording.flush(recording.n_frames)?;
+    // The encoder writes the GIF trailer when it is dropped
+    Ok(recording.path)
+}
+
+/// Capture the current display as the next frame of the recording, if there is one.
+/// This should be called once per frame.
+///
+/// # Errors
+/// Returns an error if the frame could not be written. The recording is stopped.
+pub fn record_frame() -> Result<(), String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let Some(recording) = (unsafe { RECORDING.as_mut() }) else {
+        return Ok(());
+    };
+
+    let display = get_full_display();
+    let n_frames = recording.n_frames;
+    if recording
+        .pending
+        .is_none_or(|(pending_display, _)| pending_display != display)
+    {
+        if let Err(e) = recording.flush(n_frames) {
+            // SAFETY: single threaded
+            unsafe { RECORDING = None };
+            return Err(e);
+        }
+        recording.pending = Some((display, n_frames));
+    }
+    recording.n_frames += 1;
+    Ok(())
+}
+
+/// Captures a frame of the recording at the start of every frame.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RecordObserver;
+
+impl Observer for RecordObserver {
+    fn on_timer_tick(&mut self) {
+        if let Err(e) = record_frame() {
+            set_status_message(format!("Recording stopped: {e}"));
+        }
+    }
+}
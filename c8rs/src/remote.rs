@@ -0,0 +1,346 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path},
+    sync::mpsc::{Receiver, Sender, channel},
+    thread,
+};
+
+use c8util::register::Register;
+
+use crate::{
+    debug_terminal::parse_num,
+    hooks::{InstructionEvent, Observer},
+    input::InputSource,
+    screenshot::{DEFAULT_SCREENSHOT_SCALE, save_screenshot},
+    system::{
+        MEMORY_SIZE, get_delay_timer, get_i, get_memory_u8, get_pc, get_register, get_sound_timer,
+        set_delay_timer, set_i, set_memory_u8, set_pc, set_register, set_sound_timer,
+    },
+};
+
+/// A command from a remote client, and where to send the response.
+type Request = (String, Sender<String>);
+
+/// A line-based remote control server, so that external tools can drive the emulator over TCP.
+///
+/// Each line sent by a client is a command, and each command gets a single line in response: `ok`,
+/// optionally followed by the result, or `error <message>`.
+///
+/// Commands:
+/// - `pause`, `resume`
+/// - `step [n]`: Execute n instructions (default 1) while paused. Responds once they've been executed.
+/// - `read mem <addr> [len]`, `write mem <addr> <byte>...`
+/// - `read reg <v0-vf | i | pc | delay | sound>`, `write reg <v0-vf | i | pc | delay | sound> <value>`
+/// - `press key <0-f>`, `release key <0-f>`
+/// - `screenshot <file> [scale]`
+pub struct RemoteControl {
+    /// Receives commands from every client.
+    rx: Receiver<Request>,
+    /// Whether execution is paused.
+    paused: bool,
+    /// The number of instructions left to step while paused, and the client to tell when they're done.
+    step: Option<(usize, Sender<String>)>,
+    /// The keys pressed by clients. Bit N is set if key N is pressed.
+    keypad: u16,
+}
+
+impl RemoteControl {
+    /// Start listening for clients on the given address, e.g. `127.0.0.1:8008`.
+    ///
+    /// # Errors
+    /// Returns an error if the address could not be bound.
+    pub fn listen(addr: &str) -> Result<Self, String> {
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("failed to listen on {addr}: {e}"))?;
+        let (tx, rx) = channel::<Request>();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let tx = tx.clone();
+                thread::spawn(move || handle_client(stream, &tx));
+            }
+        });
+        Ok(Self {
+            rx,
+            paused: false,
+            step: None,
+            keypad: 0,
+        })
+    }
+
+    /// Handle any pending commands. Returns whether the next instruction should be executed.
+    fn poll(&mut self) -> bool {
+        while let Ok((line, response_tx)) = self.rx.try_recv() {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if let Some(&"step") = args.first() {
+                // The response is sent once the instructions have been executed
+                match self.step_command(&args) {
+                    Ok(n) => self.step = Some((n, response_tx)),
+                    Err(e) => {
+                        let _ = response_tx.send(format!("error {e}"));
+                    }
+                }
+                continue;
+            }
+            let response = match self.command(&args) {
+                Ok(None) => String::from("ok"),
+                Ok(Some(result)) => format!("ok {result}"),
+                Err(e) => format!("error {e}"),
+            };
+            let _ = response_tx.send(response);
+        }
+        !self.paused || self.step.is_some()
+    }
+
+    /// Called after an instruction has been executed.
+    fn instruction_executed(&mut self) {
+        if let Some((n, response_tx)) = &mut self.step {
+            *n -= 1;
+            if *n == 0 {
+                let _ = response_tx.send(format!("ok {:#06X}", get_pc()));
+                self.step = None;
+            }
+        }
+    }
+
+    /// Parse a `step` command, returning the number of instructions to step.
+    fn step_command(&self, args: &[&str]) -> Result<usize, String> {
+        if !self.paused {
+            return Err(String::from("not paused"));
+        }
+        if self.step.is_some() {
+            return Err(String::from("already stepping"));
+        }
+        match args {
+            [_] => Ok(1),
+            [_, n] => match parse_num(n)? {
+                0 => Err(String::from("n must be greater than 0")),
+                n => Ok(n),
+            },
+            _ => Err(String::from("usage: step [n]")),
+        }
+    }
+
+    /// Run a command, returning the result, if any.
+    fn command(&mut self, args: &[&str]) -> Result<Option<String>, String> {
+        match args {
+            ["pause"] => {
+                self.paused = true;
+                Ok(None)
+            }
+            ["resume"] => {
+                self.paused = false;
+                Ok(None)
+            }
+            ["read", "mem", addr, rest @ ..] => {
+                let addr = parse_addr(parse_num(addr)?)?;
+                let len = match rest {
+                    [] => 1,
+                    [len] => parse_num(len)?,
+                    _ => return Err(String::from("usage: read mem <addr> [len]")),
+                };
+                let bytes = memory_range(addr, len)?
+                    .map(|addr| {
+                        let byte = get_memory_u8(addr).map_err(|e| e.to_string())?;
+                        Ok(format!("{byte:#04X}"))
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok(Some(bytes.join(" ")))
+            }
+            ["write", "mem", addr, bytes @ ..] if !bytes.is_empty() => {
+                let addr = parse_addr(parse_num(addr)?)?;
+                let bytes = bytes
+                    .iter()
+                    .map(|byte| {
+                        u8::try_from(parse_num(byte)?)
+                            .map_err(|_| format!("value ({byte}) was more than 8 bits"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                for (addr, byte) in memory_range(addr, bytes.len())?.zip(bytes) {
+                    set_memory_u8(addr, byte).map_err(|e| e.to_string())?;
+                }
+                Ok(None)
+            }
+            ["read", "reg", name] => Ok(Some(format!("{:#X}", read_register(name)?))),
+            ["write", "reg", name, val] => {
+                write_register(name, parse_num(val)?)?;
+                Ok(None)
+            }
+            ["press", "key", key] => {
+                self.keypad |= 1 << parse_key(key)?;
+                Ok(None)
+            }
+            ["release", "key", key] => {
+                self.keypad &= !(1 << parse_key(key)?);
+                Ok(None)
+            }
+            ["screenshot", path, rest @ ..] => {
+                let scale = match rest {
+                    [] => DEFAULT_SCREENSHOT_SCALE,
+                    [scale] => parse_num(scale)?,
+                    _ => return Err(String::from("usage: screenshot <file> [scale]")),
+                };
+                save_screenshot(check_file_name(path)?, scale)?;
+                Ok(None)
+            }
+            [] => Err(String::from("empty command")),
+            _ => Err(format!("unknown command: {}", args.join(" "))),
+        }
+    }
+}
+
+/// Read commands from the client, and write back the responses.
+fn handle_client(stream: TcpStream, tx: &Sender<Request>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let (response_tx, response_rx) = channel();
+        if tx.send((line, response_tx)).is_err() {
+            return;
+        }
+        let Ok(response) = response_rx.recv() else {
+            return;
+        };
+        if writeln!(writer, "{response}").is_err() {
+            return;
+        }
+    }
+}
+
+/// Checks that the address is within memory.
+fn parse_addr(addr: usize) -> Result<u16, String> {
+    u16::try_from(addr)
+        .ok()
+        .filter(|addr| addr & 0x0FFF == *addr)
+        .ok_or_else(|| format!("address {addr:#X} is out of range"))
+}
+
+/// Returns the addresses of the `len` bytes starting at `addr`, checking that they're all within memory.
+fn memory_range(addr: u16, len: usize) -> Result<impl Iterator<Item = u16>, String> {
+    let end = usize::from(addr)
+        .checked_add(len)
+        .filter(|end| *end <= MEMORY_SIZE)
+        .ok_or_else(|| format!("{len} bytes from {addr:#X} go past the end of memory"))?;
+    // The end is at most MEMORY_SIZE, so it fits
+    Ok(addr..u16::try_from(end).unwrap())
+}
+
+/// Checks that a path sent by a client is a file name in the current directory, so that clients can't write
+/// anywhere else.
+fn check_file_name(path: &str) -> Result<&str, String> {
+    let mut components = Path::new(path).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(path),
+        _ => Err(format!(
+            "{path} is not a file name (screenshots are saved in the current directory)"
+        )),
+    }
+}
+
+/// Parse a key on the keypad, from 0 to F.
+fn parse_key(key: &str) -> Result<u8, String> {
+    u8::from_str_radix(key, 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .ok_or_else(|| format!("invalid key: {key}"))
+}
+
+/// Parse a general-purpose register (V0-VF).
+fn parse_register(name: &str) -> Option<Register> {
+    let index = name.to_lowercase().strip_prefix('v')?.to_string();
+    let index = u8::from_str_radix(&index, 16).ok()?;
+    Register::try_from(index).ok()
+}
+
+fn read_register(name: &str) -> Result<usize, String> {
+    if let Some(reg) = parse_register(name) {
+        return Ok(get_register(reg).into());
+    }
+    match name.to_lowercase().as_str() {
+        "i" => Ok(get_i().into()),
+        "pc" => Ok(get_pc().into()),
+        "delay" => Ok(get_delay_timer().into()),
+        "sound" => Ok(get_sound_timer().into()),
+        _ => Err(format!("unknown register: {name}")),
+    }
+}
+
+fn write_register(name: &str, val: usize) -> Result<(), String> {
+    let too_large = |bits| format!("value ({val}) was more than {bits} bits");
+    let to_u8 = || u8::try_from(val).map_err(|_| too_large(8));
+    let to_u12 = || parse_addr(val).map_err(|_| too_large(12));
+    if let Some(reg) = parse_register(name) {
+        set_register(reg, to_u8()?);
+        return Ok(());
+    }
+    match name.to_lowercase().as_str() {
+        "i" => set_i(to_u12()?).map_err(|e| e.to_string())?,
+        "pc" => set_pc(to_u12()?).map_err(|e| e.to_string())?,
+        "delay" => set_delay_timer(to_u8()?),
+        "sound" => set_sound_timer(to_u8()?),
+        _ => return Err(format!("unknown register: {name}")),
+    }
+    Ok(())
+}
+
+/// The remote control server, if it's enabled.
+pub static mut REMOTE: Option<RemoteControl> = None;
+
+/// Start the remote control server on the given address.
+///
+/// # Errors
+/// Returns an error if the address could not be bound.
+pub fn start_remote(addr: &str) -> Result<(), String> {
+    let remote = RemoteControl::listen(addr)?;
+    // SAFETY: single threaded
+    unsafe { REMOTE = Some(remote) };
+    Ok(())
+}
+
+/// Handle any pending remote commands. Returns whether the next instruction should be executed, which is
+/// false while a remote client has paused execution.
+pub fn remote_poll() -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe { REMOTE.as_mut() }.is_none_or(RemoteControl::poll)
+}
+
+/// Let the remote control server know that an instruction has been executed.
+pub fn remote_instruction_executed() {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    if let Some(remote) = unsafe { REMOTE.as_mut() } {
+        remote.instruction_executed();
+    }
+}
+
+/// Lets the remote control server know when instructions are executed, so that `step` can finish.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RemoteObserver;
+
+impl Observer for RemoteObserver {
+    fn on_instruction(&mut self, _event: &InstructionEvent) {
+        remote_instruction_executed();
+    }
+}
+
+/// Returns the keys pressed by remote clients. Bit N is set if key N is pressed.
+pub fn get_remote_keypad() -> u16 {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe { REMOTE.as_ref() }.map_or(0, |remote| remote.keypad)
+}
+
+/// The keys pressed by remote clients, as an input source.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RemoteKeypad;
+
+impl InputSource for RemoteKeypad {
+    fn keypad(&mut self) -> u16 {
+        get_remote_keypad()
+    }
+}
@@ -0,0 +1,146 @@
+use c8util::{decode::decode, instructions::Instruction};
+
+use crate::{
+    execute::{execute, get_instruction_set},
+    movie::movie_frame,
+    run::{cancel_exit, fetch, get_exit_code},
+    snapshot::{HISTORY, HISTORY_SIZE, Snapshot, push_history},
+    system::{get_pc, set_pc},
+    timing::{end_instruction, frame_tick, get_frame_count},
+};
+
+/// The state of the machine when the ROM was started, which replays start from.
+pub static mut START_STATE: Option<Snapshot> = None;
+
+/// The state of the keypad on each frame since the ROM was started, so that the input can be replayed.
+/// Bit N is set if key N is pressed.
+pub static mut INPUT_LOG: Vec<u16> = Vec::new();
+
+/// Save the current state of the machine as the state the ROM was started in.
+/// This should be called once everything has been loaded, right before the first instruction is executed.
+pub fn save_start_state() {
+    // SAFETY: single threaded
+    unsafe { START_STATE = Some(Snapshot::take(0)) };
+}
+
+/// Save the state of the keypad on the given frame.
+/// Any input logged for later frames is dropped, since it no longer happened.
+pub fn log_input(frame: u64, keypad: u16) {
+    let frame = usize::try_from(frame).unwrap();
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        INPUT_LOG.truncate(frame);
+        // Frames that weren't sampled (e.g. skipped over in the debug terminal) keep the keys that were held
+        let last = INPUT_LOG.last().copied().unwrap_or(0);
+        INPUT_LOG.resize(frame, last);
+        INPUT_LOG.push(keypad);
+    }
+}
+
+/// Returns the state of the keypad on the given frame when replaying.
+/// Frames that haven't been run yet take their input from the movie that's being played back, if any, and
+/// otherwise no keys are pressed.
+fn replay_input(frame: u64) -> u16 {
+    let frame = usize::try_from(frame).unwrap();
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let logged = unsafe { INPUT_LOG.get(frame).copied() };
+    logged.or_else(|| movie_frame(frame)).unwrap_or(0)
+}
+
+/// Where a replay stopped.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    /// The number of instructions that were executed. This is less than the target if the replay stopped early.
+    pub instructions: u128,
+    /// The state of the keypad on the current frame.
+    pub keypad: u16,
+    /// The state of the keypad on the last instruction. Used for the `GetKey` instruction.
+    pub last_keypad: u16,
+    /// Why the replay stopped before reaching the target, if it did.
+    pub stopped_early: Option<String>,
+}
+
+/// Restart the ROM, and run it without drawing until `target` instructions have been executed, using the
+/// input that was logged on each frame. Since the RNG is restored along with the rest of the machine, this
+/// ends in exactly the same state as when instruction `target` was reached originally, unless something
+/// was changed from the debug terminal.
+///
+/// Breakpoints, watches and tracepoints are ignored. The history is replaced with the last instructions
+/// of the replay, so they can be stepped back over. The replay stops early if an instruction fails, or
+/// before the program exits.
+///
+/// # Errors
+/// Returns an error if the start state hasn't been saved.
+pub fn replay_to(target: u128) -> Result<Replay, String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let start = unsafe { START_STATE.as_ref() }
+        .ok_or_else(|| String::from("the start state hasn't been saved"))?;
+    start.restore();
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        HISTORY.clear();
+    }
+
+    let mut n_instructions_executed = 0u128;
+    let mut keypad = replay_input(get_frame_count());
+    let mut last_keypad = 0u16;
+    let mut stopped_early = None;
+    while n_instructions_executed < target {
+        let instruction_addr = get_pc();
+        // Only keep the instructions that fit in the history, since taking a snapshot is slow
+        let snapshot = (target - n_instructions_executed <= HISTORY_SIZE as u128)
+            .then(|| Snapshot::take(n_instructions_executed));
+        let fetched = fetch();
+        let instruction_raw = *fetched.as_ref().unwrap_or(&0);
+        let instruction = decode(instruction_raw, get_instruction_set())
+            .unwrap_or(Instruction::Db(instruction_raw));
+        if let Err(e) =
+            fetched.and_then(|_| execute(instruction, get_instruction_set(), keypad, last_keypad))
+        {
+            let _ = set_pc(instruction_addr);
+            stopped_early = Some(format!(
+                "instruction #{n_instructions_executed} at {instruction_addr:#06X} failed: {e}"
+            ));
+            break;
+        }
+        if get_exit_code().is_some() {
+            cancel_exit();
+            let _ = set_pc(instruction_addr);
+            stopped_early = Some(format!(
+                "the program exits at instruction #{n_instructions_executed} ({instruction_addr:#06X})"
+            ));
+            break;
+        }
+        if let Some(snapshot) = snapshot {
+            push_history(snapshot);
+        }
+        last_keypad = keypad;
+
+        let is_new_frame = end_instruction(n_instructions_executed, instruction);
+        n_instructions_executed += 1;
+        if is_new_frame {
+            frame_tick();
+            keypad = replay_input(get_frame_count());
+        }
+    }
+
+    // The history wasn't kept yet if the replay stopped well before the target, so replay it again up to where
+    // it stopped. This ends in the same state, since it's deterministic.
+    if stopped_early.is_some() && target - n_instructions_executed > HISTORY_SIZE as u128 {
+        return Ok(Replay {
+            stopped_early,
+            ..replay_to(n_instructions_executed)?
+        });
+    }
+
+    Ok(Replay {
+        instructions: n_instructions_executed,
+        keypad,
+        last_keypad,
+        stopped_early,
+    })
+}
@@ -0,0 +1,58 @@
+use std::time::SystemTime;
+
+/// The state of the random number generator used by the CXNN instruction.
+/// Seeding it with the same value always produces the same sequence of numbers, which is needed to replay
+/// movies deterministically.
+pub static mut RNG_STATE: u64 = 0;
+
+/// The seed that the random number generator was last seeded with.
+pub static mut RNG_SEED: u64 = 0;
+
+pub fn get_rng_state() -> u64 {
+    // SAFETY: single threaded
+    unsafe { RNG_STATE }
+}
+
+pub fn set_rng_state(val: u64) {
+    // SAFETY: single threaded
+    unsafe { RNG_STATE = val };
+}
+
+pub fn get_rng_seed() -> u64 {
+    // SAFETY: single threaded
+    unsafe { RNG_SEED }
+}
+
+/// Seed the random number generator.
+pub fn seed_rng(seed: u64) {
+    // SAFETY: single threaded
+    unsafe {
+        RNG_SEED = seed;
+        RNG_STATE = seed;
+    };
+}
+
+/// Returns a seed based on the current time.
+pub fn time_seed() -> u64 {
+    let duration_since_epoch = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    #[allow(clippy::cast_possible_truncation)]
+    let seed = duration_since_epoch.as_nanos() as u64;
+    seed
+}
+
+/// Returns the next random byte.
+/// This uses `SplitMix64`, which is fast and works with any seed (including 0).
+pub fn random_u8() -> u8 {
+    // SAFETY: single threaded
+    let state = unsafe {
+        RNG_STATE = RNG_STATE.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        RNG_STATE
+    };
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    u8::try_from(z >> 56).unwrap()
+}
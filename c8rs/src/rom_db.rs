@@ -0,0 +1,149 @@
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    init::get_rom,
+    input::Key,
+    quirks::{Quirks, get_quirks, set_quirks},
+    run::{get_keymap, set_keymap},
+    timing::{get_ips, set_ips},
+};
+
+/// The default path of the ROM database.
+pub const DEFAULT_ROM_DB_PATH: &str = "roms.toml";
+
+/// The settings that a particular ROM needs.
+/// Anything that isn't set uses the defaults (or the command line options).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RomSettings {
+    /// The file name of the ROM. Only used to make the database easier to read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The number of instructions to execute per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ips: Option<usize>,
+    /// The compatibility quirks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<Quirks>,
+    /// The key on the keyboard for each key on the keypad, from 0 to F.
+    /// Keys are named like `Key1` and `Q`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<[String; 16]>,
+}
+
+impl RomSettings {
+    /// Returns these settings, with any that aren't set taken from `defaults`.
+    #[must_use]
+    pub fn or(self, defaults: Self) -> Self {
+        Self {
+            name: self.name.or(defaults.name),
+            ips: self.ips.or(defaults.ips),
+            quirks: self.quirks.or(defaults.quirks),
+            keymap: self.keymap.or(defaults.keymap),
+        }
+    }
+}
+
+/// The ROM database, keyed by the SHA-1 of the ROM.
+///
+/// e.g.
+/// ```toml
+/// [roms.0123456789abcdef0123456789abcdef01234567]
+/// name = "pong.ch8"
+/// ips = 1000
+///
+/// [roms.0123456789abcdef0123456789abcdef01234567.quirks]
+/// get_key_on_release = false
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RomDb {
+    #[serde(default)]
+    pub roms: BTreeMap<String, RomSettings>,
+}
+
+pub static mut ROM_DB_PATH: &str = DEFAULT_ROM_DB_PATH;
+
+pub fn set_rom_db_path(path: &'static str) {
+    // SAFETY: single threaded
+    unsafe {
+        ROM_DB_PATH = path;
+    }
+}
+
+pub fn get_rom_db_path() -> &'static str {
+    // SAFETY: single threaded
+    unsafe { ROM_DB_PATH }
+}
+
+/// Read the ROM database. If it doesn't exist, an empty database is returned.
+///
+/// # Errors
+/// Returns an error if the database could not be read or is invalid.
+pub fn read_rom_db() -> Result<RomDb, String> {
+    let path = get_rom_db_path();
+    match fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents).map_err(|e| format!("invalid {path}: {e}")),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(RomDb::default()),
+        Err(e) => Err(format!("failed to read {path}: {e}")),
+    }
+}
+
+/// Returns the settings for the current ROM, if it's in the database.
+///
+/// # Errors
+/// Returns an error if the database could not be read.
+pub fn load_rom_settings() -> Result<Option<RomSettings>, String> {
+    Ok(read_rom_db()?.roms.remove(get_rom().sha1()))
+}
+
+/// Apply the given settings.
+///
+/// # Errors
+/// Returns an error if the keymap contains an unknown key.
+pub fn apply_rom_settings(settings: &RomSettings) -> Result<(), String> {
+    if let Some(keymap) = &settings.keymap {
+        let mut keys = get_keymap();
+        for (key, name) in keys.iter_mut().zip(keymap) {
+            *key = name
+                .parse::<Key>()
+                .map_err(|_| format!("unknown key in keymap: {name}"))?;
+        }
+        set_keymap(keys);
+    }
+    if let Some(ips) = settings.ips {
+        if ips == 0 {
+            return Err(String::from("ips must be greater than 0"));
+        }
+        set_ips(ips);
+    }
+    if let Some(quirks) = settings.quirks {
+        set_quirks(quirks);
+    }
+    Ok(())
+}
+
+/// Save the current quirks, instructions per second and keymap to the database for the current ROM.
+///
+/// # Errors
+/// Returns an error if the database could not be read or written.
+pub fn save_rom_settings() -> Result<(), String> {
+    let rom = get_rom();
+    let mut db = read_rom_db()?;
+    db.roms.insert(
+        rom.sha1().to_string(),
+        RomSettings {
+            name: rom
+                .path()
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().into_owned()),
+            ips: Some(get_ips()),
+            quirks: Some(get_quirks()),
+            keymap: Some(get_keymap().map(|key| key.to_string())),
+        },
+    );
+
+    let path = get_rom_db_path();
+    let contents = toml::to_string_pretty(&db).map_err(|e| e.to_string())?;
+    fs::write(path, contents).map_err(|e| format!("failed to write {path}: {e}"))
+}
@@ -1,32 +1,122 @@
-use core::panic;
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::VecDeque,
+    fmt::Write as _,
+    io::{self, Write},
+    process,
     sync::OnceLock,
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use device_query::{DeviceQuery, DeviceState, Keycode};
+use clap::ValueEnum;
+use crossterm::event::{KeyCode, KeyModifiers};
+use serde::Deserialize;
 
 use c8util::{decode::decode, instructions::Instruction, register::Register};
 
 use crate::{
-    debug_terminal::{DebugState, debug_terminal},
-    execute::execute,
+    automation::{check_stop, final_state, save_dumps},
+    coverage::save_coverage_out,
+    debug_server::poll_client_line,
+    debug_terminal::{DebugState, SpriteView, debug_terminal, is_break_requested, request_break},
+    display::{DisplayBackend, Frame, display_backend},
+    error::C8Error,
+    execute::{execute, get_instruction_set},
+    hooks::{
+        InstructionEvent, Outcome, notify_before_instruction, notify_draw, notify_instruction,
+        notify_should_break,
+    },
+    inject::take_key_changes,
+    input::{
+        InputSource, Key, Keyboard, LineEditor, disable_input, is_interrupt, is_interrupted,
+        poll_input_sources,
+    },
     instructions::FancyInstruction,
-    stdin::NonblockingReader,
+    movie::movie_input,
+    phosphor::{fade_color, get_phosphor, get_phosphor_frames},
+    profile::save_profile_out,
+    quicksave::{quickload, quicksave},
+    record::{is_recording, start_recording, stop_recording},
+    remote::remote_poll,
+    replay::{log_input, save_start_state},
+    screenshot::{get_screenshot_scale, save_screenshot, timestamped_path},
+    session::save_session,
+    snapshot::{Snapshot, push_history},
+    stats::{is_stats_shown, pause_speed, record_speed, set_stats_shown, stats_line},
     system::{
-        DISPLAY_HEIGHT, DISPLAY_WIDTH, decrement_delay_timer, decrement_sound_timer,
-        get_delay_timer, get_display, get_full_display, get_i, get_memory_u8, get_memory_u16,
-        get_pc, get_register, get_registers, get_sound_timer, get_stack, peek_stack, set_pc,
+        DISPLAY_HEIGHT, DISPLAY_WIDTH, get_delay_timer, get_full_display, get_i, get_memory_u8,
+        get_memory_u16, get_pc, get_register, get_registers, get_sound_timer, get_stack,
+        get_vblank, peek_stack, set_pc,
     },
+    terminal::{check_terminal_size, display_margin, has_room_for_sound_indicator},
+    timing::{FramePacer, end_instruction, frame_tick, get_frame_count, skip_to_frame_boundary},
 };
 
-/// The number of instructions to execute per second.
+/// The default number of instructions to execute per second.
 pub const INSTRUCTION_SPEED: usize = 720;
 
-pub static KEYPRESS_MAP: OnceLock<HashMap<Keycode, u8>> = OnceLock::new();
-pub static REVERSE_KEYPRESS_MAP: OnceLock<HashMap<u8, Keycode>> = OnceLock::new();
+/// The key on the keyboard for each key on the keypad, from 0 to F.
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+pub const DEFAULT_KEYMAP: [Key; 16] = [
+    Key(KeyCode::Char('x')), // 0
+    Key(KeyCode::Char('1')), // 1
+    Key(KeyCode::Char('2')), // 2
+    Key(KeyCode::Char('3')), // 3
+    Key(KeyCode::Char('q')), // 4
+    Key(KeyCode::Char('w')), // 5
+    Key(KeyCode::Char('e')), // 6
+    Key(KeyCode::Char('a')), // 7
+    Key(KeyCode::Char('s')), // 8
+    Key(KeyCode::Char('d')), // 9
+    Key(KeyCode::Char('z')), // A
+    Key(KeyCode::Char('c')), // B
+    Key(KeyCode::Char('4')), // C
+    Key(KeyCode::Char('r')), // D
+    Key(KeyCode::Char('f')), // E
+    Key(KeyCode::Char('v')), // F
+];
+
+/// The key on the keyboard for each key on the keypad, from 0 to F.
+pub static mut KEYMAP: [Key; 16] = DEFAULT_KEYMAP;
+
+/// Set the key on the keyboard for each key on the keypad, from 0 to F.
+pub fn set_keymap(keymap: [Key; 16]) {
+    // SAFETY: single threaded
+    unsafe { KEYMAP = keymap };
+}
+
+/// Returns the key on the keyboard for each key on the keypad, from 0 to F.
+pub fn get_keymap() -> [Key; 16] {
+    // SAFETY: single threaded
+    unsafe { KEYMAP }
+}
+
+/// How long a status message is shown below the display.
+const STATUS_MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+/// A message to show below the display, and when it was set.
+pub static mut STATUS_MESSAGE: Option<(String, Instant)> = None;
+
+/// Show a message below the display for a couple of seconds.
+pub fn set_status_message(message: String) {
+    // SAFETY: single threaded
+    unsafe { STATUS_MESSAGE = Some((message, Instant::now())) };
+}
+
+/// Returns the current status message, if it hasn't expired yet.
+pub fn get_status_message() -> Option<String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        STATUS_MESSAGE
+            .as_ref()
+            .filter(|(_, set_at)| set_at.elapsed() < STATUS_MESSAGE_DURATION)
+            .map(|(message, _)| message.clone())
+    }
+}
 
 macro_rules! info {
     ($lines:tt, $($arg:tt)*) => {
@@ -42,74 +132,91 @@ macro_rules! infop {
     };
 }
 
+/// Whether execution starts in the debug terminal, rather than running the ROM right away.
+pub static mut START_PAUSED: bool = false;
+
+pub fn set_start_paused(start_paused: bool) {
+    // SAFETY: single threaded
+    unsafe { START_PAUSED = start_paused };
+}
+
+/// The exit code requested by the 00FD instruction, if it has been executed.
+pub static mut EXIT_CODE: Option<u8> = None;
+
+/// Request that the emulator exit with the given code once the current instruction has finished.
+pub fn request_exit(code: u8) {
+    // SAFETY: single threaded
+    unsafe { EXIT_CODE = Some(code) };
+}
+
+pub fn get_exit_code() -> Option<u8> {
+    // SAFETY: single threaded
+    unsafe { EXIT_CODE }
+}
+
+/// Cancel a requested exit, e.g. when an exit is replayed.
+pub fn cancel_exit() {
+    // SAFETY: single threaded
+    unsafe { EXIT_CODE = None };
+}
+
+/// Exit the emulator with the given code, finishing any recording and restoring the terminal first.
+pub fn exit(code: u8) -> ! {
+    if is_recording() {
+        if let Err(e) = stop_recording() {
+            eprintln!("Could not save recording: {e}");
+        }
+    }
+    if let Err(e) = save_profile_out() {
+        eprintln!("Could not save profile: {e}");
+    }
+    if let Err(e) = save_coverage_out() {
+        eprintln!("Could not save coverage: {e}");
+    }
+    if let Err(e) = save_dumps() {
+        eprintln!("Could not save dump: {e}");
+    }
+    if let Err(e) = save_session() {
+        eprintln!("Could not save session: {e}");
+    }
+    print!("\x1b[0m"); // Reset any colors
+    io::stdout().flush().unwrap();
+    disable_input();
+    if let Some(state) = final_state(code) {
+        print!("{state}");
+        io::stdout().flush().unwrap();
+    }
+    process::exit(i32::from(code));
+}
+
 /// Handles the core loop.
 #[allow(clippy::too_many_lines)]
 pub fn run() {
-    for _ in 0..DISPLAY_HEIGHT + 5 {
+    for _ in 0..frame_height(false) {
         println!();
     }
 
     let mut n_instructions_executed = 0u128;
 
-    let device_state = DeviceState::new();
-    let mut pressed_keys: HashSet<Keycode> = HashSet::new();
-    // Used for the GetKey instruction
-    #[allow(unused_assignments)] // it thinks this is unused
-    let mut last_pressed_keys: HashSet<Keycode> = HashSet::new();
+    let mut keyboard = Keyboard::new();
+    // The state of the Chip-8 keypad. Bit N is set if key N is pressed.
+    let mut keypad = 0u16;
+    // The state of the keypad on the previous instruction. Used for the GetKey instruction
+    let mut last_keypad = 0u16;
+    // The frame that input was last sampled on. Input is only sampled once per frame, so that movies can
+    // be replayed exactly.
+    let mut sampled_frame = None;
 
     // Whether we are currently debugging.
-    let mut is_debug = false;
+    // SAFETY: single threaded
+    let mut is_debug = unsafe { START_PAUSED };
+    // Keeps the frame rate at 60 FPS
+    let mut frame_pacer = FramePacer::new();
     // The last command that was used in the debugger
     // let mut last_debug_command = String::new();
 
-    KEYPRESS_MAP.get_or_init(|| {
-        // 1 2 3 C
-        // 4 5 6 D
-        // 7 8 9 E
-        // A 0 B F
-        let mut h = HashMap::new();
-        h.insert(Keycode::Key1, 0x1);
-        h.insert(Keycode::Key2, 0x2);
-        h.insert(Keycode::Key3, 0x3);
-        h.insert(Keycode::Key4, 0xC);
-        h.insert(Keycode::Q, 0x4);
-        h.insert(Keycode::W, 0x5);
-        h.insert(Keycode::E, 0x6);
-        h.insert(Keycode::R, 0xD);
-        h.insert(Keycode::A, 0x7);
-        h.insert(Keycode::S, 0x8);
-        h.insert(Keycode::D, 0x9);
-        h.insert(Keycode::F, 0xE);
-        h.insert(Keycode::Z, 0xA);
-        h.insert(Keycode::X, 0x0);
-        h.insert(Keycode::C, 0xB);
-        h.insert(Keycode::V, 0xF);
-        h
-    });
-    REVERSE_KEYPRESS_MAP.get_or_init(|| {
-        // 1 2 3 C
-        // 4 5 6 D
-        // 7 8 9 E
-        // A 0 B F
-        let mut h = HashMap::new();
-        h.insert(0x1, Keycode::Key1);
-        h.insert(0x2, Keycode::Key2);
-        h.insert(0x3, Keycode::Key3);
-        h.insert(0xC, Keycode::Key4);
-        h.insert(0x4, Keycode::Q);
-        h.insert(0x5, Keycode::W);
-        h.insert(0x6, Keycode::E);
-        h.insert(0xD, Keycode::R);
-        h.insert(0x7, Keycode::A);
-        h.insert(0x8, Keycode::S);
-        h.insert(0x9, Keycode::D);
-        h.insert(0xE, Keycode::F);
-        h.insert(0xA, Keycode::Z);
-        h.insert(0x0, Keycode::X);
-        h.insert(0xB, Keycode::C);
-        h.insert(0xF, Keycode::V);
-        h
-    });
+    // Keep the state the ROM started in, so that it can be replayed from the start in the debug terminal
+    save_start_state();
 
     // Used for printing debug messages to the right of the display
     // let mut info_lines: Vec<String> = Vec::with_capacity(DISPLAY_HEIGHT);
@@ -125,56 +232,173 @@ pub fn run() {
         last_instructions: VecDeque::with_capacity(3),
         info_lines: Vec::with_capacity(DISPLAY_HEIGHT),
         old_register_state: get_registers(),
-        old_i_state: (get_i(), get_memory_u8(get_i()), get_memory_u8(get_i() + 2)),
+        old_i_state: i_state(),
         old_display_state: get_full_display(),
-        breakpoints: HashSet::new(),
-        history: Vec::new(),
-        reader: NonblockingReader::new(),
-        last_pressed_keys: Vec::new(),
+        temporary_breakpoint: None,
+        frame_step: None,
+        replayed_keypad: None,
+        list_addr: None,
+        sprite_view: SpriteView::Hidden,
+        last_draw_height: 0,
+        pending_commands: VecDeque::new(),
+        pending_line: None,
+        line_editor: LineEditor::new("> "),
     };
 
     loop {
+        // Stopped with a signal instead of Ctrl+C (e.g. kill)
+        if is_interrupted() {
+            exit(130);
+        }
+
+        // Let remote clients pause execution
+        if !remote_poll() {
+            pause_speed();
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
         debug_state.info_lines.clear();
 
-        // Update keyboard state
-        let keys = device_state.get_keys();
-        last_pressed_keys.clone_from(&pressed_keys);
-        pressed_keys.clear();
-        for key in keys {
-            pressed_keys.insert(key);
+        // Sample input at the start of each frame
+        if sampled_frame != Some(get_frame_count()) {
+            sampled_frame = Some(get_frame_count());
+
+            // Update keyboard state. While debugging, keys are typed into the debug terminal instead.
+            let pressed = if is_debug {
+                keyboard.release_all();
+                Vec::new()
+            } else {
+                keyboard.update()
+            };
+            for event in pressed {
+                if is_interrupt(&event) {
+                    exit(130);
+                }
+                match event.code {
+                    KeyCode::Esc => is_debug = true,
+                    // Take a screenshot
+                    KeyCode::F(12) => {
+                        let path = timestamped_path("screenshot", "png");
+                        match save_screenshot(&path, get_screenshot_scale()) {
+                            Ok(()) => set_status_message(format!("Saved screenshot to {path}")),
+                            Err(e) => {
+                                set_status_message(format!("Could not save screenshot: {e}"));
+                            }
+                        }
+                    }
+                    // Show or hide the speed statistics
+                    KeyCode::F(10) => set_stats_shown(!is_stats_shown()),
+                    // Start or stop recording
+                    KeyCode::F(11) => {
+                        if is_recording() {
+                            match stop_recording() {
+                                Ok(path) => {
+                                    set_status_message(format!("Saved recording to {path}"));
+                                }
+                                Err(e) => {
+                                    set_status_message(format!("Could not save recording: {e}"));
+                                }
+                            }
+                        } else {
+                            let path = timestamped_path("recording", "gif");
+                            match start_recording(&path) {
+                                Ok(()) => set_status_message(format!("Recording to {path}")),
+                                Err(e) => {
+                                    set_status_message(format!("Could not start recording: {e}"));
+                                }
+                            }
+                        }
+                    }
+                    // Save to a quicksave slot, or load from it with shift
+                    KeyCode::F(key @ 5..=8) => {
+                        let slot = usize::from(key - 5);
+                        if event.modifiers.contains(KeyModifiers::SHIFT) {
+                            match quickload(slot) {
+                                Ok(instructions) => {
+                                    n_instructions_executed = instructions;
+                                    set_status_message(format!("Loaded slot {}", slot + 1));
+                                }
+                                Err(e) => set_status_message(format!("Could not load: {e}")),
+                            }
+                        } else {
+                            quicksave(slot, n_instructions_executed);
+                            set_status_message(format!("Saved slot {}", slot + 1));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            // A line sent to the debug terminal over TCP breaks into the debugger, and is run as a command
+            if !is_debug {
+                if let Some(line) = poll_client_line() {
+                    is_debug = true;
+                    if !line.trim().is_empty() {
+                        debug_state.pending_commands.push_back(line);
+                    }
+                }
+            }
+            keypad = movie_input(keyboard.keypad() | poll_input_sources());
+            log_input(get_frame_count(), keypad);
         }
 
-        if pressed_keys.contains(&Keycode::Escape) {
+        // If an observer asks to (e.g. at a breakpoint, or when the next instruction is about to see a key that
+        // `b key` is waiting for), enter debug mode
+        if notify_should_break(get_pc(), keypad, last_keypad) {
             is_debug = true;
-            print!("\x1b[2K\r"); // Clear the current line to remove the escape code
         }
 
-        // If we're at a breakpoint, enter debug mode
-        if debug_state.breakpoints.contains(&get_pc()) {
+        // If the last instruction failed (e.g. a stack overflow or an invalid instruction), enter debug mode.
+        // The instruction will be executed again, and the reason will be shown in the debug terminal.
+        if is_break_requested() {
             is_debug = true;
         }
 
+        // If we're at the end of a step over, enter debug mode.
+        // Temporary breakpoints are removed whenever execution breaks, even if it's for another reason.
+        if debug_state
+            .temporary_breakpoint
+            .is_some_and(|breakpoint| breakpoint.is_hit())
+        {
+            is_debug = true;
+        }
+        // If a frame step has finished, enter debug mode
+        if debug_state
+            .frame_step
+            .is_some_and(|frame_step| frame_step.is_done())
+        {
+            is_debug = true;
+        }
+        if is_debug {
+            debug_state.temporary_breakpoint = None;
+            debug_state.frame_step = None;
+        }
+
         // Fetch the next instruction
-        let instruction_raw = fetch();
+        let instruction_addr = get_pc();
+        let fetched = fetch();
+        let instruction_raw = *fetched.as_ref().unwrap_or(&0);
 
-        // Decode the instruction
-        let Some(instruction) = decode(instruction_raw) else {
-            invalid_instruction(instruction_raw);
-        };
+        // Decode the instruction. Invalid instructions fail when they're executed.
+        let instruction = decode(instruction_raw, get_instruction_set())
+            .unwrap_or(Instruction::Db(instruction_raw));
 
         // If debugging, print debug info
         if is_debug {
-            print_debug(
-                &mut n_instructions_executed,
-                instruction,
-                instruction_raw,
-                &mut debug_state,
-            );
+            // If the current instruction is draw, skip to the next vertical blank
+            if matches!(instruction, Instruction::Draw(..)) && !get_vblank() {
+                skip_to_frame_boundary(&mut n_instructions_executed);
+                // Properly update delay and sound timers
+                frame_tick();
+                n_instructions_executed += 1;
+            }
+
+            print_debug(instruction, instruction_raw, &mut debug_state);
         }
 
         debug_state.old_register_state = get_registers();
         debug_state.old_display_state = get_full_display();
-        debug_state.old_i_state = (get_i(), get_memory_u8(get_i()), get_memory_u8(get_i() + 2));
+        debug_state.old_i_state = i_state();
 
         if debug_state.last_instructions.len() == 3 {
             debug_state.last_instructions.pop_back();
@@ -184,32 +408,71 @@ pub fn run() {
             instruction_raw,
             instruction,
         ));
+        if let Instruction::Draw(_, _, n) = instruction {
+            debug_state.last_draw_height = n;
+        }
 
         // Execute the instruction
-        execute(
+        notify_before_instruction(instruction_addr, instruction, n_instructions_executed);
+        // Keep the state from before the instruction, so that it can be stepped back over in the debug terminal
+        let mut snapshot = Snapshot::take(n_instructions_executed);
+        snapshot.pc = instruction_addr;
+        let result =
+            fetched.and_then(|_| execute(instruction, get_instruction_set(), keypad, last_keypad));
+        let outcome = Outcome::of(&result, instruction_addr, instruction);
+        match result {
+            Ok(()) => push_history(snapshot),
+            Err(e) => {
+                // Go back to the failed instruction, so that it's shown in the debug terminal
+                let _ = set_pc(instruction_addr);
+                request_break(e.to_string());
+            }
+        }
+        last_keypad = keypad;
+        // Draws that are waiting for the vertical blank haven't drawn anything yet
+        if let Some(frame_step) = &mut debug_state.frame_step {
+            if matches!(instruction, Instruction::Draw(..)) && get_pc() != instruction_addr {
+                frame_step.is_drawn = true;
+            }
+        }
+        notify_instruction(&InstructionEvent {
+            addr: instruction_addr,
             instruction,
-            &pressed_keys,
-            &last_pressed_keys,
-            n_instructions_executed,
-        );
+            instruction_count: n_instructions_executed,
+            outcome,
+        });
 
-        // Count down delay and sound timers
-        if n_instructions_executed % 12 == 0 {
-            decrement_delay_timer();
-            decrement_sound_timer();
+        // Start a new frame: count down delay and sound timers, and wait to stay in sync with the wall clock
+        let is_new_frame = end_instruction(n_instructions_executed, instruction);
+        if is_new_frame {
+            if !is_debug {
+                frame_pacer.wait();
+            }
+            frame_tick();
         }
 
-        // Delay for 1/720 of a second
-        #[allow(clippy::cast_precision_loss)]
-        thread::sleep(Duration::from_secs_f32(1.0 / INSTRUCTION_SPEED as f32));
-
         // Draw
-        draw(
-            n_instructions_executed,
+        let is_drawn = draw(
+            is_new_frame,
             is_debug,
             &debug_state.old_display_state,
             &mut debug_state.info_lines,
         );
+        // Time spent in the debug terminal doesn't count towards the speed
+        if is_debug {
+            pause_speed();
+        } else {
+            record_speed(is_new_frame, is_drawn);
+        }
+
+        // The program asked to exit
+        if let Some(code) = get_exit_code() {
+            exit(code);
+        }
+        // Scripted runs stop after a number of instructions, or once the program halts
+        if check_stop(instruction_addr, instruction, n_instructions_executed + 1) {
+            exit(0);
+        }
 
         // If debugging: wait for user input to continue
         if is_debug {
@@ -219,6 +482,20 @@ pub fn run() {
                 instruction_raw,
                 &mut debug_state,
             );
+            // After a replay, carry on with the input that was replayed instead of sampling it again
+            if let Some((replayed_keypad, replayed_last_keypad)) =
+                debug_state.replayed_keypad.take()
+            {
+                keypad = replayed_keypad;
+                last_keypad = replayed_last_keypad;
+                sampled_frame = Some(get_frame_count());
+            }
+            // Keys pressed and released in the debug terminal take effect right away, rather than on the next
+            // frame
+            if let Some((pressed, released)) = take_key_changes() {
+                keypad = (keypad & !released) | pressed;
+                log_input(get_frame_count(), keypad);
+            }
         }
 
         // Misc logging
@@ -226,78 +503,468 @@ pub fn run() {
     }
 }
 
+/// A color, as red, green and blue.
+pub type Rgb = [u8; 3];
+
+/// How the display is drawn in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderMode {
+    /// Each pixel is 2 characters wide. Needs a terminal at least 130 columns wide.
+    #[default]
+    Double,
+    /// Unicode half blocks (▀), with 2 pixels stacked in each character.
+    #[value(name = "halfblock")]
+    HalfBlock,
+    /// Unicode Braille patterns, with 2x4 pixels in each character. Pixels that changed are highlighted by
+    /// character, rather than by pixel.
+    Braille,
+}
+
+impl RenderMode {
+    /// The number of pixels across and down each character.
+    fn block_size(self) -> (usize, usize) {
+        match self {
+            Self::Double => (1, 1),
+            Self::HalfBlock => (1, 2),
+            Self::Braille => (2, 4),
+        }
+    }
+
+    /// The number of terminal columns each character takes.
+    fn char_width(self) -> usize {
+        match self {
+            Self::Double => 2,
+            Self::HalfBlock | Self::Braille => 1,
+        }
+    }
+
+    /// The number of characters across the display.
+    pub fn columns(self) -> usize {
+        DISPLAY_WIDTH / self.block_size().0
+    }
+
+    /// The number of lines down the display.
+    pub fn rows(self) -> usize {
+        DISPLAY_HEIGHT / self.block_size().1
+    }
+
+    /// The width of the display in the terminal, not including the borders.
+    pub fn width(self) -> usize {
+        self.columns() * self.char_width()
+    }
+}
+
+pub static mut RENDER_MODE: RenderMode = RenderMode::Double;
+
+pub fn get_render_mode() -> RenderMode {
+    // SAFETY: single threaded
+    unsafe { RENDER_MODE }
+}
+
+pub fn set_render_mode(mode: RenderMode) {
+    // SAFETY: single threaded
+    unsafe { RENDER_MODE = mode };
+}
+
+/// The number of lines the debug info next to the display is laid out for.
+/// If the display is shorter than this, the lines below it are padded.
+const INFO_PANEL_HEIGHT: usize = DISPLAY_HEIGHT;
+
+/// The number of lines a full frame takes up in the terminal, including the borders and the status line.
+/// This many lines need to be printed before a full frame is drawn, so that it doesn't overwrite anything.
+pub fn frame_height(is_debug: bool) -> usize {
+    // The top border, the display and the bottom border
+    let mut lines = get_render_mode().rows() + 2;
+    if is_debug {
+        lines = lines.max(INFO_PANEL_HEIGHT + 1);
+    }
+    // A blank line, the status line, and the line the cursor is left on
+    lines + 3
+}
+
+/// The foreground and background SGR parameters for each kind of cell, in the order of `Cell::index`.
+/// None if the cell is drawn in the terminal's background color.
+pub static CELL_COLORS: OnceLock<Vec<Option<(String, String)>>> = OnceLock::new();
+
+/// The text printed for each kind of cell in `RenderMode::Double`, in the order of `Cell::index`.
+pub static CELL_TEXT: OnceLock<Vec<String>> = OnceLock::new();
+
+/// The color fading pixels fade from when no color is set for pixels that are on, close to the terminal's white.
+const DEFAULT_ON_COLOR: Rgb = [0xC0, 0xC0, 0xC0];
+
+/// Set the colors of pixels that are on and off, as RGB. None uses the default colors: white for pixels that are
+/// on, and the terminal's background for pixels that are off.
+/// The colors of fading pixels (see `phosphor`) are worked out from these, so the phosphor needs to be set up
+/// first.
+/// This can only be done once, before running. Later calls are ignored.
+pub fn init_display_colors(on: Option<Rgb>, off: Option<Rgb>) {
+    let rgb = |[r, g, b]: Rgb| (format!("38;2;{r};{g};{b}"), format!("48;2;{r};{g};{b}"));
+    let ansi = |fg: &str, bg: &str| Some((String::from(fg), String::from(bg)));
+    let colors = CELL_COLORS.get_or_init(|| {
+        let mut colors = vec![
+            off.map(rgb),
+            on.map(rgb).or_else(|| ansi("37", "47")),
+            ansi("32", "42"),
+            ansi("31", "41"),
+        ];
+        // The terminal's background is usually close to black
+        let (on, off) = (on.unwrap_or(DEFAULT_ON_COLOR), off.unwrap_or([0; 3]));
+        for level in 1..=get_phosphor_frames() {
+            colors.push(Some(rgb(fade_color(on, off, level))));
+        }
+        colors
+    });
+    CELL_TEXT.get_or_init(|| {
+        colors
+            .iter()
+            .map(|color| {
+                color.as_ref().map_or_else(
+                    || String::from("  "),
+                    |(_, bg)| format!("\x1b[{bg}m  \x1b[0m"),
+                )
+            })
+            .collect()
+    });
+}
+
+/// What is drawn in a cell of the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cell {
+    Off,
+    On,
+    /// Turned on by the last instruction.
+    TurnedOn,
+    /// Turned off by the last instruction.
+    TurnedOff,
+    /// Turned off, but still fading out at the given level (see `phosphor`).
+    Fading(u8),
+}
+
+impl Cell {
+    fn new(is_set: bool, is_old_set: bool, phosphor_level: u8) -> Self {
+        match (is_set, is_old_set) {
+            (true, true) => Self::On,
+            (true, false) => Self::TurnedOn,
+            (false, true) => Self::TurnedOff,
+            (false, false) if phosphor_level > 0 => Self::Fading(phosphor_level),
+            (false, false) => Self::Off,
+        }
+    }
+
+    /// The index of the cell in `CELL_COLORS` and `CELL_TEXT`.
+    fn index(self) -> usize {
+        match self {
+            Self::Off => 0,
+            Self::On => 1,
+            Self::TurnedOn => 2,
+            Self::TurnedOff => 3,
+            Self::Fading(level) => 3 + usize::from(level.min(get_phosphor_frames())),
+        }
+    }
+
+    /// The text to print for the cell in `RenderMode::Double`.
+    fn as_str(self) -> &'static str {
+        if CELL_TEXT.get().is_none() {
+            init_display_colors(None, None);
+        }
+        &CELL_TEXT.get().unwrap()[self.index()]
+    }
+
+    /// The foreground and background SGR parameters for the cell, or None if it's drawn in the terminal's
+    /// background color.
+    fn colors(self) -> Option<&'static (String, String)> {
+        if CELL_COLORS.get().is_none() {
+            init_display_colors(None, None);
+        }
+        CELL_COLORS.get().unwrap()[self.index()].as_ref()
+    }
+}
+
+/// The cells that were drawn on the last frame, so that cells that haven't changed can be skipped.
+/// None if the next frame needs to be drawn in full (e.g. after the debug terminal printed over it).
+pub static mut LAST_DRAWN_CELLS: Option<[[Cell; DISPLAY_HEIGHT]; DISPLAY_WIDTH]> = None;
+
+/// Draw the display, if a new frame has started or we're debugging. Returns whether it was drawn.
 pub fn draw(
-    n_instructions_executed: u128,
+    is_new_frame: bool,
     is_debug: bool,
     old_display_state: &[[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
     info_lines: &mut [String],
-) {
-    // If we're not in debug mode, only draw at ~60FPS
-    if n_instructions_executed % 12 == 0 || is_debug {
-        // Clear the terminal
-        for _ in 0..DISPLAY_HEIGHT + 5 {
-            print!("\x1b[2K\x1b[1A\r"); // Clear the line, then move the cursor up a line
+) -> bool {
+    // If we're not in debug mode, only draw once per frame
+    if !is_new_frame && !is_debug {
+        return false;
+    }
+
+    let pixels = get_full_display();
+    let is_drawn = display_backend().blit(&Frame {
+        pixels: &pixels,
+        old_pixels: old_display_state,
+        is_debug,
+        info_lines,
+    });
+    if is_drawn {
+        notify_draw();
+    }
+    is_drawn
+}
+
+/// Draws the display in the terminal with ANSI escape codes, in the current render mode. This is the default
+/// display backend.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TerminalDisplay;
+
+impl DisplayBackend for TerminalDisplay {
+    fn set_resolution(&mut self, _width: usize, _height: usize) {
+        // The size of the display in the terminal comes from the render mode, so just start again from a full frame
+        self.clear();
+    }
+
+    fn clear(&mut self) {
+        // SAFETY: single threaded
+        unsafe { LAST_DRAWN_CELLS = None };
+    }
+
+    fn blit(&mut self, frame: &Frame<'_>) -> bool {
+        let Some(mut out) = check_terminal_size(frame.is_debug) else {
+            io::stdout().flush().unwrap();
+            return false;
+        };
+        out.push_str(&render_frame(frame));
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(out.as_bytes()).unwrap();
+        stdout.flush().unwrap();
+        true
+    }
+}
+
+/// The line below the display: the speed statistics (if they're shown), and the status message.
+fn status_line() -> String {
+    [stats_line(), get_status_message()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("  |  ")
+}
+
+/// Returns the text that draws the next frame in the terminal, without printing it.
+pub fn render_frame(frame: &Frame<'_>) -> String {
+    let Frame {
+        pixels,
+        old_pixels,
+        is_debug,
+        info_lines,
+    } = *frame;
+    let phosphor = get_phosphor();
+    let mut cells = [[Cell::Off; DISPLAY_HEIGHT]; DISPLAY_WIDTH];
+    for (x, row) in cells.iter_mut().enumerate() {
+        for (y, cell) in row.iter_mut().enumerate() {
+            *cell = Cell::new(pixels[x][y], old_pixels[x][y], phosphor[x][y]);
         }
-        print!("\x1b[2K\r"); // Clear the last line
+    }
 
-        print!("{}", (0..=DISPLAY_WIDTH).map(|_| "__").collect::<String>());
-        // Show a colored square to indicate sound
-        if get_sound_timer() > 0 && n_instructions_executed % 12 == 0 {
-            print!(" \x1b[43m  \x1b[0m");
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let last_drawn_cells = unsafe { LAST_DRAWN_CELLS.take() };
+
+    // Build the whole frame first, so that it can be written all at once
+    let mut out = String::new();
+    match last_drawn_cells {
+        Some(last_drawn_cells) if !is_debug => {
+            draw_changes(&mut out, &cells, &last_drawn_cells);
         }
+        _ => draw_full(&mut out, &cells, is_debug, info_lines),
+    }
 
-        println!();
-        for y in 0..DISPLAY_HEIGHT {
-            print!("|");
-            for (x, old_row) in old_display_state.iter().enumerate() {
-                let is_set = get_display(u8::try_from(x).unwrap(), u8::try_from(y).unwrap());
-                let is_old_set = old_row[y];
-                if is_set && is_old_set {
-                    print!("\x1b[47m  \x1b[0m");
-                } else if is_set && !is_old_set {
-                    print!("\x1b[42m  \x1b[0m");
-                } else if !is_set && is_old_set {
-                    print!("\x1b[41m  \x1b[0m");
-                } else {
-                    print!("  ");
+    // The debug terminal prints below the display, so the next frame needs to be drawn in full
+    if !is_debug {
+        // SAFETY: single threaded
+        unsafe { LAST_DRAWN_CELLS = Some(cells) };
+    }
+
+    out
+}
+
+/// Write the character at the given column and row of the display, in the current render mode.
+fn draw_char(
+    out: &mut String,
+    cells: &[[Cell; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    column: usize,
+    row: usize,
+) {
+    let mode = get_render_mode();
+    let (block_width, block_height) = mode.block_size();
+    let (x, y) = (column * block_width, row * block_height);
+    match mode {
+        RenderMode::Double => out.push_str(cells[x][y].as_str()),
+        RenderMode::HalfBlock => match (cells[x][y].colors(), cells[x][y + 1].colors()) {
+            (None, None) => out.push(' '),
+            (Some((top, _)), None) => write!(out, "\x1b[{top}m▀\x1b[0m").unwrap(),
+            (None, Some((bottom, _))) => write!(out, "\x1b[{bottom}m▄\x1b[0m").unwrap(),
+            (Some((top, _)), Some((_, bottom))) => {
+                write!(out, "\x1b[{top};{bottom}m▀\x1b[0m").unwrap();
+            }
+        },
+        RenderMode::Braille => {
+            // The bit for each dot, by its position in the character
+            const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+            let mut dots = 0;
+            let mut changed = None;
+            for (dx, column_dots) in DOTS.iter().enumerate() {
+                for (dy, dot) in column_dots.iter().enumerate() {
+                    let cell = cells[x + dx][y + dy];
+                    // Each dot can't have its own color, so fading pixels are left out
+                    if !matches!(cell, Cell::Off | Cell::Fading(_)) {
+                        dots |= dot;
+                    }
+                    // Pixels that were turned on take priority when highlighting the character
+                    if cell == Cell::TurnedOn || (cell == Cell::TurnedOff && changed.is_none()) {
+                        changed = Some(cell);
+                    }
                 }
             }
-            print!("|");
-            if is_debug && y < info_lines.len() {
-                print!(" {}", info_lines[y]);
+            let foreground = changed
+                .unwrap_or(Cell::On)
+                .colors()
+                .map(|(fg, _)| fg)
+                .filter(|_| dots != 0);
+            let background = Cell::Off.colors().map(|(_, bg)| bg);
+            let glyph = char::from_u32(0x2800 + dots).unwrap();
+            match (foreground, background) {
+                (Some(fg), Some(bg)) => write!(out, "\x1b[{fg};{bg}m{glyph}\x1b[0m").unwrap(),
+                (Some(fg), None) => write!(out, "\x1b[{fg}m{glyph}\x1b[0m").unwrap(),
+                (None, _) => out.push(glyph),
             }
-            println!();
         }
-        println!("|{}|", (0..DISPLAY_WIDTH).map(|_| "__").collect::<String>());
-        println!();
-        if is_debug {
-            println!("Welcome to the debug terminal! h: help, c: continue");
-        } else {
-            println!();
-            println!();
+    }
+}
+
+/// Returns whether any of the pixels in the character at the given column and row changed between frames.
+fn char_changed(
+    cells: &[[Cell; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    last_drawn_cells: &[[Cell; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    column: usize,
+    row: usize,
+) -> bool {
+    let (block_width, block_height) = get_render_mode().block_size();
+    (column * block_width..(column + 1) * block_width).any(|x| {
+        (row * block_height..(row + 1) * block_height)
+            .any(|y| cells[x][y] != last_drawn_cells[x][y])
+    })
+}
+
+/// The top border of the display, with a colored square to indicate sound.
+fn draw_top_border(out: &mut String, is_debug: bool) {
+    out.push_str(&" ".repeat(display_margin(is_debug)));
+    out.push_str(&"_".repeat(get_render_mode().width() + 2));
+    if get_sound_timer() > 0 && has_room_for_sound_indicator(is_debug) {
+        out.push_str(" \x1b[43m  \x1b[0m");
+    }
+}
+
+/// Draw the display and everything around it, replacing the previous frame.
+fn draw_full(
+    out: &mut String,
+    cells: &[[Cell; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    is_debug: bool,
+    info_lines: &[String],
+) {
+    let mode = get_render_mode();
+    let margin = " ".repeat(display_margin(is_debug));
+
+    // Clear the terminal
+    for _ in 0..frame_height(is_debug) {
+        out.push_str("\x1b[2K\x1b[1A\r"); // Clear the line, then move the cursor up a line
+    }
+    out.push_str("\x1b[2K\r"); // Clear the last line
+
+    draw_top_border(out, is_debug);
+    out.push('\n');
+    let mut info_lines = info_lines.iter().filter(|_| is_debug);
+    for row in 0..mode.rows() {
+        out.push_str(&margin);
+        out.push('|');
+        for column in 0..mode.columns() {
+            draw_char(out, cells, column, row);
         }
+        out.push('|');
+        if let Some(line) = info_lines.next() {
+            write!(out, " {line}").unwrap();
+        }
+        out.push('\n');
+    }
+    write!(out, "{margin}|{}|", "_".repeat(mode.width())).unwrap();
+    // If the display is shorter than the debug info, the rest of it goes below
+    if is_debug {
+        for i in mode.rows()..INFO_PANEL_HEIGHT {
+            if let Some(line) = info_lines.next() {
+                if i > mode.rows() {
+                    out.push_str(&" ".repeat(mode.width() + 2));
+                }
+                write!(out, " {line}").unwrap();
+            }
+            out.push('\n');
+        }
+    }
+    if !is_debug || mode.rows() >= INFO_PANEL_HEIGHT {
+        out.push('\n');
+    }
+    out.push('\n');
+    if is_debug {
+        out.push_str("Welcome to the debug terminal! h: help, c: continue\n");
+    } else {
+        writeln!(out, "{}", status_line()).unwrap();
+        out.push('\n');
     }
 }
 
-#[allow(clippy::too_many_lines)]
-pub fn print_debug(
-    n_instructions_executed: &mut u128,
-    instruction: Instruction,
-    instruction_raw: u16,
-    debug_state: &mut DebugState,
+/// Draw only the characters that changed since the last frame, along with the top border and status line.
+/// The cursor is expected to be just below the previous frame, and it's left there.
+fn draw_changes(
+    out: &mut String,
+    cells: &[[Cell; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    last_drawn_cells: &[[Cell; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
 ) {
-    // If the current instruction is draw, skip to the next vertical blank
-    if let Instruction::Draw(_, _, _) = instruction {
-        while *n_instructions_executed % 12 != 1 {
-            // Properly update delay and sound timers
-            if *n_instructions_executed % 12 == 0 {
-                decrement_delay_timer();
-                decrement_sound_timer();
+    let mode = get_render_mode();
+    let margin = display_margin(false);
+
+    // Lines are counted from the top border
+    let bottom_line = frame_height(false);
+    let status_line = bottom_line - 2;
+
+    write!(out, "\x1b[{bottom_line}A\r\x1b[2K").unwrap();
+    draw_top_border(out, false);
+
+    let mut line = 0;
+    for row in 0..mode.rows() {
+        for column in 0..mode.columns() {
+            if !char_changed(cells, last_drawn_cells, column, row) {
+                continue;
+            }
+            if line != row + 1 {
+                write!(out, "\x1b[{}B", row + 1 - line).unwrap();
+                line = row + 1;
             }
-            *n_instructions_executed += 1;
+            // After the margin and the left border
+            write!(out, "\x1b[{}G", margin + 2 + mode.char_width() * column).unwrap();
+            draw_char(out, cells, column, row);
         }
     }
 
+    write!(
+        out,
+        "\x1b[{}B\r\x1b[2K{}",
+        status_line - line,
+        self::status_line()
+    )
+    .unwrap();
+    write!(out, "\x1b[{}B\r", bottom_line - status_line).unwrap();
+}
+
+#[allow(clippy::too_many_lines)]
+pub fn print_debug(instruction: Instruction, instruction_raw: u16, debug_state: &mut DebugState) {
     let info_lines = &mut debug_state.info_lines;
 
     info!(
@@ -332,7 +999,7 @@ pub fn print_debug(
                 info_lines,
                 "| {:#06X}: {:#04X} -> {}",
                 next_addr,
-                get_memory_u16(next_addr),
+                get_memory_u16(next_addr).unwrap_or(0),
                 ins.fancy_fmt()
             );
         } else {
@@ -340,21 +1007,35 @@ pub fn print_debug(
                 info_lines,
                 "| {:#06X}: {:#04X} -> (invalid)",
                 next_addr,
-                get_memory_u16(next_addr)
+                get_memory_u16(next_addr).unwrap_or(0)
             );
         }
     }
 
     // Registers + stack
     let stack = get_stack();
+    // The sprite at I, next to the registers
+    let draw_height = match instruction {
+        Instruction::Draw(_, _, n) => n,
+        _ => debug_state.last_draw_height,
+    };
+    let sprite_rows = debug_state.sprite_view.rows(draw_height);
     info!(
         info_lines,
         "|-----REGISTERS-----|----STACK({:X})----|------TIMERS------|",
         stack.len()
     );
-    for (i, old_reg) in debug_state.old_register_state.iter().enumerate() {
+    if let Some(rows) = sprite_rows {
+        infop!(info_lines, "{:-^18}|", format!("SPRITE({rows:X})"));
+    }
+    for ((i, old_reg), reg) in debug_state
+        .old_register_state
+        .iter()
+        .enumerate()
+        .zip(Register::ALL)
+    {
         // Register
-        let reg = get_register(u8::try_from(i).unwrap().into());
+        let reg = get_register(reg);
 
         if *old_reg == reg {
             // Register did not change
@@ -384,72 +1065,123 @@ pub fn print_debug(
         } else {
             infop!(info_lines, "                  |");
         }
+
+        // Sprite
+        if let Some(rows) = sprite_rows {
+            infop!(info_lines, " {} |", sprite_row(i, rows));
+        }
     }
+    // The rest of the panel is widened to fit the sprite
+    let sprite_padding = if sprite_rows.is_some() { 19 } else { 0 };
     info!(
         info_lines,
-        "|-------------------|----------------|------------------|"
+        "|-------------------|----------------|------------------|{}",
+        if sprite_rows.is_some() {
+            "------------------|"
+        } else {
+            ""
+        }
     );
     // I
-    let i_state = (get_i(), get_memory_u8(get_i()), get_memory_u8(get_i() + 2));
+    let i_state = i_state();
     if debug_state.old_i_state == i_state {
         info!(
             info_lines,
-            "| I: {:#06X} -> {:#04X} {:#04X}                                |",
+            "| I: {:#06X} -> {:#04X} {:#04X}                                {:sprite_padding$}|",
             i_state.0,
             i_state.1,
-            i_state.2
+            i_state.2,
+            ""
         );
     } else {
         info!(
             info_lines,
-            "| \x1b[32mI: {:#06X} -> {:#04X} {:#04X}  \x1b[2;37m{:#06X} -> {:#04X} {:#04X}\x1b[0m           |",
+            "| \x1b[32mI: {:#06X} -> {:#04X} {:#04X}  \x1b[2;37m{:#06X} -> {:#04X} {:#04X}\x1b[0m           {:sprite_padding$}|",
             i_state.0,
             i_state.1,
             i_state.2,
             debug_state.old_i_state.0,
             debug_state.old_i_state.1,
-            debug_state.old_i_state.2
+            debug_state.old_i_state.2,
+            ""
         );
     }
     info!(
         info_lines,
-        "|-------------------------------------------------------|"
+        "|-------------------------------------------------------{:-<sprite_padding$}|", ""
     );
 }
 
+/// Returns the given row of the sprite at I for the debug panel, with each pixel 2 characters wide, or blank if
+/// the sprite doesn't have that many rows.
+fn sprite_row(row: usize, rows: u8) -> String {
+    if row >= usize::from(rows) {
+        return " ".repeat(16);
+    }
+    let Some(Ok(val)) = u16::try_from(usize::from(get_i()) + row)
+        .ok()
+        .map(get_memory_u8)
+    else {
+        return format!("{:^16}", "??");
+    };
+    (0..8)
+        .rev()
+        .map(|bit| {
+            if val & (1 << bit) == 0 {
+                "··"
+            } else {
+                "██"
+            }
+        })
+        .collect()
+}
+
+/// Returns I, and the memory at I and I + 2, for showing in the debug terminal.
+/// Memory that's out of range is shown as 0.
+fn i_state() -> (u16, u8, u8) {
+    let i = get_i();
+    (
+        i,
+        get_memory_u8(i).unwrap_or(0),
+        get_memory_u8(i + 2).unwrap_or(0),
+    )
+}
+
+/// Decode the instruction at the given address, if it's in memory and valid.
+pub fn decode_at(addr: u16) -> Option<Instruction> {
+    decode(get_memory_u16(addr).ok()?, get_instruction_set())
+}
+
 /// Given an instruction, predict the next instruction and its address.
 /// This is not always accurate.
 fn predict_instruction(addr: u16) -> (Option<Instruction>, u16) {
-    let Some(ins) = decode(get_memory_u16(addr)) else {
+    let Some(ins) = decode_at(addr) else {
         return (None, addr + 2);
     };
     match ins {
-        Instruction::Jump(nnn) | Instruction::SubroutineCall(nnn) => {
-            (decode(get_memory_u16(nnn)), nnn)
+        Instruction::Jump(nnn) | Instruction::SubroutineCall(nnn) => (decode_at(nnn), nnn),
+        Instruction::JumpOffset(nnn) => {
+            let vx = get_register(get_instruction_set().jump_offset_register(nnn));
+            (decode_at(u16::from(vx) + nnn), nnn)
         }
-        Instruction::JumpOffset(nnn) => (
-            decode(get_memory_u16(u16::from(get_register(Register::V0)) + nnn)),
-            nnn,
-        ),
         Instruction::SubroutineReturn => {
             if let Some(s) = peek_stack() {
-                (decode(get_memory_u16(s)), s)
+                (decode_at(s), s)
             } else {
-                (decode(get_memory_u16(addr + 2)), addr + 2) // TODO change this to be something more clear?
+                (decode_at(addr + 2), addr + 2) // TODO change this to be something more clear?
             }
         }
-        _ => (decode(get_memory_u16(addr + 2)), addr + 2),
+        _ => (decode_at(addr + 2), addr + 2),
     }
 }
 
 /// Fetch the next instruction and increment the PC by 2.
-fn fetch() -> u16 {
+///
+/// # Errors
+/// Returns an error if the PC is at the end of memory.
+pub fn fetch() -> Result<u16, C8Error> {
     let pc = get_pc();
-    let instruction = get_memory_u16(pc);
-    set_pc(pc + 2);
-    instruction
-}
-
-fn invalid_instruction(instruction: u16) -> ! {
-    panic!("Invalid instruction at {:#x}: {:#x}", get_i(), instruction);
+    let instruction = get_memory_u16(pc)?;
+    set_pc(pc + 2)?;
+    Ok(instruction)
 }
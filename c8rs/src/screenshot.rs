@@ -0,0 +1,127 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    time::SystemTime,
+};
+
+use crate::system::{DISPLAY_HEIGHT, DISPLAY_WIDTH, get_full_display};
+
+/// The default number of image pixels per display pixel.
+pub const DEFAULT_SCREENSHOT_SCALE: usize = 8;
+
+/// The largest number of image pixels per display pixel, which makes a 4096x2048 image.
+pub const MAX_SCREENSHOT_SCALE: usize = 32;
+
+/// The number of image pixels per display pixel for screenshots taken with the hotkey, and for recordings.
+pub static mut SCREENSHOT_SCALE: usize = DEFAULT_SCREENSHOT_SCALE;
+
+pub fn get_screenshot_scale() -> usize {
+    // SAFETY: single threaded
+    unsafe { SCREENSHOT_SCALE }
+}
+
+pub fn set_screenshot_scale(val: usize) {
+    // SAFETY: single threaded
+    unsafe { SCREENSHOT_SCALE = val };
+}
+
+/// Returns a path for a new file in the current directory, based on the current time.
+/// e.g. `timestamped_path("screenshot", "png")` -> `screenshot-1700000000000.png`
+pub fn timestamped_path(prefix: &str, extension: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    format!("{prefix}-{timestamp}.{extension}")
+}
+
+/// Save the current display to the given path, scaling each pixel up to `scale` x `scale` pixels.
+/// The format is chosen based on the extension: `.pbm` for a plain PBM, otherwise PNG.
+///
+/// # Errors
+/// Returns an error if the scale isn't between 1 and `MAX_SCREENSHOT_SCALE`, or if the file could not be written.
+pub fn save_screenshot(path: &str, scale: usize) -> Result<(), String> {
+    if !(1..=MAX_SCREENSHOT_SCALE).contains(&scale) {
+        return Err(format!(
+            "scale must be between 1 and {MAX_SCREENSHOT_SCALE}"
+        ));
+    }
+
+    let is_pbm = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pbm"));
+    if is_pbm {
+        save_pbm(path, scale)
+    } else {
+        save_png(path, scale)
+    }
+}
+
+/// Returns whether the image pixel at the given position is set, for an image scaled up `scale` times.
+pub fn is_pixel_set(
+    display: &[[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    x: usize,
+    y: usize,
+    scale: usize,
+) -> bool {
+    display[x / scale][y / scale]
+}
+
+/// Save the display as a plain (ASCII) PBM. In PBM, `1` is black, so pixels that are on are written as `0` to look
+/// the same as in a PNG.
+fn save_pbm(path: &str, scale: usize) -> Result<(), String> {
+    let display = get_full_display();
+    let width = DISPLAY_WIDTH * scale;
+    let height = DISPLAY_HEIGHT * scale;
+
+    let mut contents = format!("P1\n{width} {height}\n");
+    for y in 0..height {
+        let row = (0..width)
+            .map(|x| {
+                if is_pixel_set(&display, x, y, scale) {
+                    "0"
+                } else {
+                    "1"
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(contents, "{row}").unwrap();
+    }
+
+    fs::write(path, contents).map_err(|e| format!("failed to write {path}: {e}"))
+}
+
+/// Save the display as an 8-bit grayscale PNG.
+fn save_png(path: &str, scale: usize) -> Result<(), String> {
+    let display = get_full_display();
+    let width = DISPLAY_WIDTH * scale;
+    let height = DISPLAY_HEIGHT * scale;
+
+    let file = File::create(path).map_err(|e| format!("failed to create {path}: {e}"))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        u32::try_from(width).map_err(|e| e.to_string())?,
+        u32::try_from(height).map_err(|e| e.to_string())?,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            data.push(if is_pixel_set(&display, x, y, scale) {
+                0xFF
+            } else {
+                0x00
+            });
+        }
+    }
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(|e| format!("failed to write {path}: {e}"))
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::ErrorKind,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use c8util::register::Register;
+
+use crate::{
+    alias::{get_aliases, get_macros, set_alias, set_macro},
+    config::config_dir,
+    debug_terminal::{add_breakpoint, get_break_on_sound, get_breakpoints, set_break_on_sound},
+    init::get_rom,
+    trace::{Tracepoint, add_tracepoint, get_tracepoints},
+    watch::{MemoryWatch, add_register_watch, add_watch, get_register_watches, get_watches},
+};
+
+/// The directory sessions are saved in, relative to the user's config directory.
+const SESSIONS_DIR: &str = "c8rs/sessions";
+
+/// The debugging state that's kept between runs of the same ROM, so that a debugging session can be resumed.
+///
+/// e.g.
+/// ```toml
+/// breakpoints = [570, 768]
+/// break_on_sound = true
+/// register_watches = [5]
+///
+/// [[memory_watches]]
+/// start = 768
+/// len = 8
+/// break_on_change = false
+///
+/// [aliases]
+/// dw = "x 8 i"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub breakpoints: BTreeSet<u16>,
+    /// Whether to break when the sound timer is set (`b sound`).
+    pub break_on_sound: bool,
+    /// The watched registers, from 0 for V0 to 15 for VF.
+    pub register_watches: Vec<u8>,
+    pub memory_watches: Vec<MemoryWatch>,
+    pub tracepoints: Vec<Tracepoint>,
+    pub aliases: BTreeMap<String, String>,
+    pub macros: BTreeMap<String, Vec<String>>,
+}
+
+impl Session {
+    /// Returns the current debugging state.
+    pub fn current() -> Self {
+        Self {
+            breakpoints: get_breakpoints(),
+            break_on_sound: get_break_on_sound(),
+            register_watches: get_register_watches().into_iter().map(u8::from).collect(),
+            memory_watches: get_watches(),
+            tracepoints: get_tracepoints(),
+            aliases: get_aliases(),
+            macros: get_macros(),
+        }
+    }
+
+    /// Add everything in the session to the current debugging state.
+    ///
+    /// # Errors
+    /// Returns an error if an address or register is out of range, or an alias or macro is invalid.
+    pub fn apply(&self) -> Result<(), String> {
+        let check_addr = |addr: u16| {
+            if addr & 0x0FFF == addr {
+                Ok(addr)
+            } else {
+                Err(format!(
+                    "address {addr:#06X} is too large (should be 12 bits)"
+                ))
+            }
+        };
+        for addr in &self.breakpoints {
+            add_breakpoint(check_addr(*addr)?);
+        }
+        if self.break_on_sound {
+            set_break_on_sound(true);
+        }
+        for reg in &self.register_watches {
+            let reg = Register::try_from(*reg).map_err(|_| format!("invalid register: {reg}"))?;
+            add_register_watch(reg);
+        }
+        for watch in &self.memory_watches {
+            check_addr(watch.start)?;
+            if watch.len == 0 {
+                return Err(format!("watch at {:#06X} has a length of 0", watch.start));
+            }
+            add_watch(*watch);
+        }
+        for tracepoint in &self.tracepoints {
+            check_addr(tracepoint.addr)?;
+            add_tracepoint(tracepoint.clone());
+        }
+        for (name, command) in &self.aliases {
+            set_alias(name, command)?;
+        }
+        for (name, commands) in &self.macros {
+            set_macro(name, commands.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// The session as it was when the ROM was started, or None if sessions aren't saved.
+pub static mut LOADED_SESSION: Option<Session> = None;
+
+/// Returns the path of the session file for the current ROM, which is named after the SHA-1 of the ROM.
+/// None if there's no config directory.
+pub fn session_path() -> Option<String> {
+    let dir = config_dir()?;
+    Some(format!("{dir}/{SESSIONS_DIR}/{}.toml", get_rom().sha1()))
+}
+
+/// Restore the breakpoints, watches, tracepoints, aliases and macros from the last time the current ROM was run,
+/// and save them again when the emulator exits.
+///
+/// # Errors
+/// Returns an error if the session file could not be read, or is invalid.
+pub fn load_session() -> Result<(), String> {
+    let Some(path) = session_path() else {
+        return Ok(());
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => toml::from_str::<Session>(&contents)
+            .map_err(|e| format!("invalid {path}: {e}"))?
+            .apply()
+            .map_err(|e| format!("invalid {path}: {e}"))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("failed to read {path}: {e}")),
+    }
+    // SAFETY: single threaded
+    unsafe { LOADED_SESSION = Some(Session::current()) };
+    Ok(())
+}
+
+/// Save the current session for the current ROM, if sessions are being saved and anything changed since it
+/// was loaded.
+///
+/// # Errors
+/// Returns an error if the session file could not be written.
+pub fn save_session() -> Result<(), String> {
+    let session = Session::current();
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let is_changed = unsafe { LOADED_SESSION.as_ref() }.is_some_and(|loaded| *loaded != session);
+    if !is_changed {
+        return Ok(());
+    }
+    let Some(path) = session_path() else {
+        return Ok(());
+    };
+    if let Some(dir) = Path::new(&path).parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+    }
+    let contents = toml::to_string_pretty(&session).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| format!("failed to write {path}: {e}"))
+}
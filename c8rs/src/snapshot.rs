@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    phosphor::{get_phosphor, set_phosphor},
+    rng::{get_rng_state, set_rng_state},
+    system::{
+        AUDIO_PATTERN_SIZE, DISPLAY_HEIGHT, DISPLAY_WIDTH, KeyWait, MEMORY_SIZE, USER_FLAGS_SIZE,
+        get_audio_pattern, get_delay_timer, get_full_display, get_full_memory, get_i, get_key_wait,
+        get_pc, get_pitch, get_registers, get_sound_timer, get_stack, get_user_flags, get_vblank,
+        set_audio_pattern, set_delay_timer, set_full_display, set_full_memory, set_i, set_key_wait,
+        set_pc, set_pitch, set_registers, set_sound_timer, set_stack, set_user_flags, set_vblank,
+    },
+    timing::{
+        get_frame_count, get_frame_elapsed_cycles, set_frame_count, set_frame_elapsed_cycles,
+    },
+};
+
+/// The maximum number of snapshots kept in the history, i.e. how many instructions can be stepped back.
+pub const HISTORY_SIZE: usize = 1024;
+
+/// Everything needed to put the machine back into the state it was in at some point.
+/// The coverage, profile and watch logs aren't included, since they describe the whole run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(with = "big_array")]
+    pub memory: [u8; MEMORY_SIZE],
+    #[serde(with = "big_array")]
+    pub display: [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    #[serde(with = "big_array")]
+    pub phosphor: [[u8; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+    pub registers: [u8; 16],
+    pub pc: u16,
+    pub i: u16,
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
+    pub vblank: bool,
+    pub key_wait: KeyWait,
+    pub user_flags: [u8; USER_FLAGS_SIZE],
+    pub rng_state: u64,
+    pub frame_count: u64,
+    pub frame_elapsed_cycles: u32,
+    /// The number of instructions that had been executed.
+    pub instructions_executed: u128,
+}
+
+impl Snapshot {
+    /// Take a snapshot of the current state of the machine.
+    pub fn take(instructions_executed: u128) -> Self {
+        Self {
+            memory: get_full_memory(),
+            display: get_full_display(),
+            phosphor: get_phosphor(),
+            registers: get_registers(),
+            pc: get_pc(),
+            i: get_i(),
+            stack: get_stack(),
+            delay_timer: get_delay_timer(),
+            sound_timer: get_sound_timer(),
+            audio_pattern: get_audio_pattern(),
+            pitch: get_pitch(),
+            vblank: get_vblank(),
+            key_wait: get_key_wait(),
+            user_flags: get_user_flags(),
+            rng_state: get_rng_state(),
+            frame_count: get_frame_count(),
+            frame_elapsed_cycles: get_frame_elapsed_cycles(),
+            instructions_executed,
+        }
+    }
+
+    /// Put the machine back into the state of the snapshot.
+    /// The user flags are restored in memory, but not in the file they were saved to.
+    pub fn restore(&self) {
+        set_full_memory(self.memory);
+        set_full_display(self.display);
+        set_phosphor(self.phosphor);
+        set_registers(self.registers);
+        // These were valid when the snapshot was taken
+        set_pc(self.pc).unwrap();
+        set_i(self.i).unwrap();
+        set_stack(self.stack.clone());
+        set_delay_timer(self.delay_timer);
+        set_sound_timer(self.sound_timer);
+        set_audio_pattern(self.audio_pattern);
+        set_pitch(self.pitch);
+        set_vblank(self.vblank);
+        set_key_wait(self.key_wait);
+        set_user_flags(self.user_flags);
+        set_rng_state(self.rng_state);
+        set_frame_count(self.frame_count);
+        set_frame_elapsed_cycles(self.frame_elapsed_cycles);
+    }
+}
+
+/// serde only implements its traits for arrays of up to 32 elements, so longer ones are (de)serialized as sequences.
+mod big_array {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        array.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[T; N], D::Error> {
+        let elements = Vec::<T>::deserialize(deserializer)?;
+        let len = elements.len();
+        elements
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &format!("{N} elements").as_str()))
+    }
+}
+
+/// The state of the machine before each of the last instructions that were executed, oldest first.
+pub static mut HISTORY: VecDeque<Snapshot> = VecDeque::new();
+
+/// Add a snapshot to the history, dropping the oldest one if it's full.
+pub fn push_history(snapshot: Snapshot) {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        if HISTORY.len() >= HISTORY_SIZE {
+            HISTORY.pop_front();
+        }
+        HISTORY.push_back(snapshot);
+    }
+}
+
+/// Returns the addresses of the last `count` instructions in the history, newest first.
+pub fn recent_history_addrs(count: usize) -> Vec<u16> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        HISTORY
+            .iter()
+            .rev()
+            .take(count)
+            .map(|snapshot| snapshot.pc)
+            .collect()
+    }
+}
+
+/// Remove the newest snapshot from the history and return it, or None if the history is empty.
+pub fn pop_history() -> Option<Snapshot> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        HISTORY.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+    use crate::system::{DISPLAY_HEIGHT, DISPLAY_WIDTH, KeyWait, MEMORY_SIZE};
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let mut snapshot = Snapshot {
+            memory: [0; MEMORY_SIZE],
+            display: [[false; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+            phosphor: [[0; DISPLAY_HEIGHT]; DISPLAY_WIDTH],
+            registers: [0; 16],
+            pc: 0x2A4,
+            i: 0xFFF,
+            stack: vec![0x200, 0x31E],
+            delay_timer: 60,
+            sound_timer: 3,
+            audio_pattern: [0xF0; 16],
+            pitch: 64,
+            vblank: true,
+            key_wait: KeyWait::Release(0xB),
+            user_flags: [7; 16],
+            rng_state: u64::MAX,
+            frame_count: 1234,
+            frame_elapsed_cycles: 11,
+            instructions_executed: u128::MAX,
+        };
+        snapshot.memory[0x200..0x204].copy_from_slice(&[0x00, 0xE0, 0x12, 0x00]);
+        snapshot.display[DISPLAY_WIDTH - 1][DISPLAY_HEIGHT - 1] = true;
+        snapshot.phosphor[3][5] = 0x80;
+        snapshot.registers[0xF] = 1;
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn snapshot_with_short_memory_is_rejected() {
+        let json = r#"{"memory": [0, 1, 2]}"#;
+        let error = serde_json::from_str::<Snapshot>(json).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("invalid length 3, expected 4096 elements")
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use crate::timing::{FRAME_RATE, TimingMode, get_ips, get_timing_mode};
+
+/// How long the speed is measured for before the statistics are updated.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The speed the emulator actually ran at over the last measurement.
+#[derive(Debug, Clone, Copy)]
+pub struct SpeedSample {
+    /// Instructions executed per second.
+    pub ips: f64,
+    /// Frames drawn per second.
+    pub fps: f64,
+    /// How many times per second the delay and sound timers were decremented.
+    pub timer_rate: f64,
+    /// How much faster (positive) or slower (negative) than real time the emulated time ran, as a percentage.
+    pub drift: f64,
+}
+
+impl Display for SpeedSample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match get_timing_mode() {
+            TimingMode::Ips => write!(f, "IPS {:.0}/{}", self.ips, get_ips())?,
+            TimingMode::Vip => write!(f, "IPS {:.0} (vip)", self.ips)?,
+        }
+        write!(
+            f,
+            "  FPS {:.1}  timers {:.1} Hz  drift {:+.1}%",
+            self.fps, self.timer_rate, self.drift
+        )
+    }
+}
+
+/// Counts what happens during a measurement.
+#[derive(Debug, Clone, Copy)]
+struct Measurement {
+    start: Instant,
+    instructions: u64,
+    frames: u64,
+    draws: u64,
+}
+
+/// Measures how fast the emulator is running, compared to the configured speed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpeedStats {
+    /// The current measurement. None while paused (e.g. in the debug terminal).
+    current: Option<Measurement>,
+    /// The last finished measurement.
+    last: Option<SpeedSample>,
+}
+
+impl SpeedStats {
+    pub const fn new() -> Self {
+        Self {
+            current: None,
+            last: None,
+        }
+    }
+
+    /// Count an instruction, and whether it started a new frame and drew it.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn record(&mut self, is_new_frame: bool, is_drawn: bool) {
+        let now = Instant::now();
+        let current = self.current.get_or_insert(Measurement {
+            start: now,
+            instructions: 0,
+            frames: 0,
+            draws: 0,
+        });
+        current.instructions += 1;
+        current.frames += u64::from(is_new_frame);
+        current.draws += u64::from(is_drawn);
+
+        let elapsed = now - current.start;
+        if elapsed >= STATS_INTERVAL {
+            let seconds = elapsed.as_secs_f64();
+            let emulated_seconds = current.frames as f64 / f64::from(FRAME_RATE);
+            self.last = Some(SpeedSample {
+                ips: current.instructions as f64 / seconds,
+                fps: current.draws as f64 / seconds,
+                timer_rate: current.frames as f64 / seconds,
+                drift: 100.0 * (emulated_seconds / seconds - 1.0),
+            });
+            self.current = None;
+        }
+    }
+
+    /// Stop measuring until the next instruction, so that time spent paused isn't counted.
+    pub fn pause(&mut self) {
+        self.current = None;
+    }
+
+    /// Returns the last finished measurement, if there is one.
+    pub fn last(&self) -> Option<SpeedSample> {
+        self.last
+    }
+}
+
+/// The speed of the emulator while it's running.
+pub static mut SPEED_STATS: SpeedStats = SpeedStats::new();
+
+/// Whether the speed statistics are shown below the display.
+pub static mut SHOW_STATS: bool = false;
+
+pub fn is_stats_shown() -> bool {
+    // SAFETY: single threaded
+    unsafe { SHOW_STATS }
+}
+
+pub fn set_stats_shown(val: bool) {
+    // SAFETY: single threaded
+    unsafe { SHOW_STATS = val };
+}
+
+/// Count an instruction. See `SpeedStats::record`.
+pub fn record_speed(is_new_frame: bool, is_drawn: bool) {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        SPEED_STATS.record(is_new_frame, is_drawn);
+    }
+}
+
+/// Stop measuring the speed until the next instruction. See `SpeedStats::pause`.
+pub fn pause_speed() {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        SPEED_STATS.pause();
+    }
+}
+
+/// Returns the speed statistics to show below the display, if they're enabled.
+pub fn stats_line() -> Option<String> {
+    if !is_stats_shown() {
+        return None;
+    }
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let last = unsafe { SPEED_STATS.last() };
+    Some(last.map_or_else(
+        || String::from("Measuring speed..."),
+        |sample| sample.to_string(),
+    ))
+}
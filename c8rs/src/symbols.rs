@@ -0,0 +1,102 @@
+use std::{collections::BTreeMap, fs, io::ErrorKind};
+
+use c8util::symbols::{SourceLocation, parse_source_lines, parse_symbols};
+
+/// The names of addresses in the ROM (e.g. subroutines), used by the debug terminal.
+pub static mut SYMBOLS: BTreeMap<u16, String> = BTreeMap::new();
+
+/// The source line that each address was assembled from, if the symbol file came from c8asm.
+pub static mut SOURCE_LINES: BTreeMap<u16, SourceLocation> = BTreeMap::new();
+
+/// Returns the default path of the symbol file for the given ROM.
+/// e.g. `roms/game.ch8` -> `roms/game.ch8.sym`
+pub fn default_symbols_path(rom_path: &str) -> String {
+    format!("{rom_path}.sym")
+}
+
+/// Load the symbols from the given file. Each line is an address followed by its name, and `#` starts a comment.
+/// Files written by c8asm also have the source line of each address (e.g. `line 0x200 main.asm:3`).
+///
+/// e.g.
+/// ```text
+/// # Subroutines
+/// 0x200 main
+/// 0x23A draw_player
+/// ```
+///
+/// # Errors
+/// Returns an error if the file could not be read or is invalid. If `is_optional` is set, a file that doesn't
+/// exist isn't an error, and no symbols are loaded.
+pub fn load_symbols(path: &str, is_optional: bool) -> Result<(), String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if is_optional && e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("failed to read {path}: {e}")),
+    };
+
+    let symbols = parse_symbols(&contents).map_err(|e| format!("{path}:{e}"))?;
+    let source_lines = parse_source_lines(&contents).map_err(|e| format!("{path}:{e}"))?;
+    // SAFETY: single threaded
+    unsafe {
+        SYMBOLS = symbols;
+        SOURCE_LINES = source_lines;
+    };
+    Ok(())
+}
+
+/// Returns the source line that the given address was assembled from, if it's known.
+pub fn source_line(addr: u16) -> Option<SourceLocation> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        SOURCE_LINES.get(&addr).cloned()
+    }
+}
+
+/// Returns all symbols, sorted by address.
+pub fn get_symbols() -> BTreeMap<u16, String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        SYMBOLS.clone()
+    }
+}
+
+/// Returns the address of the symbol with the given name, if there is one.
+pub fn symbol_addr(name: &str) -> Option<u16> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        SYMBOLS
+            .iter()
+            .find(|(_, symbol)| *symbol == name)
+            .map(|(addr, _)| *addr)
+    }
+}
+
+/// Returns the name of the given address if it has one, or the address in hex.
+pub fn addr_label(addr: u16) -> String {
+    symbol_name(addr).unwrap_or_else(|| format!("{addr:#06X}"))
+}
+
+/// Returns the name of the given address, if it has one.
+pub fn symbol_name(addr: u16) -> Option<String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        SYMBOLS.get(&addr).cloned()
+    }
+}
+
+/// Returns the given address relative to the closest symbol at or before it (e.g. `draw_player+0x4`), or None
+/// if there is no such symbol.
+pub fn symbolize(addr: u16) -> Option<String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    let (symbol_addr, name) = unsafe { SYMBOLS.range(..=addr).next_back()? };
+    if *symbol_addr == addr {
+        Some(name.clone())
+    } else {
+        Some(format!("{name}+{:#X}", addr - symbol_addr))
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+/// Handles 0NNN instructions (calls to machine language routines) for a specific address.
+///
+/// The original interpreters ran native code at the address. Instead, the embedder can register a handler
+/// to provide host services, e.g. printing debug values. Handlers can read and change the machine state
+/// through the functions in `system`.
+pub trait SyscallHandler: Send {
+    /// Called when a 0NNN instruction with the given address is executed.
+    ///
+    /// # Errors
+    /// Returns an error if the call failed. Execution breaks into the debug terminal, showing the error.
+    fn call(&mut self, addr: u16) -> Result<(), String>;
+}
+
+impl<F> SyscallHandler for F
+where
+    F: FnMut(u16) -> Result<(), String> + Send,
+{
+    fn call(&mut self, addr: u16) -> Result<(), String> {
+        self(addr)
+    }
+}
+
+/// The registered handlers, keyed by address.
+pub static SYSCALL_HANDLERS: LazyLock<Mutex<HashMap<u16, Box<dyn SyscallHandler>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Register a handler for 0NNN instructions with the given address, replacing any existing handler.
+///
+/// # Errors
+/// Returns an error if the address doesn't fit in the 12 bits of a 0NNN instruction.
+pub fn register_syscall_handler(addr: u16, handler: Box<dyn SyscallHandler>) -> Result<(), String> {
+    if addr > 0x0FFF {
+        return Err(format!(
+            "{addr:#06X} isn't a machine language routine address (0NNN)"
+        ));
+    }
+    SYSCALL_HANDLERS.lock().unwrap().insert(addr, handler);
+    Ok(())
+}
+
+/// Remove the handler for the given address, if there is one.
+pub fn unregister_syscall_handler(addr: u16) {
+    SYSCALL_HANDLERS.lock().unwrap().remove(&addr);
+}
+
+/// Returns whether there is a handler for the given address.
+pub fn has_syscall_handler(addr: u16) -> bool {
+    SYSCALL_HANDLERS.lock().unwrap().contains_key(&addr)
+}
+
+/// Call the handler for the given address.
+/// Handlers must not register or unregister handlers, since the handlers are locked while one is running.
+///
+/// # Errors
+/// Returns an error if there is no handler for the address, or if the handler failed.
+pub fn call_syscall(addr: u16) -> Result<(), String> {
+    let mut handlers = SYSCALL_HANDLERS.lock().unwrap();
+    let Some(handler) = handlers.get_mut(&addr) else {
+        return Err(format!(
+            "no handler for machine language routine at {addr:#05X} (0NNN)"
+        ));
+    };
+    handler.call(addr)
+}
@@ -1,39 +1,98 @@
 use std::sync::{LazyLock, Mutex};
 
 use c8util::register::Register;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bus::{Bus, Ram},
+    error::C8Error,
+};
 
 pub const MEMORY_SIZE: usize = 4096;
 
-/// MEMORY: 4KB of RAM
-pub static mut MEMORY: [u8; MEMORY_SIZE] = [0u8; MEMORY_SIZE];
+/// MEMORY: what instructions read and write through. None until it's first accessed, when it becomes 4KB of RAM
+/// (unless `set_bus` was called first).
+pub static mut BUS: Option<Box<dyn Bus>> = None;
 
-/// Get the memory value at the current position.
-pub fn get_memory_u8(addr: u16) -> u8 {
-    assert!((addr & 0xf000) == 0, "Address must be 12-bit!");
+/// The number of bytes of memory that have been read or written, including instruction fetches. Used for benchmarking.
+pub static mut MEMORY_ACCESSES: u64 = 0;
+
+pub fn get_memory_accesses() -> u64 {
+    // SAFETY: single threaded
+    unsafe { MEMORY_ACCESSES }
+}
+
+/// Returns the memory bus.
+fn bus() -> &'static mut dyn Bus {
+    // SAFETY: single threaded, and the reference isn't held across calls that could replace the bus
+    #[allow(static_mut_refs)]
+    unsafe {
+        BUS.get_or_insert_with(|| Box::new(Ram::default())).as_mut()
+    }
+}
+
+/// Replace the memory bus, e.g. with one that adds banked memory or write protection. Its contents are set to the
+/// current contents of memory, so this can be called before or after the ROM is loaded.
+pub fn set_bus(mut new_bus: Box<dyn Bus>) {
+    new_bus.set_contents(get_full_memory());
     // SAFETY: single threaded
-    unsafe { MEMORY[addr as usize] }
+    unsafe { BUS = Some(new_bus) };
+}
+
+/// Add to the number of memory accesses.
+fn count_accesses(n: u64) {
+    // SAFETY: single threaded
+    unsafe { MEMORY_ACCESSES += n };
+}
+
+/// Returns the full memory. This doesn't count as a memory access.
+pub fn get_full_memory() -> [u8; MEMORY_SIZE] {
+    bus().contents()
+}
+
+/// Replace the full memory. This doesn't count as a memory access.
+pub fn set_full_memory(memory: [u8; MEMORY_SIZE]) {
+    bus().set_contents(memory);
+}
+
+/// Get the memory value at the current position.
+///
+/// # Errors
+/// Returns an error if the address is outside of memory, or the bus can't read it.
+pub fn get_memory_u8(addr: u16) -> Result<u8, C8Error> {
+    let val = bus().read8(addr)?;
+    count_accesses(1);
+    Ok(val)
 }
 
 /// Return a 16-byte memory value at the current position.
-pub fn get_memory_u16(addr: u16) -> u16 {
-    assert!(((addr + 1) & 0xf000) == 0, "Address must be 12-bit!");
-    (u16::from(get_memory_u8(addr)) << 8) | u16::from(get_memory_u8(addr + 1))
+///
+/// # Errors
+/// Returns an error if either byte is outside of memory, or the bus can't read it.
+pub fn get_memory_u16(addr: u16) -> Result<u16, C8Error> {
+    let val = bus().read16(addr)?;
+    count_accesses(2);
+    Ok(val)
 }
 
 /// Set the memory value at the current position.
-pub fn set_memory_u8(addr: u16, val: u8) {
-    assert!((addr & 0xf000) == 0, "Address must be 12-bit!");
-    // SAFETY: single threaded
-    unsafe {
-        MEMORY[addr as usize] = val;
-    }
+///
+/// # Errors
+/// Returns an error if the address is outside of memory, or the bus can't write it.
+pub fn set_memory_u8(addr: u16, val: u8) -> Result<(), C8Error> {
+    bus().write8(addr, val)?;
+    count_accesses(1);
+    Ok(())
 }
 
 /// Set the memory value at the current position.
-pub fn set_memory_u16(addr: u16, val: u16) {
-    assert!((addr & 0xf000) == 0, "Address must be 12-bit!");
-    set_memory_u8(addr, ((val >> 8) & 0x00FF) as u8);
-    set_memory_u8(addr + 1, (val & 0x00FF) as u8);
+///
+/// # Errors
+/// Returns an error if either byte is outside of memory, or the bus can't write it.
+pub fn set_memory_u16(addr: u16, val: u16) -> Result<(), C8Error> {
+    bus().write16(addr, val)?;
+    count_accesses(2);
+    Ok(())
 }
 
 pub const DISPLAY_WIDTH: usize = 64;
@@ -44,18 +103,16 @@ pub static mut DISPLAY: [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH] =
     [[false; DISPLAY_HEIGHT]; DISPLAY_WIDTH];
 
 /// Gets the current value of the display at the given position.
-pub fn get_display(x: u8, y: u8) -> bool {
-    assert!(
-        (x as usize) < DISPLAY_WIDTH,
-        "x-coord ({x}) was out of range of display width ({DISPLAY_WIDTH})"
-    );
-    assert!(
-        (y as usize) < DISPLAY_HEIGHT,
-        "y-coord ({y}) was out of range of display width ({DISPLAY_HEIGHT})"
-    );
+///
+/// # Errors
+/// Returns an error if the position is outside of the display.
+pub fn get_display(x: u8, y: u8) -> Result<bool, C8Error> {
+    if x as usize >= DISPLAY_WIDTH || y as usize >= DISPLAY_HEIGHT {
+        return Err(C8Error::PixelOutOfRange { x, y });
+    }
 
     // SAFETY: single threaded
-    unsafe { DISPLAY[x as usize][y as usize] }
+    unsafe { Ok(DISPLAY[x as usize][y as usize]) }
 }
 
 /// Returns the full display.
@@ -64,19 +121,24 @@ pub fn get_full_display() -> [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH] {
     unsafe { DISPLAY }
 }
 
+/// Replace the full display.
+pub fn set_full_display(display: [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH]) {
+    // SAFETY: single threaded
+    unsafe { DISPLAY = display };
+}
+
 /// Sets the display to the given value at the given position.
-pub fn set_display(x: u8, y: u8, val: bool) {
-    assert!(
-        (x as usize) < DISPLAY_WIDTH,
-        "x-coord ({x}) was out of range of display width ({DISPLAY_WIDTH})"
-    );
-    assert!(
-        (y as usize) < DISPLAY_HEIGHT,
-        "y-coord ({y}) was out of range of display width ({DISPLAY_HEIGHT})"
-    );
+///
+/// # Errors
+/// Returns an error if the position is outside of the display.
+pub fn set_display(x: u8, y: u8, val: bool) -> Result<(), C8Error> {
+    if x as usize >= DISPLAY_WIDTH || y as usize >= DISPLAY_HEIGHT {
+        return Err(C8Error::PixelOutOfRange { x, y });
+    }
 
     // SAFETY: single threaded
     unsafe { DISPLAY[x as usize][y as usize] = val };
+    Ok(())
 }
 
 /// The program counter (PC). Points at the current instruction in memory. Can only address 12 bits of memory.
@@ -87,11 +149,18 @@ pub fn get_pc() -> u16 {
     unsafe { PC }
 }
 
-pub fn set_pc(val: u16) {
-    assert!((val & 0xF000) == 0, "Address must be 12-bit");
+/// Set the PC.
+///
+/// # Errors
+/// Returns an error if the address doesn't fit in 12 bits.
+pub fn set_pc(val: u16) -> Result<(), C8Error> {
+    if (val & 0xF000) != 0 {
+        return Err(C8Error::PcOutOfRange(val));
+    }
 
     // SAFETY: single threaded
     unsafe { PC = val };
+    Ok(())
 }
 
 /// The index register (I). Points at a location in memory. Can only address 12 bits of memory.
@@ -102,11 +171,18 @@ pub fn get_i() -> u16 {
     unsafe { I }
 }
 
-pub fn set_i(val: u16) {
-    assert!((val & 0xF000) == 0, "Address must be 12-bit");
+/// Set I.
+///
+/// # Errors
+/// Returns an error if the address doesn't fit in 12 bits.
+pub fn set_i(val: u16) -> Result<(), C8Error> {
+    if (val & 0xF000) != 0 {
+        return Err(C8Error::IndexOutOfRange(val));
+    }
 
     // SAFETY: single threaded
     unsafe { I = val };
+    Ok(())
 }
 
 pub const STACK_SIZE: usize = 16;
@@ -115,12 +191,34 @@ pub const STACK_SIZE: usize = 16;
 pub static mut STACK: LazyLock<Mutex<Vec<u16>>> =
     LazyLock::new(|| Mutex::new(Vec::with_capacity(STACK_SIZE)));
 
-pub fn stack_push(val: u16) {
+/// The maximum number of entries in the stack.
+pub static mut STACK_DEPTH: usize = STACK_SIZE;
+
+pub fn get_stack_depth() -> usize {
+    // SAFETY: single threaded
+    unsafe { STACK_DEPTH }
+}
+
+pub fn set_stack_depth(val: usize) {
+    // SAFETY: single threaded
+    unsafe { STACK_DEPTH = val };
+}
+
+/// Push the value to the stack.
+///
+/// # Errors
+/// Returns an error if the stack is already at its maximum depth.
+pub fn stack_push(val: u16) -> Result<(), C8Error> {
     // SAFETY: single threaded
     #[allow(static_mut_refs)]
     unsafe {
-        STACK.lock().unwrap().push(val);
+        let mut stack = STACK.lock().unwrap();
+        if stack.len() >= get_stack_depth() {
+            return Err(C8Error::StackOverflow(stack.len()));
+        }
+        stack.push(val);
     };
+    Ok(())
 }
 
 pub fn stack_pop() -> Option<u16> {
@@ -139,6 +237,15 @@ pub fn get_stack() -> Vec<u16> {
     }
 }
 
+/// Replace the whole stack.
+pub fn set_stack(stack: Vec<u16>) {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        *STACK.lock().unwrap() = stack;
+    }
+}
+
 pub fn peek_stack() -> Option<u16> {
     // SAFETY: single threaded
     #[allow(static_mut_refs)]
@@ -188,6 +295,56 @@ pub fn decrement_sound_timer() {
     unsafe { SOUND_TIMER = SOUND_TIMER.saturating_sub(1) }
 }
 
+/// The size of the XO-CHIP audio pattern buffer, in bytes.
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+
+/// The XO-CHIP audio pattern buffer, loaded by F002. Each bit is one sample, played from the most significant bit of
+/// the first byte while the sound timer is not 0. Defaults to a square wave, so programs that never load a pattern
+/// still beep.
+pub static mut AUDIO_PATTERN: [u8; AUDIO_PATTERN_SIZE] = [
+    0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF,
+];
+
+pub fn get_audio_pattern() -> [u8; AUDIO_PATTERN_SIZE] {
+    // SAFETY: single threaded
+    unsafe { AUDIO_PATTERN }
+}
+
+pub fn set_audio_pattern(pattern: [u8; AUDIO_PATTERN_SIZE]) {
+    // SAFETY: single threaded
+    unsafe { AUDIO_PATTERN = pattern }
+}
+
+/// The XO-CHIP pitch register, set by FX3A. The audio pattern is played at 4000 * 2^((PITCH - 64) / 48) bits per
+/// second, so the default of 64 plays it at 4000 bits per second.
+pub static mut PITCH: u8 = DEFAULT_PITCH;
+
+pub const DEFAULT_PITCH: u8 = 64;
+
+pub fn get_pitch() -> u8 {
+    // SAFETY: single threaded
+    unsafe { PITCH }
+}
+
+pub fn set_pitch(val: u8) {
+    // SAFETY: single threaded
+    unsafe { PITCH = val }
+}
+
+/// Whether a vertical blank has happened since the last draw. Set at the start of every frame, and
+/// cleared by DXYN, which waits for it before drawing.
+pub static mut VBLANK: bool = false;
+
+pub fn get_vblank() -> bool {
+    // SAFETY: single threaded
+    unsafe { VBLANK }
+}
+
+pub fn set_vblank(val: bool) {
+    // SAFETY: single threaded
+    unsafe { VBLANK = val }
+}
+
 pub static mut REGISTERS: [u8; 16] = [0u8; 16];
 
 pub fn get_registers() -> [u8; 16] {
@@ -195,6 +352,11 @@ pub fn get_registers() -> [u8; 16] {
     unsafe { REGISTERS }
 }
 
+pub fn set_registers(registers: [u8; 16]) {
+    // SAFETY: single threaded
+    unsafe { REGISTERS = registers };
+}
+
 pub fn get_register(reg: Register) -> u8 {
     // SAFETY: single threaded
     unsafe { REGISTERS[reg as usize] }
@@ -204,3 +366,43 @@ pub fn set_register(reg: Register, val: u8) {
     // SAFETY: single threaded
     unsafe { REGISTERS[reg as usize] = val };
 }
+
+/// The number of user flags. The HP48 only had 8, but later interpreters (e.g. XO-CHIP) allow all 16 registers
+/// to be stored.
+pub const USER_FLAGS_SIZE: usize = 16;
+
+/// The user flags, used by FX75 and FX85. These are saved to disk, so they persist between runs.
+pub static mut USER_FLAGS: [u8; USER_FLAGS_SIZE] = [0u8; USER_FLAGS_SIZE];
+
+pub fn get_user_flags() -> [u8; USER_FLAGS_SIZE] {
+    // SAFETY: single threaded
+    unsafe { USER_FLAGS }
+}
+
+pub fn set_user_flags(flags: [u8; USER_FLAGS_SIZE]) {
+    // SAFETY: single threaded
+    unsafe { USER_FLAGS = flags };
+}
+
+/// The state of an in-progress FX0A instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyWait {
+    /// Not currently waiting for a key.
+    Idle,
+    /// Waiting for a key to be pressed.
+    Press,
+    /// The given key was pressed, and we are waiting for it to be released.
+    Release(u8),
+}
+
+pub static mut KEY_WAIT: KeyWait = KeyWait::Idle;
+
+pub fn get_key_wait() -> KeyWait {
+    // SAFETY: single threaded
+    unsafe { KEY_WAIT }
+}
+
+pub fn set_key_wait(val: KeyWait) {
+    // SAFETY: single threaded
+    unsafe { KEY_WAIT = val };
+}
@@ -0,0 +1,98 @@
+use terminal_size::{Height, Width};
+
+use crate::run::{LAST_DRAWN_CELLS, RenderMode, frame_height, get_render_mode};
+
+/// The size of the terminal when the last frame was drawn, as columns and lines. None if it isn't known.
+pub static mut LAST_TERMINAL_SIZE: Option<(usize, usize)> = None;
+
+/// Whether the terminal was too small to draw the last frame.
+pub static mut TERMINAL_TOO_SMALL: bool = false;
+
+/// Returns the size of the terminal as columns and lines, or None if it can't be found (e.g. if stdout isn't a
+/// terminal).
+pub fn terminal_size() -> Option<(usize, usize)> {
+    terminal_size::terminal_size().map(|(Width(w), Height(h))| (usize::from(w), usize::from(h)))
+}
+
+/// The smallest terminal the display can be drawn in with the current render mode, as columns and lines.
+pub fn min_terminal_size(is_debug: bool) -> (usize, usize) {
+    (get_render_mode().width() + 2, frame_height(is_debug))
+}
+
+/// The number of columns to the left of the display, so that it's centered in the terminal.
+/// The display isn't centered in the debug terminal, since the debug info is drawn to the right of it.
+pub fn display_margin(is_debug: bool) -> usize {
+    // SAFETY: single threaded
+    let size = unsafe { LAST_TERMINAL_SIZE };
+    match size {
+        Some((columns, _)) if !is_debug => columns.saturating_sub(min_terminal_size(false).0) / 2,
+        _ => 0,
+    }
+}
+
+/// Returns whether there's room for the sound indicator to the right of the top border.
+pub fn has_room_for_sound_indicator(is_debug: bool) -> bool {
+    // SAFETY: single threaded
+    let size = unsafe { LAST_TERMINAL_SIZE };
+    size.is_none_or(|(columns, _)| {
+        columns >= display_margin(is_debug) + min_terminal_size(is_debug).0 + 3
+    })
+}
+
+/// Check whether the terminal was resized since the last frame, and whether the display fits in it.
+/// Returns the text to print before the next frame, or None if the display doesn't fit and shouldn't be drawn.
+///
+/// The terminal reflows what's already been drawn when it's resized, so the next frame is drawn in full on a
+/// clear screen. If the terminal is too small, a message is shown instead of the display.
+pub fn check_terminal_size(is_debug: bool) -> Option<String> {
+    let Some((columns, lines)) = terminal_size() else {
+        return Some(String::new());
+    };
+    // SAFETY: single threaded
+    let (last_size, was_too_small) = unsafe { (LAST_TERMINAL_SIZE, TERMINAL_TOO_SMALL) };
+    // SAFETY: single threaded
+    unsafe { LAST_TERMINAL_SIZE = Some((columns, lines)) };
+    let is_resized = last_size.is_some_and(|size| size != (columns, lines));
+
+    let (min_columns, min_lines) = min_terminal_size(is_debug);
+    let is_too_small = columns < min_columns || lines < min_lines;
+    // SAFETY: single threaded
+    unsafe { TERMINAL_TOO_SMALL = is_too_small };
+
+    if is_too_small {
+        // SAFETY: single threaded
+        unsafe { LAST_DRAWN_CELLS = None };
+        // Only show the message when something changes, so that it doesn't flicker
+        if is_resized || !was_too_small {
+            print!(
+                "\x1b[2J\x1b[H{}",
+                too_small_message(columns, lines, min_columns, min_lines)
+            );
+        }
+        return None;
+    }
+
+    if is_resized || was_too_small {
+        // SAFETY: single threaded
+        unsafe { LAST_DRAWN_CELLS = None };
+        // Make room for the frame, which moves the cursor up to its top before drawing
+        return Some(format!(
+            "\x1b[2J\x1b[H{}",
+            "\n".repeat(frame_height(is_debug))
+        ));
+    }
+    Some(String::new())
+}
+
+/// The message shown instead of the display when the terminal is too small.
+fn too_small_message(columns: usize, lines: usize, min_columns: usize, min_lines: usize) -> String {
+    let mut message = format!(
+        "Terminal too small: the display needs {min_columns}x{min_lines}, but the terminal is {columns}x{lines}.\n\
+         Make the terminal bigger"
+    );
+    if get_render_mode() != RenderMode::Braille {
+        message.push_str(", or use a smaller render mode (e.g. --render braille)");
+    }
+    message.push_str(".\n");
+    message
+}
@@ -0,0 +1,185 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use c8util::instructions::{Instruction, VIP_CYCLES_PER_FRAME};
+
+use crate::{
+    audio::update_audio,
+    execute::get_instruction_set,
+    hooks::notify_timer_tick,
+    phosphor::update_phosphor,
+    run::INSTRUCTION_SPEED,
+    system::{decrement_delay_timer, decrement_sound_timer, set_vblank},
+};
+
+/// The number of frames per second. The delay and sound timers are decremented once per frame,
+/// and the display is redrawn once per frame.
+pub const FRAME_RATE: u32 = 60;
+
+/// How long instructions take to execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimingMode {
+    /// Execute a fixed number of instructions per second.
+    Ips,
+    /// Each instruction takes roughly as long as it did on the COSMAC VIP, for ROMs that rely on the
+    /// original timing.
+    Vip,
+}
+
+pub static mut TIMING_MODE: TimingMode = TimingMode::Ips;
+
+pub fn get_timing_mode() -> TimingMode {
+    // SAFETY: single threaded
+    unsafe { TIMING_MODE }
+}
+
+pub fn set_timing_mode(mode: TimingMode) {
+    // SAFETY: single threaded
+    unsafe { TIMING_MODE = mode };
+}
+
+/// The number of instructions to execute per second.
+pub static mut IPS: usize = INSTRUCTION_SPEED;
+
+pub fn get_ips() -> usize {
+    // SAFETY: single threaded
+    unsafe { IPS }
+}
+
+pub fn set_ips(val: usize) {
+    assert!(val > 0, "instructions per second must be greater than 0");
+
+    // SAFETY: single threaded
+    unsafe { IPS = val };
+}
+
+/// The number of frames that have started since the emulator was started.
+pub static mut FRAME_COUNT: u64 = 0;
+
+pub fn get_frame_count() -> u64 {
+    // SAFETY: single threaded
+    unsafe { FRAME_COUNT }
+}
+
+pub fn set_frame_count(val: u64) {
+    // SAFETY: single threaded
+    unsafe { FRAME_COUNT = val };
+}
+
+/// Returns whether a new frame starts after the given instruction.
+/// Frames are spread evenly over the instructions executed in a second, so the timers always run at 60 HZ
+/// regardless of the number of instructions per second.
+pub fn is_frame_boundary(n_instructions_executed: u128) -> bool {
+    if n_instructions_executed == 0 {
+        return true;
+    }
+    let ips = get_ips() as u128;
+    let frame_rate = u128::from(FRAME_RATE);
+    (n_instructions_executed * frame_rate) / ips
+        != ((n_instructions_executed - 1) * frame_rate) / ips
+}
+
+/// The number of VIP machine cycles that have passed in the current frame. Only used for VIP timing.
+pub static mut FRAME_ELAPSED_CYCLES: u32 = 0;
+
+pub fn get_frame_elapsed_cycles() -> u32 {
+    // SAFETY: single threaded
+    unsafe { FRAME_ELAPSED_CYCLES }
+}
+
+pub fn set_frame_elapsed_cycles(val: u32) {
+    // SAFETY: single threaded
+    unsafe { FRAME_ELAPSED_CYCLES = val };
+}
+
+/// Account for the time taken by an instruction that was just executed.
+/// Returns whether a new frame starts after it.
+pub fn end_instruction(n_instructions_executed: u128, instruction: Instruction) -> bool {
+    match get_timing_mode() {
+        TimingMode::Ips => is_frame_boundary(n_instructions_executed),
+        TimingMode::Vip => {
+            // SAFETY: single threaded
+            unsafe {
+                FRAME_ELAPSED_CYCLES += instruction.cycles(get_instruction_set());
+                if FRAME_ELAPSED_CYCLES >= VIP_CYCLES_PER_FRAME {
+                    FRAME_ELAPSED_CYCLES -= VIP_CYCLES_PER_FRAME;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Skip the rest of the current frame, as if instructions had been executed until it ended.
+pub fn skip_to_frame_boundary(n_instructions_executed: &mut u128) {
+    match get_timing_mode() {
+        TimingMode::Ips => {
+            while !is_frame_boundary(*n_instructions_executed) {
+                *n_instructions_executed += 1;
+            }
+        }
+        // SAFETY: single threaded
+        TimingMode::Vip => unsafe { FRAME_ELAPSED_CYCLES = 0 },
+    }
+}
+
+/// Handles the start of a new frame: decrement the timers, signal the vertical blank, count the frame,
+/// and capture the display if it's being recorded.
+pub fn frame_tick() {
+    decrement_delay_timer();
+    decrement_sound_timer();
+    set_vblank(true);
+    update_phosphor();
+    // SAFETY: single threaded
+    unsafe { FRAME_COUNT += 1 };
+    update_audio();
+    notify_timer_tick();
+}
+
+/// Keeps emulation in sync with the wall clock by waiting for the start of each frame.
+pub struct FramePacer {
+    /// When the next frame should start.
+    next_frame: Instant,
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        Self {
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// The length of a single frame.
+    pub fn frame_duration() -> Duration {
+        Duration::from_secs(1) / FRAME_RATE
+    }
+
+    /// Sleep until the start of the next frame.
+    /// If we've fallen more than a frame behind (e.g. after sitting in the debug terminal), resync with the
+    /// wall clock instead of rushing to catch up.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if now < self.next_frame {
+            thread::sleep(self.next_frame - now);
+            self.next_frame += Self::frame_duration();
+        } else if now - self.next_frame > Self::frame_duration() {
+            self.next_frame = now + Self::frame_duration();
+        } else {
+            self.next_frame += Self::frame_duration();
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::Write,
+};
+
+use serde::{Deserialize, Serialize};
+
+use c8util::instructions::Instruction;
+
+use crate::{
+    expr::{eval, format_value},
+    hooks::{InstructionEvent, Observer, Outcome},
+    run::set_status_message,
+};
+
+/// The maximum number of lines kept in the trace log. Older lines are dropped.
+pub const TRACE_LOG_SIZE: usize = 1000;
+
+/// An address that logs the values of some expressions whenever it's executed, without breaking.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tracepoint {
+    pub addr: u16,
+    pub exprs: Vec<String>,
+}
+
+/// The tracepoints that are set.
+pub static mut TRACEPOINTS: Vec<Tracepoint> = Vec::new();
+
+/// The most recent trace lines, oldest first.
+pub static mut TRACE_LOG: VecDeque<String> = VecDeque::new();
+
+/// The file that trace lines are also written to, and its path.
+pub static mut TRACE_FILE: Option<(String, File)> = None;
+
+pub fn get_tracepoints() -> Vec<Tracepoint> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        TRACEPOINTS.clone()
+    }
+}
+
+/// Add a tracepoint, replacing any tracepoint at the same address.
+pub fn add_tracepoint(tracepoint: Tracepoint) {
+    remove_tracepoint(tracepoint.addr);
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        TRACEPOINTS.push(tracepoint);
+    }
+}
+
+/// Remove the tracepoint at the given address. Returns whether there was one.
+pub fn remove_tracepoint(addr: u16) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        let n_tracepoints = TRACEPOINTS.len();
+        TRACEPOINTS.retain(|tracepoint| tracepoint.addr != addr);
+        TRACEPOINTS.len() != n_tracepoints
+    }
+}
+
+pub fn get_trace_log() -> Vec<String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        TRACE_LOG.iter().cloned().collect()
+    }
+}
+
+pub fn clear_trace_log() {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        TRACE_LOG.clear();
+    }
+}
+
+/// Returns the path of the file that trace lines are written to, if any.
+pub fn get_trace_file_path() -> Option<String> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        TRACE_FILE.as_ref().map(|(path, _)| path.clone())
+    }
+}
+
+/// Also write trace lines to the given file, appending to it if it exists. None stops writing them to a file.
+///
+/// # Errors
+/// Returns an error if the file can't be opened.
+pub fn set_trace_file(path: Option<&str>) -> Result<(), String> {
+    let file = path
+        .map(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map(|file| (path.to_string(), file))
+                .map_err(|e| format!("failed to open {path}: {e}"))
+        })
+        .transpose()?;
+    // SAFETY: single threaded
+    unsafe { TRACE_FILE = file };
+    Ok(())
+}
+
+/// Returns the trace lines for the tracepoints at the given address, evaluated on the current state of the
+/// machine. They're passed to `log_traces` once the instruction has been executed.
+pub fn eval_tracepoints(addr: u16, instruction_count: u128) -> Vec<String> {
+    get_tracepoints()
+        .into_iter()
+        .filter(|tracepoint| tracepoint.addr == addr)
+        .map(|tracepoint| {
+            let values = tracepoint
+                .exprs
+                .iter()
+                .map(|expr| match eval(expr) {
+                    Ok(val) => format!("{expr} = {}", format_value(val)),
+                    Err(e) => format!("{expr} = ({e})"),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("#{instruction_count:<10} {addr:#06X}  {values}")
+        })
+        .collect()
+}
+
+/// Add the given lines to the trace log, write them to the trace file if there is one, and show the last one
+/// below the display.
+pub fn log_traces(lines: Vec<String>) {
+    let Some(last) = lines.last() else {
+        return;
+    };
+    set_status_message(last.clone());
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        if let Some((path, file)) = &mut TRACE_FILE {
+            if let Err(e) = lines.iter().try_for_each(|line| writeln!(file, "{line}")) {
+                set_status_message(format!("Tracing to {path} stopped: {e}"));
+                TRACE_FILE = None;
+            }
+        }
+        for line in lines {
+            if TRACE_LOG.len() == TRACE_LOG_SIZE {
+                TRACE_LOG.pop_front();
+            }
+            TRACE_LOG.push_back(line);
+        }
+    }
+}
+
+/// Evaluates the tracepoints before each instruction, and logs them once it has finished.
+#[derive(Debug, Default)]
+pub struct TraceObserver {
+    /// The lines for the instruction that is being executed.
+    pending: Vec<String>,
+}
+
+impl Observer for TraceObserver {
+    fn before_instruction(
+        &mut self,
+        addr: u16,
+        _instruction: Instruction,
+        instruction_count: u128,
+    ) {
+        self.pending = eval_tracepoints(addr, instruction_count);
+    }
+
+    fn on_instruction(&mut self, event: &InstructionEvent) {
+        let lines = std::mem::take(&mut self.pending);
+        // Draws waiting for the vertical blank and GetKey waiting for a key are executed again, so they're only
+        // traced once they've finished
+        if event.outcome == Outcome::Executed {
+            log_traces(lines);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::{fs, io::ErrorKind};
+
+use crate::system::{USER_FLAGS_SIZE, get_user_flags, set_user_flags};
+
+/// The file the user flags are saved to.
+pub static mut USER_FLAGS_PATH: &str = "";
+
+pub fn set_user_flags_path(path: &'static str) {
+    // SAFETY: single threaded
+    unsafe {
+        USER_FLAGS_PATH = path;
+    }
+}
+
+pub fn get_user_flags_path() -> &'static str {
+    // SAFETY: single threaded
+    unsafe { USER_FLAGS_PATH }
+}
+
+/// Returns the default path of the user flags file for the given ROM.
+/// e.g. `roms/game.ch8` -> `roms/game.ch8.flags`
+pub fn default_user_flags_path(rom_path: &str) -> String {
+    format!("{rom_path}.flags")
+}
+
+/// Load the user flags from disk. If the file doesn't exist, the flags are left as they are.
+/// The file contains one byte per flag.
+///
+/// # Errors
+/// Returns an error if the file could not be read.
+pub fn load_user_flags() -> Result<(), String> {
+    let path = get_user_flags_path();
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("failed to read {path}: {e}")),
+    };
+
+    let mut flags = [0u8; USER_FLAGS_SIZE];
+    for (flag, byte) in flags.iter_mut().zip(bytes) {
+        *flag = byte;
+    }
+    set_user_flags(flags);
+    Ok(())
+}
+
+/// Save the user flags to disk.
+///
+/// # Errors
+/// Returns an error if the file could not be written.
+pub fn save_user_flags() -> Result<(), String> {
+    let path = get_user_flags_path();
+    fs::write(path, get_user_flags()).map_err(|e| format!("failed to write {path}: {e}"))
+}
@@ -0,0 +1,231 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use c8util::{instructions::Instruction, register::Register};
+
+use crate::{
+    debug_terminal::request_break,
+    hooks::{InstructionEvent, Observer},
+    system::{MEMORY_SIZE, get_memory_u8, get_register, get_registers},
+};
+
+/// The maximum number of writes kept in the log. Older writes are dropped.
+pub const WRITE_LOG_SIZE: usize = 1000;
+
+/// A range of memory whose writes are logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryWatch {
+    pub start: u16,
+    pub len: u16,
+    /// Whether to break into the debugger when a byte in the range changes.
+    pub break_on_change: bool,
+}
+
+impl MemoryWatch {
+    fn addresses(&self) -> impl Iterator<Item = u16> {
+        (self.start..self.start + self.len).map(|addr| addr % u16::try_from(MEMORY_SIZE).unwrap())
+    }
+}
+
+/// A write to a watched range of memory.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryWrite {
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+    /// The address of the instruction that wrote it.
+    pub pc: u16,
+    pub instruction: Instruction,
+    /// The number of instructions that had been executed before it.
+    pub instruction_count: u128,
+}
+
+/// The ranges of memory that are being watched.
+pub static mut WATCHES: Vec<MemoryWatch> = Vec::new();
+
+/// The registers that break into the debugger when they change.
+pub static mut REGISTER_WATCHES: Vec<Register> = Vec::new();
+
+/// The most recent writes to the watched ranges, oldest first.
+pub static mut WRITE_LOG: VecDeque<MemoryWrite> = VecDeque::new();
+
+pub fn get_watches() -> Vec<MemoryWatch> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        WATCHES.clone()
+    }
+}
+
+/// Start watching the given range of memory, replacing any watch that starts at the same address.
+pub fn add_watch(watch: MemoryWatch) {
+    remove_watch(watch.start);
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        WATCHES.push(watch);
+    }
+}
+
+/// Stop watching the range that starts at the given address. Returns whether there was one.
+pub fn remove_watch(start: u16) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        let n_watches = WATCHES.len();
+        WATCHES.retain(|watch| watch.start != start);
+        WATCHES.len() != n_watches
+    }
+}
+
+pub fn get_register_watches() -> Vec<Register> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        REGISTER_WATCHES.clone()
+    }
+}
+
+/// Break into the debugger whenever the given register changes. Returns whether it wasn't already watched.
+pub fn add_register_watch(reg: Register) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        if REGISTER_WATCHES.contains(&reg) {
+            return false;
+        }
+        REGISTER_WATCHES.push(reg);
+        true
+    }
+}
+
+/// Stop watching the given register. Returns whether it was watched.
+pub fn remove_register_watch(reg: Register) -> bool {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        let n_watches = REGISTER_WATCHES.len();
+        REGISTER_WATCHES.retain(|watched| *watched != reg);
+        REGISTER_WATCHES.len() != n_watches
+    }
+}
+
+pub fn get_write_log() -> Vec<MemoryWrite> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        WRITE_LOG.iter().copied().collect()
+    }
+}
+
+pub fn clear_write_log() {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        WRITE_LOG.clear();
+    }
+}
+
+/// Returns the current contents of the watched ranges, to be passed to `check_watches` after the next instruction.
+pub fn snapshot_watches() -> Vec<u8> {
+    // SAFETY: single threaded
+    #[allow(static_mut_refs)]
+    unsafe {
+        WATCHES
+            .iter()
+            .flat_map(MemoryWatch::addresses)
+            .map(|addr| get_memory_u8(addr).unwrap_or(0))
+            .collect()
+    }
+}
+
+/// Log any changes to the watched ranges made by the instruction that was just executed, and break into the
+/// debugger if one of them asks for it.
+pub fn check_watches(snapshot: &[u8], pc: u16, instruction: Instruction, instruction_count: u128) {
+    let watches = get_watches();
+    let mut old_values = snapshot.iter();
+    let mut break_reason = None;
+    for watch in watches {
+        for addr in watch.addresses() {
+            // The watches can't change while an instruction is executing, so the snapshot always lines up
+            let old = *old_values.next().unwrap();
+            // Watched addresses are always wrapped into memory
+            let new = get_memory_u8(addr).unwrap_or(0);
+            if old == new {
+                continue;
+            }
+
+            // SAFETY: single threaded
+            #[allow(static_mut_refs)]
+            unsafe {
+                if WRITE_LOG.len() == WRITE_LOG_SIZE {
+                    WRITE_LOG.pop_front();
+                }
+                WRITE_LOG.push_back(MemoryWrite {
+                    addr,
+                    old,
+                    new,
+                    pc,
+                    instruction,
+                    instruction_count,
+                });
+            }
+            if watch.break_on_change && break_reason.is_none() {
+                break_reason = Some(format!(
+                    "{addr:#06X} changed from {old:#04X} to {new:#04X} (written by {instruction} at {pc:#06X})"
+                ));
+            }
+        }
+    }
+    if let Some(reason) = break_reason {
+        request_break(reason);
+    }
+}
+
+/// Break into the debugger if the instruction that was just executed changed a watched register.
+/// `old_registers` is the state of the registers before the instruction.
+pub fn check_register_watches(old_registers: &[u8; 16], pc: u16, instruction: Instruction) {
+    let changed = get_register_watches().into_iter().find_map(|reg| {
+        let old = old_registers[usize::from(u8::from(reg))];
+        let new = get_register(reg);
+        (old != new).then_some((reg, old, new))
+    });
+    if let Some((reg, old, new)) = changed {
+        request_break(format!(
+            "{reg} changed from {old:#04X} to {new:#04X} (written by {instruction} at {pc:#06X})"
+        ));
+    }
+}
+
+/// Logs writes to the watched ranges and breaks into the debugger when a watched register changes, by comparing the
+/// state before and after each instruction.
+#[derive(Debug, Default)]
+pub struct WatchObserver {
+    /// The contents of the watched ranges before the instruction.
+    memory: Vec<u8>,
+    /// The registers before the instruction.
+    registers: [u8; 16],
+}
+
+impl Observer for WatchObserver {
+    fn before_instruction(
+        &mut self,
+        _addr: u16,
+        _instruction: Instruction,
+        _instruction_count: u128,
+    ) {
+        self.memory = snapshot_watches();
+        self.registers = get_registers();
+    }
+
+    fn on_instruction(&mut self, event: &InstructionEvent) {
+        check_watches(
+            &self.memory,
+            event.addr,
+            event.instruction,
+            event.instruction_count,
+        );
+        check_register_watches(&self.registers, event.addr, event.instruction);
+    }
+}
@@ -0,0 +1,1103 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    disasm::ROM_START, instructions::Instruction, register::Register, rom::MEMORY_SIZE,
+    symbols::SourceLocation,
+};
+
+/// The instructions, and how many arguments each one takes.
+const MNEMONICS: &[(&str, usize)] = &[
+    ("clear", 0),
+    ("ret", 0),
+    ("exit", 0),
+    ("audio", 0),
+    ("scr", 0),
+    ("scl", 0),
+    ("lores", 0),
+    ("hires", 0),
+    ("scd", 1),
+    ("scu", 1),
+    ("jmp", 1),
+    ("call", 1),
+    ("jo", 1),
+    ("ske", 2),
+    ("skn", 2),
+    ("mov", 2),
+    ("add", 2),
+    ("or", 2),
+    ("and", 2),
+    ("xor", 2),
+    ("sub1", 2),
+    ("sub2", 2),
+    ("shr", 2),
+    ("shl", 2),
+    ("rand", 2),
+    ("draw", 3),
+    ("saver", 2),
+    ("loadr", 2),
+    ("skk", 1),
+    ("sknk", 1),
+    ("key", 1),
+    ("font", 1),
+    ("bfont", 1),
+    ("bcd", 1),
+    ("pitch", 1),
+    ("plane", 1),
+    ("longi", 1),
+    ("store", 1),
+    ("load", 1),
+    ("savef", 1),
+    ("loadf", 1),
+    ("dw", 1),
+];
+
+/// The directives that write data, instead of an instruction.
+const DIRECTIVES: &[&str] = &["byte", "db", "dw", "ds", "ascii"];
+
+/// An error in a program, and where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The file that the error is in, or None if the source wasn't read from a file.
+    pub file: Option<PathBuf>,
+    /// The line number, starting at 1. 0 if the error isn't in a line (e.g. the file couldn't be read).
+    pub line: usize,
+    /// The column, starting at 1.
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = self.file.as_ref().map_or_else(
+            || String::from("<source>"),
+            |file| file.display().to_string(),
+        );
+        match self.line {
+            0 => write!(f, "{file}: error: {}", self.message),
+            line => write!(f, "{file}:{line}:{}: error: {}", self.column, self.message),
+        }
+    }
+}
+
+/// An assembled program, and the debug info that ties it back to its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    /// The name of each address that has a label. If an address has more than one, this is the first.
+    pub labels: BTreeMap<u16, String>,
+    /// The source line of each instruction and directive, by the address it was assembled to.
+    pub source_lines: BTreeMap<u16, SourceLocation>,
+}
+
+/// Returns the column (starting at 1) that `at`, which is part of `text`, starts at. 1 if it isn't part of `text`.
+pub(crate) fn column(text: &str, at: &str) -> usize {
+    let start = text.as_ptr() as usize;
+    let at = at.as_ptr() as usize;
+    if (start..=start + text.len()).contains(&at) {
+        text[..at - start].chars().count() + 1
+    } else {
+        1
+    }
+}
+
+/// An error in a line, and the part of the line that it's about, which gives its column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LineError<'a> {
+    message: String,
+    at: &'a str,
+}
+
+impl<'a> LineError<'a> {
+    fn new(at: &'a str, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            at,
+        }
+    }
+}
+
+/// An argument to an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Reg(Register),
+    I,
+    Delay,
+    Sound,
+    Val(usize),
+}
+
+impl Operand {
+    /// Parse a register, a number, or one of the `labels`, which is the value of its address.
+    fn parse<'a>(s: &'a str, labels: &Labels) -> Result<Self, LineError<'a>> {
+        let Some(reg) = s.strip_prefix('$') else {
+            return match labels.resolve(s) {
+                Ok(Some(addr)) => Ok(Self::Val(usize::from(addr))),
+                Ok(None) => str_to_num(s)
+                    .map(Self::Val)
+                    .map_err(|e| LineError::new(s, e)),
+                Err(e) => Err(LineError::new(s, e)),
+            };
+        };
+        match reg.to_lowercase().as_str() {
+            "i" => Ok(Self::I),
+            "d" => Ok(Self::Delay),
+            "s" => Ok(Self::Sound),
+            reg => reg
+                .strip_prefix('v')
+                .filter(|index| index.len() == 1)
+                .and_then(|index| u8::from_str_radix(index, 16).ok())
+                .and_then(|index| Register::try_from(index).ok())
+                .map(Self::Reg)
+                .ok_or_else(|| LineError::new(s, format!("{s} is not a register"))),
+        }
+    }
+}
+
+/// An argument of an instruction or directive, and where it is in the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Arg<'a> {
+    operand: Operand,
+    text: &'a str,
+    /// Which argument this is, starting at 1.
+    index: usize,
+    /// The instruction or directive that this is an argument of.
+    mnemonic: &'a str,
+}
+
+impl<'a> Arg<'a> {
+    /// Returns the general-purpose register (VX) this argument names.
+    fn vx(&self) -> Result<Register, LineError<'a>> {
+        match self.operand {
+            Operand::Reg(vx) => Ok(vx),
+            _ => Err(LineError::new(
+                self.text,
+                format!(
+                    "argument {} of '{}' must be a general-purpose register (VX), found {}",
+                    self.index, self.mnemonic, self.text
+                ),
+            )),
+        }
+    }
+
+    /// Returns the value of this argument, which must fit in the given number of bits.
+    fn val(&self, n_bits: u32) -> Result<usize, LineError<'a>> {
+        match self.operand {
+            Operand::Val(val) if val >> n_bits == 0 => Ok(val),
+            Operand::Val(val) => Err(LineError::new(
+                self.text,
+                format!(
+                    "value {val:#X} doesn't fit in {n_bits}-bit argument of '{}'",
+                    self.mnemonic
+                ),
+            )),
+            _ => Err(LineError::new(
+                self.text,
+                format!(
+                    "argument {} of '{}' must be a value, found register {}",
+                    self.index, self.mnemonic, self.text
+                ),
+            )),
+        }
+    }
+
+    fn u4(&self) -> Result<u8, LineError<'a>> {
+        self.val(4).map(|val| u8::try_from(val).unwrap())
+    }
+
+    fn u8(&self) -> Result<u8, LineError<'a>> {
+        self.val(8).map(|val| u8::try_from(val).unwrap())
+    }
+
+    fn u12(&self) -> Result<u16, LineError<'a>> {
+        self.val(12).map(|val| u16::try_from(val).unwrap())
+    }
+
+    fn u16(&self) -> Result<u16, LineError<'a>> {
+        self.val(16).map(|val| u16::try_from(val).unwrap())
+    }
+
+    /// Returns the last register that `savef` or `loadf` copies, which must be V0 - V7, as SUPER-CHIP only has 8
+    /// user flags.
+    fn flags(&self) -> Result<u8, LineError<'a>> {
+        match self.u4()? {
+            n @ 0..=7 => Ok(n),
+            n => Err(LineError::new(
+                self.text,
+                format!(
+                    "'{}' can only use registers V0 - V7 (there are 8 user flags), found {n:#X}",
+                    self.mnemonic
+                ),
+            )),
+        }
+    }
+}
+
+/// Try to convert the given string to a number.
+/// Supports hex (0x123), binary (0b111), and base 10 (123).
+fn str_to_num(s: &str) -> Result<usize, String> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16).map_err(|e| format!("could not parse hex value {s}: {e}"))
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        usize::from_str_radix(bin, 2).map_err(|e| format!("could not parse binary value {s}: {e}"))
+    } else {
+        s.parse()
+            .map_err(|e| format!("could not parse base 10 value {s}: {e}"))
+    }
+}
+
+/// Returns whether the string can be the name of a label: letters, digits and `_`, not starting with a digit, and not
+/// the name of an instruction or directive.
+fn is_label_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !DIRECTIVES.contains(&s)
+        && MNEMONICS.iter().all(|(name, _)| *name != s)
+}
+
+/// Returns whether the string is the name of an anonymous label, which is a number (`1:`).
+fn is_anonymous_label(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The labels of a program, and the line being assembled, which local and anonymous labels are relative to.
+#[derive(Debug, Clone, Default)]
+struct Labels {
+    /// The address of each global label and define. Local labels are named `global.local`.
+    addrs: BTreeMap<String, u16>,
+    /// The line index and address of each definition of each anonymous label, in order.
+    anonymous: BTreeMap<String, Vec<(usize, u16)>>,
+    /// The last global label before the line being assembled, which local labels belong to.
+    scope: Option<String>,
+    /// The index of the line being assembled.
+    line: usize,
+}
+
+impl Labels {
+    /// Define a label at `addr`, on the line with the given index. Returns its full name, or None if it's anonymous.
+    fn define(&mut self, label: &str, addr: u16, line: usize) -> Result<Option<String>, String> {
+        if is_anonymous_label(label) {
+            self.anonymous
+                .entry(label.to_string())
+                .or_default()
+                .push((line, addr));
+            return Ok(None);
+        }
+        let name = self.full_name(label)?;
+        if !label.starts_with('.') {
+            self.scope = Some(name.clone());
+        }
+        if self.addrs.insert(name.clone(), addr).is_some() {
+            return Err(format!("label {name} is defined more than once"));
+        }
+        Ok(Some(name))
+    }
+
+    /// Returns the full name of a label: `.local` is named after the global label it belongs to.
+    fn full_name(&self, label: &str) -> Result<String, String> {
+        let Some(local) = label.strip_prefix('.') else {
+            return Ok(label.to_string());
+        };
+        self.scope
+            .as_ref()
+            .map(|scope| format!("{scope}.{local}"))
+            .ok_or_else(|| format!("local label {label} has no global label before it"))
+    }
+
+    /// Returns the address of the label `s`, or None if it isn't a label. `1b` is the closest `1:` before the line
+    /// being assembled (or on it), and `1f` is the closest one after it.
+    fn resolve(&self, s: &str) -> Result<Option<u16>, String> {
+        let anonymous =
+            s.split_at_checked(s.len().saturating_sub(1))
+                .filter(|(number, direction)| {
+                    is_anonymous_label(number) && matches!(*direction, "b" | "f")
+                });
+        if let Some((number, direction)) = anonymous {
+            let definitions = self.anonymous.get(number).map_or(&[][..], Vec::as_slice);
+            let (found, place) = if direction == "b" {
+                (
+                    definitions
+                        .iter()
+                        .rev()
+                        .find(|(line, _)| *line <= self.line),
+                    "before",
+                )
+            } else {
+                (
+                    definitions.iter().find(|(line, _)| *line > self.line),
+                    "after",
+                )
+            };
+            return found
+                .map(|(_, addr)| Some(*addr))
+                .ok_or_else(|| format!("no anonymous label {number}: {place} this line"));
+        }
+
+        let is_label = match s.split_once('.') {
+            Some((global, local)) => {
+                (global.is_empty() || is_label_name(global)) && is_label_name(local)
+            }
+            None => is_label_name(s),
+        };
+        if !is_label {
+            return Ok(None);
+        }
+        let name = self.full_name(s)?;
+        self.addrs
+            .get(&name)
+            .map(|addr| Some(*addr))
+            .ok_or_else(|| format!("unknown label: {name}"))
+    }
+}
+
+/// Remove the comment from a line. Comments start with `;`, except inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Split the definition of a label (`loop:`, `.loop:` or `1:`) off the start of a line, and remove its comment.
+/// Returns the label, if there is one, and the rest of the line.
+fn split_label(line: &str) -> Result<(Option<&str>, &str), LineError<'_>> {
+    let code = strip_comment(line).trim_start();
+    let end = code
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(code.len());
+    if !code[end..].starts_with(':') {
+        return Ok((None, code));
+    }
+    let label = &code[..end];
+    let valid = match label.strip_prefix('.') {
+        Some(local) => is_label_name(local),
+        None => is_label_name(label) || is_anonymous_label(label),
+    };
+    if !valid {
+        return Err(LineError::new(
+            label,
+            format!("invalid label name: {label}"),
+        ));
+    }
+    Ok((Some(label), &code[end + 1..]))
+}
+
+/// Split an instruction or directive into its name and its arguments.
+fn split_mnemonic(code: &str) -> (&str, &str) {
+    let code = code.trim();
+    code.split_once(char::is_whitespace).unwrap_or((code, ""))
+}
+
+/// Split the arguments of an instruction or directive, which are separated by commas or whitespace.
+fn split_args(args: &str) -> impl Iterator<Item = &str> {
+    args.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|arg| !arg.is_empty())
+}
+
+/// Parse the arguments of the instruction or directive `mnemonic`.
+fn parse_args<'a>(
+    mnemonic: &'a str,
+    args: &'a str,
+    labels: &Labels,
+) -> Result<Vec<Arg<'a>>, LineError<'a>> {
+    split_args(args)
+        .enumerate()
+        .map(|(i, text)| {
+            Ok(Arg {
+                operand: Operand::parse(text, labels)?,
+                text,
+                index: i + 1,
+                mnemonic,
+            })
+        })
+        .collect()
+}
+
+/// A line of source code, and where it came from.
+#[derive(Debug, Clone)]
+struct SourceLine {
+    /// The file that the line is in, or None if the source wasn't read from a file.
+    file: Option<Rc<PathBuf>>,
+    /// The line number in its file, starting at 1.
+    number: usize,
+    text: Rc<str>,
+}
+
+impl SourceLine {
+    fn location(&self) -> SourceLocation {
+        SourceLocation {
+            file: self.file.as_ref().map_or_else(
+                || String::from("<source>"),
+                |file| file.display().to_string(),
+            ),
+            line: self.number,
+        }
+    }
+
+    /// Make a diagnostic for an error in this line.
+    fn diagnostic(&self, e: LineError) -> Diagnostic {
+        Diagnostic {
+            file: self.file.as_ref().map(|file| file.to_path_buf()),
+            line: self.number,
+            column: column(&self.text, e.at),
+            message: e.message,
+        }
+    }
+}
+
+/// Parse the name and value of a define, from `-D NAME=value` on the command line. The value defaults to 1.
+///
+/// # Errors
+/// Returns an error if the name can't be used as a label, or the value isn't a 16-bit number.
+pub fn parse_define(arg: &str) -> Result<(String, u16), String> {
+    let (name, value) = arg.split_once('=').unwrap_or((arg, "1"));
+    check_define(name.trim(), value.trim())
+}
+
+/// Check the name and value of a define.
+fn check_define(name: &str, value: &str) -> Result<(String, u16), String> {
+    if !is_label_name(name) {
+        return Err(format!("invalid name: {name}"));
+    }
+    let value = u16::try_from(str_to_num(value)?)
+        .map_err(|_| format!("value {value} doesn't fit in 16 bits"))?;
+    Ok((name.to_string(), value))
+}
+
+/// Evaluate the condition of `%if`: a value, which is true if it isn't 0, or two values compared with `==`, `!=`,
+/// `<`, `>`, `<=` or `>=`. Values are numbers or defined names.
+fn evaluate<'a>(
+    condition: &'a str,
+    defines: &BTreeMap<String, u16>,
+) -> Result<bool, LineError<'a>> {
+    let value = |s: &'a str| match defines.get(s) {
+        Some(value) => Ok(usize::from(*value)),
+        None if is_label_name(s) => Err(LineError::new(s, format!("{s} isn't defined"))),
+        None => str_to_num(s).map_err(|e| LineError::new(s, e)),
+    };
+    match condition.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [a] => Ok(value(a)? != 0),
+        [a, op, b] => {
+            let (a, b) = (value(a)?, value(b)?);
+            match *op {
+                "==" => Ok(a == b),
+                "!=" => Ok(a != b),
+                "<" => Ok(a < b),
+                ">" => Ok(a > b),
+                "<=" => Ok(a <= b),
+                ">=" => Ok(a >= b),
+                op => Err(LineError::new(op, format!("unknown comparison: {op}"))),
+            }
+        }
+        _ => Err(LineError::new(
+            condition,
+            format!("invalid condition: {condition}"),
+        )),
+    }
+}
+
+/// An `%if` block that the preprocessor is in.
+struct Conditional {
+    /// Whether the lines around the block are assembled.
+    outer: bool,
+    /// Whether the lines in the current branch (before or after `%else`) are assembled.
+    active: bool,
+    in_else: bool,
+    /// The `%if` line, for the error if the block is never closed.
+    start: SourceLine,
+}
+
+/// Expands the directives that start with `%` (`%include`, `%define`, and `%if` blocks) into the lines that are
+/// assembled.
+#[derive(Default)]
+struct Preprocessor {
+    /// The values of the names defined with `%define`, or on the command line.
+    defines: BTreeMap<String, u16>,
+    /// The files that are currently being included, as (canonical path, path), to detect cycles.
+    including: Vec<(PathBuf, PathBuf)>,
+    lines: Vec<SourceLine>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Preprocessor {
+    /// Add the lines of `source`, which was read from `file` (if it was read from a file). Included paths are
+    /// relative to `dir`, the directory of the file that includes them. Errors are added to `self.errors`, and the
+    /// lines they're in are skipped.
+    fn read(&mut self, file: Option<&Path>, source: &str, dir: &Path) {
+        let file = file.map(|file| Rc::new(file.to_path_buf()));
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        for (i, text) in source.lines().enumerate() {
+            let line = SourceLine {
+                file: file.clone(),
+                number: i + 1,
+                text: Rc::from(text),
+            };
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let text = Rc::clone(&line.text);
+            let Some(directive) = strip_comment(&text).trim().strip_prefix('%') else {
+                if active {
+                    self.lines.push(line);
+                }
+                continue;
+            };
+            if let Err(e) = self.directive(&line, directive, active, &mut conditionals, dir) {
+                self.errors.push(line.diagnostic(e));
+            }
+        }
+        if let Some(conditional) = conditionals.last() {
+            let start = &conditional.start;
+            self.errors.push(start.diagnostic(LineError::new(
+                &start.text,
+                "%if is never closed with %endif",
+            )));
+        }
+    }
+
+    /// Handle a directive (without its `%`) in `line`. `active` is whether the lines around it are assembled.
+    fn directive<'a>(
+        &mut self,
+        line: &SourceLine,
+        directive: &'a str,
+        active: bool,
+        conditionals: &mut Vec<Conditional>,
+        dir: &Path,
+    ) -> Result<(), LineError<'a>> {
+        let (name, args) = split_mnemonic(directive);
+        let args = args.trim();
+        match name {
+            "ifdef" | "ifndef" if !is_label_name(args) => {
+                return Err(LineError::new(args, format!("%{name} takes 1 name")));
+            }
+            "if" | "ifdef" | "ifndef" => {
+                let condition = active
+                    && match name {
+                        "ifdef" => self.defines.contains_key(args),
+                        "ifndef" => !self.defines.contains_key(args),
+                        _ => evaluate(args, &self.defines)?,
+                    };
+                conditionals.push(Conditional {
+                    outer: active,
+                    active: condition,
+                    in_else: false,
+                    start: line.clone(),
+                });
+            }
+            "else" => match conditionals.last_mut() {
+                None => return Err(LineError::new(name, "%else without %if")),
+                Some(conditional) if conditional.in_else => {
+                    return Err(LineError::new(name, "%if has more than one %else"));
+                }
+                Some(conditional) => {
+                    conditional.in_else = true;
+                    conditional.active = conditional.outer && !conditional.active;
+                }
+            },
+            "endif" => {
+                if conditionals.pop().is_none() {
+                    return Err(LineError::new(name, "%endif without %if"));
+                }
+            }
+            // The other directives are skipped with the lines around them
+            _ if !active => {}
+            "define" => {
+                let (name, value) = split_mnemonic(args);
+                let (name, value) =
+                    check_define(name, value.trim()).map_err(|e| LineError::new(args, e))?;
+                self.defines.insert(name, value);
+            }
+            "include" => self.include(args, dir)?,
+            _ => {
+                return Err(LineError::new(name, format!("unknown directive: %{name}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the lines of the file included with `%include "path"`, where `args` is the quoted path. The path is
+    /// relative to `dir`.
+    fn include<'a>(&mut self, args: &'a str, dir: &Path) -> Result<(), LineError<'a>> {
+        let include = args
+            .strip_prefix('"')
+            .and_then(|args| args.strip_suffix('"'))
+            .filter(|path| !path.is_empty() && !path.contains('"'))
+            .ok_or_else(|| {
+                LineError::new(args, "directive '%include' takes a path in double quotes")
+            })?;
+
+        let path = dir.join(include);
+        let canonical = path.canonicalize().map_err(|e| {
+            LineError::new(include, format!("failed to open {}: {e}", path.display()))
+        })?;
+        if let Some(start) = self.including.iter().position(|(c, _)| *c == canonical) {
+            let cycle = self.including[start..]
+                .iter()
+                .map(|(_, path)| path.display().to_string())
+                .chain([path.display().to_string()])
+                .collect::<Vec<_>>();
+            return Err(LineError::new(
+                include,
+                format!("include cycle: {}", cycle.join(" -> ")),
+            ));
+        }
+        let included = fs::read_to_string(&path).map_err(|e| {
+            LineError::new(include, format!("failed to read {}: {e}", path.display()))
+        })?;
+
+        self.including.push((canonical, path.clone()));
+        self.read(
+            Some(&path),
+            &included,
+            path.parent().unwrap_or(Path::new("")),
+        );
+        self.including.pop();
+        Ok(())
+    }
+}
+
+/// Returns the number of bytes that a line (without its label) assembles to. Labels aren't resolved, so this can be
+/// used before they're all known, but the `defines` (which are all known before the first line) are.
+fn line_size<'a>(code: &'a str, defines: &Labels) -> Result<usize, LineError<'a>> {
+    let (directive, args) = split_mnemonic(code);
+    let n_args = split_args(args).count();
+    Ok(match directive {
+        "" => 0,
+        "byte" | "db" => n_args,
+        "dw" => 2 * n_args,
+        "ds" | "ascii" => parse_data(code, defines)?.map_or(0, |data| data.len()),
+        "longi" => 4,
+        _ => 2,
+    })
+}
+
+/// How to assemble a program.
+#[derive(Debug, Clone)]
+pub struct AssemblyOptions {
+    /// The address that the program is assembled to run at, which is the address of its first line.
+    pub org: usize,
+    /// Names that are defined before the first line, as if with `%define` (e.g. from `-D NAME=value` on the command
+    /// line, to build variants of a program, or the symbols of the ROM that a snippet is assembled into).
+    pub defines: BTreeMap<String, u16>,
+    /// The directory that `%include` paths are relative to. [`assemble_file`] ignores this, and includes files
+    /// relative to the file that includes them.
+    pub include_dir: PathBuf,
+}
+
+impl Default for AssemblyOptions {
+    fn default() -> Self {
+        Self {
+            org: ROM_START,
+            defines: BTreeMap::new(),
+            include_dir: PathBuf::new(),
+        }
+    }
+}
+
+/// Assemble a whole program, with an instruction or directive on each line. A line can start with a label
+/// (`loop: jmp loop`), which can be used instead of the address of that line in any instruction. The program is
+/// assembled to run at `opts.org` (usually 0x200), so that's the address of the first line.
+///
+/// `%include "path"` lines are replaced with the contents of that file, relative to `opts.include_dir`. Use
+/// [`assemble_file`] to include files relative to the source file instead.
+///
+/// `%define NAME value` defines a name, which can be used as a value like a label. The lines between `%if condition`
+/// (or `%ifdef NAME`, or `%ifndef NAME`) and `%else` or `%endif` are only assembled if the condition is true, and
+/// the lines between `%else` and `%endif` if it isn't.
+///
+/// # Errors
+/// Returns a diagnostic, with its line and column, for each line that could not be assembled, label that is defined
+/// more than once, included file that could not be read, and `%if` block that isn't closed, and for the first line
+/// that goes past the end of memory. Assembly continues after an error, so that all of them are reported.
+pub fn assemble(source: &str, opts: &AssemblyOptions) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let mut preprocessor = Preprocessor {
+        defines: opts.defines.clone(),
+        ..Preprocessor::default()
+    };
+    preprocessor.read(None, source, &opts.include_dir);
+    assemble_preprocessed(&preprocessor, opts.org).map(|program| program.rom)
+}
+
+/// Assemble the program in the file at `path`, like [`assemble`]. Files that it includes are relative to the file
+/// that includes them, so a project can be split across directories.
+///
+/// Returns the program with its labels and the source line of each address, which can be written to a symbol file
+/// for the debugger and the disassembler.
+///
+/// # Errors
+/// Returns a diagnostic, with its file, line and column, for each error, like [`assemble`]. A file that includes
+/// itself (directly or through other files) is an error too.
+pub fn assemble_file(path: &Path, opts: &AssemblyOptions) -> Result<Program, Vec<Diagnostic>> {
+    let open_error = |e| {
+        vec![Diagnostic {
+            file: Some(path.to_path_buf()),
+            line: 0,
+            column: 0,
+            message: format!("failed to read: {e}"),
+        }]
+    };
+    let source = fs::read_to_string(path).map_err(open_error)?;
+    let canonical = path.canonicalize().map_err(open_error)?;
+    let mut preprocessor = Preprocessor {
+        defines: opts.defines.clone(),
+        including: vec![(canonical, path.to_path_buf())],
+        ..Preprocessor::default()
+    };
+    preprocessor.read(Some(path), &source, path.parent().unwrap_or(Path::new("")));
+    assemble_preprocessed(&preprocessor, opts.org)
+}
+
+/// Assemble the lines that the preprocessor expanded to run at `org`. The defines can be used like labels. The
+/// errors that the preprocessor found are reported first.
+fn assemble_preprocessed(
+    preprocessor: &Preprocessor,
+    org: usize,
+) -> Result<Program, Vec<Diagnostic>> {
+    let lines = &preprocessor.lines;
+    let defines = &preprocessor.defines;
+    let mut errors = preprocessor.errors.clone();
+
+    // Find the address of each label first, so that they can be used before they're defined. Lines with errors
+    // are assumed to be 2 bytes long, and aren't assembled again.
+    let mut labels = Labels {
+        addrs: defines.clone(),
+        ..Labels::default()
+    };
+    // Sizes only depend on the defines, not on labels that may not have been found yet
+    let sizing = labels.clone();
+    let mut names = BTreeMap::new();
+    let mut failed = vec![false; lines.len()];
+    let mut addr = org;
+    for (i, (line, failed)) in lines.iter().zip(&mut failed).enumerate() {
+        let size = split_label(&line.text).and_then(|(label, code)| {
+            if let Some(label) = label {
+                if defines.contains_key(label) {
+                    return Err(LineError::new(
+                        label,
+                        format!("{label} is already defined with %define"),
+                    ));
+                }
+                let addr = u16::try_from(addr).unwrap_or(u16::MAX);
+                if let Some(name) = labels
+                    .define(label, addr, i)
+                    .map_err(|e| LineError::new(label, e))?
+                {
+                    names.entry(addr).or_insert(name);
+                }
+            }
+            line_size(code, &sizing)
+        });
+        match size {
+            Ok(size) => addr += size,
+            Err(e) => {
+                errors.push(line.diagnostic(e));
+                *failed = true;
+                addr += 2;
+            }
+        }
+    }
+
+    let mut bytes = Vec::new();
+    let mut source_lines = BTreeMap::new();
+    // Only the first line past the end of memory is reported, since all the ones after it are too
+    let mut is_past_end = false;
+    labels.scope = None;
+    for (i, (line, failed)) in lines.iter().zip(failed).enumerate() {
+        labels.line = i;
+        let global_label = split_label(&line.text)
+            .ok()
+            .and_then(|(label, _)| label)
+            .filter(|label| !label.starts_with('.') && !is_anonymous_label(label));
+        if let Some(label) = global_label {
+            labels.scope = Some(label.to_string());
+        }
+        if failed {
+            continue;
+        }
+        let assembled = split_label(&line.text).and_then(|(_, code)| {
+            if let Some(data) = parse_data(code, &labels)? {
+                return Ok(data);
+            }
+            Ok(parse_instruction(code, &labels)?
+                .map(|instruction| instruction.to_bytes())
+                .unwrap_or_default())
+        });
+        match assembled {
+            Ok(assembled) if assembled.is_empty() => {}
+            Ok(assembled) => {
+                let end = org + bytes.len() + assembled.len();
+                if end > MEMORY_SIZE && !is_past_end {
+                    is_past_end = true;
+                    errors.push(line.diagnostic(past_end_error(&line.text, end)));
+                }
+                if let Ok(addr) = u16::try_from(org + bytes.len()) {
+                    source_lines.insert(addr, line.location());
+                }
+                bytes.extend(assembled);
+            }
+            Err(e) => errors.push(line.diagnostic(e)),
+        }
+    }
+    if errors.is_empty() {
+        return Ok(Program {
+            rom: bytes,
+            labels: names,
+            source_lines,
+        });
+    }
+
+    // Report the errors in order: by file (in the order they were first seen in), then by line
+    let mut files = Vec::new();
+    for error in &errors {
+        if !files.contains(&error.file) {
+            files.push(error.file.clone());
+        }
+    }
+    errors.sort_by_key(|error| {
+        (
+            files.iter().position(|file| *file == error.file),
+            error.line,
+        )
+    });
+    Err(errors)
+}
+
+/// Returns the error for a line that ends at `end`, past the end of memory.
+fn past_end_error(line: &str, end: usize) -> LineError<'_> {
+    let code = split_label(line).map_or(line, |(_, code)| code);
+    LineError::new(
+        code.trim(),
+        format!("the program doesn't fit in memory (this line ends at {end:#06X})"),
+    )
+}
+
+/// Assemble a data directive, which writes data instead of an instruction (e.g. for sprites and tables):
+/// - `db nn, nn, ...` (or `byte`): Bytes
+/// - `dw nnnn, nnnn, ...`: 16-bit words, most significant byte first
+/// - `ds n`: n zero bytes, to reserve space
+/// - `ascii "text"`: The bytes of an ASCII string, which can't contain escapes
+///
+/// Values can be labels. Returns None if the line isn't a directive.
+fn parse_data<'a>(line: &'a str, labels: &Labels) -> Result<Option<Vec<u8>>, LineError<'a>> {
+    let (directive, args) = split_mnemonic(strip_comment(line));
+    if !DIRECTIVES.contains(&directive) {
+        return Ok(None);
+    }
+    if directive == "ascii" {
+        let args = args.trim();
+        let text = args
+            .strip_prefix('"')
+            .and_then(|args| args.strip_suffix('"'))
+            .filter(|text| !text.contains('"'))
+            .ok_or_else(|| {
+                LineError::new(args, "directive 'ascii' takes a string in double quotes")
+            })?;
+        if !text.is_ascii() {
+            return Err(LineError::new(text, format!("\"{text}\" isn't ASCII")));
+        }
+        if let Some(i) = text.find('\\') {
+            let escape = &text[i..text.len().min(i + 2)];
+            return Err(LineError::new(
+                escape,
+                format!(
+                    "escapes like {escape} aren't supported in 'ascii' (use 'db' for the byte)"
+                ),
+            ));
+        }
+        return Ok(Some(text.as_bytes().to_vec()));
+    }
+
+    let args = parse_args(directive, args, labels)?;
+    let data = match (directive, args.as_slice()) {
+        (_, []) => {
+            return Err(LineError::new(
+                directive,
+                format!("directive '{directive}' takes at least 1 argument"),
+            ));
+        }
+        ("byte" | "db", args) => args.iter().map(Arg::u8).collect::<Result<Vec<_>, _>>()?,
+        ("dw", args) => args
+            .iter()
+            .map(|nnnn| nnnn.u16().map(u16::to_be_bytes))
+            .collect::<Result<Vec<_>, _>>()?
+            .concat(),
+        ("ds", [n]) => vec![0; usize::from(n.u12()?)],
+        ("ds", args) => {
+            return Err(LineError::new(
+                args[1].text,
+                format!("directive 'ds' takes 1 argument, found {}", args.len()),
+            ));
+        }
+        _ => unreachable!("ascii was handled above"),
+    };
+    Ok(Some(data))
+}
+
+/// Assemble a single line of assembly, e.g. `add $v0, 0x01`. Comments start with `;`.
+/// This is the syntax that [`DisplayInstruction`](crate::instructions::DisplayInstruction) writes with
+/// [`Flavor::C8asm`](crate::instructions::Flavor::C8asm). Returns None if the line has no instruction.
+///
+/// # Errors
+/// Returns an error if the instruction is unknown, or its arguments are invalid.
+pub fn assemble_line(line: &str) -> Result<Option<Instruction>, String> {
+    parse_instruction(line, &Labels::default()).map_err(|e| e.message)
+}
+
+/// Assemble a single line of assembly, which can use the given labels instead of addresses.
+fn parse_instruction<'a>(
+    line: &'a str,
+    labels: &Labels,
+) -> Result<Option<Instruction>, LineError<'a>> {
+    let (mnemonic, args) = split_mnemonic(strip_comment(line));
+    if mnemonic.is_empty() {
+        return Ok(None);
+    }
+    let Some((_, n_args)) = MNEMONICS.iter().find(|(name, _)| *name == mnemonic) else {
+        return Err(LineError::new(
+            mnemonic,
+            format!("unknown instruction: {mnemonic}"),
+        ));
+    };
+    let args = parse_args(mnemonic, args, labels)?;
+    if args.len() != *n_args {
+        return Err(LineError::new(
+            mnemonic,
+            format!(
+                "instruction '{mnemonic}' takes {n_args} argument(s), found {}",
+                args.len()
+            ),
+        ));
+    }
+
+    let operands = args.iter().map(|arg| arg.operand).collect::<Vec<_>>();
+    let instruction = match (mnemonic, operands.as_slice()) {
+        ("clear", []) => Instruction::Clear,
+        ("ret", []) => Instruction::SubroutineReturn,
+        ("exit", []) => Instruction::Exit,
+        ("audio", []) => Instruction::LoadAudio,
+        ("scr", []) => Instruction::ScrollRight,
+        ("scl", []) => Instruction::ScrollLeft,
+        ("lores", []) => Instruction::LowRes,
+        ("hires", []) => Instruction::HighRes,
+        ("scd", _) => Instruction::ScrollDown(args[0].u4()?),
+        ("scu", _) => Instruction::ScrollUp(args[0].u4()?),
+        ("jmp", _) => Instruction::Jump(args[0].u12()?),
+        ("call", _) => Instruction::SubroutineCall(args[0].u12()?),
+        ("jo", _) => Instruction::JumpOffset(args[0].u12()?),
+        ("ske", [_, Operand::Val(_)]) => {
+            Instruction::SkipConditional1(args[0].vx()?, args[1].u8()?)
+        }
+        ("ske", _) => Instruction::SkipConditional3(args[0].vx()?, args[1].vx()?),
+        ("skn", [_, Operand::Val(_)]) => {
+            Instruction::SkipConditional2(args[0].vx()?, args[1].u8()?)
+        }
+        ("skn", _) => Instruction::SkipConditional4(args[0].vx()?, args[1].vx()?),
+        ("mov", [Operand::Reg(vx), Operand::Delay]) => Instruction::GetDelayTimer(*vx),
+        ("mov", [Operand::Reg(vx), Operand::Val(_)]) => {
+            Instruction::SetRegister(*vx, args[1].u8()?)
+        }
+        ("mov", [Operand::Reg(vx), _]) => Instruction::RegSet(*vx, args[1].vx()?),
+        ("mov", [Operand::I, _]) => Instruction::SetIndexRegister(args[1].u12()?),
+        ("mov", [Operand::Delay, _]) => Instruction::SetDelayTimer(args[1].vx()?),
+        ("mov", [Operand::Sound, _]) => Instruction::SetSoundTimer(args[1].vx()?),
+        ("add", [Operand::Reg(vx), Operand::Val(_)]) => Instruction::Add(*vx, args[1].u8()?),
+        ("add", [Operand::Reg(vx), _]) => Instruction::RegAdd(*vx, args[1].vx()?),
+        ("add", [Operand::I, _]) => Instruction::AddToIndex(args[1].vx()?),
+        ("mov" | "add", _) => {
+            return Err(LineError::new(
+                args[0].text,
+                "only general-purpose registers (VX), I and the timers can be written to",
+            ));
+        }
+        ("or", _) => Instruction::BinaryOr(args[0].vx()?, args[1].vx()?),
+        ("and", _) => Instruction::BinaryAnd(args[0].vx()?, args[1].vx()?),
+        ("xor", _) => Instruction::BinaryXor(args[0].vx()?, args[1].vx()?),
+        ("sub1", _) => Instruction::Subtract1(args[0].vx()?, args[1].vx()?),
+        ("sub2", _) => Instruction::Subtract2(args[0].vx()?, args[1].vx()?),
+        ("shr", _) => Instruction::ShiftRight(args[0].vx()?, args[1].vx()?),
+        ("shl", _) => Instruction::ShiftLeft(args[0].vx()?, args[1].vx()?),
+        ("rand", _) => Instruction::Random(args[0].vx()?, args[1].u8()?),
+        ("draw", _) => Instruction::Draw(args[0].vx()?, args[1].vx()?, args[2].u4()?),
+        ("saver", _) => Instruction::SaveRange(args[0].vx()?, args[1].vx()?),
+        ("loadr", _) => Instruction::LoadRange(args[0].vx()?, args[1].vx()?),
+        ("skk", _) => Instruction::SkipIfKey(args[0].vx()?),
+        ("sknk", _) => Instruction::SkipIfNotKey(args[0].vx()?),
+        ("key", _) => Instruction::GetKey(args[0].vx()?),
+        ("font", _) => Instruction::FontCharacter(args[0].vx()?),
+        ("bfont", _) => Instruction::BigFontCharacter(args[0].vx()?),
+        ("bcd", _) => Instruction::BCD(args[0].vx()?),
+        ("pitch", _) => Instruction::SetPitch(args[0].vx()?),
+        ("plane", _) => Instruction::SelectPlanes(args[0].u4()?),
+        ("longi", _) => Instruction::LoadIndexLong(args[0].u16()?),
+        ("store", _) => Instruction::StoreMemory(args[0].u4()?),
+        ("load", _) => Instruction::LoadMemory(args[0].u4()?),
+        ("savef", _) => Instruction::StoreFlags(args[0].flags()?),
+        ("loadf", _) => Instruction::LoadFlags(args[0].flags()?),
+        ("dw", _) => Instruction::Db(args[0].u16()?),
+        _ => unreachable!("every mnemonic is handled, and the number of arguments was checked"),
+    };
+    Ok(Some(instruction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AssemblyOptions, assemble};
+
+    fn error_message(source: &str) -> String {
+        let diagnostics = assemble(source, &AssemblyOptions::default()).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        diagnostics[0].message.clone()
+    }
+
+    #[test]
+    fn store_and_load_take_a_register_count() {
+        let rom = assemble("store 0xF\nload 3", &AssemblyOptions::default()).unwrap();
+        assert_eq!(rom, [0xFF, 0x55, 0xF3, 0x65]);
+        assert_eq!(
+            error_message("store 0x20"),
+            "value 0x20 doesn't fit in 4-bit argument of 'store'"
+        );
+        assert_eq!(
+            error_message("load 0x10"),
+            "value 0x10 doesn't fit in 4-bit argument of 'load'"
+        );
+    }
+
+    #[test]
+    fn ds_sizes_can_use_defines() {
+        let source = "%define SIZE 3\nstart: ds SIZE\njmp start\nend: jmp end";
+        let rom = assemble(source, &AssemblyOptions::default()).unwrap();
+        assert_eq!(rom, [0, 0, 0, 0x12, 0x00, 0x12, 0x05]);
+
+        let opts = AssemblyOptions {
+            defines: [(String::from("SIZE"), 1)].into(),
+            ..AssemblyOptions::default()
+        };
+        assert_eq!(
+            assemble("ds SIZE\nend: jmp end", &opts).unwrap(),
+            [0, 0x12, 0x01]
+        );
+    }
+
+    #[test]
+    fn flags_only_go_up_to_v7() {
+        let rom = assemble("savef 7\nloadf 0", &AssemblyOptions::default()).unwrap();
+        assert_eq!(rom, [0xF7, 0x75, 0xF0, 0x85]);
+        assert_eq!(
+            error_message("savef 8"),
+            "'savef' can only use registers V0 - V7 (there are 8 user flags), found 0x8"
+        );
+        assert_eq!(
+            error_message("loadf 0x20"),
+            "value 0x20 doesn't fit in 4-bit argument of 'loadf'"
+        );
+    }
+}
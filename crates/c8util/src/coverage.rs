@@ -0,0 +1,119 @@
+use std::fmt::{self, Display};
+
+/// The address was executed as (part of) an instruction.
+pub const EXECUTED: u8 = 0b001;
+/// The address was read by an instruction (e.g. a sprite, or FX65).
+pub const READ: u8 = 0b010;
+/// The address was written by an instruction (e.g. FX33 or FX55).
+pub const WRITTEN: u8 = 0b100;
+
+/// The first line of a coverage file.
+const HEADER: &str = "c8cov 1";
+
+/// Which memory addresses were executed, read and written during a run.
+///
+/// Coverage maps are saved as text, with one line for each address that was used:
+/// ```text
+/// c8cov 1
+/// 0200 x
+/// 0201 x
+/// 0300 r
+/// 0301 rw
+/// ```
+/// `x` means executed, `r` read, and `w` written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageMap {
+    flags: Vec<u8>,
+}
+
+impl CoverageMap {
+    /// Create an empty coverage map for the given amount of memory.
+    pub fn new(size: usize) -> Self {
+        Self {
+            flags: vec![0; size],
+        }
+    }
+
+    /// The number of addresses in the map.
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    /// Add the given flags to the address. Addresses outside the map are ignored.
+    pub fn mark(&mut self, addr: usize, flags: u8) {
+        if let Some(f) = self.flags.get_mut(addr) {
+            *f |= flags;
+        }
+    }
+
+    /// Returns the flags for the address, or 0 if it is outside the map.
+    pub fn get(&self, addr: usize) -> u8 {
+        self.flags.get(addr).copied().unwrap_or(0)
+    }
+
+    pub fn is_executed(&self, addr: usize) -> bool {
+        self.get(addr) & EXECUTED != 0
+    }
+
+    /// Parse a coverage map in the format written by `Display`.
+    ///
+    /// # Errors
+    /// Returns an error if the map is invalid.
+    pub fn parse(s: &str, size: usize) -> Result<Self, String> {
+        let mut lines = s.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err(format!("not a coverage map: expected \"{HEADER}\""));
+        }
+
+        let mut map = Self::new(size);
+        for (i, line) in lines {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {msg}: {line}", i + 1);
+            let (addr, flags) = line
+                .split_once(' ')
+                .ok_or_else(|| err("expected an address and flags"))?;
+            let addr = usize::from_str_radix(addr, 16).map_err(|_| err("invalid address"))?;
+            if addr >= size {
+                return Err(err("address out of range"));
+            }
+            for c in flags.trim().chars() {
+                map.mark(
+                    addr,
+                    match c {
+                        'x' => EXECUTED,
+                        'r' => READ,
+                        'w' => WRITTEN,
+                        _ => return Err(err("invalid flag")),
+                    },
+                );
+            }
+        }
+        Ok(map)
+    }
+}
+
+impl Display for CoverageMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{HEADER}")?;
+        for (addr, &flags) in self.flags.iter().enumerate() {
+            if flags == 0 {
+                continue;
+            }
+            write!(f, "{addr:04x} ")?;
+            for (flag, c) in [(EXECUTED, 'x'), (READ, 'r'), (WRITTEN, 'w')] {
+                if flags & flag != 0 {
+                    write!(f, "{c}")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -1,76 +1,188 @@
-use crate::instructions::Instruction;
+use std::fmt::{self, Display};
 
-pub fn decode(ins: u16) -> Option<Instruction> {
+use crate::{
+    instructions::{Instruction, InstructionSet},
+    register::Register,
+};
+
+/// Decode `bytes`, which are loaded at `org`, one instruction after the other from the start, without following jumps
+/// like `disasm::trace` does. Yields the address, bytes and instruction of each one, or None as the instruction if
+/// its bytes aren't one. `F000 NNNN` is 4 bytes and everything else is 2, except for an odd byte at the end, which
+/// is yielded on its own.
+pub fn iter(
+    bytes: &[u8],
+    org: u16,
+    set: InstructionSet,
+) -> impl Iterator<Item = (u16, &[u8], Option<Instruction>)> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let rest = &bytes[offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let addr = org.checked_add(u16::try_from(offset).ok()?)?;
+        let instruction = decode_bytes(rest, set);
+        let size = instruction.map_or(2, |ins| ins.size()).min(rest.len());
+        offset += size;
+        Some((addr, &rest[..size], instruction))
+    })
+}
+
+/// Decode the instruction at the start of `bytes`. Unlike `decode`, this also decodes XO-CHIP's 4 byte
+/// `F000 NNNN`. Returns None if the bytes aren't a valid instruction, or there aren't enough of them.
+pub fn decode_bytes(bytes: &[u8], set: InstructionSet) -> Option<Instruction> {
+    match bytes {
+        [0xF0, 0x00, high, low, ..] if set >= InstructionSet::XoChip => {
+            Some(Instruction::LoadIndexLong(u16::from_be_bytes([
+                *high, *low,
+            ])))
+        }
+        [high, low, ..] => decode(u16::from_be_bytes([*high, *low]), set),
+        _ => None,
+    }
+}
+
+/// Why a word isn't an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The word isn't an instruction in any of the instruction sets (e.g. `5XY1` or `EX00`).
+    Unknown(u16),
+    /// One of the arithmetic instructions (`8XYN`) that no instruction set defines: 8XY8 to 8XYD, and 8XYF.
+    ReservedArithmetic(u16),
+    /// The instruction is in a newer instruction set than the one being decoded (e.g. `00FF` from SUPER-CHIP when
+    /// decoding CHIP-8).
+    NotEnabled { ins: u16, needs: InstructionSet },
+    /// `F000`, which is the first half of XO-CHIP's 4 byte `F000 NNNN`. Use `decode_bytes` to decode it.
+    Incomplete,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(ins) => write!(f, "{ins:04X} isn't an instruction"),
+            Self::ReservedArithmetic(ins) => {
+                write!(f, "{ins:04X} is a reserved 8XYN arithmetic instruction")
+            }
+            Self::NotEnabled { ins, needs } => {
+                write!(f, "{ins:04X} needs {needs}, which isn't enabled")
+            }
+            Self::Incomplete => f.write_str("F000 is the first half of the 4 byte F000 NNNN"),
+        }
+    }
+}
+
+/// Decode a 2 byte instruction, if it's in the given instruction set. Instructions from newer sets (e.g. `00FF` from
+/// SUPER-CHIP when decoding CHIP-8) are decoded as what they were in the older one, or None if they weren't valid.
+/// `F000 NNNN` is 4 bytes long, so it's only decoded by `decode_bytes`. See `decode_verbose` for why a word isn't an
+/// instruction.
+pub fn decode(ins: u16, set: InstructionSet) -> Option<Instruction> {
+    decode_verbose(ins, set).ok()
+}
+
+/// Decode a 2 byte instruction like `decode`, but return why it isn't an instruction, so that tools can explain it.
+///
+/// # Errors
+/// Returns an error if the word isn't an instruction, or only is in a newer instruction set than `set`.
+#[allow(clippy::too_many_lines)]
+pub fn decode_verbose(ins: u16, set: InstructionSet) -> Result<Instruction, DecodeError> {
+    use InstructionSet::{Chip8, SuperChip, XoChip};
+
+    let is_schip = set >= SuperChip;
+    let is_xo_chip = set >= XoChip;
     let first = ((ins & 0xF000) >> 12) as u8;
     let second = ((ins & 0x0F00) >> 8) as u8;
     let third = ((ins & 0x00F0) >> 4) as u8;
     let fourth = (ins & 0x000F) as u8;
+    let vx = Register::from_nibble(second);
+    let vy = Register::from_nibble(third);
+    let unknown = Err(DecodeError::Unknown(ins));
 
-    match first {
-        0x0 => match second {
-            0x0 => match third {
-                0xE => match fourth {
-                    0xE => Some(Instruction::SubroutineReturn),
-                    0x0 => Some(Instruction::Clear),
-                    _ => None,
-                },
-                _ => None,
-            },
-            _ => None,
-        },
-        0x1 => Some(Instruction::Jump(ins & 0x0FFF)),
-        0x2 => Some(Instruction::SubroutineCall(ins & 0x0FFF)),
-        0x3 => Some(Instruction::SkipConditional1(
-            second.into(),
-            (ins & 0x00FF) as u8,
-        )),
-        0x4 => Some(Instruction::SkipConditional2(
-            second.into(),
-            (ins & 0x00FF) as u8,
-        )),
+    // The instruction, and the oldest instruction set that has it
+    let (instruction, needs) = match first {
+        // Anything else in 0NNN is a machine language routine, so newer instructions aren't errors
+        0x0 => {
+            return Ok(match ins {
+                0x00E0 => Instruction::Clear,
+                0x00EE => Instruction::SubroutineReturn,
+                0x00C0..=0x00CF if is_schip => Instruction::ScrollDown(fourth),
+                0x00D0..=0x00DF if is_xo_chip => Instruction::ScrollUp(fourth),
+                0x00FB if is_schip => Instruction::ScrollRight,
+                0x00FC if is_schip => Instruction::ScrollLeft,
+                0x00FD if is_schip => Instruction::Exit,
+                0x00FE if is_schip => Instruction::LowRes,
+                0x00FF if is_schip => Instruction::HighRes,
+                _ => Instruction::ExecuteMachineLanguageRoutine(ins & 0x0FFF),
+            });
+        }
+        0x1 => (Instruction::Jump(ins & 0x0FFF), Chip8),
+        0x2 => (Instruction::SubroutineCall(ins & 0x0FFF), Chip8),
+        0x3 => (
+            Instruction::SkipConditional1(vx, (ins & 0x00FF) as u8),
+            Chip8,
+        ),
+        0x4 => (
+            Instruction::SkipConditional2(vx, (ins & 0x00FF) as u8),
+            Chip8,
+        ),
         0x5 => match fourth {
-            0 => Some(Instruction::SkipConditional3(second.into(), third.into())),
-            _ => None,
+            0 => (Instruction::SkipConditional3(vx, vy), Chip8),
+            2 => (Instruction::SaveRange(vx, vy), XoChip),
+            3 => (Instruction::LoadRange(vx, vy), XoChip),
+            _ => return unknown,
         },
-        0x6 => Some(Instruction::SetRegister(second.into(), (ins & 0xff) as u8)),
-        0x7 => Some(Instruction::Add(second.into(), (ins & 0x00FF) as u8)),
+        0x6 => (Instruction::SetRegister(vx, (ins & 0xff) as u8), Chip8),
+        0x7 => (Instruction::Add(vx, (ins & 0x00FF) as u8), Chip8),
         0x8 => match fourth {
-            0 => Some(Instruction::RegSet(second.into(), third.into())),
-            1 => Some(Instruction::BinaryOr(second.into(), third.into())),
-            2 => Some(Instruction::BinaryAnd(second.into(), third.into())),
-            3 => Some(Instruction::BinaryXor(second.into(), third.into())),
-            4 => Some(Instruction::RegAdd(second.into(), third.into())),
-            5 => Some(Instruction::Subtract1(second.into(), third.into())),
-            6 => Some(Instruction::ShiftRight(second.into(), third.into())),
-            7 => Some(Instruction::Subtract2(second.into(), third.into())),
-            0xE => Some(Instruction::ShiftLeft(second.into(), third.into())),
-            _ => None,
+            0 => (Instruction::RegSet(vx, vy), Chip8),
+            1 => (Instruction::BinaryOr(vx, vy), Chip8),
+            2 => (Instruction::BinaryAnd(vx, vy), Chip8),
+            3 => (Instruction::BinaryXor(vx, vy), Chip8),
+            4 => (Instruction::RegAdd(vx, vy), Chip8),
+            5 => (Instruction::Subtract1(vx, vy), Chip8),
+            6 => (Instruction::ShiftRight(vx, vy), Chip8),
+            7 => (Instruction::Subtract2(vx, vy), Chip8),
+            0xE => (Instruction::ShiftLeft(vx, vy), Chip8),
+            _ => return Err(DecodeError::ReservedArithmetic(ins)),
         },
         0x9 => match fourth {
-            0 => Some(Instruction::SkipConditional4(second.into(), third.into())),
-            _ => None,
+            0 => (Instruction::SkipConditional4(vx, vy), Chip8),
+            _ => return unknown,
         },
-        0xA => Some(Instruction::SetIndexRegister(ins & 0xFFF)),
-        0xB => Some(Instruction::JumpOffset(ins & 0xFFF)),
-        0xC => Some(Instruction::Random(second.into(), (ins & 0x00FF) as u8)),
-        0xD => Some(Instruction::Draw(second.into(), third.into(), fourth)),
+        0xA => (Instruction::SetIndexRegister(ins & 0xFFF), Chip8),
+        0xB => (Instruction::JumpOffset(ins & 0xFFF), Chip8),
+        0xC => (Instruction::Random(vx, (ins & 0x00FF) as u8), Chip8),
+        0xD => (Instruction::Draw(vx, vy, fourth), Chip8),
         0xE => match ins & 0x00FF {
-            0x9E => Some(Instruction::SkipIfKey(second.into())),
-            0xA1 => Some(Instruction::SkipIfNotKey(second.into())),
-            _ => None,
+            0x9E => (Instruction::SkipIfKey(vx), Chip8),
+            0xA1 => (Instruction::SkipIfNotKey(vx), Chip8),
+            _ => return unknown,
         },
-        0xF => match ins & 0x00FF {
-            0x07 => Some(Instruction::GetDelayTimer(second.into())),
-            0x0A => Some(Instruction::GetKey(second.into())),
-            0x15 => Some(Instruction::SetDelayTimer(second.into())),
-            0x18 => Some(Instruction::SetSoundTimer(second.into())),
-            0x1E => Some(Instruction::AddToIndex(second.into())),
-            0x29 => Some(Instruction::FontCharacter(second.into())),
-            0x33 => Some(Instruction::BCD(second.into())),
-            0x55 => Some(Instruction::StoreMemory(second)),
-            0x65 => Some(Instruction::LoadMemory(second)),
-            _ => None,
+        _ => match ins & 0x00FF {
+            0x00 if second == 0 && is_xo_chip => return Err(DecodeError::Incomplete),
+            0x00 if second == 0 => {
+                return Err(DecodeError::NotEnabled { ins, needs: XoChip });
+            }
+            0x01 => (Instruction::SelectPlanes(second), XoChip),
+            0x02 if second == 0 => (Instruction::LoadAudio, XoChip),
+            0x07 => (Instruction::GetDelayTimer(vx), Chip8),
+            0x0A => (Instruction::GetKey(vx), Chip8),
+            0x15 => (Instruction::SetDelayTimer(vx), Chip8),
+            0x18 => (Instruction::SetSoundTimer(vx), Chip8),
+            0x1E => (Instruction::AddToIndex(vx), Chip8),
+            0x29 => (Instruction::FontCharacter(vx), Chip8),
+            0x30 => (Instruction::BigFontCharacter(vx), SuperChip),
+            0x33 => (Instruction::BCD(vx), Chip8),
+            0x3A => (Instruction::SetPitch(vx), XoChip),
+            0x55 => (Instruction::StoreMemory(second), Chip8),
+            0x65 => (Instruction::LoadMemory(second), Chip8),
+            0x75 => (Instruction::StoreFlags(second), SuperChip),
+            0x85 => (Instruction::LoadFlags(second), SuperChip),
+            _ => return unknown,
         },
-        _ => None,
+    };
+    if set >= needs {
+        Ok(instruction)
+    } else {
+        Err(DecodeError::NotEnabled { ins, needs })
     }
 }
@@ -0,0 +1,472 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+};
+
+use crate::{
+    coverage::{CoverageMap, READ, WRITTEN},
+    decode::{decode_bytes, iter},
+    instructions::{Instruction, InstructionSet},
+};
+
+/// The address that ROMs are loaded at.
+pub const ROM_START: usize = 0x200;
+
+/// The instructions that refer to each address, as the address of the instruction and how it refers to it: `call`,
+/// `jmp`, `jo`, or `load` for pointing I at it.
+pub type CrossReferences = BTreeMap<u16, Vec<(usize, &'static str)>>;
+
+/// A line of a disassembly: an instruction, or a byte of data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledLine {
+    /// The address of the line.
+    pub addr: usize,
+    /// The bytes of the line: 2 or 4 for an instruction, and 1 for data.
+    pub raw: Vec<u8>,
+    /// The instruction, or None if the line is data.
+    pub instruction: Option<Instruction>,
+    /// The name of the address, if it's labeled.
+    pub label: Option<String>,
+}
+
+/// How to disassemble a ROM.
+#[derive(Debug, Clone)]
+pub struct DisassemblyOptions<'a> {
+    /// The address that the ROM is loaded at.
+    pub org: usize,
+    /// The instructions to decode. Instructions from newer sets are disassembled as data.
+    pub instruction_set: InstructionSet,
+    /// A coverage map saved by c8rs. Code that was executed is disassembled even if it can't be reached by following
+    /// the program from its start (e.g. after a `BNNN` jump).
+    pub coverage: Option<&'a CoverageMap>,
+    /// Names for addresses, which are used instead of the generated labels.
+    pub symbols: BTreeMap<u16, String>,
+    /// The addresses to split into lines, or None for the whole ROM. The whole ROM is traced and labeled either way.
+    pub range: Option<Range<usize>>,
+}
+
+impl Default for DisassemblyOptions<'_> {
+    fn default() -> Self {
+        Self {
+            org: ROM_START,
+            instruction_set: InstructionSet::default(),
+            coverage: None,
+            symbols: BTreeMap::new(),
+            range: None,
+        }
+    }
+}
+
+/// A disassembled ROM, and what was found out about it along the way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    /// The lines of `DisassemblyOptions::range`.
+    pub lines: Vec<DisassembledLine>,
+    /// The name of each address: the generated labels, and `DisassemblyOptions::symbols`.
+    pub labels: BTreeMap<u16, String>,
+    pub xrefs: CrossReferences,
+    /// The warnings about self-modifying code for each address (see `self_modification_warnings`).
+    pub warnings: BTreeMap<usize, Vec<String>>,
+}
+
+/// Disassemble the ROM. The code is found by following the program from its start (see `trace`), and everything
+/// else is data. The addresses that instructions refer to are labeled (see `generate_labels`).
+///
+/// # Panics
+/// Panics if `opts.range` isn't inside the ROM.
+pub fn disassemble(rom: &[u8], opts: &DisassemblyOptions) -> Disassembly {
+    let set = opts.instruction_set;
+    let rom_end = opts.org + rom.len();
+    let range = opts.range.clone().unwrap_or(opts.org..rom_end);
+    assert!(
+        opts.org <= range.start && range.end <= rom_end,
+        "{range:#06X?} isn't inside the ROM"
+    );
+    let is_instruction = trace(rom, opts.org, opts.coverage, set);
+    let xrefs = cross_references(rom, opts.org, &is_instruction, set);
+    let mut labels = generate_labels(&xrefs, opts.org, rom_end);
+    labels.extend(opts.symbols.clone());
+    let lines = split_lines(
+        rom,
+        opts.org,
+        range.start,
+        range.end,
+        &is_instruction,
+        &labels,
+        set,
+    );
+    let warnings =
+        self_modification_warnings(rom, opts.org, &is_instruction, &xrefs, opts.coverage, set);
+    Disassembly {
+        lines,
+        labels,
+        xrefs,
+        warnings,
+    }
+}
+
+/// Find the instructions that can be reached by following the program from its start (`org`) through jumps, calls,
+/// skips and returns. Returns whether each byte of `rom` is the start of an instruction. Everything else is data.
+///
+/// With a coverage map, every instruction that was executed is followed too, so code that static analysis can't
+/// find (e.g. after a `BNNN` jump) is included. Addresses that were read but never executed (e.g. sprites) are
+/// known to be data, so they're never decoded as code, even if a skip or a call that never returned seems to lead
+/// there.
+pub fn trace(
+    rom: &[u8],
+    org: usize,
+    coverage: Option<&CoverageMap>,
+    set: InstructionSet,
+) -> Vec<bool> {
+    let mut is_instruction = vec![false; rom.len()];
+    let mut to_visit = vec![org];
+    if let Some(coverage) = coverage {
+        to_visit.extend(executed_instructions(rom, org, coverage, set));
+    }
+    let is_data = |addr: usize| {
+        coverage
+            .is_some_and(|coverage| coverage.get(addr) & READ != 0 && !coverage.is_executed(addr))
+    };
+    while let Some(addr) = to_visit.pop() {
+        let Some(offset) = addr
+            .checked_sub(org)
+            .filter(|offset| *offset < rom.len() && !is_instruction[*offset] && !is_data(addr))
+        else {
+            continue;
+        };
+        let Some(instruction) = decode_bytes(&rom[offset..], set) else {
+            continue;
+        };
+        is_instruction[offset] = true;
+        if let Instruction::SubroutineCall(nnn) = instruction {
+            to_visit.push(usize::from(nnn));
+        }
+        to_visit.extend(successors(rom, org, addr, instruction, set));
+    }
+    is_instruction
+}
+
+/// Returns the address of each instruction that was executed, according to `coverage`. Each run of executed addresses
+/// is decoded from its start, one instruction after the other (see `decode::iter`).
+fn executed_instructions(
+    rom: &[u8],
+    org: usize,
+    coverage: &CoverageMap,
+    set: InstructionSet,
+) -> Vec<usize> {
+    let mut executed = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let Ok(start) = u16::try_from(org + offset) else {
+            break;
+        };
+        if !coverage.is_executed(org + offset) {
+            offset += 1;
+            continue;
+        }
+        let run = iter(&rom[offset..], start, set)
+            .map(|(addr, raw, _)| (usize::from(addr), raw.len()))
+            .take_while(|(addr, _)| coverage.is_executed(*addr));
+        for (addr, size) in run {
+            executed.push(addr);
+            offset = addr + size - org;
+        }
+    }
+    executed
+}
+
+/// Returns the addresses that can run after the instruction at `addr`, not including the subroutine that it calls.
+fn successors(
+    rom: &[u8],
+    org: usize,
+    addr: usize,
+    instruction: Instruction,
+    set: InstructionSet,
+) -> Vec<usize> {
+    let next = addr + instruction.size();
+    match instruction {
+        Instruction::Jump(nnn) => vec![usize::from(nnn)],
+        Instruction::SkipConditional1(..)
+        | Instruction::SkipConditional2(..)
+        | Instruction::SkipConditional3(..)
+        | Instruction::SkipConditional4(..)
+        | Instruction::SkipIfKey(_)
+        | Instruction::SkipIfNotKey(_) => {
+            // Skipping over F000 NNNN skips all 4 bytes of it
+            let skipped = rom
+                .get(next - org..)
+                .and_then(|rest| decode_bytes(rest, set))
+                .map_or(2, |skipped| skipped.size());
+            vec![next, next + skipped]
+        }
+        // The target of BNNN depends on V0, so it can only be found with a coverage map
+        Instruction::SubroutineReturn | Instruction::Exit | Instruction::JumpOffset(_) => vec![],
+        _ => vec![next],
+    }
+}
+
+/// Returns the calls made by each routine, as the address of the call and the subroutine that it calls. The routines
+/// are the program from its start (`org`), and each subroutine that's called. A routine is everything that can be
+/// reached from its start without following calls.
+pub fn call_graph(
+    rom: &[u8],
+    org: usize,
+    set: InstructionSet,
+) -> BTreeMap<usize, Vec<(usize, usize)>> {
+    let mut routines = BTreeMap::new();
+    let mut to_walk = vec![org];
+    while let Some(start) = to_walk.pop() {
+        if routines.contains_key(&start) {
+            continue;
+        }
+        let mut calls = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut to_visit = vec![start];
+        while let Some(addr) = to_visit.pop() {
+            let Some(offset) = addr.checked_sub(org).filter(|offset| *offset < rom.len()) else {
+                continue;
+            };
+            if !visited.insert(addr) {
+                continue;
+            }
+            let Some(instruction) = decode_bytes(&rom[offset..], set) else {
+                continue;
+            };
+            if let Instruction::SubroutineCall(nnn) = instruction {
+                calls.push((addr, usize::from(nnn)));
+                to_walk.push(usize::from(nnn));
+            }
+            to_visit.extend(successors(rom, org, addr, instruction, set));
+        }
+        calls.sort_unstable();
+        routines.insert(start, calls);
+    }
+    routines
+}
+
+/// Returns the bits of a byte as a row of a sprite, from the most significant bit: `█` for each 1 and `.` for each 0.
+pub fn sprite_row(byte: u8) -> String {
+    (0..8)
+        .rev()
+        .map(|bit| if byte >> bit & 1 == 1 { '█' } else { '.' })
+        .collect()
+}
+
+/// Name the addresses in the ROM (from `org` up to `rom_end`) that instructions refer to: `sub_XXX` for
+/// subroutines, `label_XXX` for the targets of jumps, and `data_XXX` for addresses that I is pointed at. An address
+/// that's referred to in more than one way gets the first of these names.
+pub fn generate_labels(
+    xrefs: &CrossReferences,
+    org: usize,
+    rom_end: usize,
+) -> BTreeMap<u16, String> {
+    xrefs
+        .iter()
+        .filter(|(addr, _)| (org..rom_end).contains(&usize::from(**addr)))
+        .map(|(addr, refs)| {
+            let prefix = if refs.iter().any(|(_, kind)| *kind == "call") {
+                "sub"
+            } else if refs.iter().any(|(_, kind)| *kind != "load") {
+                "label"
+            } else {
+                "data"
+            };
+            (*addr, format!("{prefix}_{addr:03X}"))
+        })
+        .collect()
+}
+
+/// Returns the instructions that refer to each address, as the address of the instruction and how it refers to it:
+/// `call`, `jmp`, `jo`, or `load` for pointing I at it.
+pub fn cross_references(
+    rom: &[u8],
+    org: usize,
+    is_instruction: &[bool],
+    set: InstructionSet,
+) -> CrossReferences {
+    let mut xrefs = BTreeMap::<u16, Vec<_>>::new();
+    for offset in (0..rom.len()).filter(|offset| is_instruction[*offset]) {
+        let Some(instruction) = decode_bytes(&rom[offset..], set) else {
+            continue;
+        };
+        let kind = match instruction {
+            Instruction::SubroutineCall(_) => "call",
+            Instruction::Jump(_) => "jmp",
+            Instruction::JumpOffset(_) => "jo",
+            _ => "load",
+        };
+        if let Some(addr) = instruction.target_addr() {
+            xrefs.entry(addr).or_default().push((org + offset, kind));
+        }
+    }
+    xrefs
+}
+
+/// Find the instructions that write into the ROM's own code, since the disassembly of the code they write to
+/// isn't what runs. A write is found when I is set by `ANNN` (or `F000 NNNN`) earlier in the same block, and is followed
+/// by `FX33`, `FX55` or `5XY2` (possibly with `FX1E` in between, in which case only the first bytes from I are
+/// checked). With a coverage map, the code that was written while running is found too. Returns the warnings for each
+/// address: for both the instruction that writes, and the code that it writes to.
+pub fn self_modification_warnings(
+    rom: &[u8],
+    org: usize,
+    is_instruction: &[bool],
+    xrefs: &CrossReferences,
+    coverage: Option<&CoverageMap>,
+    set: InstructionSet,
+) -> BTreeMap<usize, Vec<String>> {
+    let mut instructions = Vec::new();
+    // The instruction that each byte of code is part of
+    let mut code = BTreeMap::new();
+    for offset in (0..rom.len()).filter(|offset| is_instruction[*offset]) {
+        let Some(instruction) = decode_bytes(&rom[offset..], set) else {
+            continue;
+        };
+        let addr = org + offset;
+        code.extend((addr..addr + instruction.size()).map(|byte| (byte, addr)));
+        instructions.push((addr, instruction));
+    }
+
+    let mut warnings = BTreeMap::<usize, Vec<String>>::new();
+    // The value of I, and whether a register has been added to it
+    let mut index: Option<(usize, bool)> = None;
+    let mut next_addr = org;
+    for &(addr, instruction) in &instructions {
+        // Another block starts here, so I could be anything
+        if addr != next_addr || u16::try_from(addr).is_ok_and(|addr| xrefs.contains_key(&addr)) {
+            index = None;
+        }
+        next_addr = addr + instruction.size();
+
+        let written = match instruction {
+            Instruction::BCD(_) => Some(3),
+            Instruction::StoreMemory(x) => Some(usize::from(x) + 1),
+            Instruction::SaveRange(vx, vy) => {
+                Some(usize::from(u8::from(vx).abs_diff(u8::from(vy))) + 1)
+            }
+            _ => None,
+        };
+        if let (Some(len), Some((i, indexed))) = (written, index) {
+            let index = if indexed {
+                format!("I = {i:#05X} + VX")
+            } else {
+                format!("I = {i:#05X}")
+            };
+            let targets = (i..i + len)
+                .filter_map(|byte| code.get(&byte).copied())
+                .collect::<BTreeSet<_>>();
+            for target in targets {
+                warnings.entry(addr).or_default().push(format!(
+                    "self-modifying code: writes to the instruction at {target:#05X} ({index})"
+                ));
+                warnings.entry(target).or_default().push(format!(
+                    "overwritten by the instruction at {addr:#05X}, so this may not be what runs"
+                ));
+            }
+        }
+
+        index = match instruction {
+            Instruction::SetIndexRegister(nnn) | Instruction::LoadIndexLong(nnn) => {
+                Some((usize::from(nnn), false))
+            }
+            Instruction::AddToIndex(_) => index.map(|(i, _)| (i, true)),
+            // FX55 and FX65 increment I with some quirks, and calls may change it
+            Instruction::StoreMemory(_)
+            | Instruction::LoadMemory(_)
+            | Instruction::FontCharacter(_)
+            | Instruction::BigFontCharacter(_)
+            | Instruction::SubroutineCall(_)
+            | Instruction::ExecuteMachineLanguageRoutine(_) => None,
+            _ => index,
+        };
+    }
+
+    if let Some(coverage) = coverage {
+        for &(addr, instruction) in &instructions {
+            if (addr..addr + instruction.size()).any(|byte| coverage.get(byte) & WRITTEN != 0) {
+                warnings.entry(addr).or_default().push(String::from(
+                    "written while running (see the coverage map), so this may not be what runs",
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/// Split the part of `rom` (which is loaded at `org`) from `start` up to `end` into lines: one for each
+/// instruction, and one for each byte of the data between instructions. Each line that starts at an address in
+/// `labels` gets its name.
+///
+/// Code can start at any address, e.g. after an odd number of bytes of data, or in the middle of another instruction
+/// that jumps into its own second byte. When an instruction overlaps the start of another one (or a label), its
+/// bytes are written as data instead, so that decoding re-synchronizes at the inner one and every label starts a line.
+/// A byte at the end of the ROM that's too short for an instruction is always data.
+pub fn split_lines(
+    rom: &[u8],
+    org: usize,
+    start: usize,
+    end: usize,
+    is_instruction: &[bool],
+    labels: &BTreeMap<u16, String>,
+    set: InstructionSet,
+) -> Vec<DisassembledLine> {
+    let mut lines = Vec::new();
+    let mut addr = start;
+    while addr < end {
+        let offset = addr - org;
+        let instruction = decode_bytes(&rom[offset..], set).filter(|ins| {
+            is_instruction[offset]
+                && addr + ins.size() <= end
+                && (1..ins.size()).all(|i| {
+                    !is_instruction[offset + i]
+                        && u16::try_from(addr + i).is_ok_and(|addr| !labels.contains_key(&addr))
+                })
+        });
+        let size = instruction.map_or(1, |ins| ins.size());
+        lines.push(DisassembledLine {
+            addr,
+            raw: rom[offset..offset + size].to_vec(),
+            instruction,
+            label: u16::try_from(addr)
+                .ok()
+                .and_then(|addr| labels.get(&addr))
+                .cloned(),
+        });
+        addr += size;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ROM_START, trace};
+    use crate::{
+        coverage::{CoverageMap, EXECUTED},
+        instructions::InstructionSet,
+        rom::MEMORY_SIZE,
+    };
+
+    #[test]
+    fn trace_follows_executed_code() {
+        let rom = crate::rom! {
+            JumpOffset(0x204);
+            [0xFF, 0xFF];
+            target:
+            LoadIndexLong(0x210);
+            Jump => target;
+        }
+        .unwrap();
+        // The code after BNNN can only be found with the coverage map
+        let mut coverage = CoverageMap::new(MEMORY_SIZE);
+        for addr in (0x200..0x202).chain(0x204..0x20A) {
+            coverage.mark(addr, EXECUTED);
+        }
+
+        let is_instruction = trace(&rom, ROM_START, Some(&coverage), InstructionSet::XoChip);
+        let starts: Vec<_> = (0..rom.len())
+            .filter(|offset| is_instruction[*offset])
+            .map(|offset| ROM_START + offset)
+            .collect();
+        assert_eq!(starts, [0x200, 0x204, 0x208]);
+    }
+}
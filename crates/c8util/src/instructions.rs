@@ -1,16 +1,155 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display};
 
 use crate::register::Register;
 
+/// The Chip-8 extensions whose instructions are decoded. Each one includes the instructions of the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InstructionSet {
+    /// The original COSMAC VIP instructions.
+    Chip8,
+    /// CHIP-48 on the HP-48 calculators: the CHIP-8 instructions, except that BNNN jumps to XNN + VX (BXNN).
+    Chip48,
+    /// SUPER-CHIP 1.1: scrolling, high resolution, the big font, exiting and the user flags.
+    SuperChip,
+    /// XO-CHIP: scrolling up, saving and loading register ranges, 16-bit addresses for I, bit planes and audio.
+    #[default]
+    XoChip,
+}
+
+/// The interpreters whose instructions can be decoded, as they're named on the command line (`--instruction-set`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Platform {
+    /// The original CHIP-8 instructions.
+    Chip8,
+    /// CHIP-8, with BNNN read as BXNN: jump to XNN + VX.
+    Chip48,
+    /// CHIP-48, and the SUPER-CHIP 1.1 instructions (e.g. `00FF` and `00CN`).
+    Schip,
+    /// CHIP-8, SUPER-CHIP, and the XO-CHIP instructions (e.g. `5XY2` and `F000 NNNN`). BNNN jumps to NNN + V0.
+    Xochip,
+}
+
+impl From<Platform> for InstructionSet {
+    fn from(platform: Platform) -> Self {
+        match platform {
+            Platform::Chip8 => Self::Chip8,
+            Platform::Chip48 => Self::Chip48,
+            Platform::Schip => Self::SuperChip,
+            Platform::Xochip => Self::XoChip,
+        }
+    }
+}
+
+impl Display for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Chip8 => f.write_str("CHIP-8"),
+            Self::Chip48 => f.write_str("CHIP-48"),
+            Self::SuperChip => f.write_str("SUPER-CHIP"),
+            Self::XoChip => f.write_str("XO-CHIP"),
+        }
+    }
+}
+
+impl InstructionSet {
+    /// Returns the register whose value BNNN adds to NNN: V0, or VX on CHIP-48 and SUPER-CHIP, which treat it as
+    /// BXNN.
+    pub fn jump_offset_register(self, nnn: u16) -> Register {
+        match self {
+            Self::Chip48 | Self::SuperChip => Register::from_nibble(nnn.to_be_bytes()[0]),
+            Self::Chip8 | Self::XoChip => Register::V0,
+        }
+    }
+}
+
+/// The number of COSMAC VIP machine cycles in each 60 Hz frame. Its CDP1802 ran at 1.76 MHz, with 8 clock cycles per
+/// machine cycle. See `Instruction::cycles`.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+/// What an instruction does, broadly. See `Instruction::category`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    /// Jumps, calls, returns, skips and exiting.
+    ControlFlow,
+    /// Setting registers, arithmetic, logic and random numbers.
+    Alu,
+    /// Setting and adding to I, and reading and writing memory (and the user flags) through it.
+    Memory,
+    /// Clearing, drawing, scrolling, switching resolutions and selecting bit planes.
+    Display,
+    /// Skipping on and waiting for keys.
+    Input,
+    /// The delay and sound timers, and the XO-CHIP audio pattern and pitch.
+    Timer,
+    /// Bytes that aren't an instruction (`Instruction::Db`).
+    Data,
+}
+
+impl Category {
+    pub const ALL: [Self; 7] = [
+        Self::ControlFlow,
+        Self::Alu,
+        Self::Memory,
+        Self::Display,
+        Self::Input,
+        Self::Timer,
+        Self::Data,
+    ];
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ControlFlow => f.write_str("Control flow"),
+            Self::Alu => f.write_str("ALU"),
+            Self::Memory => f.write_str("Memory"),
+            Self::Display => f.write_str("Display"),
+            Self::Input => f.write_str("Input"),
+            Self::Timer => f.write_str("Timers/sound"),
+            Self::Data => f.write_str("Data"),
+        }
+    }
+}
+
+/// How an instruction is written by [`Instruction::display`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Flavor {
+    /// The syntax of c8asm, e.g. `mov   $V0, 0x01`. This is what `Display` writes.
+    #[default]
+    C8asm,
+    /// The syntax of the Octo assembler, e.g. `v0 := 0x01`. Instructions that Octo has no syntax for are written as
+    /// their 2 bytes.
+    Octo,
+    /// The name of the instruction and its arguments, e.g. `SetRegister(V0, 0x01)`, like the debugger's trace.
+    Named,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     /// 0NNN. Pause execution of the program and call a subroutine written in machine language instead.
-    /// NOT TO BE USED.
-    ExecuteMachineLanguageRoutine,
+    /// Interpreters can't run the original machine code, but some let the host handle specific addresses.
+    ExecuteMachineLanguageRoutine(u16),
     /// 00E0. Clear the screen.
     Clear,
     /// 00EE. Return from a subroutine.
     SubroutineReturn,
+    /// 00FD. SUPER-CHIP: Exit the interpreter.
+    Exit,
+    /// 00CN. SUPER-CHIP: Scroll the display down by N pixels.
+    ScrollDown(u8),
+    /// 00DN. XO-CHIP: Scroll the display up by N pixels.
+    ScrollUp(u8),
+    /// 00FB. SUPER-CHIP: Scroll the display right by 4 pixels.
+    ScrollRight,
+    /// 00FC. SUPER-CHIP: Scroll the display left by 4 pixels.
+    ScrollLeft,
+    /// 00FE. SUPER-CHIP: Switch to the low resolution (64x32) display.
+    LowRes,
+    /// 00FF. SUPER-CHIP: Switch to the high resolution (128x64) display.
+    HighRes,
     /// 1NNN. Jump to the given address.
     Jump(u16),
     /// 2NNN. Jump to the subroutine at the given address, pushing the current PC to the stack.
@@ -21,6 +160,12 @@ pub enum Instruction {
     SkipConditional2(Register, u8),
     /// 5XY0. Increase PC by 2 if the values in the registers are equal.
     SkipConditional3(Register, Register),
+    /// 5XY2. XO-CHIP: Store the values of the registers from VX to VY, inclusive, in successive memory addresses,
+    /// starting at I. I isn't changed. If X is greater than Y, the registers are stored in reverse order.
+    SaveRange(Register, Register),
+    /// 5XY3. XO-CHIP: Load the registers from VX to VY, inclusive, from successive memory addresses, starting at I.
+    /// I isn't changed.
+    LoadRange(Register, Register),
     /// 6XNN. Set the register to the given value.
     SetRegister(Register, u8),
     /// 7XNN. Add the given value to the register. Does NOT set the carry bit of an overflow occurs.
@@ -37,17 +182,22 @@ pub enum Instruction {
     RegAdd(Register, Register),
     /// 8XY5. Set VX to VX - VY. If the operation underflows, set VF to 0. Otherwise, set VF to 1.
     Subtract1(Register, Register),
-    /// 8XY6. Set VX to VY. Shift the value of VX right one bit. If the bit that was shifted out was 1, set VF to 1. Otherwise, set VF to 0. TODO: Add an option to disable the "Set VX to VY" behavior.
+    /// 8XY6. Set VX to VY. Shift the value of VX right one bit. If the bit that was shifted out was 1, set VF to 1.
+    /// Otherwise, set VF to 0. CHIP-48 and SUPER-CHIP don't set VX to VY first, and shift VX in place (the
+    /// `shift_vx_only` quirk in c8rs).
     ShiftRight(Register, Register),
     /// 8XY7. Set VX to VY - VX. If the operation underflows, set VF to 0. Otherwise, set VF to 1.
     Subtract2(Register, Register),
-    /// 8XYE. Set VX to VY. Shift the value of VX left one bit. If the bit that was shifted out was 1, set VF to 1. Otherwise, set VF to 0. TODO: Add an option to disable the "Set VX to VY" behavior.
+    /// 8XYE. Set VX to VY. Shift the value of VX left one bit. If the bit that was shifted out was 1, set VF to 1.
+    /// Otherwise, set VF to 0. CHIP-48 and SUPER-CHIP don't set VX to VY first, and shift VX in place (the
+    /// `shift_vx_only` quirk in c8rs).
     ShiftLeft(Register, Register),
     /// 9XY0. Increase PC by 2 if the values in the registers are not equal.
     SkipConditional4(Register, Register),
     /// ANNN. Set the index register to the given value.
     SetIndexRegister(u16),
-    /// BNNN. Jump to the address NNN + V0. TODO: Add compatibility option to jump to act as BXNN: jump to address XNN + the value of VX.
+    /// BNNN. Jump to the address NNN + V0. On CHIP-48 and SUPER-CHIP, it's BXNN: jump to the address XNN + VX (see
+    /// `InstructionSet::jump_offset_register`).
     JumpOffset(u16),
     /// CXNN. Generate a random number, AND it with NN, and put the result in VX.
     Random(Register, u8),
@@ -57,12 +207,19 @@ pub enum Instruction {
     ///
     /// All pixels "off" in the sprite are treated as transparent.
     ///
-    /// The drawing of the sprite should not wrap.
+    /// The drawing of the sprite should not wrap, although some interpreters wrap it around to the other side of the screen.
     Draw(Register, Register, u8),
     /// EX9E. Increment PC by 2 if the key corresponding to the value in VX is pressed.
     SkipIfKey(Register),
     /// EXA1. Increment PC by 2 if the key corresponding to the value in VX is not pressed.
     SkipIfNotKey(Register),
+    /// F000 NNNN. XO-CHIP: Set I to the 16-bit address NNNN. This is the only instruction that's 4 bytes long.
+    LoadIndexLong(u16),
+    /// FN01. XO-CHIP: Select the bit planes (1 to 3) that drawing, clearing and scrolling affect.
+    SelectPlanes(u8),
+    /// F002. XO-CHIP: Load the 16 bytes starting at I into the audio pattern buffer.
+    /// The pattern is played one bit at a time while the sound timer is not 0.
+    LoadAudio,
     /// FX07. Set VX to the current value of the delay timer.
     GetDelayTimer(Register),
     /// FX0A. Block until a key is pressed, then put that key into VX.
@@ -75,23 +232,59 @@ pub enum Instruction {
     AddToIndex(Register),
     /// FX29. Set I to the address of the hexadecimal character in VX.
     FontCharacter(Register),
+    /// FX30. SUPER-CHIP: Set I to the address of the 10 byte tall (big font) decimal character in VX.
+    BigFontCharacter(Register),
     /// FX33. Convert the binary number in VX to three decimal digits, then store those digits in memory at the address pointed to by I.
     BCD(Register),
+    /// FX3A. XO-CHIP: Set the pitch register to the value in VX. This controls how fast the audio pattern is played.
+    SetPitch(Register),
     /// FX55. Store the values of each register from V0 to VX, inclusive, in successive memory addresses, starting at I. TODO: Add a compatibility option to increment I each time a register is stored.
     StoreMemory(u8),
     /// FX65. Load the values of each register from V0 to VX, inclusive, at successive memory addresses, starting at I. TODO: Add a compatibility option to increment I each time a register is loaded.
     LoadMemory(u8),
+    /// FX75. SUPER-CHIP: Store the values of each register from V0 to VX, inclusive, in the user flags.
+    /// On the HP48 these were the RPL user flags, which kept their values after the interpreter exited.
+    StoreFlags(u8),
+    /// FX85. SUPER-CHIP: Load the values of each register from V0 to VX, inclusive, from the user flags.
+    LoadFlags(u8),
     /// Dedicate 4 bytes of space. Only used in assembly.
     Db(u16),
 }
 
 impl Instruction {
+    /// Returns the number of bytes the instruction takes up: 4 for `F000 NNNN`, and 2 for everything else.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LoadIndexLong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Converts Self to the bytes of the instruction, as they're stored in memory.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Instruction::LoadIndexLong(nnnn) => {
+                let [high, low] = nnnn.to_be_bytes();
+                vec![0xF0, 0x00, high, low]
+            }
+            _ => self.serialize().to_be_bytes().to_vec(),
+        }
+    }
+
     /// Converts Self to the u16 representation of the instruction.
+    /// For `F000 NNNN`, this is only the first 2 bytes (see `to_bytes`).
     pub fn serialize(&self) -> u16 {
         match self {
-            Instruction::ExecuteMachineLanguageRoutine => 0x0000,
+            Instruction::ExecuteMachineLanguageRoutine(nnn) => *nnn,
             Instruction::Clear => 0x00E0,
             Instruction::SubroutineReturn => 0x00EE,
+            Instruction::Exit => 0x00FD,
+            Instruction::ScrollDown(n) => 0x00C0 | u16::from(*n),
+            Instruction::ScrollUp(n) => 0x00D0 | u16::from(*n),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
             Instruction::Jump(nnn) => 0x1000 | nnn,
             Instruction::SubroutineCall(nnn) => 0x2000 | nnn,
             Instruction::SkipConditional1(vx, nn) => {
@@ -103,6 +296,12 @@ impl Instruction {
             Instruction::SkipConditional3(vx, vy) => {
                 0x5000 | (u16::from(*vx) << 8) | (u16::from(*vy) << 4)
             }
+            Instruction::SaveRange(vx, vy) => {
+                0x5002 | (u16::from(*vx) << 8) | (u16::from(*vy) << 4)
+            }
+            Instruction::LoadRange(vx, vy) => {
+                0x5003 | (u16::from(*vx) << 8) | (u16::from(*vy) << 4)
+            }
             Instruction::SetRegister(vx, nn) => 0x6000 | (u16::from(*vx) << 8) | u16::from(*nn),
             Instruction::Add(vx, nn) => 0x7000 | (u16::from(*vx) << 8) | u16::from(*nn),
             Instruction::RegSet(vx, vy) => 0x8000 | (u16::from(*vx) << 8) | (u16::from(*vy) << 4),
@@ -137,58 +336,520 @@ impl Instruction {
             }
             Instruction::SkipIfKey(vx) => 0xE09E | (u16::from(*vx) << 8),
             Instruction::SkipIfNotKey(vx) => 0xE0A1 | (u16::from(*vx) << 8),
+            Instruction::LoadIndexLong(_) => 0xF000,
+            Instruction::SelectPlanes(n) => 0xF001 | (u16::from(*n) << 8),
+            Instruction::LoadAudio => 0xF002,
             Instruction::GetKey(vx) => 0xF00A | (u16::from(*vx) << 8),
             Instruction::GetDelayTimer(vx) => 0xF007 | (u16::from(*vx) << 8),
             Instruction::SetDelayTimer(vx) => 0xF015 | (u16::from(*vx) << 8),
             Instruction::SetSoundTimer(vx) => 0xF018 | (u16::from(*vx) << 8),
             Instruction::AddToIndex(vx) => 0xF01E | (u16::from(*vx) << 8),
             Instruction::FontCharacter(vx) => 0xF029 | (u16::from(*vx) << 8),
+            Instruction::BigFontCharacter(vx) => 0xF030 | (u16::from(*vx) << 8),
             Instruction::BCD(vx) => 0xF033 | (u16::from(*vx) << 8),
+            Instruction::SetPitch(vx) => 0xF03A | (u16::from(*vx) << 8),
             Instruction::StoreMemory(vx) => 0xF055 | (u16::from(*vx) << 8),
             Instruction::LoadMemory(vx) => 0xF065 | (u16::from(*vx) << 8),
+            Instruction::StoreFlags(vx) => 0xF075 | (u16::from(*vx) << 8),
+            Instruction::LoadFlags(vx) => 0xF085 | (u16::from(*vx) << 8),
             Instruction::Db(nnnn) => *nnnn,
         }
     }
 
-    /// Converts Self to its assembly equivalent.
-    pub fn asm(&self) -> String {
-        match self {
-            Instruction::ExecuteMachineLanguageRoutine => "db    0x0000".to_string(),
-            Instruction::Clear => "clear".to_string(),
-            Instruction::SubroutineReturn => "ret".to_string(),
-            Instruction::Jump(nnn) => format!("jmp   {nnn:#06X}"),
-            Instruction::SubroutineCall(nnn) => format!("call  {nnn:#06X}"),
-            Instruction::SkipConditional1(vx, nn) => format!("ske   ${vx}, {nn:#04X}"),
-            Instruction::SkipConditional2(vx, nn) => format!("skn   ${vx}, {nn:#04X}"),
-            Instruction::SkipConditional3(vx, vy) => format!("ske   ${vx}, ${vy}"),
-            Instruction::SetRegister(vx, nn) => format!("mov   ${vx}, {nn:#04X}"),
-            Instruction::Add(vx, nn) => format!("add   ${vx}, {nn:#04X}"),
-            Instruction::RegSet(vx, vy) => format!("mov   ${vx}, ${vy}"),
-            Instruction::BinaryOr(vx, vy) => format!("or    ${vx}, ${vy}"),
-            Instruction::BinaryAnd(vx, vy) => format!("and   ${vx}, ${vy}"),
-            Instruction::BinaryXor(vx, vy) => format!("xor   ${vx}, ${vy}"),
-            Instruction::RegAdd(vx, vy) => format!("add   ${vx}, ${vy}"),
-            Instruction::Subtract1(vx, vy) => format!("sub1  ${vx}, ${vy}"),
-            Instruction::ShiftRight(vx, vy) => format!("shr   ${vx}, ${vy}"),
-            Instruction::Subtract2(vx, vy) => format!("sub2  ${vx}, ${vy}"),
-            Instruction::ShiftLeft(vx, vy) => format!("shl   ${vx}, ${vy}"),
-            Instruction::SkipConditional4(vx, vy) => format!("skn   ${vx}, ${vy}"),
-            Instruction::SetIndexRegister(nnn) => format!("mov   $i, {nnn:#06X}"),
-            Instruction::JumpOffset(nnn) => format!("jo    {nnn:#06X}"),
-            Instruction::Random(vx, nn) => format!("rand  ${vx}, {nn:#04X}"),
-            Instruction::Draw(vx, vy, n) => format!("draw  ${vx}, ${vy}, {n:#04X}"),
-            Instruction::SkipIfKey(vx) => format!("skk   ${vx}"),
-            Instruction::SkipIfNotKey(vx) => format!("sknk  ${vx}"),
-            Instruction::GetDelayTimer(vx) => format!("mov   ${vx}, $d"),
-            Instruction::GetKey(vx) => format!("key   ${vx}"),
-            Instruction::SetDelayTimer(vx) => format!("mov   $d, ${vx}"),
-            Instruction::SetSoundTimer(vx) => format!("mov   $s, ${vx}"),
-            Instruction::AddToIndex(vx) => format!("add   $i, ${vx}"),
-            Instruction::FontCharacter(vx) => format!("font  ${vx}"),
-            Instruction::BCD(vx) => format!("bcd   ${vx}"),
-            Instruction::StoreMemory(n) => format!("store {n:#04X}"),
-            Instruction::LoadMemory(n) => format!("load  {n:#04X}"),
-            Instruction::Db(nnnn) => format!("db    {nnnn:#06X}"),
+    /// Returns the address that the instruction jumps to, calls, or points I at (its NNN, or the NNNN of
+    /// `F000 NNNN`), if it has one. `BNNN` jumps to an offset from it.
+    pub fn target_addr(&self) -> Option<u16> {
+        match self {
+            Instruction::Jump(nnn)
+            | Instruction::SubroutineCall(nnn)
+            | Instruction::SetIndexRegister(nnn)
+            | Instruction::JumpOffset(nnn) => Some(*nnn),
+            Instruction::LoadIndexLong(nnnn) => Some(*nnnn),
+            _ => None,
+        }
+    }
+
+    /// Returns the register in the X position of the instruction (e.g. `8XY4`), if it has one. For `FX55`, `FX65`,
+    /// `FX75` and `FX85`, this is the last register of the range.
+    pub fn x(&self) -> Option<Register> {
+        match self {
+            Instruction::SkipConditional1(vx, _)
+            | Instruction::SkipConditional2(vx, _)
+            | Instruction::SkipConditional3(vx, _)
+            | Instruction::SaveRange(vx, _)
+            | Instruction::LoadRange(vx, _)
+            | Instruction::SetRegister(vx, _)
+            | Instruction::Add(vx, _)
+            | Instruction::RegSet(vx, _)
+            | Instruction::BinaryOr(vx, _)
+            | Instruction::BinaryAnd(vx, _)
+            | Instruction::BinaryXor(vx, _)
+            | Instruction::RegAdd(vx, _)
+            | Instruction::Subtract1(vx, _)
+            | Instruction::ShiftRight(vx, _)
+            | Instruction::Subtract2(vx, _)
+            | Instruction::ShiftLeft(vx, _)
+            | Instruction::SkipConditional4(vx, _)
+            | Instruction::Random(vx, _)
+            | Instruction::Draw(vx, _, _)
+            | Instruction::SkipIfKey(vx)
+            | Instruction::SkipIfNotKey(vx)
+            | Instruction::GetDelayTimer(vx)
+            | Instruction::GetKey(vx)
+            | Instruction::SetDelayTimer(vx)
+            | Instruction::SetSoundTimer(vx)
+            | Instruction::AddToIndex(vx)
+            | Instruction::FontCharacter(vx)
+            | Instruction::BigFontCharacter(vx)
+            | Instruction::BCD(vx)
+            | Instruction::SetPitch(vx) => Some(*vx),
+            Instruction::StoreMemory(x)
+            | Instruction::LoadMemory(x)
+            | Instruction::StoreFlags(x)
+            | Instruction::LoadFlags(x) => Register::try_from(*x).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the register in the Y position of the instruction (e.g. `8XY4`), if it has one.
+    pub fn y(&self) -> Option<Register> {
+        match self {
+            Instruction::SkipConditional3(_, vy)
+            | Instruction::SaveRange(_, vy)
+            | Instruction::LoadRange(_, vy)
+            | Instruction::RegSet(_, vy)
+            | Instruction::BinaryOr(_, vy)
+            | Instruction::BinaryAnd(_, vy)
+            | Instruction::BinaryXor(_, vy)
+            | Instruction::RegAdd(_, vy)
+            | Instruction::Subtract1(_, vy)
+            | Instruction::ShiftRight(_, vy)
+            | Instruction::Subtract2(_, vy)
+            | Instruction::ShiftLeft(_, vy)
+            | Instruction::SkipConditional4(_, vy)
+            | Instruction::Draw(_, vy, _) => Some(*vy),
+            _ => None,
+        }
+    }
+
+    /// Returns what the instruction does, broadly, e.g. for grouping instructions in a profile or colouring them.
+    pub fn category(&self) -> Category {
+        match self {
+            Instruction::ExecuteMachineLanguageRoutine(_)
+            | Instruction::SubroutineReturn
+            | Instruction::Exit
+            | Instruction::Jump(_)
+            | Instruction::SubroutineCall(_)
+            | Instruction::JumpOffset(_)
+            | Instruction::SkipConditional1(..)
+            | Instruction::SkipConditional2(..)
+            | Instruction::SkipConditional3(..)
+            | Instruction::SkipConditional4(..) => Category::ControlFlow,
+            Instruction::SetRegister(..)
+            | Instruction::Add(..)
+            | Instruction::RegSet(..)
+            | Instruction::BinaryOr(..)
+            | Instruction::BinaryAnd(..)
+            | Instruction::BinaryXor(..)
+            | Instruction::RegAdd(..)
+            | Instruction::Subtract1(..)
+            | Instruction::ShiftRight(..)
+            | Instruction::Subtract2(..)
+            | Instruction::ShiftLeft(..)
+            | Instruction::Random(..) => Category::Alu,
+            Instruction::SetIndexRegister(_)
+            | Instruction::LoadIndexLong(_)
+            | Instruction::AddToIndex(_)
+            | Instruction::FontCharacter(_)
+            | Instruction::BigFontCharacter(_)
+            | Instruction::BCD(_)
+            | Instruction::StoreMemory(_)
+            | Instruction::LoadMemory(_)
+            | Instruction::StoreFlags(_)
+            | Instruction::LoadFlags(_)
+            | Instruction::SaveRange(..)
+            | Instruction::LoadRange(..) => Category::Memory,
+            Instruction::Clear
+            | Instruction::Draw(..)
+            | Instruction::ScrollDown(_)
+            | Instruction::ScrollUp(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::SelectPlanes(_) => Category::Display,
+            Instruction::SkipIfKey(_) | Instruction::SkipIfNotKey(_) | Instruction::GetKey(_) => {
+                Category::Input
+            }
+            Instruction::GetDelayTimer(_)
+            | Instruction::SetDelayTimer(_)
+            | Instruction::SetSoundTimer(_)
+            | Instruction::SetPitch(_)
+            | Instruction::LoadAudio => Category::Timer,
+            Instruction::Db(_) => Category::Data,
+        }
+    }
+
+    /// Returns roughly how many machine cycles the instruction took in the COSMAC VIP's interpreter (see
+    /// `VIP_CYCLES_PER_FRAME`). Most instructions take a fixed amount of time, and drawing takes longer for taller
+    /// sprites. The VIP only ran CHIP-8, so with `InstructionSet::Chip8`, the instructions it didn't have take 0.
+    /// With newer sets, they're estimated from the VIP routine that's closest to what they do (e.g. `5XY2` takes as
+    /// long as `FX55`). 0NNN runs machine code, so it always takes 0.
+    pub fn cycles(&self, set: InstructionSet) -> u32 {
+        let vip = match self {
+            Instruction::Clear => Some(24),
+            Instruction::SubroutineReturn
+            | Instruction::Jump(_)
+            | Instruction::SubroutineCall(_)
+            | Instruction::JumpOffset(_) => Some(23),
+            Instruction::SkipConditional3(_, _)
+            | Instruction::SkipConditional4(_, _)
+            | Instruction::SkipIfKey(_)
+            | Instruction::SkipIfNotKey(_) => Some(16),
+            Instruction::SetRegister(_, _) => Some(6),
+            Instruction::SkipConditional1(_, _)
+            | Instruction::SkipConditional2(_, _)
+            | Instruction::Add(_, _)
+            | Instruction::GetDelayTimer(_)
+            | Instruction::GetKey(_)
+            | Instruction::SetDelayTimer(_)
+            | Instruction::SetSoundTimer(_) => Some(10),
+            Instruction::RegSet(_, _)
+            | Instruction::BinaryOr(_, _)
+            | Instruction::BinaryAnd(_, _)
+            | Instruction::BinaryXor(_, _)
+            | Instruction::RegAdd(_, _)
+            | Instruction::Subtract1(_, _)
+            | Instruction::ShiftRight(_, _)
+            | Instruction::Subtract2(_, _)
+            | Instruction::ShiftLeft(_, _) => Some(44),
+            Instruction::SetIndexRegister(_) => Some(12),
+            Instruction::Random(_, _) => Some(36),
+            Instruction::Draw(_, _, n) => Some(37 + 46 * u32::from(*n)),
+            Instruction::AddToIndex(_) => Some(19),
+            Instruction::FontCharacter(_) => Some(20),
+            Instruction::BCD(_) => Some(204),
+            Instruction::StoreMemory(_)
+            | Instruction::LoadMemory(_)
+            | Instruction::StoreFlags(_)
+            | Instruction::LoadFlags(_) => Some(133),
+            Instruction::ExecuteMachineLanguageRoutine(_) | Instruction::Db(_) => return 0,
+            _ => None,
+        };
+        if let Some(cycles) = vip {
+            return cycles;
+        }
+        if set == InstructionSet::Chip8 {
+            return 0;
+        }
+        match self {
+            // Like 00E0, since they rewrite the whole display, and like two ANNNs
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollUp(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::LoadIndexLong(_) => 24,
+            Instruction::Exit => 23,
+            Instruction::SaveRange(..) | Instruction::LoadRange(..) | Instruction::LoadAudio => 133,
+            Instruction::BigFontCharacter(_) => 20,
+            Instruction::SelectPlanes(_) => 6,
+            Instruction::SetPitch(_) => 10,
+            _ => 0,
+        }
+    }
+
+    /// Returns the constant operand of the instruction, if it has one: the NN of e.g. `6XNN` and `CXNN`, or the N
+    /// of `DXYN`, `00CN`, `00DN` and `FN01`.
+    pub fn immediate(&self) -> Option<u8> {
+        match self {
+            Instruction::SkipConditional1(_, nn)
+            | Instruction::SkipConditional2(_, nn)
+            | Instruction::SetRegister(_, nn)
+            | Instruction::Add(_, nn)
+            | Instruction::Random(_, nn)
+            | Instruction::Draw(_, _, nn)
+            | Instruction::ScrollDown(nn)
+            | Instruction::ScrollUp(nn)
+            | Instruction::SelectPlanes(nn) => Some(*nn),
+            _ => None,
+        }
+    }
+
+    /// Returns something that writes the instruction in the given flavor of syntax with `{}`. The `Display` of
+    /// the instruction itself writes the syntax of c8asm.
+    pub fn display(&self, flavor: Flavor) -> DisplayInstruction {
+        DisplayInstruction {
+            instruction: *self,
+            flavor,
+        }
+    }
+
+    /// Write the instruction in the syntax of c8asm.
+    fn fmt_c8asm(self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::ExecuteMachineLanguageRoutine(nnn) => write!(f, "dw    {nnn:#06X}"),
+            Instruction::Clear => f.write_str("clear"),
+            Instruction::SubroutineReturn => f.write_str("ret"),
+            Instruction::Exit => f.write_str("exit"),
+            Instruction::ScrollDown(n) => write!(f, "scd   {n:#04X}"),
+            Instruction::ScrollUp(n) => write!(f, "scu   {n:#04X}"),
+            Instruction::ScrollRight => f.write_str("scr"),
+            Instruction::ScrollLeft => f.write_str("scl"),
+            Instruction::LowRes => f.write_str("lores"),
+            Instruction::HighRes => f.write_str("hires"),
+            Instruction::Jump(nnn) => write!(f, "jmp   {nnn:#06X}"),
+            Instruction::SubroutineCall(nnn) => write!(f, "call  {nnn:#06X}"),
+            Instruction::SkipConditional1(vx, nn) => write!(f, "ske   ${vx}, {nn:#04X}"),
+            Instruction::SkipConditional2(vx, nn) => write!(f, "skn   ${vx}, {nn:#04X}"),
+            Instruction::SkipConditional3(vx, vy) => write!(f, "ske   ${vx}, ${vy}"),
+            Instruction::SaveRange(vx, vy) => write!(f, "saver ${vx}, ${vy}"),
+            Instruction::LoadRange(vx, vy) => write!(f, "loadr ${vx}, ${vy}"),
+            Instruction::SetRegister(vx, nn) => write!(f, "mov   ${vx}, {nn:#04X}"),
+            Instruction::Add(vx, nn) => write!(f, "add   ${vx}, {nn:#04X}"),
+            Instruction::RegSet(vx, vy) => write!(f, "mov   ${vx}, ${vy}"),
+            Instruction::BinaryOr(vx, vy) => write!(f, "or    ${vx}, ${vy}"),
+            Instruction::BinaryAnd(vx, vy) => write!(f, "and   ${vx}, ${vy}"),
+            Instruction::BinaryXor(vx, vy) => write!(f, "xor   ${vx}, ${vy}"),
+            Instruction::RegAdd(vx, vy) => write!(f, "add   ${vx}, ${vy}"),
+            Instruction::Subtract1(vx, vy) => write!(f, "sub1  ${vx}, ${vy}"),
+            Instruction::ShiftRight(vx, vy) => write!(f, "shr   ${vx}, ${vy}"),
+            Instruction::Subtract2(vx, vy) => write!(f, "sub2  ${vx}, ${vy}"),
+            Instruction::ShiftLeft(vx, vy) => write!(f, "shl   ${vx}, ${vy}"),
+            Instruction::SkipConditional4(vx, vy) => write!(f, "skn   ${vx}, ${vy}"),
+            Instruction::SetIndexRegister(nnn) => write!(f, "mov   $i, {nnn:#06X}"),
+            Instruction::JumpOffset(nnn) => write!(f, "jo    {nnn:#06X}"),
+            Instruction::Random(vx, nn) => write!(f, "rand  ${vx}, {nn:#04X}"),
+            Instruction::Draw(vx, vy, n) => write!(f, "draw  ${vx}, ${vy}, {n:#04X}"),
+            Instruction::SkipIfKey(vx) => write!(f, "skk   ${vx}"),
+            Instruction::SkipIfNotKey(vx) => write!(f, "sknk  ${vx}"),
+            Instruction::LoadIndexLong(nnnn) => write!(f, "longi {nnnn:#06X}"),
+            Instruction::SelectPlanes(n) => write!(f, "plane {n:#04X}"),
+            Instruction::LoadAudio => f.write_str("audio"),
+            Instruction::GetDelayTimer(vx) => write!(f, "mov   ${vx}, $d"),
+            Instruction::GetKey(vx) => write!(f, "key   ${vx}"),
+            Instruction::SetDelayTimer(vx) => write!(f, "mov   $d, ${vx}"),
+            Instruction::SetSoundTimer(vx) => write!(f, "mov   $s, ${vx}"),
+            Instruction::AddToIndex(vx) => write!(f, "add   $i, ${vx}"),
+            Instruction::FontCharacter(vx) => write!(f, "font  ${vx}"),
+            Instruction::BigFontCharacter(vx) => write!(f, "bfont ${vx}"),
+            Instruction::BCD(vx) => write!(f, "bcd   ${vx}"),
+            Instruction::SetPitch(vx) => write!(f, "pitch ${vx}"),
+            Instruction::StoreMemory(n) => write!(f, "store {n:#04X}"),
+            Instruction::LoadMemory(n) => write!(f, "load  {n:#04X}"),
+            Instruction::StoreFlags(n) => write!(f, "savef {n:#04X}"),
+            Instruction::LoadFlags(n) => write!(f, "loadf {n:#04X}"),
+            Instruction::Db(nnnn) => write!(f, "dw    {nnnn:#06X}"),
+        }
+    }
+
+    /// Write the instruction in the syntax of the Octo assembler.
+    fn fmt_octo(self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = |vx: Register| format!("v{:x}", u8::from(vx));
+        match self {
+            Instruction::ExecuteMachineLanguageRoutine(nnnn) | Instruction::Db(nnnn) => {
+                write!(f, "{:#04X} {:#04X}", nnnn >> 8, nnnn & 0xFF)
+            }
+            Instruction::Clear => f.write_str("clear"),
+            Instruction::SubroutineReturn => f.write_str("return"),
+            Instruction::Exit => f.write_str("exit"),
+            Instruction::ScrollDown(n) => write!(f, "scroll-down {n}"),
+            Instruction::ScrollUp(n) => write!(f, "scroll-up {n}"),
+            Instruction::ScrollRight => f.write_str("scroll-right"),
+            Instruction::ScrollLeft => f.write_str("scroll-left"),
+            Instruction::LowRes => f.write_str("lores"),
+            Instruction::HighRes => f.write_str("hires"),
+            Instruction::Jump(nnn) => write!(f, "jump {nnn:#05X}"),
+            Instruction::SubroutineCall(nnn) => write!(f, ":call {nnn:#05X}"),
+            Instruction::SkipConditional1(vx, nn) => write!(f, "if {} != {nn:#04X} then", v(vx)),
+            Instruction::SkipConditional2(vx, nn) => write!(f, "if {} == {nn:#04X} then", v(vx)),
+            Instruction::SkipConditional3(vx, vy) => write!(f, "if {} != {} then", v(vx), v(vy)),
+            Instruction::SaveRange(vx, vy) => write!(f, "save {} - {}", v(vx), v(vy)),
+            Instruction::LoadRange(vx, vy) => write!(f, "load {} - {}", v(vx), v(vy)),
+            Instruction::SetRegister(vx, nn) => write!(f, "{} := {nn:#04X}", v(vx)),
+            Instruction::Add(vx, nn) => write!(f, "{} += {nn:#04X}", v(vx)),
+            Instruction::RegSet(vx, vy) => write!(f, "{} := {}", v(vx), v(vy)),
+            Instruction::BinaryOr(vx, vy) => write!(f, "{} |= {}", v(vx), v(vy)),
+            Instruction::BinaryAnd(vx, vy) => write!(f, "{} &= {}", v(vx), v(vy)),
+            Instruction::BinaryXor(vx, vy) => write!(f, "{} ^= {}", v(vx), v(vy)),
+            Instruction::RegAdd(vx, vy) => write!(f, "{} += {}", v(vx), v(vy)),
+            Instruction::Subtract1(vx, vy) => write!(f, "{} -= {}", v(vx), v(vy)),
+            Instruction::ShiftRight(vx, vy) => write!(f, "{} >>= {}", v(vx), v(vy)),
+            Instruction::Subtract2(vx, vy) => write!(f, "{} =- {}", v(vx), v(vy)),
+            Instruction::ShiftLeft(vx, vy) => write!(f, "{} <<= {}", v(vx), v(vy)),
+            Instruction::SkipConditional4(vx, vy) => write!(f, "if {} == {} then", v(vx), v(vy)),
+            Instruction::SetIndexRegister(nnn) => write!(f, "i := {nnn:#05X}"),
+            Instruction::JumpOffset(nnn) => write!(f, "jump0 {nnn:#05X}"),
+            Instruction::Random(vx, nn) => write!(f, "{} := random {nn:#04X}", v(vx)),
+            Instruction::Draw(vx, vy, n) => write!(f, "sprite {} {} {n}", v(vx), v(vy)),
+            Instruction::SkipIfKey(vx) => write!(f, "if {} -key then", v(vx)),
+            Instruction::SkipIfNotKey(vx) => write!(f, "if {} key then", v(vx)),
+            Instruction::LoadIndexLong(nnnn) => write!(f, "i := long {nnnn:#06X}"),
+            Instruction::SelectPlanes(n) => write!(f, "plane {n}"),
+            Instruction::LoadAudio => f.write_str("audio"),
+            Instruction::GetDelayTimer(vx) => write!(f, "{} := delay", v(vx)),
+            Instruction::GetKey(vx) => write!(f, "{} := key", v(vx)),
+            Instruction::SetDelayTimer(vx) => write!(f, "delay := {}", v(vx)),
+            Instruction::SetSoundTimer(vx) => write!(f, "buzzer := {}", v(vx)),
+            Instruction::AddToIndex(vx) => write!(f, "i += {}", v(vx)),
+            Instruction::FontCharacter(vx) => write!(f, "i := hex {}", v(vx)),
+            Instruction::BigFontCharacter(vx) => write!(f, "i := bighex {}", v(vx)),
+            Instruction::BCD(vx) => write!(f, "bcd {}", v(vx)),
+            Instruction::SetPitch(vx) => write!(f, "pitch := {}", v(vx)),
+            Instruction::StoreMemory(n) => write!(f, "save v{n:x}"),
+            Instruction::LoadMemory(n) => write!(f, "load v{n:x}"),
+            Instruction::StoreFlags(n) => write!(f, "saveflags v{n:x}"),
+            Instruction::LoadFlags(n) => write!(f, "loadflags v{n:x}"),
+        }
+    }
+
+    /// Write the name of the instruction and its arguments.
+    fn fmt_named(self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::ExecuteMachineLanguageRoutine(nnn) => {
+                write!(f, "ExecuteMachineLanguageRoutine({nnn:#05X})")
+            }
+            Instruction::Clear => f.write_str("Clear"),
+            Instruction::SubroutineReturn => f.write_str("SubroutineReturn"),
+            Instruction::Exit => f.write_str("Exit"),
+            Instruction::ScrollDown(n) => write!(f, "ScrollDown({n})"),
+            Instruction::ScrollUp(n) => write!(f, "ScrollUp({n})"),
+            Instruction::ScrollRight => f.write_str("ScrollRight"),
+            Instruction::ScrollLeft => f.write_str("ScrollLeft"),
+            Instruction::LowRes => f.write_str("LowRes"),
+            Instruction::HighRes => f.write_str("HighRes"),
+            Instruction::Jump(nnn) => write!(f, "Jump({nnn:#05X})"),
+            Instruction::SubroutineCall(nnn) => write!(f, "SubroutineCall({nnn:#05X})"),
+            Instruction::SkipConditional1(vx, nn) => write!(f, "SkipEqual({vx}, {nn:#04X})"),
+            Instruction::SkipConditional2(vx, nn) => write!(f, "SkipNotEqual({vx}, {nn:#04X})"),
+            Instruction::SkipConditional3(vx, vy) => write!(f, "SkipEqual({vx}, {vy})"),
+            Instruction::SaveRange(vx, vy) => write!(f, "SaveRange({vx}, {vy})"),
+            Instruction::LoadRange(vx, vy) => write!(f, "LoadRange({vx}, {vy})"),
+            Instruction::SetRegister(vx, nn) => write!(f, "SetRegister({vx}, {nn:#04X})"),
+            Instruction::Add(vx, nn) => write!(f, "Add({vx}, {nn:#04X})"),
+            Instruction::RegSet(vx, vy) => write!(f, "SetRegister({vx}, {vy})"),
+            Instruction::BinaryOr(vx, vy) => write!(f, "BinaryOr({vx}, {vy})"),
+            Instruction::BinaryAnd(vx, vy) => write!(f, "BinaryAnd({vx}, {vy})"),
+            Instruction::BinaryXor(vx, vy) => write!(f, "BinaryXor({vx}, {vy})"),
+            Instruction::RegAdd(vx, vy) => write!(f, "Add({vx}, {vy})"),
+            Instruction::Subtract1(vx, vy) => write!(f, "Subtract({vx}, {vy}) ({vx} - {vy})"),
+            Instruction::ShiftRight(vx, vy) => write!(f, "ShiftRight({vx}, {vy})"),
+            Instruction::Subtract2(vx, vy) => write!(f, "Subtract({vx}, {vy}) ({vy} - {vx})"),
+            Instruction::ShiftLeft(vx, vy) => write!(f, "ShiftLeft({vx}, {vy})"),
+            Instruction::SkipConditional4(vx, vy) => write!(f, "SkipNotEqual({vx}, {vy})"),
+            Instruction::SetIndexRegister(nnn) => write!(f, "SetI({nnn:#05X})"),
+            Instruction::JumpOffset(nnn) => write!(f, "JumpOffset({nnn:#05X})"),
+            Instruction::Random(vx, nn) => write!(f, "Random({vx}, {nn:#04X})"),
+            Instruction::Draw(vx, vy, n) => write!(f, "Draw({vx}, {vy}, {n:#04X})"),
+            Instruction::SkipIfKey(vx) => write!(f, "SkipIfKey({vx})"),
+            Instruction::SkipIfNotKey(vx) => write!(f, "SkipIfNotKey({vx})"),
+            Instruction::LoadIndexLong(nnnn) => write!(f, "SetILong({nnnn:#06X})"),
+            Instruction::SelectPlanes(n) => write!(f, "SelectPlanes({n})"),
+            Instruction::LoadAudio => f.write_str("LoadAudio"),
+            Instruction::GetDelayTimer(vx) => write!(f, "GetDelayTimer({vx})"),
+            Instruction::GetKey(vx) => write!(f, "GetKey({vx})"),
+            Instruction::SetDelayTimer(vx) => write!(f, "SetDelayTimer({vx})"),
+            Instruction::SetSoundTimer(vx) => write!(f, "SetSoundTimer({vx})"),
+            Instruction::AddToIndex(vx) => write!(f, "AddToI({vx})"),
+            Instruction::FontCharacter(vx) => write!(f, "FontAddress({vx})"),
+            Instruction::BigFontCharacter(vx) => write!(f, "BigFontAddress({vx})"),
+            Instruction::BCD(vx) => write!(f, "BinaryCodedDecimal({vx})"),
+            Instruction::SetPitch(vx) => write!(f, "SetPitch({vx})"),
+            Instruction::StoreMemory(n) => write!(f, "StoreMemory({n})"),
+            Instruction::LoadMemory(n) => write!(f, "LoadMemory({n})"),
+            Instruction::StoreFlags(n) => write!(f, "StoreFlags({n})"),
+            Instruction::LoadFlags(n) => write!(f, "LoadFlags({n})"),
+            Instruction::Db(nnnn) => write!(f, "Db({nnnn:#06X})"),
+        }
+    }
+
+    /// Returns a short plain-English description of what the instruction does, e.g. `skip next if V3 == 0x10`.
+    pub fn describe(&self) -> String {
+        match self {
+            Instruction::ExecuteMachineLanguageRoutine(nnn) => {
+                format!("run the machine language routine at {nnn:#05X}")
+            }
+            Instruction::Clear => "clear the screen".to_string(),
+            Instruction::SubroutineReturn => "return from the subroutine".to_string(),
+            Instruction::Exit => "exit the interpreter".to_string(),
+            Instruction::ScrollDown(n) => format!("scroll the screen down {n} pixels"),
+            Instruction::ScrollUp(n) => format!("scroll the screen up {n} pixels"),
+            Instruction::ScrollRight => "scroll the screen right 4 pixels".to_string(),
+            Instruction::ScrollLeft => "scroll the screen left 4 pixels".to_string(),
+            Instruction::LowRes => "switch to the 64x32 screen".to_string(),
+            Instruction::HighRes => "switch to the 128x64 screen".to_string(),
+            Instruction::Jump(nnn) => format!("jump to {nnn:#05X}"),
+            Instruction::SubroutineCall(nnn) => format!("call the subroutine at {nnn:#05X}"),
+            Instruction::SkipConditional1(vx, nn) => format!("skip next if {vx} == {nn:#04X}"),
+            Instruction::SkipConditional2(vx, nn) => format!("skip next if {vx} != {nn:#04X}"),
+            Instruction::SkipConditional3(vx, vy) => format!("skip next if {vx} == {vy}"),
+            Instruction::SaveRange(vx, vy) => format!("store {vx} to {vy} at I"),
+            Instruction::LoadRange(vx, vy) => format!("load {vx} to {vy} from I"),
+            Instruction::SetRegister(vx, nn) => format!("{vx} = {nn:#04X}"),
+            Instruction::Add(vx, nn) => format!("{vx} += {nn:#04X} (VF isn't changed)"),
+            Instruction::RegSet(vx, vy) => format!("{vx} = {vy}"),
+            Instruction::BinaryOr(vx, vy) => format!("{vx} |= {vy}"),
+            Instruction::BinaryAnd(vx, vy) => format!("{vx} &= {vy}"),
+            Instruction::BinaryXor(vx, vy) => format!("{vx} ^= {vy}"),
+            Instruction::RegAdd(vx, vy) => format!("{vx} += {vy}, VF = carry"),
+            Instruction::Subtract1(vx, vy) => format!("{vx} -= {vy}, VF = no borrow"),
+            Instruction::ShiftRight(vx, vy) => {
+                format!("{vx} = {vy} >> 1 ({vx} >> 1 with shift_vx_only), VF = the bit shifted out")
+            }
+            Instruction::Subtract2(vx, vy) => format!("{vx} = {vy} - {vx}, VF = no borrow"),
+            Instruction::ShiftLeft(vx, vy) => {
+                format!("{vx} = {vy} << 1 ({vx} << 1 with shift_vx_only), VF = the bit shifted out")
+            }
+            Instruction::SkipConditional4(vx, vy) => format!("skip next if {vx} != {vy}"),
+            Instruction::SetIndexRegister(nnn) => format!("I = {nnn:#05X}"),
+            Instruction::JumpOffset(nnn) => format!("jump to {nnn:#05X} + V0"),
+            Instruction::Random(vx, nn) => format!("{vx} = random & {nn:#04X}"),
+            Instruction::Draw(vx, vy, n) => {
+                format!("draw {n} rows of the sprite at I at ({vx}, {vy}), VF = collision")
+            }
+            Instruction::SkipIfKey(vx) => format!("skip next if the key in {vx} is pressed"),
+            Instruction::SkipIfNotKey(vx) => format!("skip next if the key in {vx} isn't pressed"),
+            Instruction::LoadIndexLong(nnnn) => format!("I = {nnnn:#06X}"),
+            Instruction::SelectPlanes(n) => format!("draw to the bit planes in {n:#04b}"),
+            Instruction::LoadAudio => "load the audio pattern from I".to_string(),
+            Instruction::GetDelayTimer(vx) => format!("{vx} = delay timer"),
+            Instruction::GetKey(vx) => format!("wait for a key press, {vx} = the key"),
+            Instruction::SetDelayTimer(vx) => format!("delay timer = {vx}"),
+            Instruction::SetSoundTimer(vx) => format!("sound timer = {vx}"),
+            Instruction::AddToIndex(vx) => format!("I += {vx}"),
+            Instruction::FontCharacter(vx) => format!("I = glyph of {vx}"),
+            Instruction::BigFontCharacter(vx) => format!("I = big glyph of {vx}"),
+            Instruction::BCD(vx) => format!("store the 3 decimal digits of {vx} at I"),
+            Instruction::SetPitch(vx) => format!("pitch = {vx}"),
+            Instruction::StoreMemory(n) => format!("store V0 to V{n:X} at I"),
+            Instruction::LoadMemory(n) => format!("load V0 to V{n:X} from I"),
+            Instruction::StoreFlags(n) => format!("store V0 to V{n:X} in the user flags"),
+            Instruction::LoadFlags(n) => format!("load V0 to V{n:X} from the user flags"),
+            Instruction::Db(_) => "2 bytes of data".to_string(),
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (*self).fmt_c8asm(f)
+    }
+}
+
+/// Writes an instruction in a flavor of syntax. Returned by [`Instruction::display`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayInstruction {
+    instruction: Instruction,
+    flavor: Flavor,
+}
+
+impl Display for DisplayInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.flavor {
+            Flavor::C8asm => self.instruction.fmt_c8asm(f),
+            Flavor::Octo => self.instruction.fmt_octo(f),
+            Flavor::Named => self.instruction.fmt_named(f),
         }
     }
 }
@@ -1,3 +1,9 @@
+pub mod asm;
+pub mod coverage;
 pub mod decode;
+pub mod disasm;
 pub mod instructions;
+pub mod octo;
 pub mod register;
+pub mod rom;
+pub mod symbols;
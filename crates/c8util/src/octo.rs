@@ -0,0 +1,696 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    asm::{Diagnostic, Program, column},
+    disasm::ROM_START,
+    instructions::Instruction,
+    register::Register,
+    symbols::SourceLocation,
+};
+
+/// The words that have a meaning in Octo, so they can't be used as names.
+const KEYWORDS: &[&str] = &[
+    "return",
+    "clear",
+    "hires",
+    "lores",
+    "exit",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "audio",
+    "plane",
+    "jump",
+    "jump0",
+    "sprite",
+    "bcd",
+    "save",
+    "load",
+    "saveflags",
+    "loadflags",
+    "delay",
+    "buzzer",
+    "pitch",
+    "i",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "again",
+    "while",
+    "key",
+    "random",
+    "hex",
+    "bighex",
+    "long",
+];
+
+/// A word of the source, and the line it's on.
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    /// The line number, starting at 1.
+    line: usize,
+}
+
+/// How the address of a label is written into an instruction, once the label is defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fixup {
+    /// The lowest 12 bits of a 2 byte instruction.
+    Nnn,
+    /// The last 2 bytes of `i := long`.
+    Long,
+}
+
+/// A block that hasn't been closed yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Block {
+    /// `if ... begin`, with the offset of the jump over the block.
+    If(usize),
+    /// `else`, with the offset of the jump over the else block.
+    Else(usize),
+    /// `loop`, with the address of its start, and the offsets of the jumps out of it from `while`.
+    Loop(u16, Vec<usize>),
+}
+
+/// The condition of an `if` or `while`: the instructions that compare (if any), followed by a skip.
+struct Condition {
+    setup: Vec<Instruction>,
+    /// Skips the next instruction if the condition is false.
+    skip_unless: Instruction,
+    /// Skips the next instruction if the condition is true.
+    skip_if: Instruction,
+}
+
+struct Compiler<'a> {
+    tokens: Vec<Token<'a>>,
+    /// The index of the next token.
+    pos: usize,
+    /// The program, starting at 0x200.
+    rom: Vec<u8>,
+    labels: BTreeMap<&'a str, u16>,
+    consts: BTreeMap<&'a str, i32>,
+    aliases: BTreeMap<&'a str, Register>,
+    /// The offsets of addresses that are written once all the labels are defined, and the label each one refers to.
+    fixups: Vec<(usize, Fixup, Token<'a>)>,
+    /// The blocks that are open, and the token that opened each one.
+    blocks: Vec<(Block, Token<'a>)>,
+}
+
+impl<'a> Compiler<'a> {
+    fn next(&mut self) -> Result<Token<'a>, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| String::from("unexpected end of the program"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(format!("expected {text}, found {}", token.text));
+        }
+        Ok(())
+    }
+
+    /// The address of the next byte of the program.
+    fn here(&self) -> Result<u16, String> {
+        u16::try_from(ROM_START + self.rom.len())
+            .map_err(|_| String::from("the program is too big"))
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.rom.extend(instruction.to_bytes());
+    }
+
+    /// Returns the register that a word names: `v0` to `vf`, or an alias.
+    fn as_register(&self, s: &str) -> Option<Register> {
+        if let Some(reg) = self.aliases.get(s) {
+            return Some(*reg);
+        }
+        let digit = s.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16)
+            .ok()
+            .and_then(|index| Register::try_from(index).ok())
+    }
+
+    /// Returns the value of a number (decimal, `0x` hex, or `0b` binary, and optionally negative) or a constant.
+    fn as_number(&self, s: &str) -> Option<i32> {
+        if let Some(value) = self.consts.get(s) {
+            return Some(*value);
+        }
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i32::from_str_radix(hex, 16).ok()?
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            i32::from_str_radix(bin, 2).ok()?
+        } else {
+            digits.parse().ok()?
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    /// Returns whether a word can be the name of a label, constant or alias.
+    fn is_name(&self, s: &str) -> bool {
+        s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            && !KEYWORDS.contains(&s)
+            && self.as_register(s).is_none()
+    }
+
+    fn name(&mut self) -> Result<&'a str, String> {
+        let token = self.next()?;
+        if !self.is_name(token.text) {
+            return Err(format!("{} can't be used as a name", token.text));
+        }
+        Ok(token.text)
+    }
+
+    fn register(&mut self) -> Result<Register, String> {
+        let token = self.next()?;
+        self.as_register(token.text)
+            .ok_or_else(|| format!("{} is not a register", token.text))
+    }
+
+    fn number(&mut self) -> Result<i32, String> {
+        let token = self.next()?;
+        self.as_number(token.text)
+            .ok_or_else(|| format!("{} is not a number", token.text))
+    }
+
+    /// The value of a byte: 0 to 255, or -128 to -1 for its two's complement.
+    fn byte_value(&self, token: Token) -> Result<u8, String> {
+        let value = self
+            .as_number(token.text)
+            .ok_or_else(|| format!("{} is not a number", token.text))?;
+        if !(-128..=255).contains(&value) {
+            return Err(format!("value {value} doesn't fit in 8 bits"));
+        }
+        Ok(u8::try_from(value.rem_euclid(256)).unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.byte_value(token)
+    }
+
+    fn nibble(&mut self) -> Result<u8, String> {
+        let value = self.byte()?;
+        if value > 0xF {
+            return Err(format!("value {value} doesn't fit in 4 bits"));
+        }
+        Ok(value)
+    }
+
+    /// Emit an instruction with the address that `token` is: a number, a constant, or a label, which may not be
+    /// defined yet.
+    fn emit_address(
+        &mut self,
+        token: Token<'a>,
+        instruction: fn(u16) -> Instruction,
+        fixup: Fixup,
+    ) -> Result<(), String> {
+        if let Some(addr) = self.as_number(token.text) {
+            let max = if fixup == Fixup::Long { 0xFFFF } else { 0xFFF };
+            let addr = u16::try_from(addr)
+                .ok()
+                .filter(|addr| *addr <= max)
+                .ok_or_else(|| format!("address {addr:#X} is out of range"))?;
+            self.emit(instruction(addr));
+            return Ok(());
+        }
+        if !self.is_name(token.text) {
+            return Err(format!("{} is not a label or a number", token.text));
+        }
+        self.fixups.push((self.rom.len(), fixup, token));
+        self.emit(instruction(0));
+        Ok(())
+    }
+
+    /// Emit a jump whose address is written later, with [`Self::patch_jump`]. Returns its offset.
+    fn emit_jump_placeholder(&mut self) -> usize {
+        let offset = self.rom.len();
+        self.emit(Instruction::Jump(0));
+        offset
+    }
+
+    /// Point the jump at `offset` to the next byte of the program.
+    fn patch_jump(&mut self, offset: usize) -> Result<(), String> {
+        let addr = self.here()?;
+        if addr > 0xFFF {
+            return Err(format!("can't jump to {addr:#X}"));
+        }
+        self.rom[offset..offset + 2].copy_from_slice(&Instruction::Jump(addr).to_bytes());
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        let instruction = match token.text {
+            text if text.starts_with(':') => return self.directive(token),
+            "if" | "else" | "end" | "loop" | "again" | "while" => return self.control_flow(token),
+            "i" => return self.index(),
+            "save" | "load" => {
+                let vx = self.register()?;
+                let save = token.text == "save";
+                if self.tokens.get(self.pos).is_some_and(|t| t.text == "-") {
+                    self.pos += 1;
+                    let vy = self.register()?;
+                    if save {
+                        Instruction::SaveRange(vx, vy)
+                    } else {
+                        Instruction::LoadRange(vx, vy)
+                    }
+                } else if save {
+                    Instruction::StoreMemory(u8::from(vx))
+                } else {
+                    Instruction::LoadMemory(u8::from(vx))
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let vx = self.register()?;
+                match token.text {
+                    "delay" => Instruction::SetDelayTimer(vx),
+                    "buzzer" => Instruction::SetSoundTimer(vx),
+                    _ => Instruction::SetPitch(vx),
+                }
+            }
+            "jump" => {
+                let target = self.next()?;
+                return self.emit_address(target, Instruction::Jump, Fixup::Nnn);
+            }
+            "jump0" => {
+                let target = self.next()?;
+                return self.emit_address(target, Instruction::JumpOffset, Fixup::Nnn);
+            }
+            "return" | ";" => Instruction::SubroutineReturn,
+            "clear" => Instruction::Clear,
+            "hires" => Instruction::HighRes,
+            "lores" => Instruction::LowRes,
+            "exit" => Instruction::Exit,
+            "scroll-left" => Instruction::ScrollLeft,
+            "scroll-right" => Instruction::ScrollRight,
+            "scroll-down" => Instruction::ScrollDown(self.nibble()?),
+            "scroll-up" => Instruction::ScrollUp(self.nibble()?),
+            "audio" => Instruction::LoadAudio,
+            "plane" => Instruction::SelectPlanes(self.nibble()?),
+            "sprite" => Instruction::Draw(self.register()?, self.register()?, self.nibble()?),
+            "bcd" => Instruction::BCD(self.register()?),
+            "saveflags" => Instruction::StoreFlags(u8::from(self.register()?)),
+            "loadflags" => Instruction::LoadFlags(u8::from(self.register()?)),
+            text => {
+                if let Some(vx) = self.as_register(text) {
+                    self.register_operation(vx)?
+                } else if self.as_number(text).is_some() {
+                    // A number on its own is a byte of data
+                    let byte = self.byte_value(token)?;
+                    self.rom.push(byte);
+                    return Ok(());
+                } else if self.is_name(text) {
+                    // A label on its own calls it
+                    return self.emit_address(token, Instruction::SubroutineCall, Fixup::Nnn);
+                } else {
+                    return Err(format!("unknown instruction: {text}"));
+                }
+            }
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    /// Compile a directive, which starts with `:`.
+    fn directive(&mut self, token: Token<'a>) -> Result<(), String> {
+        match token.text {
+            ":" => {
+                let name = self.name()?;
+                let addr = self.here()?;
+                if self.labels.insert(name, addr).is_some() {
+                    return Err(format!("label {name} is defined more than once"));
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.number()?;
+                self.consts.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.register()?;
+                self.aliases.insert(name, reg);
+            }
+            ":org" => {
+                let addr = self.number()?;
+                let offset = usize::try_from(addr)
+                    .ok()
+                    .and_then(|addr| addr.checked_sub(ROM_START))
+                    .filter(|offset| *offset >= self.rom.len())
+                    .ok_or_else(|| format!(":org can't go back to {addr:#X}"))?;
+                self.rom.resize(offset, 0);
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_address(target, Instruction::SubroutineCall, Fixup::Nnn)?;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.rom.push(byte);
+            }
+            text => return Err(format!("{text} isn't supported")),
+        }
+        Ok(())
+    }
+
+    /// Compile `if`, `else`, `end`, `loop`, `again` or `while`.
+    fn control_flow(&mut self, token: Token<'a>) -> Result<(), String> {
+        match token.text {
+            "if" => {
+                let condition = self.condition()?;
+                let then = self.next()?;
+                match then.text {
+                    "then" => self.emit(condition.skip_unless),
+                    "begin" => {
+                        self.emit(condition.skip_if);
+                        let offset = self.emit_jump_placeholder();
+                        self.blocks.push((Block::If(offset), then));
+                    }
+                    text => return Err(format!("expected then or begin, found {text}")),
+                }
+            }
+            "else" => {
+                let Some((Block::If(offset), _)) = self.blocks.pop() else {
+                    return Err(String::from("else without if ... begin"));
+                };
+                let end = self.emit_jump_placeholder();
+                self.patch_jump(offset)?;
+                self.blocks.push((Block::Else(end), token));
+            }
+            "end" => {
+                let Some((Block::If(offset) | Block::Else(offset), _)) = self.blocks.pop() else {
+                    return Err(String::from("end without if ... begin"));
+                };
+                self.patch_jump(offset)?;
+            }
+            "loop" => {
+                let start = self.here()?;
+                self.blocks.push((Block::Loop(start, Vec::new()), token));
+            }
+            "again" => {
+                let Some((Block::Loop(start, exits), _)) = self.blocks.pop() else {
+                    return Err(String::from("again without loop"));
+                };
+                if start > 0xFFF {
+                    return Err(format!("can't jump to {start:#X}"));
+                }
+                self.emit(Instruction::Jump(start));
+                for exit in exits {
+                    self.patch_jump(exit)?;
+                }
+            }
+            _ => {
+                let condition = self.condition()?;
+                self.emit(condition.skip_if);
+                let exit = self.emit_jump_placeholder();
+                let Some(exits) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find_map(|(block, _)| match block {
+                        Block::Loop(_, exits) => Some(exits),
+                        _ => None,
+                    })
+                else {
+                    return Err(String::from("while without loop"));
+                };
+                exits.push(exit);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a condition, e.g. `v0 == 5`, `v1 != v2`, `v3 key` or `v4 < 10`, and emit the instructions that compare.
+    fn condition(&mut self) -> Result<Condition, String> {
+        let vx = self.register()?;
+        let op = self.next()?;
+        let condition = |skip_unless, skip_if| Condition {
+            setup: Vec::new(),
+            skip_unless,
+            skip_if,
+        };
+        match op.text {
+            "key" => {
+                return Ok(condition(
+                    Instruction::SkipIfNotKey(vx),
+                    Instruction::SkipIfKey(vx),
+                ));
+            }
+            "-key" => {
+                return Ok(condition(
+                    Instruction::SkipIfKey(vx),
+                    Instruction::SkipIfNotKey(vx),
+                ));
+            }
+            _ => {}
+        }
+
+        let rhs = self.next()?;
+        let condition = match (op.text, self.as_register(rhs.text)) {
+            ("==", Some(vy)) => condition(
+                Instruction::SkipConditional4(vx, vy),
+                Instruction::SkipConditional3(vx, vy),
+            ),
+            ("!=", Some(vy)) => condition(
+                Instruction::SkipConditional3(vx, vy),
+                Instruction::SkipConditional4(vx, vy),
+            ),
+            ("==", None) => {
+                let nn = self.byte_value(rhs)?;
+                condition(
+                    Instruction::SkipConditional2(vx, nn),
+                    Instruction::SkipConditional1(vx, nn),
+                )
+            }
+            ("!=", None) => {
+                let nn = self.byte_value(rhs)?;
+                condition(
+                    Instruction::SkipConditional1(vx, nn),
+                    Instruction::SkipConditional2(vx, nn),
+                )
+            }
+            ("<" | ">=" | ">" | "<=", vy) => {
+                // Like Octo, subtract into VF. VF is then 1 if the subtraction didn't borrow: VX >= rhs for < and >=,
+                // or rhs >= VX for > and <=.
+                let set_vf = match vy {
+                    Some(vy) => Instruction::RegSet(Register::VF, vy),
+                    None => Instruction::SetRegister(Register::VF, self.byte_value(rhs)?),
+                };
+                let subtract = match op.text {
+                    "<" | ">=" => Instruction::Subtract2(Register::VF, vx),
+                    _ => Instruction::Subtract1(Register::VF, vx),
+                };
+                let vf_if_false = u8::from(matches!(op.text, "<" | ">"));
+                Condition {
+                    setup: vec![set_vf, subtract],
+                    skip_unless: Instruction::SkipConditional1(Register::VF, vf_if_false),
+                    skip_if: Instruction::SkipConditional1(Register::VF, 1 - vf_if_false),
+                }
+            }
+            (text, _) => return Err(format!("unknown comparison: {text}")),
+        };
+        for instruction in &condition.setup {
+            self.emit(*instruction);
+        }
+        Ok(condition)
+    }
+
+    /// Compile a statement that starts with `i`.
+    fn index(&mut self) -> Result<(), String> {
+        let op = self.next()?;
+        match op.text {
+            ":=" => {
+                let value = self.next()?;
+                match value.text {
+                    "long" => {
+                        let target = self.next()?;
+                        self.emit_address(target, Instruction::LoadIndexLong, Fixup::Long)?;
+                    }
+                    "hex" => {
+                        let vx = self.register()?;
+                        self.emit(Instruction::FontCharacter(vx));
+                    }
+                    "bighex" => {
+                        let vx = self.register()?;
+                        self.emit(Instruction::BigFontCharacter(vx));
+                    }
+                    _ => self.emit_address(value, Instruction::SetIndexRegister, Fixup::Nnn)?,
+                }
+            }
+            "+=" => {
+                let vx = self.register()?;
+                self.emit(Instruction::AddToIndex(vx));
+            }
+            text => return Err(format!("unknown operator for i: {text}")),
+        }
+        Ok(())
+    }
+
+    /// Compile a statement that starts with the register `vx`, e.g. `v0 += 1`.
+    fn register_operation(&mut self, vx: Register) -> Result<Instruction, String> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let vy = self.as_register(rhs.text);
+        Ok(match (op.text, vy) {
+            (":=", Some(vy)) => Instruction::RegSet(vx, vy),
+            (":=", None) => match rhs.text {
+                "random" => Instruction::Random(vx, self.byte()?),
+                "delay" => Instruction::GetDelayTimer(vx),
+                "key" => Instruction::GetKey(vx),
+                _ => Instruction::SetRegister(vx, self.byte_value(rhs)?),
+            },
+            ("+=", Some(vy)) => Instruction::RegAdd(vx, vy),
+            ("+=", None) => Instruction::Add(vx, self.byte_value(rhs)?),
+            ("-=", Some(vy)) => Instruction::Subtract1(vx, vy),
+            ("-=", None) => Instruction::Add(vx, self.byte_value(rhs)?.wrapping_neg()),
+            ("=-", Some(vy)) => Instruction::Subtract2(vx, vy),
+            ("|=", Some(vy)) => Instruction::BinaryOr(vx, vy),
+            ("&=", Some(vy)) => Instruction::BinaryAnd(vx, vy),
+            ("^=", Some(vy)) => Instruction::BinaryXor(vx, vy),
+            (">>=", Some(vy)) => Instruction::ShiftRight(vx, vy),
+            ("<<=", Some(vy)) => Instruction::ShiftLeft(vx, vy),
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                return Err(format!("{} is not a register", rhs.text));
+            }
+            (text, _) => return Err(format!("unknown operator: {text}")),
+        })
+    }
+
+    /// Write the addresses of the labels that were used before they were defined.
+    fn resolve_fixups(&mut self) -> Result<(), (Token<'a>, String)> {
+        for (offset, fixup, token) in &self.fixups {
+            let addr = *self
+                .labels
+                .get(token.text)
+                .ok_or_else(|| (*token, format!("unknown label: {}", token.text)))?;
+            match fixup {
+                Fixup::Nnn if addr > 0xFFF => {
+                    return Err((*token, format!("address {addr:#X} doesn't fit in 12 bits")));
+                }
+                Fixup::Nnn => {
+                    let [high, low] = addr.to_be_bytes();
+                    self.rom[*offset] |= high;
+                    self.rom[offset + 1] = low;
+                }
+                Fixup::Long => {
+                    self.rom[offset + 2..offset + 4].copy_from_slice(&addr.to_be_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Assemble a program written for Octo, e.g. `: main  v0 += 1  loop ... again`. Supports labels (`: name`),
+/// `:const`, `:alias`, `:org`, `:call` and `:byte`, every instruction, `if ... then`, `if ... begin ... else ... end`,
+/// `loop ... while ... again`, comparisons with `<`, `>`, `<=` and `>=`, and numbers as bytes of data. Macros,
+/// `:calc`, `:next` and `:unpack` aren't supported.
+///
+/// Like Octo, the program starts at 0x200, and if a `main` label isn't at the start, a jump to it is put there.
+///
+/// Returns the program with its labels and the source line of each address, like `asm::assemble_file`. The file of
+/// each source line is `<source>`.
+///
+/// # Errors
+/// Returns a diagnostic with the line and column of the first error: a line that could not be assembled, a label that
+/// is used but never defined, or a block that isn't closed.
+pub fn assemble(source: &str) -> Result<Program, Diagnostic> {
+    let lines = source.lines().collect::<Vec<_>>();
+    let tokens = lines
+        .iter()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace()
+                .map(move |text| Token { text, line: i + 1 })
+        })
+        .collect::<Vec<_>>();
+    let error = |token: Token, message| Diagnostic {
+        file: None,
+        line: token.line,
+        column: column(lines[token.line - 1], token.text),
+        message,
+    };
+
+    let mut compiler = Compiler {
+        tokens,
+        pos: 0,
+        rom: Vec::new(),
+        labels: BTreeMap::new(),
+        consts: BTreeMap::new(),
+        aliases: BTreeMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    let main = compiler
+        .tokens
+        .windows(2)
+        .position(|pair| pair[0].text == ":" && pair[1].text == "main");
+    if let Some(i) = main.filter(|i| *i != 0) {
+        let main = compiler.tokens[i + 1];
+        compiler
+            .emit_address(main, Instruction::Jump, Fixup::Nnn)
+            .map_err(|e| error(main, e))?;
+    }
+
+    let mut source_lines = BTreeMap::new();
+    while compiler.pos < compiler.tokens.len() {
+        let start = compiler.rom.len();
+        let line = compiler.tokens[compiler.pos].line;
+        compiler
+            .statement()
+            .map_err(|e| error(compiler.tokens[compiler.pos - 1], e))?;
+        if compiler.rom.len() > start {
+            if let Ok(addr) = u16::try_from(ROM_START + start) {
+                source_lines.entry(addr).or_insert(SourceLocation {
+                    file: String::from("<source>"),
+                    line,
+                });
+            }
+        }
+    }
+    if let Some((block, token)) = compiler.blocks.last() {
+        let closing = match block {
+            Block::If(_) | Block::Else(_) => "end",
+            Block::Loop(..) => "again",
+        };
+        return Err(error(
+            *token,
+            format!("{} is never closed with {closing}", token.text),
+        ));
+    }
+    compiler
+        .resolve_fixups()
+        .map_err(|(token, e)| error(token, e))?;
+
+    let mut labels = BTreeMap::new();
+    for (name, addr) in &compiler.labels {
+        labels.entry(*addr).or_insert_with(|| name.to_string());
+    }
+    Ok(Program {
+        rom: compiler.rom,
+        labels,
+        source_lines,
+    })
+}
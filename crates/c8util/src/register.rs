@@ -2,6 +2,7 @@ use std::fmt::Display;
 
 /// Registers
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Register {
     V0 = 0,
     V1,
@@ -51,27 +52,52 @@ impl From<Register> for u16 {
     }
 }
 
-impl From<u8> for Register {
-    fn from(value: u8) -> Self {
-        match value {
-            0 => Self::V0,
-            1 => Self::V1,
-            2 => Self::V2,
-            3 => Self::V3,
-            4 => Self::V4,
-            5 => Self::V5,
-            6 => Self::V6,
-            7 => Self::V7,
-            8 => Self::V8,
-            9 => Self::V9,
-            0xA => Self::VA,
-            0xB => Self::VB,
-            0xC => Self::VC,
-            0xD => Self::VD,
-            0xE => Self::VE,
-            0xF => Self::VF,
-            _ => panic!("value {value} could not be converted to register"),
-        }
+/// The error from converting a number above 0xF to a `Register`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidRegister(pub u8);
+
+impl Display for InvalidRegister {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "there is no register V{:X} (should be V0-VF)", self.0)
+    }
+}
+
+impl TryFrom<u8> for Register {
+    type Error = InvalidRegister;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .get(usize::from(value))
+            .copied()
+            .ok_or(InvalidRegister(value))
+    }
+}
+
+impl Register {
+    /// Every register, in order from V0 to VF.
+    pub const ALL: [Self; 16] = [
+        Self::V0,
+        Self::V1,
+        Self::V2,
+        Self::V3,
+        Self::V4,
+        Self::V5,
+        Self::V6,
+        Self::V7,
+        Self::V8,
+        Self::V9,
+        Self::VA,
+        Self::VB,
+        Self::VC,
+        Self::VD,
+        Self::VE,
+        Self::VF,
+    ];
+
+    /// Returns the register in the low 4 bits of `value`, ignoring the rest, e.g. the X in an instruction's `0X00`
+    /// nibble after shifting it down. Use `Register::try_from` to reject values above 0xF instead.
+    pub fn from_nibble(value: u8) -> Self {
+        Self::ALL[usize::from(value & 0xF)]
     }
 }
 
@@ -0,0 +1,309 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{disasm::ROM_START, instructions::Instruction};
+
+/// The size of the Chip-8 memory. ROMs have to fit between the address they're loaded at and the end of it.
+pub const MEMORY_SIZE: usize = 4096;
+
+/// A ROM, the address it's loaded at, and where it was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    data: Vec<u8>,
+    load_addr: usize,
+    /// The file that the ROM was read from, or None if it wasn't read from a file (e.g. stdin).
+    path: Option<PathBuf>,
+    /// The SHA-1 of the data, as lowercase hex. ROMs are identified by it (e.g. in c8rs's ROM database).
+    sha1: String,
+}
+
+impl Rom {
+    /// Make a ROM from its bytes, which are loaded at `load_addr` (usually `disasm::ROM_START`).
+    ///
+    /// # Errors
+    /// Returns an error if the load address is outside of memory, or the ROM doesn't fit between it and the end of
+    /// memory.
+    pub fn from_bytes(data: Vec<u8>, load_addr: usize) -> Result<Self, String> {
+        if load_addr >= MEMORY_SIZE {
+            return Err(format!(
+                "the load address {load_addr:#06X} is outside of memory"
+            ));
+        }
+        if load_addr + data.len() > MEMORY_SIZE {
+            return Err(format!(
+                "the ROM is too large: {} bytes, but at most {} fit in memory after {load_addr:#06X}",
+                data.len(),
+                MEMORY_SIZE - load_addr
+            ));
+        }
+        let mut sha1 = String::with_capacity(40);
+        for byte in Sha1::digest(&data) {
+            write!(sha1, "{byte:02x}").unwrap();
+        }
+        Ok(Self {
+            data,
+            load_addr,
+            path: None,
+            sha1,
+        })
+    }
+
+    /// Read the ROM at `path`, which is loaded at `load_addr`.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be read, or the ROM doesn't fit in memory (see `from_bytes`).
+    pub fn load(path: &Path, load_addr: usize) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        let rom = Self::from_bytes(data, load_addr)
+            .map_err(|e| format!("invalid ROM {}: {e}", path.display()))?;
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            ..rom
+        })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load_addr(&self) -> usize {
+        self.load_addr
+    }
+
+    /// Returns the address after the last byte of the ROM.
+    pub fn end_addr(&self) -> usize {
+        self.load_addr + self.data.len()
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the SHA-1 of the ROM, as lowercase hex.
+    pub fn sha1(&self) -> &str {
+        &self.sha1
+    }
+}
+
+/// Builds the bytes of a ROM from instructions and data, with labels for the addresses that jumps and calls go to.
+/// It's for making small ROMs inline (e.g. in tests) without going through the assembler. See also `rom!`.
+#[derive(Debug, Clone)]
+pub struct RomBuilder {
+    org: usize,
+    data: Vec<u8>,
+    labels: BTreeMap<String, usize>,
+    /// Instructions that go to a label, which are written once every label is defined.
+    fixups: Vec<Fixup>,
+    /// The first error (e.g. a label that's defined twice), which is returned by `build`.
+    error: Option<String>,
+}
+
+/// An instruction added with `RomBuilder::ins_to`.
+#[derive(Debug, Clone)]
+struct Fixup {
+    /// Where the instruction goes in the data.
+    offset: usize,
+    label: String,
+    /// Makes the instruction from the address of the label.
+    make: fn(u16) -> Instruction,
+}
+
+impl Default for RomBuilder {
+    fn default() -> Self {
+        Self::new(ROM_START)
+    }
+}
+
+impl RomBuilder {
+    /// Start an empty ROM that's loaded at `org`.
+    pub fn new(org: usize) -> Self {
+        Self {
+            org,
+            data: Vec::new(),
+            labels: BTreeMap::new(),
+            fixups: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Define a label at the address of the next instruction or data.
+    #[must_use]
+    pub fn label(mut self, name: &str) -> Self {
+        let addr = self.org + self.data.len();
+        if self.labels.insert(name.to_string(), addr).is_some() {
+            self.error
+                .get_or_insert_with(|| format!("label {name} is defined more than once"));
+        }
+        self
+    }
+
+    /// Add an instruction.
+    #[must_use]
+    pub fn ins(mut self, instruction: Instruction) -> Self {
+        self.data.extend(instruction.to_bytes());
+        self
+    }
+
+    /// Add an instruction that takes the address of a label, e.g. `ins_to("loop", Instruction::Jump)`. The label
+    /// can be defined before or after it.
+    #[must_use]
+    pub fn ins_to(mut self, label: &str, make: fn(u16) -> Instruction) -> Self {
+        // The address isn't known yet, so leave space for the instruction and write it in `build`
+        let size = make(0).size();
+        self.fixups.push(Fixup {
+            offset: self.data.len(),
+            label: label.to_string(),
+            make,
+        });
+        self.data.resize(self.data.len() + size, 0);
+        self
+    }
+
+    /// Add data, e.g. a sprite.
+    #[must_use]
+    pub fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    /// Returns the bytes of the ROM.
+    ///
+    /// # Errors
+    /// Returns an error if a label is used but never defined, is defined more than once, or is outside of memory.
+    pub fn build(mut self) -> Result<Vec<u8>, String> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        for Fixup {
+            offset,
+            label,
+            make,
+        } in &self.fixups
+        {
+            let addr = *self
+                .labels
+                .get(label)
+                .ok_or_else(|| format!("label {label} is never defined"))?;
+            let addr = u16::try_from(addr)
+                .ok()
+                .filter(|addr| usize::from(*addr) < MEMORY_SIZE)
+                .ok_or_else(|| format!("label {label} is outside of memory ({addr:#06X})"))?;
+            let bytes = make(addr).to_bytes();
+            self.data[*offset..offset + bytes.len()].copy_from_slice(&bytes);
+        }
+        Ok(self.data)
+    }
+}
+
+/// Builds the bytes of a ROM that's loaded at `disasm::ROM_START`, using a `RomBuilder`. Returns the same result as
+/// `RomBuilder::build`. Each item ends with `;`, except for labels:
+/// - An instruction, written as its `Instruction` variant (e.g. `Draw(V0, V1, 4);` or `Clear;`). The registers are
+///   in scope, so they can be written as `V0` to `VF`.
+/// - `Variant => label;`: an instruction that takes an address (e.g. `Jump` or `SetIndexRegister`), which is the
+///   address of the label.
+/// - `label:`: a label at the address of the next item.
+/// - `[bytes];`: data, e.g. a sprite.
+///
+/// ```
+/// let bytes = c8util::rom! {
+///     SetRegister(V0, 5);
+///     SetIndexRegister => sprite;
+///     SubroutineCall => draw;
+///     Exit;
+///     draw:
+///     Draw(V0, V1, 2);
+///     SubroutineReturn;
+///     sprite:
+///     [0xFF, 0x81];
+/// }
+/// .unwrap();
+/// assert_eq!(
+///     bytes,
+///     [0x60, 0x05, 0xA2, 0x0C, 0x22, 0x08, 0x00, 0xFD, 0xD0, 0x12, 0x00, 0xEE, 0xFF, 0x81]
+/// );
+/// ```
+#[macro_export]
+macro_rules! rom {
+    (@items $builder:expr;) => {
+        $builder
+    };
+    (@items $builder:expr; $label:ident : $($rest:tt)*) => {
+        $crate::rom!(@items $builder.label(stringify!($label)); $($rest)*)
+    };
+    (@items $builder:expr; $variant:ident => $label:ident; $($rest:tt)*) => {
+        $crate::rom!(
+            @items $builder.ins_to(stringify!($label), $crate::instructions::Instruction::$variant);
+            $($rest)*
+        )
+    };
+    (@items $builder:expr; $variant:ident ($($args:tt)*); $($rest:tt)*) => {
+        $crate::rom!(
+            @items $builder.ins($crate::instructions::Instruction::$variant($($args)*));
+            $($rest)*
+        )
+    };
+    (@items $builder:expr; $variant:ident; $($rest:tt)*) => {
+        $crate::rom!(@items $builder.ins($crate::instructions::Instruction::$variant); $($rest)*)
+    };
+    (@items $builder:expr; [$($byte:expr),* $(,)?]; $($rest:tt)*) => {
+        $crate::rom!(@items $builder.bytes(&[$($byte),*]); $($rest)*)
+    };
+    ($($items:tt)*) => {{
+        #[allow(unused_imports)]
+        use $crate::register::Register::*;
+        $crate::rom!(@items $crate::rom::RomBuilder::default(); $($items)*).build()
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        decode::iter,
+        instructions::{Instruction, InstructionSet},
+        register::Register::{V0, V1},
+    };
+
+    #[test]
+    fn rom_macro_resolves_labels() {
+        let bytes = crate::rom! {
+            start:
+            SetRegister(V0, 0);
+            LoadIndexLong => sprite;
+            Draw(V0, V1, 1);
+            Add(V0, 1);
+            Jump => start;
+            sprite:
+            [0x80];
+        }
+        .unwrap();
+
+        let instructions: Vec<_> = iter(&bytes, 0x200, InstructionSet::XoChip)
+            .map(|(addr, _, instruction)| (addr, instruction))
+            .collect();
+        assert_eq!(
+            instructions,
+            [
+                (0x200, Some(Instruction::SetRegister(V0, 0))),
+                (0x202, Some(Instruction::LoadIndexLong(0x20C))),
+                (0x206, Some(Instruction::Draw(V0, V1, 1))),
+                (0x208, Some(Instruction::Add(V0, 1))),
+                (0x20A, Some(Instruction::Jump(0x200))),
+                (0x20C, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn rom_macro_reports_missing_labels() {
+        let result = crate::rom! {
+            Jump => nowhere;
+        };
+        assert_eq!(result, Err(String::from("label nowhere is never defined")));
+    }
+}
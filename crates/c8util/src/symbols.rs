@@ -0,0 +1,116 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Write as _},
+};
+
+/// A line in the source code of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    /// The line number, starting at 1.
+    pub line: usize,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Parse a symbol file, which names addresses in a ROM (e.g. subroutines). Each line is an address in hex followed
+/// by its name, and `#` starts a comment. Lines that start with `line` give the source line of an address instead
+/// (see `parse_source_lines`), and are skipped.
+///
+/// e.g.
+/// ```text
+/// # Subroutines
+/// 0x200 main
+/// 0x23A draw_player
+/// ```
+///
+/// # Errors
+/// Returns an error with the line number if a line is invalid.
+pub fn parse_symbols(contents: &str) -> Result<BTreeMap<u16, String>, String> {
+    let mut symbols = BTreeMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() || line.starts_with("line ") {
+            continue;
+        }
+        let invalid = |reason: &str| format!("{}: {reason}", i + 1);
+        let mut parts = line.split_whitespace();
+        let (Some(addr), Some(name), None) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid("expected an address and a name"));
+        };
+        let addr = parse_addr(addr).ok_or_else(|| invalid("invalid address"))?;
+        symbols.insert(addr, name.to_string());
+    }
+    Ok(symbols)
+}
+
+/// Parse the source lines in a symbol file, which c8asm writes so that addresses can be traced back to the code they
+/// were assembled from. Each one is `line`, an address in hex, and the file and line number. Other lines are skipped.
+///
+/// e.g.
+/// ```text
+/// line 0x200 main.asm:3
+/// line 0x202 gfx/sprites.inc:1
+/// ```
+///
+/// # Errors
+/// Returns an error with the line number if a source line is invalid.
+pub fn parse_source_lines(contents: &str) -> Result<BTreeMap<u16, SourceLocation>, String> {
+    let mut source_lines = BTreeMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let Some(rest) = line.strip_prefix("line ") else {
+            continue;
+        };
+        let invalid = |reason: &str| format!("{}: {reason}", i + 1);
+        let Some((addr, location)) = rest.trim().split_once(char::is_whitespace) else {
+            return Err(invalid("expected an address and a file and line number"));
+        };
+        let addr = parse_addr(addr).ok_or_else(|| invalid("invalid address"))?;
+        let (file, line) = location
+            .trim()
+            .rsplit_once(':')
+            .and_then(|(file, line)| Some((file, line.parse().ok()?)))
+            .ok_or_else(|| invalid("expected file:line"))?;
+        source_lines.insert(
+            addr,
+            SourceLocation {
+                file: file.to_string(),
+                line,
+            },
+        );
+    }
+    Ok(source_lines)
+}
+
+/// Write source lines in the format that `parse_source_lines` reads, with a line for each address.
+pub fn format_source_lines(source_lines: &BTreeMap<u16, SourceLocation>) -> String {
+    source_lines
+        .iter()
+        .fold(String::new(), |mut contents, (addr, location)| {
+            writeln!(contents, "line {addr:#05X} {location}").unwrap();
+            contents
+        })
+}
+
+/// Write symbols in the format that `parse_symbols` reads, with a line for each one.
+pub fn format_symbols(symbols: &BTreeMap<u16, String>) -> String {
+    symbols
+        .iter()
+        .fold(String::new(), |mut contents, (addr, name)| {
+            writeln!(contents, "{addr:#05X} {name}").unwrap();
+            contents
+        })
+}
+
+/// Parse an address written in hex, with or without `0x`.
+fn parse_addr(s: &str) -> Option<u16> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    u16::from_str_radix(hex, 16)
+        .ok()
+        .filter(|addr| *addr <= 0x0FFF)
+}